@@ -0,0 +1,6 @@
+ALTER TABLE tasks
+  ADD COLUMN IF NOT EXISTS external_uid TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_tasks_user_external_uid
+  ON tasks(user_id, external_uid)
+  WHERE external_uid IS NOT NULL;
@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS links (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  source_note_id UUID NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+  target_type TEXT NOT NULL,
+  target_id UUID,
+  target_title TEXT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_links_source_note_id ON links(source_note_id);
+CREATE INDEX IF NOT EXISTS idx_links_target_id ON links(target_id);
+CREATE INDEX IF NOT EXISTS idx_links_target_title ON links(user_id, lower(target_title));
+CREATE INDEX IF NOT EXISTS idx_notes_user_title ON notes(user_id, lower(title));
+
+-- Backfill links for notes written before link tracking existed.
+INSERT INTO links (id, user_id, source_note_id, target_type, target_id, target_title)
+SELECT
+  uuid_generate_v4(),
+  user_id,
+  note_id,
+  CASE WHEN target ~* '^task:' THEN 'task' ELSE 'note' END,
+  CASE WHEN target ~* '^(task|note):[0-9a-f-]{36}$' THEN substring(target FROM 6)::uuid END,
+  CASE WHEN target ~* '^(task|note):[0-9a-f-]{36}$' THEN NULL ELSE target END
+FROM (
+  SELECT DISTINCT n.user_id, n.id AS note_id, btrim(m[1]) AS target
+  FROM notes n, regexp_matches(n.content, '\[\[([^\]|#]+)[^\]]*\]\]', 'g') AS m
+) found
+WHERE target <> ''
+  AND (target !~* '^task:' OR target ~* '^task:[0-9a-f-]{36}$');
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS note_revisions (
+  id UUID PRIMARY KEY,
+  note_id UUID NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  revision INT NOT NULL,
+  title TEXT NOT NULL,
+  content TEXT NOT NULL,
+  tags TEXT[] NOT NULL DEFAULT '{}',
+  restored_from UUID REFERENCES note_revisions(id) ON DELETE SET NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (note_id, revision)
+);
+
+CREATE INDEX IF NOT EXISTS idx_note_revisions_note_id ON note_revisions(note_id);
+
+-- Every existing note starts with its current state as revision 1.
+INSERT INTO note_revisions (id, note_id, user_id, revision, title, content, tags, created_at, updated_at)
+SELECT uuid_generate_v4(), id, user_id, 1, title, content, tags, updated_at, updated_at
+FROM notes
+WHERE NOT EXISTS (SELECT 1 FROM note_revisions r WHERE r.note_id = notes.id);
@@ -0,0 +1,19 @@
+-- Embedding chunks of notes and tasks for retrieval. Vectors are plain
+-- float arrays scored in-process, so no database extension is required.
+CREATE TABLE IF NOT EXISTS embeddings (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  source_type TEXT NOT NULL CHECK (source_type IN ('note', 'task')),
+  source_id UUID NOT NULL,
+  chunk_index INT NOT NULL,
+  content TEXT NOT NULL,
+  model TEXT NOT NULL,
+  embedding REAL[] NOT NULL,
+  -- updated_at of the note or task when it was embedded; older means stale.
+  source_updated_at TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  UNIQUE (source_type, source_id, chunk_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_embeddings_user_id ON embeddings(user_id, model);
+CREATE INDEX IF NOT EXISTS idx_embeddings_source ON embeddings(source_type, source_id);
@@ -0,0 +1,6 @@
+-- Subtasks accepted from a task breakdown point at their parent task.
+ALTER TABLE tasks
+  ADD COLUMN IF NOT EXISTS parent_id UUID REFERENCES tasks(id) ON DELETE CASCADE,
+  ADD COLUMN IF NOT EXISTS estimate_minutes INT CHECK (estimate_minutes > 0);
+
+CREATE INDEX IF NOT EXISTS idx_tasks_parent_id ON tasks(parent_id) WHERE parent_id IS NOT NULL;
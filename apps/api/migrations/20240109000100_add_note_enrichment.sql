@@ -0,0 +1,10 @@
+-- Model-generated summary, tag suggestions and action items of a note. Kept
+-- apart from the user's own tags; suggestions are only copied into `tags`
+-- when the user accepts them.
+ALTER TABLE notes
+  ADD COLUMN IF NOT EXISTS ai_summary TEXT,
+  ADD COLUMN IF NOT EXISTS ai_tags TEXT[] NOT NULL DEFAULT '{}',
+  ADD COLUMN IF NOT EXISTS ai_action_items JSONB NOT NULL DEFAULT '[]',
+  -- Set to the note's updated_at when the results were made from it, so an
+  -- older value means the note changed since.
+  ADD COLUMN IF NOT EXISTS ai_enriched_at TIMESTAMPTZ;
@@ -0,0 +1,31 @@
+-- When to send a user's daily briefing, on their local clock.
+CREATE TABLE IF NOT EXISTS briefing_schedules (
+  user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  local_time TIME NOT NULL,
+  -- Lowercase three-letter day names, e.g. {mon,tue,wed,thu,fri}.
+  weekdays TEXT[] NOT NULL,
+  -- IANA zone; the server's DEFAULT_TIMEZONE when null.
+  timezone TEXT,
+  email TEXT,
+  webhook_url TEXT,
+  enabled BOOLEAN NOT NULL DEFAULT TRUE,
+  -- Local date of the last scheduled run, so a day is never sent twice.
+  last_run_on DATE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS briefings (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  briefing_date DATE NOT NULL,
+  content TEXT NOT NULL,
+  -- 'template' when the model could not be reached.
+  source TEXT NOT NULL CHECK (source IN ('model', 'template')),
+  model TEXT,
+  -- Outcome per channel: [{"channel", "target", "ok", "error"}].
+  deliveries JSONB NOT NULL DEFAULT '[]',
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_briefings_user_id ON briefings(user_id, created_at DESC);
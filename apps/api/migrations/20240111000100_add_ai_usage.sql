@@ -0,0 +1,20 @@
+-- One row per model call, for per-user quotas and the usage endpoint.
+CREATE TABLE IF NOT EXISTS ai_usage (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  model TEXT NOT NULL,
+  -- chat, chat_json, chat_stream, chat_with_tools or embed.
+  operation TEXT NOT NULL,
+  -- What asked for it, e.g. 'chat', 'breakdown', 'enrichment'.
+  feature TEXT NOT NULL,
+  prompt_tokens INT NOT NULL DEFAULT 0,
+  completion_tokens INT NOT NULL DEFAULT 0,
+  -- Counted by us rather than reported by the model server (streams, embeddings).
+  tokens_estimated BOOLEAN NOT NULL DEFAULT FALSE,
+  latency_ms INT NOT NULL,
+  -- 'ok', 'cancelled' or the LlmError code.
+  outcome TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_ai_usage_user_id ON ai_usage(user_id, created_at);
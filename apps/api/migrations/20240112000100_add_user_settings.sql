@@ -0,0 +1,9 @@
+-- Per-user preferences; a missing row or null column means the server default.
+CREATE TABLE IF NOT EXISTS user_settings (
+  user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  -- Language of prompts and replies, a directory under PROMPTS_DIR.
+  locale TEXT,
+  -- How the assistant addresses the user; the email's local part when null.
+  display_name TEXT,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
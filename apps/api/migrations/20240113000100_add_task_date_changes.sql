@@ -0,0 +1,11 @@
+-- One row per change of a task's end date, so reports can tell which tasks slipped.
+CREATE TABLE IF NOT EXISTS task_date_changes (
+  id UUID PRIMARY KEY,
+  task_id UUID NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  previous_end_date DATE,
+  end_date DATE,
+  changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_task_date_changes_user_id ON task_date_changes(user_id, changed_at);
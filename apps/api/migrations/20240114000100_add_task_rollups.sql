@@ -0,0 +1,32 @@
+-- Per-user daily task counts behind the analytics endpoint, rebuilt from
+-- tasks whenever the user's tasks changed since the last build. Each task
+-- is counted once under its priority and once under each of its tags.
+CREATE TABLE IF NOT EXISTS task_daily_rollups (
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  day DATE NOT NULL,
+  -- 'priority' or 'tag'.
+  dimension TEXT NOT NULL,
+  value TEXT NOT NULL,
+  created INT NOT NULL DEFAULT 0,
+  completed INT NOT NULL DEFAULT 0,
+  -- Completed tasks that had an end date, and those done by it.
+  completed_with_end_date INT NOT NULL DEFAULT 0,
+  completed_on_time INT NOT NULL DEFAULT 0,
+  -- Hours from creation to completion of each task completed that day.
+  cycle_hours DOUBLE PRECISION[] NOT NULL DEFAULT '{}',
+  PRIMARY KEY (user_id, dimension, value, day)
+);
+
+CREATE INDEX IF NOT EXISTS idx_task_daily_rollups_day ON task_daily_rollups(user_id, day);
+
+-- What the user's rollup was built from, to tell when it is stale.
+CREATE TABLE IF NOT EXISTS task_rollup_state (
+  user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  timezone TEXT NOT NULL,
+  task_count BIGINT NOT NULL,
+  last_change TIMESTAMPTZ,
+  built_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Keeps the staleness check (count and latest change) to an index scan.
+CREATE INDEX IF NOT EXISTS idx_tasks_user_updated_at ON tasks(user_id, updated_at);
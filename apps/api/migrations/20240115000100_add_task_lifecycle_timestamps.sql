@@ -0,0 +1,11 @@
+-- When a task was first moved out of 'todo' and when it was last moved to
+-- 'done'; kept by the application on status changes. Both are cleared when
+-- a task goes back to 'todo', and completed_at when it is reopened.
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS started_at TIMESTAMPTZ;
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS completed_at TIMESTAMPTZ;
+
+-- Existing rows have no history, so their last change stands in for both.
+UPDATE tasks SET completed_at = updated_at WHERE status = 'done' AND completed_at IS NULL;
+UPDATE tasks SET started_at = updated_at WHERE status IN ('in_progress', 'done') AND started_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_tasks_user_completed_at ON tasks(user_id, completed_at) WHERE completed_at IS NOT NULL;
@@ -0,0 +1,8 @@
+-- More per-user preferences; null still means the server default.
+-- IANA zone name such as 'Asia/Seoul'; DEFAULT_TIMEZONE when null.
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS timezone TEXT;
+-- 'monday' .. 'sunday'; monday when null.
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS week_start TEXT;
+-- Used for new tasks that do not name one; 'medium' and 'todo' when null.
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS default_priority TEXT;
+ALTER TABLE user_settings ADD COLUMN IF NOT EXISTS default_status TEXT;
@@ -0,0 +1,7 @@
+-- Widgets of each user's dashboard, in order, as `dashboard::Widget` JSON.
+-- Users without a row get the default layout.
+CREATE TABLE IF NOT EXISTS dashboard_layouts (
+  user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  widgets JSONB NOT NULL DEFAULT '[]',
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,9 @@
+-- `[[task:...]]` links without a valid id were not stored before; keep them
+-- so they are listed as missing.
+INSERT INTO links (id, user_id, source_note_id, target_type, target_id, target_title)
+SELECT uuid_generate_v4(), user_id, note_id, 'task', NULL, target
+FROM (
+  SELECT DISTINCT n.user_id, n.id AS note_id, btrim(m[1]) AS target
+  FROM notes n, regexp_matches(n.content, '\[\[([^\]|#]+)[^\]]*\]\]', 'g') AS m
+) found
+WHERE target ~* '^task:' AND target !~* '^task:\s*[0-9a-f-]{36}\s*$';
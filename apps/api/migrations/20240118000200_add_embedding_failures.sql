@@ -0,0 +1,17 @@
+-- Notes and tasks whose last embedding attempt failed. They are retried
+-- after a backoff that doubles with each attempt, so sources that keep
+-- failing do not hold up the rest of the queue. Cleared once one succeeds.
+CREATE TABLE IF NOT EXISTS embedding_failures (
+  source_type TEXT NOT NULL CHECK (source_type IN ('note', 'task')),
+  source_id UUID NOT NULL,
+  model TEXT NOT NULL,
+  -- updated_at of the source when it failed; a newer edit resets attempts.
+  source_updated_at TIMESTAMPTZ NOT NULL,
+  attempts INT NOT NULL DEFAULT 1,
+  failed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (source_type, source_id)
+);
+
+-- Task chunks used to carry Korean labels; they are embedded again with
+-- the neutral ones.
+DELETE FROM embeddings WHERE source_type = 'task';
@@ -0,0 +1,4 @@
+-- Calls made on one user request share an id, so the request quota counts
+-- the request once however many model calls it took. Background jobs and
+-- embeddings leave it NULL and do not count.
+ALTER TABLE ai_usage ADD COLUMN IF NOT EXISTS request_id UUID;
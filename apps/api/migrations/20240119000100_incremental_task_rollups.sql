@@ -0,0 +1,7 @@
+-- Task writes now recount the days they touch, so the rollup is no longer
+-- compared with the tasks to tell when it is stale. The state row keeps
+-- the zone the days are counted on.
+ALTER TABLE task_rollup_state DROP COLUMN IF EXISTS task_count;
+ALTER TABLE task_rollup_state DROP COLUMN IF EXISTS last_change;
+-- It was there for that comparison.
+DROP INDEX IF EXISTS idx_tasks_user_updated_at;
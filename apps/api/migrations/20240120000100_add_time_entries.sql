@@ -0,0 +1,32 @@
+-- Spans of time the user ran the timer for, optionally against a task. The
+-- running one has no stopped_at; a user has at most one.
+CREATE TABLE IF NOT EXISTS time_entries (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  -- Kept when the task is deleted, so the time still counts.
+  task_id UUID REFERENCES tasks(id) ON DELETE SET NULL,
+  started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  stopped_at TIMESTAMPTZ,
+  CHECK (stopped_at IS NULL OR stopped_at >= started_at)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_time_entries_running ON time_entries(user_id) WHERE stopped_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_time_entries_user_started_at ON time_entries(user_id, started_at);
+
+-- Timers running in a dashboard layout become running entries, and the
+-- timer widget no longer carries state.
+INSERT INTO time_entries (id, user_id, task_id, started_at)
+SELECT DISTINCT ON (l.user_id) uuid_generate_v4(), l.user_id, t.id, (w->>'started_at')::timestamptz
+FROM dashboard_layouts l
+CROSS JOIN LATERAL jsonb_array_elements(l.widgets) AS w
+LEFT JOIN tasks t ON t.id::text = w->>'task_id' AND t.user_id = l.user_id
+WHERE w->>'type' = 'timer' AND w->>'started_at' IS NOT NULL
+ORDER BY l.user_id, (w->>'started_at')::timestamptz
+ON CONFLICT DO NOTHING;
+
+UPDATE dashboard_layouts
+SET widgets = (
+  SELECT jsonb_agg(CASE WHEN w->>'type' = 'timer' THEN '{"type": "timer"}'::jsonb ELSE w END ORDER BY i)
+  FROM jsonb_array_elements(widgets) WITH ORDINALITY AS e(w, i)
+)
+WHERE widgets @> '[{"type": "timer"}]';
@@ -0,0 +1,481 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Serialize;
+use sqlx::{Acquire, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::settings;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Day,
+    Week,
+}
+
+impl Bucket {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "day" => Some(Bucket::Day),
+            "week" => Some(Bucket::Week),
+            _ => None,
+        }
+    }
+
+    /// First day of the bucket `day` falls in.
+    fn start(self, day: NaiveDate, week_start: Weekday) -> NaiveDate {
+        match self {
+            Bucket::Day => day,
+            Bucket::Week => settings::week_of(day, week_start),
+        }
+    }
+
+    fn next(self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Bucket::Day => start + TimeDelta::days(1),
+            Bucket::Week => start + TimeDelta::days(7),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Analytics {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub bucket: Bucket,
+    pub timezone: String,
+    /// Every bucket of the range, empty ones included.
+    pub series: Vec<Point>,
+    /// `None` when nothing was completed in the range.
+    pub cycle_time_hours: Option<CycleTime>,
+    pub on_time: OnTime,
+    pub streaks: Streaks,
+    pub by_priority: Vec<Breakdown>,
+    /// Tasks with several tags count under each; most completed first.
+    pub by_tag: Vec<Breakdown>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Point {
+    pub start: NaiveDate,
+    pub created: i64,
+    pub completed: i64,
+}
+
+#[derive(Serialize)]
+pub struct CycleTime {
+    pub count: i64,
+    pub p50: f64,
+    pub p75: f64,
+    pub p90: f64,
+}
+
+/// Completed tasks that had an end date, and how many were done by it.
+#[derive(Serialize)]
+pub struct OnTime {
+    pub completed_with_end_date: i64,
+    pub on_time: i64,
+    pub rate: Option<f64>,
+}
+
+impl OnTime {
+    fn new(completed_with_end_date: i64, on_time: i64) -> Self {
+        Self {
+            completed_with_end_date,
+            on_time,
+            rate: (completed_with_end_date > 0).then(|| on_time as f64 / completed_with_end_date as f64),
+        }
+    }
+}
+
+/// Runs of consecutive days with at least one completion, within the range.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Streaks {
+    /// Ending on the last day of the range, or the day before while that
+    /// day has nothing done yet.
+    pub current: i64,
+    pub longest: i64,
+    pub longest_start: Option<NaiveDate>,
+    pub longest_end: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+pub struct Breakdown {
+    pub value: String,
+    pub created: i64,
+    pub completed: i64,
+    pub on_time: OnTime,
+}
+
+struct Day {
+    day: NaiveDate,
+    created: i64,
+    completed: i64,
+    completed_with_end_date: i64,
+    completed_on_time: i64,
+}
+
+struct Group {
+    dimension: String,
+    value: String,
+    created: i64,
+    completed: i64,
+    completed_with_end_date: i64,
+    completed_on_time: i64,
+}
+
+/// Analytics of the user's tasks from `from` to `to`, inclusive, on the
+/// `tz` clock, with weeks starting on `week_start`. Reads the rollup that
+/// the task writes keep; see `touch`.
+pub async fn compute(
+    pool: &PgPool,
+    user_id: Uuid,
+    tz: Tz,
+    week_start: Weekday,
+    from: NaiveDate,
+    to: NaiveDate,
+    bucket: Bucket,
+) -> Result<Analytics, sqlx::Error> {
+    let days = sqlx::query_as!(
+        Day,
+        r#"
+        SELECT day,
+               SUM(created)::BIGINT AS "created!",
+               SUM(completed)::BIGINT AS "completed!",
+               SUM(completed_with_end_date)::BIGINT AS "completed_with_end_date!",
+               SUM(completed_on_time)::BIGINT AS "completed_on_time!"
+        FROM task_daily_rollups
+        WHERE user_id = $1 AND dimension = 'priority' AND day BETWEEN $2 AND $3
+        GROUP BY day
+        ORDER BY day
+        "#,
+        user_id,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let cycle = sqlx::query!(
+        r#"
+        SELECT COUNT(h) AS "count!",
+               percentile_cont(0.5) WITHIN GROUP (ORDER BY h) AS p50,
+               percentile_cont(0.75) WITHIN GROUP (ORDER BY h) AS p75,
+               percentile_cont(0.9) WITHIN GROUP (ORDER BY h) AS p90
+        FROM task_daily_rollups r, unnest(r.cycle_hours) AS h
+        WHERE r.user_id = $1 AND r.dimension = 'priority' AND r.day BETWEEN $2 AND $3
+        "#,
+        user_id,
+        from,
+        to
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let groups = sqlx::query_as!(
+        Group,
+        r#"
+        SELECT dimension, value,
+               SUM(created)::BIGINT AS "created!",
+               SUM(completed)::BIGINT AS "completed!",
+               SUM(completed_with_end_date)::BIGINT AS "completed_with_end_date!",
+               SUM(completed_on_time)::BIGINT AS "completed_on_time!"
+        FROM task_daily_rollups
+        WHERE user_id = $1 AND day BETWEEN $2 AND $3
+        GROUP BY dimension, value
+        ORDER BY 4 DESC, 3 DESC, value
+        "#,
+        user_id,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let (with_end_date, on_time) = days
+        .iter()
+        .fold((0, 0), |(w, o), d| (w + d.completed_with_end_date, o + d.completed_on_time));
+    let completed_days: Vec<NaiveDate> = days.iter().filter(|d| d.completed > 0).map(|d| d.day).collect();
+    let totals: Vec<(NaiveDate, i64, i64)> = days.iter().map(|d| (d.day, d.created, d.completed)).collect();
+
+    let (mut by_priority, mut by_tag) = (Vec::new(), Vec::new());
+    for group in groups {
+        let breakdown = Breakdown {
+            value: group.value,
+            created: group.created,
+            completed: group.completed,
+            on_time: OnTime::new(group.completed_with_end_date, group.completed_on_time),
+        };
+        match group.dimension.as_str() {
+            "priority" => by_priority.push(breakdown),
+            _ => by_tag.push(breakdown),
+        }
+    }
+
+    Ok(Analytics {
+        from,
+        to,
+        bucket,
+        timezone: tz.name().to_string(),
+        series: series(&totals, from, to, bucket, week_start),
+        cycle_time_hours: match (cycle.p50, cycle.p75, cycle.p90) {
+            (Some(p50), Some(p75), Some(p90)) => Some(CycleTime {
+                count: cycle.count,
+                p50,
+                p75,
+                p90,
+            }),
+            _ => None,
+        },
+        on_time: OnTime::new(with_end_date, on_time),
+        streaks: streaks(&completed_days, to),
+        by_priority,
+        by_tag,
+    })
+}
+
+/// Recounts the rollup days `instants` fall on, after tasks were written
+/// on `conn` (in the caller's transaction, if any). Days are on the zone
+/// the rollup was built on; until it is built there is nothing to update.
+pub async fn touch(conn: &mut PgConnection, user_id: Uuid, instants: &[DateTime<Utc>]) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    // Locking the state row keeps concurrent recounts of a user apart.
+    let timezone = sqlx::query_scalar!(
+        "SELECT timezone FROM task_rollup_state WHERE user_id = $1 FOR UPDATE",
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(timezone) = timezone else {
+        return Ok(());
+    };
+    let tz = settings::zone(Some(&timezone), chrono_tz::UTC);
+    let mut days: Vec<NaiveDate> = instants.iter().map(|at| at.with_timezone(&tz).date_naive()).collect();
+    days.sort();
+    days.dedup();
+    if !days.is_empty() {
+        recount(&mut tx, user_id, &timezone, Some(&days)).await?;
+    }
+    tx.commit().await
+}
+
+/// Counts the user's whole rollup afresh on `tz`: at signup, when the
+/// user's zone changes, and for rollups missing at startup.
+pub async fn rebuild(conn: &mut PgConnection, user_id: Uuid, tz: Tz) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    // Written first so a concurrent rebuild waits on the row instead of
+    // colliding on the rollup keys.
+    sqlx::query!(
+        r#"
+        INSERT INTO task_rollup_state (user_id, timezone, built_at)
+        VALUES ($1, $2, NOW())
+        ON CONFLICT (user_id) DO UPDATE
+        SET timezone = $2, built_at = NOW()
+        "#,
+        user_id,
+        tz.name()
+    )
+    .execute(&mut *tx)
+    .await?;
+    recount(&mut tx, user_id, tz.name(), None).await?;
+    tx.commit().await
+}
+
+/// Rebuilds, in the background, the rollups of users that have none or
+/// one on another zone than theirs, e.g. after `DEFAULT_TIMEZONE` changed.
+pub fn spawn_backfill(pool: PgPool, default_tz: Tz) {
+    tokio::spawn(async move {
+        if let Err(err) = backfill(&pool, default_tz).await {
+            tracing::warn!("task rollup backfill failed: {err}");
+        }
+    });
+}
+
+async fn backfill(pool: &PgPool, default_tz: Tz) -> Result<(), sqlx::Error> {
+    let users = sqlx::query!(
+        r#"
+        SELECT u.id AS "id!", s.timezone AS "preferred?", r.timezone AS "built?"
+        FROM users u
+        LEFT JOIN user_settings s ON s.user_id = u.id
+        LEFT JOIN task_rollup_state r ON r.user_id = u.id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    for user in users {
+        let tz = settings::zone(user.preferred.as_deref(), default_tz);
+        if user.built.as_deref() != Some(tz.name()) {
+            rebuild(&mut *pool.acquire().await?, user.id, tz).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the rollup rows of `days`, or of every day when `None`.
+async fn recount(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    timezone: &str,
+    days: Option<&[NaiveDate]>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM task_daily_rollups WHERE user_id = $1 AND ($2::date[] IS NULL OR day = ANY($2))",
+        user_id,
+        days as Option<&[NaiveDate]>
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        r#"
+        WITH t AS (
+            SELECT priority, tags,
+                   (created_at AT TIME ZONE $2)::date AS created_day,
+                   (completed_at AT TIME ZONE $2)::date AS done_day,
+                   EXTRACT(EPOCH FROM completed_at - created_at)::float8 / 3600 AS cycle,
+                   COALESCE(end_date, due_date) AS end_date
+            FROM tasks
+            WHERE user_id = $1
+              AND ($3::date[] IS NULL
+                   OR (created_at AT TIME ZONE $2)::date = ANY($3)
+                   OR (completed_at AT TIME ZONE $2)::date = ANY($3))
+        ),
+        dims AS (
+            SELECT 'priority' AS dimension, priority AS value, created_day, done_day, cycle, end_date FROM t
+            UNION ALL
+            SELECT 'tag', tag, created_day, done_day, cycle, end_date FROM t, unnest(t.tags) AS tag
+        ),
+        events AS (
+            SELECT dimension, value, created_day AS day, 1 AS created, 0 AS completed,
+                   0 AS with_end_date, 0 AS on_time, NULL::float8 AS cycle
+            FROM dims
+            UNION ALL
+            SELECT dimension, value, done_day, 0, 1,
+                   (end_date IS NOT NULL)::int, COALESCE((done_day <= end_date)::int, 0), cycle
+            FROM dims
+            WHERE done_day IS NOT NULL
+        )
+        INSERT INTO task_daily_rollups
+            (user_id, day, dimension, value, created, completed, completed_with_end_date, completed_on_time, cycle_hours)
+        SELECT $1, day, dimension, value, SUM(created), SUM(completed), SUM(with_end_date), SUM(on_time),
+               COALESCE(array_agg(GREATEST(cycle, 0)) FILTER (WHERE cycle IS NOT NULL), '{}')
+        FROM events
+        -- A task of a touched day may have its other event on another day.
+        WHERE $3::date[] IS NULL OR day = ANY($3)
+        GROUP BY day, dimension, value
+        "#,
+        user_id,
+        timezone,
+        days as Option<&[NaiveDate]>
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// `days` (sorted `(day, created, completed)`) summed into buckets from
+/// `from` to `to`. A partial first or last week covers only its days in the
+/// range, but is labelled with its first day.
+fn series(
+    days: &[(NaiveDate, i64, i64)],
+    from: NaiveDate,
+    to: NaiveDate,
+    bucket: Bucket,
+    week_start: Weekday,
+) -> Vec<Point> {
+    let mut points: BTreeMap<NaiveDate, Point> = BTreeMap::new();
+    let mut start = bucket.start(from, week_start);
+    while start <= to {
+        points.insert(
+            start,
+            Point {
+                start,
+                created: 0,
+                completed: 0,
+            },
+        );
+        start = bucket.next(start);
+    }
+    for &(day, created, completed) in days {
+        if let Some(point) = points.get_mut(&bucket.start(day, week_start)) {
+            point.created += created;
+            point.completed += completed;
+        }
+    }
+    points.into_values().collect()
+}
+
+/// Streaks over `days` (sorted days with a completion) as of `to`.
+fn streaks(days: &[NaiveDate], to: NaiveDate) -> Streaks {
+    let mut longest = (0, None, None);
+    let mut run: Option<(NaiveDate, NaiveDate, i64)> = None;
+    for &day in days {
+        run = match run {
+            Some((start, end, n)) if end.succ_opt() == Some(day) => Some((start, day, n + 1)),
+            _ => Some((day, day, 1)),
+        };
+        if let Some((start, end, n)) = run {
+            if n > longest.0 {
+                longest = (n, Some(start), Some(end));
+            }
+        }
+    }
+    let current = match run {
+        Some((_, end, n)) if end == to || end.succ_opt() == Some(to) => n,
+        _ => 0,
+    };
+    Streaks {
+        current,
+        longest: longest.0,
+        longest_start: longest.1,
+        longest_end: longest.2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, m, d).unwrap()
+    }
+
+    #[test]
+    fn fills_and_sums_buckets() {
+        let days = [(date(11, 26), 2, 1), (date(11, 28), 1, 0), (date(12, 2), 0, 3)];
+        let daily = series(&days, date(11, 27), date(12, 2), Bucket::Day, Weekday::Mon);
+        assert_eq!(daily.len(), 6);
+        assert_eq!(daily[0], Point { start: date(11, 27), created: 0, completed: 0 });
+        assert_eq!(daily[1], Point { start: date(11, 28), created: 1, completed: 0 });
+        assert_eq!(daily[5], Point { start: date(12, 2), created: 0, completed: 3 });
+
+        // Wednesday 11-27 falls in the week of Monday 11-25.
+        let weekly = series(&days, date(11, 27), date(12, 2), Bucket::Week, Weekday::Mon);
+        assert_eq!(
+            weekly,
+            [
+                Point { start: date(11, 25), created: 3, completed: 1 },
+                Point { start: date(12, 2), created: 0, completed: 3 },
+            ]
+        );
+
+        // Weeks starting on Tuesday put Monday 12-02 with the days before it.
+        let weekly = series(&days, date(11, 27), date(12, 2), Bucket::Week, Weekday::Tue);
+        assert_eq!(weekly, [Point { start: date(11, 26), created: 3, completed: 4 }]);
+    }
+
+    #[test]
+    fn counts_streaks() {
+        let days = [date(11, 1), date(11, 2), date(11, 3), date(11, 5), date(11, 6)];
+        let found = streaks(&days, date(11, 7));
+        assert_eq!(found.longest, 3);
+        assert_eq!(found.longest_start, Some(date(11, 1)));
+        assert_eq!(found.longest_end, Some(date(11, 3)));
+        // Nothing done yet on the last day keeps yesterday's run alive.
+        assert_eq!(found.current, 2);
+        assert_eq!(streaks(&days, date(11, 6)).current, 2);
+        assert_eq!(streaks(&days, date(11, 8)).current, 0);
+        assert_eq!(streaks(&[], date(11, 8)), Streaks { current: 0, longest: 0, longest_start: None, longest_end: None });
+    }
+}
@@ -0,0 +1,306 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+
+use crate::embeddings::RetrievedChunk;
+use crate::llm::estimate_tokens;
+use crate::models::{Note, Task};
+use crate::prompts::{Audience, Prompt, Prompts};
+
+/// Characters of a note body shown after its title.
+const NOTE_PREVIEW_CHARS: usize = 120;
+/// Kept free for the line that lists omitted items.
+const OMISSION_NOTE_TOKENS: usize = 32;
+
+/// Parts of the prompt context, highest priority first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Section {
+    Related,
+    Overdue,
+    DueToday,
+    InProgress,
+    Upcoming,
+    Notes,
+}
+
+impl Section {
+    const ALL: [Section; 6] = [
+        Section::Related,
+        Section::Overdue,
+        Section::DueToday,
+        Section::InProgress,
+        Section::Upcoming,
+        Section::Notes,
+    ];
+}
+
+/// Items of a section left out to stay within the budget.
+#[derive(Serialize, Clone, Debug)]
+pub struct Omitted {
+    pub section: Section,
+    pub count: usize,
+}
+
+pub struct PromptContext {
+    pub text: String,
+    pub omitted: Vec<Omitted>,
+}
+
+/// Builds the prompt context within `budget` estimated tokens. Sections are
+/// filled in priority order; if that overflows, whole items are trimmed from
+/// the bottom, lowest priority section first, to make room for the line that
+/// lists them. Headers and that line are in the audience's locale.
+pub fn assemble(
+    prompts: &Prompts,
+    audience: &Audience,
+    tasks: &[Task],
+    notes: &[Note],
+    related: &[RetrievedChunk],
+    today: NaiveDate,
+    budget: usize,
+) -> PromptContext {
+    let label = |section: &Section, count: Option<usize>| {
+        prompts.render(audience, Prompt::ContextSection, minijinja::context! { section, count })
+    };
+    let mut sections: Vec<Vec<String>> = vec![Vec::new(); Section::ALL.len()];
+    for chunk in related {
+        sections[Section::Related as usize].push(format!(
+            "- ({}) {}",
+            chunk.title,
+            chunk.content.replace('\n', " ")
+        ));
+    }
+    for task in tasks {
+        if let Some(section) = classify(task, today) {
+            sections[section as usize].push(task_line(prompts, audience, task));
+        }
+    }
+    for note in notes {
+        sections[Section::Notes as usize].push(note_line(note));
+    }
+    if sections.iter().all(Vec::is_empty) {
+        return PromptContext {
+            text: prompts.render(audience, Prompt::ContextEmpty, minijinja::Value::UNDEFINED),
+            omitted: Vec::new(),
+        };
+    }
+
+    // The header is charged to a section's first item.
+    let costs: Vec<Vec<usize>> = Section::ALL
+        .iter()
+        .zip(&sections)
+        .map(|(section, items)| {
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let header = if i == 0 { estimate_tokens(&label(section, None)) + 2 } else { 0 };
+                    header + estimate_tokens(item) + 1
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut taken: Vec<usize> = sections.iter().map(Vec::len).collect();
+    let mut used: usize = costs.iter().flatten().sum();
+    if used > budget {
+        let limit = budget.saturating_sub(OMISSION_NOTE_TOKENS);
+        for (taken, costs) in taken.iter_mut().zip(&costs).rev() {
+            while used > limit && *taken > 0 {
+                *taken -= 1;
+                used -= costs[*taken];
+            }
+        }
+    }
+
+    let mut parts = Vec::new();
+    let mut omitted = Vec::new();
+    for ((section, items), taken) in Section::ALL.iter().zip(&sections).zip(taken) {
+        if taken > 0 {
+            parts.push(format!("[{}]\n{}", label(section, None), items[..taken].join("\n")));
+        }
+        if taken < items.len() {
+            omitted.push(Omitted {
+                section: *section,
+                count: items.len() - taken,
+            });
+        }
+    }
+    if !omitted.is_empty() {
+        let items: Vec<String> = omitted.iter().map(|o| label(&o.section, Some(o.count))).collect();
+        parts.push(prompts.render(audience, Prompt::ContextOmitted, minijinja::context! { items }));
+    }
+    PromptContext {
+        text: parts.join("\n"),
+        omitted,
+    }
+}
+
+/// Section of an open task, by the same date rules as the dashboard.
+fn classify(task: &Task, today: NaiveDate) -> Option<Section> {
+    if task.status == "done" {
+        return None;
+    }
+    let start = task.start_date.or(task.due_date);
+    let end = task.end_date.or(task.due_date);
+    Some(match (start, end) {
+        (_, Some(end)) if end < today => Section::Overdue,
+        (Some(start), Some(end)) if start <= today && today <= end => Section::DueToday,
+        _ if task.status == "in_progress" => Section::InProgress,
+        _ => Section::Upcoming,
+    })
+}
+
+fn task_line(prompts: &Prompts, audience: &Audience, task: &Task) -> String {
+    let due = prompts.render(
+        audience,
+        Prompt::ContextDue,
+        minijinja::context! {
+            start_date => task.start_date.map(|d| d.to_string()),
+            end_date => task.end_date.or(task.due_date).map(|d| d.to_string()),
+        },
+    );
+    let tags = if task.tags.is_empty() {
+        "".to_string()
+    } else {
+        format!(" #{}", task.tags.join(" #"))
+    };
+    format!("- [{}][{}][{}] {}{}", task.status, task.priority, due, task.title, tags)
+}
+
+fn note_line(note: &Note) -> String {
+    let tags = if note.tags.is_empty() {
+        "".to_string()
+    } else {
+        format!(" #{}", note.tags.join(" #"))
+    };
+    let body = note.content.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut preview: String = body.chars().take(NOTE_PREVIEW_CHARS).collect();
+    if body.chars().count() > NOTE_PREVIEW_CHARS {
+        preview.push('…');
+    }
+    if preview.is_empty() {
+        format!("- {}{}", note.title, tags)
+    } else {
+        format!("- {}{}: {}", note.title, tags, preview)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::prompts::tests::{audience, shipped};
+
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, 10).unwrap()
+    }
+
+    fn task(title: &str, status: &str, due: Option<NaiveDate>) -> Task {
+        Task {
+            id: Uuid::new_v4(),
+            user_id: Uuid::nil(),
+            title: title.to_string(),
+            description: None,
+            status: status.to_string(),
+            priority: "medium".to_string(),
+            due_date: due,
+            start_date: None,
+            end_date: None,
+            tags: Vec::new(),
+            parent_id: None,
+            estimate_minutes: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            started_at: None,
+            completed_at: None,
+        }
+    }
+
+    fn note(title: &str, content: &str) -> Note {
+        Note {
+            id: Uuid::new_v4(),
+            user_id: Uuid::nil(),
+            title: title.to_string(),
+            content: content.to_string(),
+            tags: Vec::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            ai_summary: None,
+            ai_tags: Vec::new(),
+            ai_action_items: serde_json::json!([]),
+            ai_enriched_at: None,
+        }
+    }
+
+    fn day(offset: i64) -> Option<NaiveDate> {
+        today().checked_add_signed(chrono::TimeDelta::days(offset))
+    }
+
+    #[test]
+    fn groups_open_items_by_section() {
+        let tasks = [
+            task("보고서", "todo", day(-1)),
+            task("회의", "todo", day(0)),
+            task("리팩터링", "in_progress", None),
+            task("발표", "todo", day(3)),
+            task("끝난 일", "done", day(-2)),
+        ];
+        let prompts = shipped();
+        let context = assemble(&prompts, &audience("ko"), &tasks, &[note("메모", "줄1\n\n줄2")], &[], today(), 4096);
+        assert!(context.omitted.is_empty());
+        assert_eq!(
+            context.text,
+            "[지난 마감]\n- [todo][medium][마감 2024-03-09] 보고서\n\
+[오늘 마감]\n- [todo][medium][마감 2024-03-10] 회의\n\
+[진행 중]\n- [in_progress][medium][마감 없음] 리팩터링\n\
+[예정]\n- [todo][medium][마감 2024-03-13] 발표\n\
+[노트]\n- 메모: 줄1 줄2"
+        );
+
+        let context = assemble(&prompts, &audience("ko"), &[task("끝난 일", "done", None)], &[], &[], today(), 4096);
+        assert_eq!(context.text, "등록된 업무나 노트가 없습니다.");
+
+        let context = assemble(&prompts, &audience("en"), &tasks[..3], &[], &[], today(), 4096);
+        assert_eq!(
+            context.text,
+            "[Overdue]\n- [todo][medium][due 2024-03-09] 보고서\n\
+[Due today]\n- [todo][medium][due 2024-03-10] 회의\n\
+[In progress]\n- [in_progress][medium][no due date] 리팩터링"
+        );
+    }
+
+    #[test]
+    fn trims_lowest_priority_items_first() {
+        let mut tasks: Vec<Task> = (0..3).map(|i| task(&format!("지난 일 {i}"), "todo", day(-1))).collect();
+        tasks.extend((0..20).map(|i| task(&format!("예정된 일 {i}"), "todo", day(5))));
+        let notes: Vec<Note> = (0..5).map(|i| note(&format!("노트 {i}"), &"내용".repeat(40))).collect();
+
+        let budget = 200;
+        let context = assemble(&shipped(), &audience("ko"), &tasks, &notes, &[], today(), budget);
+        assert!(estimate_tokens(&context.text) <= budget, "{}", context.text);
+        assert!((0..3).all(|i| context.text.contains(&format!("지난 일 {i}"))));
+        // The upcoming section keeps its first items and loses its tail.
+        assert!(context.text.contains("예정된 일 0"));
+        assert!(!context.text.contains("예정된 일 19"));
+        assert!(!context.text.contains("[노트]"));
+
+        let omitted: Vec<_> = context.omitted.iter().map(|o| (o.section, o.count)).collect();
+        let kept = context.text.matches("예정된 일 ").count();
+        assert_eq!(omitted, [(Section::Upcoming, 20 - kept), (Section::Notes, 5)]);
+        assert!(context.text.ends_with(&format!("(분량 제한으로 생략: 예정 {}건, 노트 5건)", 20 - kept)));
+    }
+
+    #[test]
+    fn drops_whole_sections_when_the_budget_is_tiny() {
+        let tasks = [task("보고서", "todo", day(-1)), task("발표", "todo", day(3))];
+        let prompts = shipped();
+        let context = assemble(&prompts, &audience("ko"), &tasks, &[], &[], today(), 10);
+        assert_eq!(context.text, "(분량 제한으로 생략: 지난 마감 1건, 예정 1건)");
+        assert_eq!(context.omitted.len(), 2);
+        let context = assemble(&prompts, &audience("en"), &tasks, &[], &[], today(), 10);
+        assert_eq!(context.text, "(Left out to fit: Overdue 1, Upcoming 1)");
+    }
+}
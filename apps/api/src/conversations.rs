@@ -0,0 +1,354 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::llm::{estimate_tokens, ChatMessage, LlmProvider};
+use crate::prompts::{Audience, Prompt, Prompts};
+
+/// Once a thread has more unsummarized messages than this, the older ones
+/// are folded into the conversation summary.
+const SUMMARIZE_AFTER_MESSAGES: usize = 24;
+/// Messages kept verbatim when summarizing; even, so turns stay paired.
+const KEEP_RECENT_MESSAGES: usize = 8;
+const TITLE_MAX_CHARS: usize = 60;
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct Conversation {
+    pub id: Uuid,
+    pub title: String,
+    pub summary: Option<String>,
+    pub message_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ConversationMessage {
+    pub id: Uuid,
+    pub role: String,
+    pub content: String,
+    pub summarized: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Prior turns of a conversation to replay before a new message: the
+/// rolling summary, then as many of the latest turns as fit in `budget`
+/// estimated tokens. Returns `None` if the conversation does not exist.
+pub async fn history(
+    conn: &mut PgConnection,
+    prompts: &Prompts,
+    audience: &Audience,
+    user_id: Uuid,
+    conversation_id: Uuid,
+    budget: usize,
+) -> Result<Option<Vec<ChatMessage>>, sqlx::Error> {
+    let Some(conversation) = sqlx::query!(
+        "SELECT summary FROM ai_conversations WHERE id = $1 AND user_id = $2",
+        conversation_id,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT role, content FROM ai_messages
+        WHERE conversation_id = $1 AND NOT summarized
+        ORDER BY created_at DESC, id DESC
+        "#,
+        conversation_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut remaining = budget;
+    let mut messages = Vec::new();
+    if let Some(summary) = conversation.summary {
+        remaining = remaining.saturating_sub(estimate_tokens(&summary));
+        messages.push(ChatMessage::system(prompts.render(
+            audience,
+            Prompt::HistorySummary,
+            minijinja::context! { summary },
+        )));
+    }
+    let mut recent = Vec::new();
+    for row in rows {
+        let cost = estimate_tokens(&row.content);
+        if cost > remaining {
+            break;
+        }
+        remaining -= cost;
+        recent.push(match row.role.as_str() {
+            "assistant" => ChatMessage::assistant(row.content),
+            _ => ChatMessage::user(row.content),
+        });
+    }
+    // Never start the replay with a dangling assistant reply.
+    if recent.last().is_some_and(|m| m.role == "assistant") {
+        recent.pop();
+    }
+    messages.extend(recent.into_iter().rev());
+    Ok(Some(messages))
+}
+
+/// Stores a user message and the reply to it, starting a new conversation
+/// titled after the message when `conversation_id` is `None`.
+pub async fn append_turn(
+    conn: &mut PgConnection,
+    prompts: &Prompts,
+    audience: &Audience,
+    user_id: Uuid,
+    conversation_id: Option<Uuid>,
+    message: &str,
+    reply: &str,
+) -> Result<Uuid, sqlx::Error> {
+    let conversation_id = match conversation_id {
+        Some(id) => {
+            sqlx::query!(
+                "UPDATE ai_conversations SET updated_at = NOW() WHERE id = $1 AND user_id = $2",
+                id,
+                user_id
+            )
+            .execute(&mut *conn)
+            .await?;
+            id
+        }
+        None => {
+            let id = Uuid::new_v4();
+            sqlx::query!(
+                "INSERT INTO ai_conversations (id, user_id, title) VALUES ($1, $2, $3)",
+                id,
+                user_id,
+                default_title(message).unwrap_or_else(|| prompts.render(
+                    audience,
+                    Prompt::ConversationUntitled,
+                    minijinja::Value::UNDEFINED,
+                ))
+            )
+            .execute(&mut *conn)
+            .await?;
+            id
+        }
+    };
+
+    // Offsetting the reply keeps the pair ordered even within one clock tick.
+    sqlx::query!(
+        r#"
+        INSERT INTO ai_messages (id, conversation_id, user_id, role, content, created_at)
+        VALUES ($1, $3, $4, 'user', $5, NOW()),
+               ($2, $3, $4, 'assistant', $6, NOW() + INTERVAL '1 microsecond')
+        "#,
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        conversation_id,
+        user_id,
+        message,
+        reply
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(conversation_id)
+}
+
+/// The message's first line, shortened; `None` for a blank message.
+fn default_title(message: &str) -> Option<String> {
+    let line = message.lines().find(|l| !l.trim().is_empty())?.trim();
+    let mut title: String = line.chars().take(TITLE_MAX_CHARS).collect();
+    if line.chars().count() > TITLE_MAX_CHARS {
+        title.push('…');
+    }
+    Some(title)
+}
+
+/// Folds all but the latest turns of a long conversation into its summary.
+/// Meant to run in the background after a turn is stored.
+pub async fn summarize_if_long(
+    pool: PgPool,
+    llm: Arc<dyn LlmProvider>,
+    prompts: &Prompts,
+    audience: &Audience,
+    conversation_id: Uuid,
+) -> anyhow::Result<()> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, role, content FROM ai_messages
+        WHERE conversation_id = $1 AND NOT summarized
+        ORDER BY created_at, id
+        "#,
+        conversation_id
+    )
+    .fetch_all(&pool)
+    .await?;
+    if rows.len() <= SUMMARIZE_AFTER_MESSAGES {
+        return Ok(());
+    }
+    let folded = &rows[..rows.len() - KEEP_RECENT_MESSAGES];
+
+    let previous = sqlx::query_scalar!(
+        "SELECT summary FROM ai_conversations WHERE id = $1",
+        conversation_id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let transcript = folded
+        .iter()
+        .map(|m| {
+            prompts.render(
+                audience,
+                Prompt::SummaryTurn,
+                minijinja::context! { role => m.role, content => m.content },
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let completion = llm
+        .chat(&[
+            ChatMessage::system(prompts.render(audience, Prompt::SummarySystem, minijinja::Value::UNDEFINED)),
+            ChatMessage::user(prompts.render(
+                audience,
+                Prompt::SummaryRequest,
+                minijinja::context! { previous, transcript },
+            )),
+        ])
+        .await?;
+    let summary = completion.content.trim();
+    if summary.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    // Another summarization that finished first wins; ours is dropped.
+    let updated = sqlx::query!(
+        r#"
+        UPDATE ai_conversations SET summary = $1
+        WHERE id = $2 AND summary IS NOT DISTINCT FROM $3
+        "#,
+        summary,
+        conversation_id,
+        previous
+    )
+    .execute(&mut *tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Ok(());
+    }
+    let ids: Vec<Uuid> = folded.iter().map(|m| m.id).collect();
+    sqlx::query!(
+        "UPDATE ai_messages SET summarized = TRUE WHERE id = ANY($1)",
+        &ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MockProvider;
+    use crate::prompts::tests::{audience, shipped};
+    use crate::testing::TestApp;
+
+    /// Stores `turns` pairs whose messages cost ten estimated tokens each.
+    async fn seed(app: &TestApp, turns: usize) -> Uuid {
+        let mut conn = app.state.pool.acquire().await.unwrap();
+        let (prompts, audience) = (shipped(), audience("ko"));
+        let mut id = None;
+        for i in 0..turns {
+            let message = format!("{i}{}", "질".repeat(9));
+            let reply = format!("{i}{}", "답".repeat(9));
+            id = Some(append_turn(&mut conn, &prompts, &audience, app.user_id, id, &message, &reply).await.unwrap());
+        }
+        id.unwrap()
+    }
+
+    async fn replay(app: &TestApp, id: Uuid, budget: usize) -> Option<Vec<ChatMessage>> {
+        let mut conn = app.state.pool.acquire().await.unwrap();
+        history(&mut conn, &shipped(), &audience("ko"), app.user_id, id, budget).await.unwrap()
+    }
+
+    fn contents(messages: &[ChatMessage]) -> Vec<(&str, &str)> {
+        messages.iter().map(|m| (m.role.as_str(), &m.content[..1])).collect()
+    }
+
+    #[test]
+    fn titles_after_the_first_line() {
+        assert_eq!(default_title("\n  회의 준비  \n자료 정리").as_deref(), Some("회의 준비"));
+        assert_eq!(default_title(&"가".repeat(61)), Some(format!("{}…", "가".repeat(60))));
+        assert_eq!(default_title(" \n "), None);
+    }
+
+    #[tokio::test]
+    async fn replays_the_latest_turns_that_fit() {
+        let app = TestApp::start().await;
+        let id = seed(&app, 5).await;
+
+        let messages = replay(&app, id, 40).await.unwrap();
+        assert_eq!(contents(&messages), [("user", "3"), ("assistant", "3"), ("user", "4"), ("assistant", "4")]);
+        // Room for three messages would start with a reply; it is dropped.
+        let messages = replay(&app, id, 35).await.unwrap();
+        assert_eq!(contents(&messages), [("user", "4"), ("assistant", "4")]);
+        assert!(replay(&app, id, 5).await.unwrap().is_empty());
+
+        sqlx::query!("UPDATE ai_conversations SET summary = '요약' WHERE id = $1", id)
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+        let messages = replay(&app, id, 42).await.unwrap();
+        assert_eq!(messages[0].role, "system");
+        assert!(messages[0].content.ends_with("요약"));
+        assert_eq!(contents(&messages[1..]), [("user", "3"), ("assistant", "3"), ("user", "4"), ("assistant", "4")]);
+
+        let other = TestApp::start().await;
+        assert!(replay(&other, id, 1000).await.is_none());
+        assert!(replay(&app, Uuid::new_v4(), 1000).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn folds_old_turns_into_the_summary() {
+        let app = TestApp::start().await;
+        let id = seed(&app, 13).await;
+        let provider = Arc::new(MockProvider::with_responses("mock", ["앞부분 요약"]));
+        let llm: Arc<dyn LlmProvider> = provider.clone();
+
+        summarize_if_long(app.state.pool.clone(), llm.clone(), &shipped(), &audience("ko"), id)
+            .await
+            .unwrap();
+        let sent = provider.requests();
+        assert_eq!(sent.len(), 1);
+        let transcript = &sent[0][1].content;
+        assert!(transcript.starts_with("기존 요약:\n없음\n\n대화:\n사용자: 0"), "{transcript}");
+        assert!(transcript.contains(&format!("0{}", "질".repeat(9))));
+        assert!(transcript.contains(&format!("8{}", "답".repeat(9))));
+        assert!(!transcript.contains(&format!("9{}", "질".repeat(9))));
+
+        let conversation = sqlx::query!(
+            r#"SELECT summary, (SELECT COUNT(*) FROM ai_messages WHERE conversation_id = $1 AND summarized) AS "folded!" FROM ai_conversations WHERE id = $1"#,
+            id
+        )
+        .fetch_one(&app.state.pool)
+        .await
+        .unwrap();
+        assert_eq!(conversation.summary.as_deref(), Some("앞부분 요약"));
+        assert_eq!(conversation.folded, (26 - KEEP_RECENT_MESSAGES) as i64);
+
+        let messages = replay(&app, id, 1000).await.unwrap();
+        assert!(messages[0].content.ends_with("앞부분 요약"));
+        assert_eq!(contents(&messages[1..3]), [("user", "9"), ("assistant", "9")]);
+        assert_eq!(messages.len(), 1 + KEEP_RECENT_MESSAGES);
+
+        // What is left is short again, so the model is not asked.
+        summarize_if_long(app.state.pool.clone(), llm, &shipped(), &audience("ko"), id)
+            .await
+            .unwrap();
+        assert_eq!(provider.requests().len(), 1);
+    }
+}
@@ -0,0 +1,321 @@
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::Task;
+use crate::settings::Preferences;
+use crate::timers;
+use crate::usage;
+
+pub const MAX_WIDGETS: usize = 20;
+pub const MAX_LIMIT: i64 = 50;
+pub const MAX_PINNED_NOTES: usize = 20;
+
+/// One dashboard widget and its parameters, stored as
+/// `{"type": "recent_tasks", "limit": 10}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Widget {
+    /// Task counts: total, due today, overdue, done this week.
+    Summary,
+    RecentTasks {
+        #[serde(default = "default_limit")]
+        limit: i64,
+    },
+    /// Open tasks past their end date, most overdue first.
+    OverdueTasks {
+        #[serde(default = "default_limit")]
+        limit: i64,
+    },
+    /// Tasks on each day of this week, which starts on the user's
+    /// `week_start`.
+    WeekCalendar,
+    /// The given notes, in the given order; deleted ones are left out.
+    PinnedNotes { note_ids: Vec<Uuid> },
+    /// Tasks per tag, most used first.
+    TagCounts {
+        #[serde(default = "default_limit")]
+        limit: i64,
+        #[serde(default)]
+        include_done: bool,
+    },
+    /// The running timer entry, or `null`; see `/api/v1/timer`.
+    Timer,
+}
+
+fn default_limit() -> i64 {
+    10
+}
+
+/// What the dashboard showed before layouts could be changed.
+pub fn default_layout() -> Vec<Widget> {
+    vec![Widget::Summary, Widget::RecentTasks { limit: 10 }]
+}
+
+pub fn validate(widgets: &[Widget]) -> Result<(), &'static str> {
+    if widgets.len() > MAX_WIDGETS {
+        return Err("too many widgets");
+    }
+    for widget in widgets {
+        match widget {
+            Widget::RecentTasks { limit } | Widget::OverdueTasks { limit } | Widget::TagCounts { limit, .. }
+                if !(1..=MAX_LIMIT).contains(limit) =>
+            {
+                return Err("invalid limit");
+            }
+            Widget::PinnedNotes { note_ids } if note_ids.len() > MAX_PINNED_NOTES => {
+                return Err("too many pinned notes");
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The user's saved layout, or the default one. A saved layout that no
+/// longer parses (a widget type was removed) also gets the default.
+pub async fn layout(pool: &PgPool, user_id: Uuid) -> Result<Vec<Widget>, sqlx::Error> {
+    let stored = sqlx::query_scalar!("SELECT widgets FROM dashboard_layouts WHERE user_id = $1", user_id)
+        .fetch_optional(pool)
+        .await?;
+    let Some(stored) = stored else {
+        return Ok(default_layout());
+    };
+    Ok(serde_json::from_value(stored).unwrap_or_else(|err| {
+        tracing::warn!(user_id = %user_id, "stored dashboard layout unreadable: {err}");
+        default_layout()
+    }))
+}
+
+#[derive(Serialize)]
+pub struct Counts {
+    pub total_tasks: i64,
+    pub due_today: i64,
+    pub overdue: i64,
+    pub done_this_week: i64,
+}
+
+/// Counts as of today on the user's clock; the week starts on their
+/// `week_start`.
+pub async fn counts(pool: &PgPool, user_id: Uuid, prefs: &Preferences) -> Result<Counts, sqlx::Error> {
+    let today = prefs.today();
+    let (week_start, _) = usage::day_bounds(prefs.week_of(today), prefs.timezone);
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "total_tasks!",
+               COUNT(*) FILTER (
+                   WHERE COALESCE(start_date, due_date) <= $2 AND COALESCE(end_date, due_date) >= $2
+               ) AS "due_today!",
+               COUNT(*) FILTER (WHERE COALESCE(end_date, due_date) < $2 AND status != 'done') AS "overdue!",
+               COUNT(*) FILTER (WHERE completed_at >= $3) AS "done_this_week!"
+        FROM tasks
+        WHERE user_id = $1
+        "#,
+        user_id,
+        today,
+        week_start
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(Counts {
+        total_tasks: row.total_tasks,
+        due_today: row.due_today,
+        overdue: row.overdue,
+        done_this_week: row.done_this_week,
+    })
+}
+
+pub async fn recent_tasks(pool: &PgPool, user_id: Uuid, limit: i64) -> Result<Vec<Task>, sqlx::Error> {
+    sqlx::query_as!(
+        Task,
+        r#"
+        SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at
+        FROM tasks WHERE user_id = $1 ORDER BY updated_at DESC LIMIT $2
+        "#,
+        user_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(Serialize)]
+struct CalendarDay {
+    date: NaiveDate,
+    tasks: Vec<CalendarTask>,
+}
+
+#[derive(Serialize, Clone)]
+struct CalendarTask {
+    id: Uuid,
+    title: String,
+    status: String,
+    priority: String,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct PinnedNote {
+    id: Uuid,
+    title: String,
+    tags: Vec<String>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct TagCount {
+    tag: String,
+    tasks: i64,
+}
+
+/// The data `widget` shows for the user.
+pub async fn compute(pool: &PgPool, user_id: Uuid, prefs: &Preferences, widget: &Widget) -> Result<Value, sqlx::Error> {
+    let value = match widget {
+        Widget::Summary => to_value(counts(pool, user_id, prefs).await?),
+        Widget::RecentTasks { limit } => to_value(recent_tasks(pool, user_id, *limit).await?),
+        Widget::OverdueTasks { limit } => to_value(
+            sqlx::query_as!(
+                Task,
+                r#"
+                SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at
+                FROM tasks
+                WHERE user_id = $1 AND COALESCE(end_date, due_date) < $2 AND status != 'done'
+                ORDER BY COALESCE(end_date, due_date), created_at
+                LIMIT $3
+                "#,
+                user_id,
+                prefs.today(),
+                limit
+            )
+            .fetch_all(pool)
+            .await?,
+        ),
+        Widget::WeekCalendar => {
+            let start = prefs.week_of(prefs.today());
+            let end = start + TimeDelta::days(6);
+            let tasks = sqlx::query_as!(
+                CalendarTask,
+                r#"
+                SELECT id, title, status, priority,
+                       COALESCE(start_date, due_date) AS start_date,
+                       COALESCE(end_date, due_date) AS end_date
+                FROM tasks
+                WHERE user_id = $1 AND COALESCE(start_date, due_date) <= $3 AND COALESCE(end_date, due_date) >= $2
+                ORDER BY COALESCE(start_date, due_date), title
+                "#,
+                user_id,
+                start,
+                end
+            )
+            .fetch_all(pool)
+            .await?;
+            to_value(week(&tasks, start))
+        }
+        Widget::PinnedNotes { note_ids } => {
+            let mut notes = sqlx::query_as!(
+                PinnedNote,
+                "SELECT id, title, tags, updated_at FROM notes WHERE user_id = $1 AND id = ANY($2)",
+                user_id,
+                note_ids
+            )
+            .fetch_all(pool)
+            .await?;
+            notes.sort_by_key(|n| note_ids.iter().position(|id| *id == n.id));
+            to_value(notes)
+        }
+        Widget::TagCounts { limit, include_done } => to_value(
+            sqlx::query_as!(
+                TagCount,
+                r#"
+                SELECT tag AS "tag!", COUNT(*) AS "tasks!"
+                FROM tasks, unnest(tags) AS tag
+                WHERE user_id = $1 AND ($2 OR status != 'done')
+                GROUP BY tag
+                ORDER BY 2 DESC, tag
+                LIMIT $3
+                "#,
+                user_id,
+                include_done,
+                limit
+            )
+            .fetch_all(pool)
+            .await?,
+        ),
+        Widget::Timer => to_value(timers::running(pool, user_id).await?),
+    };
+    Ok(value)
+}
+
+fn to_value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).unwrap_or_default()
+}
+
+/// Seven days from `start`, each with the tasks whose range covers it.
+fn week(tasks: &[CalendarTask], start: NaiveDate) -> Vec<CalendarDay> {
+    (0..7)
+        .map(|offset| {
+            let date = start + TimeDelta::days(offset);
+            let tasks = tasks
+                .iter()
+                .filter(|t| t.start_date.is_none_or(|s| s <= date) && t.end_date.is_none_or(|e| e >= date))
+                .cloned()
+                .collect();
+            CalendarDay { date, tasks }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widgets_read_with_default_parameters() {
+        let widgets: Vec<Widget> = serde_json::from_str(
+            r#"[{"type": "summary"}, {"type": "recent_tasks"}, {"type": "tag_counts", "limit": 3}, {"type": "timer"}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            widgets,
+            [
+                Widget::Summary,
+                Widget::RecentTasks { limit: 10 },
+                Widget::TagCounts { limit: 3, include_done: false },
+                Widget::Timer,
+            ]
+        );
+        assert!(validate(&widgets).is_ok());
+        assert_eq!(validate(&[Widget::OverdueTasks { limit: 0 }]), Err("invalid limit"));
+        assert!(serde_json::from_str::<Widget>(r#"{"type": "weather"}"#).is_err());
+        // Timers saved before they had their own table.
+        let timer = serde_json::from_str::<Widget>(r#"{"type": "timer", "task_id": null, "started_at": "2024-12-02T09:00:00Z"}"#);
+        assert_eq!(timer.ok(), Some(Widget::Timer));
+    }
+
+    #[test]
+    fn spreads_tasks_over_the_week() {
+        let day = |d: u32| NaiveDate::from_ymd_opt(2024, 12, d).unwrap();
+        let task = |title: &str, start: Option<NaiveDate>, end: Option<NaiveDate>| CalendarTask {
+            id: Uuid::new_v4(),
+            title: title.to_string(),
+            status: "todo".to_string(),
+            priority: "medium".to_string(),
+            start_date: start,
+            end_date: end,
+        };
+        let tasks = [
+            task("워크숍", Some(day(3)), Some(day(4))),
+            task("이월된 일", Some(day(1)), Some(day(2))),
+            task("마감", None, Some(day(6))),
+        ];
+        let days = week(&tasks, day(2));
+        let titles = |i: usize| days[i].tasks.iter().map(|t| t.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(days.len(), 7);
+        assert_eq!(titles(0), ["이월된 일", "마감"]);
+        assert_eq!(titles(2), ["워크숍", "마감"]);
+        assert_eq!(titles(5), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,587 @@
+use std::ops::Range;
+use std::sync::LazyLock;
+
+use chrono::{Datelike, Duration, Months, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use regex::{Captures, Regex};
+
+/// Today's date on the user's wall clock.
+pub fn today_in(tz: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&tz).date_naive()
+}
+
+/// Dates and time of day found in free text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extracted {
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub time: Option<NaiveTime>,
+    /// The input with only the matched expressions cut out.
+    pub rest: String,
+}
+
+/// Resolves a match against today and the first day of the user's week.
+type DateResolver = fn(&Captures, NaiveDate, Weekday) -> Option<(NaiveDate, NaiveDate)>;
+type TimeResolver = fn(&Captures) -> Option<NaiveTime>;
+
+struct Rule<F> {
+    pattern: Regex,
+    resolve: F,
+    /// A bare day of the month, which takes its month from the start of a range.
+    day_only: bool,
+}
+
+struct Found {
+    span: Range<usize>,
+    start: NaiveDate,
+    end: NaiveDate,
+    day: Option<u32>,
+    opens_range: bool,
+}
+
+/// Connectors and particles around an expression, cut out along with it:
+/// "from", "by", "부터", "까지", "에" and the like.
+fn rule<F>(core: &str, resolve: F, day_only: bool) -> Rule<F> {
+    let pattern = format!(
+        r"(?i)(?:\b(?P<pre>from|by|on|until|till|due|before|at)\s+)?(?:{core})(?P<suf>\s*(?:부터|까지|에))?"
+    );
+    Rule {
+        pattern: Regex::new(&pattern).expect("valid date pattern"),
+        resolve,
+        day_only,
+    }
+}
+
+const MONTHS: &str = "january|jan|february|feb|march|mar|april|apr|may|june|jun|july|jul|august|aug|\
+september|sept|sep|october|oct|november|nov|december|dec";
+const MONTH_PREFIXES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// Most specific first: a span taken by an earlier rule is not matched again.
+static DATE_RULES: LazyLock<Vec<Rule<DateResolver>>> = LazyLock::new(|| {
+    vec![
+        rule(r"\b(?P<y>\d{4})[-./](?P<m>\d{1,2})[-./](?P<d>\d{1,2})\b", |c, _, _| single(ymd(c)?), false),
+        rule(r"(?P<y>\d{4})\s*년\s*(?P<m>\d{1,2})\s*월\s*(?P<d>\d{1,2})\s*일", |c, _, _| single(ymd(c)?), false),
+        rule(r"\b(?P<m>\d{1,2})\s*월\s*(?P<d>\d{1,2})\s*일", |c, today, _| single(month_day(c, today)?), false),
+        rule(
+            &format!(r"\b(?P<mon>{MONTHS})\b\.?\s+(?P<d>\d{{1,2}})(?:st|nd|rd|th)?\b(?:,?\s+(?P<y>\d{{4}})\b)?"),
+            |c, today, _| single(month_day(c, today)?),
+            false,
+        ),
+        rule(
+            &format!(r"\b(?P<d>\d{{1,2}})(?:st|nd|rd|th)?\s+(?:of\s+)?(?P<mon>{MONTHS})\b"),
+            |c, today, _| single(month_day(c, today)?),
+            false,
+        ),
+        rule(r"\b(?P<m>\d{1,2})/(?P<d>\d{1,2})\b", |c, today, _| single(month_day(c, today)?), false),
+        rule(
+            r"(?P<n>\d+)\s*(?P<u>일|주일|주|개월|달)\s*(?:후|뒤)",
+            |c, today, _| single(shift(today, num(c, "n")?, &c["u"])?),
+            false,
+        ),
+        rule(
+            r"\bin\s+(?P<n>\d+|an?|one)\s+(?P<u>days?|weeks?|months?)\b",
+            |c, today, _| single(shift(today, num(c, "n")?, &c["u"])?),
+            false,
+        ),
+        rule(
+            r"\b(?P<n>\d+)\s+(?P<u>days?|weeks?|months?)\s+from\s+now\b",
+            |c, today, _| single(shift(today, num(c, "n")?, &c["u"])?),
+            false,
+        ),
+        rule(
+            r"(?P<w>내일\s*모레|오늘|금일|내일|모레|글피|어제)",
+            |c, today, _| {
+                let days = match c["w"].chars().filter(|ch| !ch.is_whitespace()).collect::<String>().as_str() {
+                    "오늘" | "금일" => 0,
+                    "내일" => 1,
+                    "모레" | "내일모레" => 2,
+                    "글피" => 3,
+                    _ => -1,
+                };
+                single(today + Duration::days(days))
+            },
+            false,
+        ),
+        rule(
+            r"\b(?P<w>(?:the\s+)?day\s+after\s+tomorrow|today|tonight|tomorrow|tmrw?|yesterday)\b",
+            |c, today, _| {
+                let w = c["w"].to_lowercase();
+                let days = if w.contains("after") {
+                    2
+                } else if w.starts_with("tom") || w.starts_with("tmr") {
+                    1
+                } else if w == "yesterday" {
+                    -1
+                } else {
+                    0
+                };
+                single(today + Duration::days(days))
+            },
+            false,
+        ),
+        rule(
+            r"(?P<wk>이번\s*주|금주|다음\s*주|담주|다다음\s*주)\s*(?P<wd>[월화수목금토일])요일",
+            |c, today, first| single(day_in_week(today, first, korean_week(&c["wk"]), korean_weekday(&c["wd"])?)),
+            false,
+        ),
+        rule(
+            r"\b(?P<wk>this|next)\s+(?:week\s+)?(?P<wd>monday|mon|tuesday|tues|tue|wednesday|wed|thursday|thurs|thur|thu|friday|fri|saturday|sat|sunday|sun)\b\.?",
+            |c, today, first| {
+                let weeks = if c["wk"].eq_ignore_ascii_case("next") { 1 } else { 0 };
+                single(day_in_week(today, first, weeks, english_weekday(&c["wd"])?))
+            },
+            false,
+        ),
+        rule(
+            r"(?P<wd>[월화수목금토일])요일",
+            |c, today, _| single(upcoming(today, korean_weekday(&c["wd"])?)),
+            false,
+        ),
+        rule(
+            r"\b(?P<wd>monday|mon|tuesday|tues|tue|wednesday|wed|thursday|thurs|thur|thu|friday|fri|saturday|sunday)\b",
+            |c, today, _| single(upcoming(today, english_weekday(&c["wd"])?)),
+            false,
+        ),
+        rule(
+            r"(?P<p>이번\s*주|금주|다음\s*주|담주|다다음\s*주|이번\s*달|이달|다음\s*달)",
+            |c, today, first| {
+                let p: String = c["p"].chars().filter(|ch| !ch.is_whitespace()).collect();
+                if p.ends_with('달') {
+                    month_range(today, if p == "다음달" { 1 } else { 0 })
+                } else {
+                    week_range(today, first, korean_week(&p))
+                }
+            },
+            false,
+        ),
+        rule(
+            r"\b(?P<wk>this|next)\s+(?P<p>week|month)\b",
+            |c, today, first| {
+                let ahead = if c["wk"].eq_ignore_ascii_case("next") { 1 } else { 0 };
+                if c["p"].eq_ignore_ascii_case("month") {
+                    month_range(today, ahead)
+                } else {
+                    week_range(today, first, ahead.into())
+                }
+            },
+            false,
+        ),
+        rule(
+            r"\b(?P<d>\d{1,2})\s*일(?P<dur>\s*(?:간|동안|전|째))?",
+            |c, today, _| {
+                // "3일간", "3일 전" and "3일째" count days, they do not name one.
+                if c.name("dur").is_some() {
+                    return None;
+                }
+                single(day_of_month(today, num(c, "d")?)?)
+            },
+            true,
+        ),
+    ]
+});
+
+static TIME_RULES: LazyLock<Vec<Rule<TimeResolver>>> = LazyLock::new(|| {
+    vec![
+        rule(
+            r"(?P<ap>오전|오후|아침|낮|저녁|밤|새벽)?\s*(?P<h>\d{1,2})\s*시(?P<dur>간)?(?:\s*(?P<min>\d{1,2})\s*분|\s*(?P<half>반))?",
+            |c| {
+                // "3시간" is a duration, not a time of day.
+                if c.name("dur").is_some() {
+                    return None;
+                }
+                let minute = if c.name("half").is_some() { 30 } else { num(c, "min").unwrap_or(0) };
+                let ap = c.name("ap").map(|ap| ap.as_str());
+                let hour = num(c, "h")?;
+                // "밤 12시" is midnight, "낮 12시" noon.
+                if hour == 12 && matches!(ap, Some("저녁" | "밤")) {
+                    return NaiveTime::from_hms_opt(0, minute, 0);
+                }
+                clock(hour, minute, matches!(ap, Some("오후" | "낮" | "저녁" | "밤")))
+            },
+            false,
+        ),
+        rule(
+            r"\b(?P<h>\d{1,2})(?::(?P<min>\d{2}))?\s*(?P<ap>am|pm|a\.m\.|p\.m\.)",
+            |c| {
+                let pm = c["ap"].to_lowercase().starts_with('p');
+                clock(num(c, "h")?, num(c, "min").unwrap_or(0), pm)
+            },
+            false,
+        ),
+        rule(
+            r"\b(?P<h>[01]?\d|2[0-3]):(?P<min>[0-5]\d)\b",
+            |c| NaiveTime::from_hms_opt(num(c, "h")?, num(c, "min")?, 0),
+            false,
+        ),
+        rule(r"(?P<noon>정오|\bnoon\b)", |_| NaiveTime::from_hms_opt(12, 0, 0), false),
+    ]
+});
+
+static RANGE_CONNECTOR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(?:~|-|–|—|to|through|thru|until|till)$").expect("valid connector pattern")
+});
+
+/// Finds date and time expressions in Korean or English text, relative to
+/// `today`. The first expression gives the dates; when two are joined as a
+/// range ("3일부터 5일까지", "Dec 3 ~ Dec 5") they give start and end. A time
+/// of day without a date is taken to mean today, and weeks begin on
+/// `week_start`.
+pub fn extract(input: &str, today: NaiveDate, week_start: Weekday) -> Extracted {
+    let mut taken: Vec<Range<usize>> = Vec::new();
+
+    let mut dates = Vec::new();
+    for rule in DATE_RULES.iter() {
+        for caps in rule.pattern.captures_iter(input) {
+            let span = caps.get(0).expect("whole match").range();
+            if overlaps(&taken, &span) || !starts_word(input, span.start) {
+                continue;
+            }
+            let Some((start, end)) = (rule.resolve)(&caps, today, week_start) else {
+                continue;
+            };
+            let pre = caps.name("pre").map(|m| m.as_str().to_lowercase());
+            let suf = caps.name("suf").map(|m| m.as_str().trim().to_string());
+            dates.push(Found {
+                day: if rule.day_only { num(&caps, "d") } else { None },
+                opens_range: pre.as_deref() == Some("from") || suf.as_deref() == Some("부터"),
+                span: span.clone(),
+                start,
+                end,
+            });
+            taken.push(span);
+        }
+    }
+    dates.sort_by_key(|f| f.span.start);
+
+    let mut time = None;
+    let mut cut = Vec::new();
+    'rules: for rule in TIME_RULES.iter() {
+        for caps in rule.pattern.captures_iter(input) {
+            let span = caps.get(0).expect("whole match").range();
+            if overlaps(&taken, &span) {
+                continue;
+            }
+            if let Some(found) = (rule.resolve)(&caps) {
+                time = Some(found);
+                cut.push(span);
+                break 'rules;
+            }
+        }
+    }
+
+    let (start_date, end_date) = match dates.as_slice() {
+        [] => (time.map(|_| today), time.map(|_| today)),
+        [first, rest @ ..] => match rest.first() {
+            Some(second) if joined(input, first, second) => {
+                cut.push(first.span.start..second.span.end);
+                let end = match second.day {
+                    // "12월 3일부터 5일까지": the bare day stays in the start's month.
+                    Some(day) => range_end(first.start, day).unwrap_or(second.end),
+                    None => second.end,
+                };
+                (Some(first.start), Some(end.max(first.start)))
+            }
+            _ => {
+                cut.push(first.span.clone());
+                (Some(first.start), Some(first.end))
+            }
+        },
+    };
+
+    Extracted {
+        start_date,
+        end_date,
+        time,
+        rest: remove_spans(input, cut),
+    }
+}
+
+/// Korean words are not split by `\b`, so "금일" would match inside
+/// "지금일단": an expression must not continue a Hangul word.
+fn starts_word(input: &str, at: usize) -> bool {
+    let hangul = |c: Option<char>| c.is_some_and(|c| ('\u{AC00}'..='\u{D7A3}').contains(&c));
+    !(hangul(input[..at].chars().next_back()) && hangul(input[at..].chars().next()))
+}
+
+fn overlaps(taken: &[Range<usize>], span: &Range<usize>) -> bool {
+    taken.iter().any(|t| t.start < span.end && span.start < t.end)
+}
+
+/// Whether `second` closes a range opened by `first`.
+fn joined(input: &str, first: &Found, second: &Found) -> bool {
+    let gap = input[first.span.end..second.span.start].trim();
+    if gap.is_empty() {
+        first.opens_range
+    } else {
+        RANGE_CONNECTOR.is_match(gap)
+    }
+}
+
+fn remove_spans(input: &str, mut spans: Vec<Range<usize>>) -> String {
+    spans.sort_by_key(|s| s.start);
+    let mut out = String::with_capacity(input.len());
+    let mut pos = 0;
+    for span in spans {
+        if span.start >= pos {
+            out.push_str(&input[pos..span.start]);
+            out.push(' ');
+            pos = span.end;
+        }
+    }
+    out.push_str(&input[pos..]);
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn single(date: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+    Some((date, date))
+}
+
+fn num(caps: &Captures, name: &str) -> Option<u32> {
+    let value = caps.name(name)?.as_str().to_lowercase();
+    match value.as_str() {
+        "a" | "an" | "one" => Some(1),
+        other => other.parse().ok(),
+    }
+}
+
+fn ymd(caps: &Captures) -> Option<NaiveDate> {
+    NaiveDate::from_ymd_opt(caps["y"].parse().ok()?, num(caps, "m")?, num(caps, "d")?)
+}
+
+/// A month and day without a year is the next such date from today.
+fn month_day(caps: &Captures, today: NaiveDate) -> Option<NaiveDate> {
+    let month = match caps.name("mon") {
+        Some(name) => {
+            let name = name.as_str().to_lowercase();
+            MONTH_PREFIXES.iter().position(|m| name.starts_with(m))? as u32 + 1
+        }
+        None => num(caps, "m")?,
+    };
+    let day = num(caps, "d")?;
+    if let Some(year) = caps.name("y") {
+        return NaiveDate::from_ymd_opt(year.as_str().parse().ok()?, month, day);
+    }
+    let this_year = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+    if this_year >= today {
+        Some(this_year)
+    } else {
+        NaiveDate::from_ymd_opt(today.year() + 1, month, day)
+    }
+}
+
+/// A bare day of the month is the next such day from today.
+fn day_of_month(today: NaiveDate, day: u32) -> Option<NaiveDate> {
+    let this_month = NaiveDate::from_ymd_opt(today.year(), today.month(), day);
+    match this_month {
+        Some(date) if date >= today => Some(date),
+        _ => {
+            let next = today.with_day(1)?.checked_add_months(Months::new(1))?;
+            NaiveDate::from_ymd_opt(next.year(), next.month(), day)
+        }
+    }
+}
+
+fn range_end(start: NaiveDate, day: u32) -> Option<NaiveDate> {
+    let same_month = NaiveDate::from_ymd_opt(start.year(), start.month(), day);
+    match same_month {
+        Some(date) if date >= start => Some(date),
+        _ => {
+            let next = start.with_day(1)?.checked_add_months(Months::new(1))?;
+            NaiveDate::from_ymd_opt(next.year(), next.month(), day)
+        }
+    }
+}
+
+fn shift(today: NaiveDate, n: u32, unit: &str) -> Option<NaiveDate> {
+    match unit.to_lowercase().as_str() {
+        "일" | "day" | "days" => today.checked_add_signed(Duration::days(n.into())),
+        "주" | "주일" | "week" | "weeks" => today.checked_add_signed(Duration::weeks(n.into())),
+        _ => today.checked_add_months(Months::new(n)),
+    }
+}
+
+fn week_start(today: NaiveDate, first: Weekday, weeks_ahead: i64) -> NaiveDate {
+    crate::settings::week_of(today, first) + Duration::weeks(weeks_ahead)
+}
+
+fn week_range(today: NaiveDate, first: Weekday, weeks_ahead: i64) -> Option<(NaiveDate, NaiveDate)> {
+    let start = week_start(today, first, weeks_ahead);
+    Some((start, start + Duration::days(6)))
+}
+
+/// `weekday` of the week `weeks_ahead` of this one.
+fn day_in_week(today: NaiveDate, first: Weekday, weeks_ahead: i64, weekday: Weekday) -> NaiveDate {
+    week_start(today, first, weeks_ahead) + Duration::days(weekday.days_since(first).into())
+}
+
+fn month_range(today: NaiveDate, months_ahead: u32) -> Option<(NaiveDate, NaiveDate)> {
+    let start = today.with_day(1)?.checked_add_months(Months::new(months_ahead))?;
+    let end = start.checked_add_months(Months::new(1))? - Duration::days(1);
+    Some((start, end))
+}
+
+/// The next `weekday`, today included.
+fn upcoming(today: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let ahead = (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+    today + Duration::days(ahead.into())
+}
+
+fn korean_week(word: &str) -> i64 {
+    let word: String = word.chars().filter(|c| !c.is_whitespace()).collect();
+    match word.as_str() {
+        "다다음주" => 2,
+        "다음주" | "담주" => 1,
+        _ => 0,
+    }
+}
+
+fn korean_weekday(day: &str) -> Option<Weekday> {
+    Some(match day {
+        "월" => Weekday::Mon,
+        "화" => Weekday::Tue,
+        "수" => Weekday::Wed,
+        "목" => Weekday::Thu,
+        "금" => Weekday::Fri,
+        "토" => Weekday::Sat,
+        "일" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn english_weekday(day: &str) -> Option<Weekday> {
+    let day = day.to_lowercase();
+    Some(match day.get(..3)? {
+        "mon" => Weekday::Mon,
+        "tue" => Weekday::Tue,
+        "wed" => Weekday::Wed,
+        "thu" => Weekday::Thu,
+        "fri" => Weekday::Fri,
+        "sat" => Weekday::Sat,
+        "sun" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+fn clock(hour: u32, minute: u32, pm: bool) -> Option<NaiveTime> {
+    let hour = match (hour, pm) {
+        (12, false) | (0, _) => hour % 12,
+        (h, true) if h < 12 => h + 12,
+        (h, _) => h,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Monday.
+    fn today() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 12, 2).unwrap()
+    }
+
+    fn date(m: u32, d: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(2024, m, d)
+    }
+
+    fn check(input: &str, start: Option<NaiveDate>, end: Option<NaiveDate>, rest: &str) {
+        let found = extract(input, today(), Weekday::Mon);
+        assert_eq!((found.start_date, found.end_date), (start, end), "{input}");
+        assert_eq!(found.rest, rest, "{input}");
+    }
+
+    #[test]
+    fn relative_days() {
+        check("모레 고객 미팅", date(12, 4), date(12, 4), "고객 미팅");
+        check("3일 후 보고서 제출", date(12, 5), date(12, 5), "보고서 제출");
+        check("내일까지 초안 작성", date(12, 3), date(12, 3), "초안 작성");
+        check("call the bank in 2 weeks", date(12, 16), date(12, 16), "call the bank");
+        check("review PRs tomorrow", date(12, 3), date(12, 3), "review PRs");
+    }
+
+    #[test]
+    fn weekdays() {
+        check("다음주 금요일 회고", date(12, 13), date(12, 13), "회고");
+        check("이번주 수요일에 배포", date(12, 4), date(12, 4), "배포");
+        check("dentist next tue", date(12, 10), date(12, 10), "dentist");
+        check("submit report by friday", date(12, 6), date(12, 6), "submit report");
+        check("금요일까지 정산", date(12, 6), date(12, 6), "정산");
+    }
+
+    #[test]
+    fn month_days() {
+        check("12월 3일 은행 서류", date(12, 3), date(12, 3), "은행 서류");
+        check("세금계산서 12/3", date(12, 3), date(12, 3), "세금계산서");
+        check("expense report Dec 3", date(12, 3), date(12, 3), "expense report");
+        check("kickoff on 5th of January", NaiveDate::from_ymd_opt(2025, 1, 5), NaiveDate::from_ymd_opt(2025, 1, 5), "kickoff");
+        check("11월 1일 회의록", NaiveDate::from_ymd_opt(2025, 11, 1), NaiveDate::from_ymd_opt(2025, 11, 1), "회의록");
+        check("회의 준비 2024-12-01", date(12, 1), date(12, 1), "회의 준비");
+    }
+
+    #[test]
+    fn ranges() {
+        check("3일부터 5일까지 워크숍", date(12, 3), date(12, 5), "워크숍");
+        check("12월 30일부터 3일까지 휴가", date(12, 30), NaiveDate::from_ymd_opt(2025, 1, 3), "휴가");
+        check("offsite from Dec 3 to Dec 5", date(12, 3), date(12, 5), "offsite");
+        check("2024-12-01 ~ 2024-12-03 출장", date(12, 1), date(12, 3), "출장");
+        check("이번주 업무 정리", date(12, 2), date(12, 8), "업무 정리");
+        check("next month budget", date(12, 1).and_then(|d| d.checked_add_months(Months::new(1))), NaiveDate::from_ymd_opt(2025, 1, 31), "budget");
+    }
+
+    #[test]
+    fn time_of_day() {
+        let found = extract("모레 오후 3시 고객 미팅", today(), Weekday::Mon);
+        assert_eq!(found.start_date, date(12, 4));
+        assert_eq!(found.time, NaiveTime::from_hms_opt(15, 0, 0));
+        assert_eq!(found.rest, "고객 미팅");
+
+        let found = extract("standup at 9:30am", today(), Weekday::Mon);
+        assert_eq!(found.start_date, date(12, 2));
+        assert_eq!(found.time, NaiveTime::from_hms_opt(9, 30, 0));
+        assert_eq!(found.rest, "standup");
+
+        let found = extract("3시간 집중 작업", today(), Weekday::Mon);
+        assert_eq!(found.time, None);
+        assert_eq!(found.rest, "3시간 집중 작업");
+    }
+
+    #[test]
+    fn leaves_other_words_alone() {
+        check("추가 예산 검토", None, None, "추가 예산 검토");
+        check("monthly sync notes", None, None, "monthly sync notes");
+        check("이번주 일정 정리", date(12, 2), date(12, 8), "일정 정리");
+        check("지금일단 정리", None, None, "지금일단 정리");
+        check("금일 마감", date(12, 2), date(12, 2), "마감");
+    }
+
+    #[test]
+    fn day_counts_are_not_days_of_the_month() {
+        check("3일간 집중 작업", None, None, "3일간 집중 작업");
+        check("3일 동안 출장", None, None, "3일 동안 출장");
+        check("3일 전 회의록 정리", None, None, "3일 전 회의록 정리");
+        check("장애 대응 3일째", None, None, "장애 대응 3일째");
+    }
+
+    #[test]
+    fn weeks_follow_the_users_first_day() {
+        let sunday = |input: &str| {
+            let found = extract(input, today(), Weekday::Sun);
+            (found.start_date, found.end_date)
+        };
+        assert_eq!(sunday("이번주 업무 정리"), (date(12, 1), date(12, 7)));
+        assert_eq!(sunday("next week review"), (date(12, 8), date(12, 14)));
+        assert_eq!(sunday("다음주 일요일 회고"), (date(12, 8), date(12, 8)));
+        assert_eq!(sunday("this sat cleanup"), (date(12, 7), date(12, 7)));
+        assert_eq!(sunday("이번주 월요일 배포"), (date(12, 2), date(12, 2)));
+    }
+
+    #[test]
+    fn twelve_oclock() {
+        let time = |input: &str| extract(input, today(), Weekday::Mon).time;
+        assert_eq!(time("밤 12시 배포"), NaiveTime::from_hms_opt(0, 0, 0));
+        assert_eq!(time("새벽 12시 반 점검"), NaiveTime::from_hms_opt(0, 30, 0));
+        assert_eq!(time("낮 12시 점심"), NaiveTime::from_hms_opt(12, 0, 0));
+        assert_eq!(time("오후 12시 회의"), NaiveTime::from_hms_opt(12, 0, 0));
+        assert_eq!(time("12 am release"), NaiveTime::from_hms_opt(0, 0, 0));
+    }
+}
@@ -0,0 +1,446 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::llm::LlmProvider;
+use crate::usage::Metered;
+
+/// Target size of a note chunk, in characters.
+const CHUNK_CHARS: usize = 500;
+/// Sources embedded per pass of the background job.
+const BATCH_SIZE: i64 = 32;
+/// How often the job looks for stale sources when nobody wakes it.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Chunks scoring below this cosine similarity are not worth showing.
+const MIN_SCORE: f32 = 0.35;
+
+/// Handle to the background embedding job. Disabled unless `AI_EMBEDDINGS`
+/// is set, in which case `wake` and `retrieve` are no-ops.
+#[derive(Clone)]
+pub struct Embeddings {
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    pool: PgPool,
+    llm: Arc<dyn LlmProvider>,
+    wake: Notify,
+    top_k: usize,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RetrievedChunk {
+    pub source_type: String,
+    pub source_id: Uuid,
+    pub title: String,
+    pub content: String,
+    pub score: f32,
+}
+
+struct StaleSource {
+    source_type: &'static str,
+    id: Uuid,
+    user_id: Uuid,
+    updated_at: DateTime<Utc>,
+    chunks: Vec<String>,
+}
+
+impl Embeddings {
+    pub fn disabled() -> Self {
+        Self { inner: None }
+    }
+
+    /// Starts the job that keeps embeddings of every note and task current.
+    pub fn spawn(pool: PgPool, llm: Arc<dyn LlmProvider>, top_k: usize) -> Self {
+        let inner = Arc::new(Inner {
+            pool,
+            llm,
+            wake: Notify::new(),
+            top_k,
+        });
+        let worker = inner.clone();
+        tokio::spawn(async move {
+            loop {
+                match worker.index_stale().await {
+                    // A full batch means there is probably more to do.
+                    Ok(n) if n as i64 >= BATCH_SIZE => continue,
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!("embedding job failed: {err}"),
+                }
+                tokio::select! {
+                    _ = worker.wake.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+        });
+        Self { inner: Some(inner) }
+    }
+
+    /// Tells the job that notes or tasks changed.
+    pub fn wake(&self) {
+        if let Some(inner) = &self.inner {
+            inner.wake.notify_one();
+        }
+    }
+
+    /// The chunks of the user's notes and tasks closest to `question`.
+    pub async fn retrieve(&self, user_id: Uuid, question: &str) -> anyhow::Result<Vec<RetrievedChunk>> {
+        let Some(inner) = &self.inner else {
+            return Ok(Vec::new());
+        };
+        let query = Metered::recorded(inner.pool.clone(), inner.llm.clone(), user_id, "retrieval")
+            .embed(&[question.to_string()])
+            .await?
+            .pop()
+            .unwrap_or_default();
+
+        // Personal-scale data: scoring every chunk in memory is cheap enough.
+        let rows = sqlx::query!(
+            r#"
+            SELECT e.source_type, e.source_id, e.content, e.embedding,
+                   COALESCE(n.title, t.title) AS "title!"
+            FROM embeddings e
+            LEFT JOIN notes n ON e.source_type = 'note' AND n.id = e.source_id
+            LEFT JOIN tasks t ON e.source_type = 'task' AND t.id = e.source_id
+            WHERE e.user_id = $1 AND e.model = $2 AND COALESCE(n.id, t.id) IS NOT NULL
+            "#,
+            user_id,
+            inner.llm.embed_model()
+        )
+        .fetch_all(&inner.pool)
+        .await?;
+
+        let mut scored: Vec<RetrievedChunk> = rows
+            .into_iter()
+            .map(|row| RetrievedChunk {
+                score: cosine(&query, &row.embedding),
+                source_type: row.source_type,
+                source_id: row.source_id,
+                title: row.title,
+                content: row.content,
+            })
+            .filter(|c| c.score >= MIN_SCORE)
+            .collect();
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        scored.truncate(inner.top_k);
+        Ok(scored)
+    }
+}
+
+impl Inner {
+    /// Embeds one batch of notes and tasks that changed since they were
+    /// last embedded, and drops chunks of deleted ones. A source that fails
+    /// is recorded and backed off. Returns the number of sources tried.
+    async fn index_stale(&self) -> anyhow::Result<usize> {
+        sqlx::query!(
+            r#"
+            DELETE FROM embeddings e
+            WHERE (e.source_type = 'note' AND NOT EXISTS (SELECT 1 FROM notes WHERE id = e.source_id))
+               OR (e.source_type = 'task' AND NOT EXISTS (SELECT 1 FROM tasks WHERE id = e.source_id))
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM embedding_failures f
+            WHERE (f.source_type = 'note' AND NOT EXISTS (SELECT 1 FROM notes WHERE id = f.source_id))
+               OR (f.source_type = 'task' AND NOT EXISTS (SELECT 1 FROM tasks WHERE id = f.source_id))
+            "#
+        )
+        .execute(&self.pool)
+        .await?;
+
+        let mut stale = self.stale_notes().await?;
+        stale.extend(self.stale_tasks().await?);
+        let tried = stale.len();
+        for source in stale {
+            let (source_type, id, updated_at) = (source.source_type, source.id, source.updated_at);
+            if let Err(err) = self.embed_source(source).await {
+                tracing::warn!(source_type, source_id = %id, "embedding failed: {err}");
+                self.record_failure(source_type, id, updated_at).await?;
+            }
+        }
+        Ok(tried)
+    }
+
+    /// Counts a failed attempt at the source as it was at `updated_at`;
+    /// attempts start over once it is edited.
+    async fn record_failure(&self, source_type: &str, id: Uuid, updated_at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO embedding_failures (source_type, source_id, model, source_updated_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (source_type, source_id) DO UPDATE SET
+                attempts = CASE
+                    WHEN embedding_failures.model = EXCLUDED.model
+                     AND embedding_failures.source_updated_at = EXCLUDED.source_updated_at
+                    THEN embedding_failures.attempts + 1
+                    ELSE 1
+                END,
+                model = EXCLUDED.model,
+                source_updated_at = EXCLUDED.source_updated_at,
+                failed_at = NOW()
+            "#,
+            source_type,
+            id,
+            self.llm.embed_model(),
+            updated_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Sources that failed before come after the rest, once their backoff
+    /// is over.
+    async fn stale_notes(&self) -> Result<Vec<StaleSource>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT n.id, n.user_id, n.title, n.content, n.updated_at
+            FROM notes n
+            LEFT JOIN embedding_failures f
+                ON f.source_type = 'note' AND f.source_id = n.id
+               AND f.model = $1 AND f.source_updated_at >= n.updated_at
+            WHERE NOT EXISTS (
+                SELECT 1 FROM embeddings e
+                WHERE e.source_type = 'note' AND e.source_id = n.id
+                  AND e.model = $1 AND e.source_updated_at >= n.updated_at
+            )
+              AND (f.failed_at IS NULL
+                   OR f.failed_at + LEAST(INTERVAL '1 minute' * POWER(2, f.attempts), INTERVAL '1 day') <= NOW())
+            ORDER BY f.failed_at NULLS FIRST, n.updated_at DESC
+            LIMIT $2
+            "#,
+            self.llm.embed_model(),
+            BATCH_SIZE
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| StaleSource {
+                source_type: "note",
+                id: row.id,
+                user_id: row.user_id,
+                updated_at: row.updated_at,
+                chunks: chunk_note(&row.title, &row.content),
+            })
+            .collect())
+    }
+
+    async fn stale_tasks(&self) -> Result<Vec<StaleSource>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT t.id, t.user_id, t.title, t.description, t.status, COALESCE(t.end_date, t.due_date) AS end_date, t.tags, t.updated_at
+            FROM tasks t
+            LEFT JOIN embedding_failures f
+                ON f.source_type = 'task' AND f.source_id = t.id
+               AND f.model = $1 AND f.source_updated_at >= t.updated_at
+            WHERE NOT EXISTS (
+                SELECT 1 FROM embeddings e
+                WHERE e.source_type = 'task' AND e.source_id = t.id
+                  AND e.model = $1 AND e.source_updated_at >= t.updated_at
+            )
+              AND (f.failed_at IS NULL
+                   OR f.failed_at + LEAST(INTERVAL '1 minute' * POWER(2, f.attempts), INTERVAL '1 day') <= NOW())
+            ORDER BY f.failed_at NULLS FIRST, t.updated_at DESC
+            LIMIT $2
+            "#,
+            self.llm.embed_model(),
+            BATCH_SIZE
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| StaleSource {
+                source_type: "task",
+                id: row.id,
+                user_id: row.user_id,
+                updated_at: row.updated_at,
+                chunks: vec![task_text(
+                    &row.title,
+                    row.description.as_deref(),
+                    &row.status,
+                    row.end_date,
+                    &row.tags,
+                )],
+            })
+            .collect())
+    }
+
+    async fn embed_source(&self, source: StaleSource) -> anyhow::Result<()> {
+        let vectors = Metered::recorded(self.pool.clone(), self.llm.clone(), source.user_id, "embeddings")
+            .embed(&source.chunks)
+            .await?;
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "DELETE FROM embeddings WHERE source_type = $1 AND source_id = $2",
+            source.source_type,
+            source.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM embedding_failures WHERE source_type = $1 AND source_id = $2",
+            source.source_type,
+            source.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        for (index, (content, vector)) in source.chunks.iter().zip(vectors).enumerate() {
+            sqlx::query!(
+                r#"
+                INSERT INTO embeddings
+                    (id, user_id, source_type, source_id, chunk_index, content, model, embedding, source_updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                "#,
+                Uuid::new_v4(),
+                source.user_id,
+                source.source_type,
+                source.id,
+                index as i32,
+                content,
+                self.llm.embed_model(),
+                &vector,
+                source.updated_at
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+/// Splits a note into paragraph-aligned chunks of about `CHUNK_CHARS`,
+/// each prefixed with the title so it embeds with its context.
+fn chunk_note(title: &str, content: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for paragraph in content.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        for piece in split_chars(paragraph, CHUNK_CHARS) {
+            // Counting the paragraph break keeps joined chunks within the cap.
+            if !current.is_empty() && current.chars().count() + 2 + piece.chars().count() > CHUNK_CHARS {
+                chunks.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(&piece);
+        }
+    }
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+        .into_iter()
+        .map(|chunk| format!("{title}\n\n{chunk}").trim().to_string())
+        .collect()
+}
+
+fn split_chars(text: &str, size: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars.chunks(size).map(|c| c.iter().collect()).collect()
+}
+
+/// A task as one chunk. Labels are field names rather than words of a
+/// locale, since chunks go into prompts of every audience as they are.
+fn task_text(
+    title: &str,
+    description: Option<&str>,
+    status: &str,
+    end_date: Option<NaiveDate>,
+    tags: &[String],
+) -> String {
+    let mut text = format!("[task][{status}] {title}");
+    if let Some(end) = end_date {
+        text.push_str(&format!(" (end_date {end})"));
+    }
+    if !tags.is_empty() {
+        text.push_str(&format!(" #{}", tags.join(" #")));
+    }
+    if let Some(description) = description.filter(|d| !d.trim().is_empty()) {
+        text.push('\n');
+        text.push_str(description.trim());
+    }
+    text
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bodies(chunks: &[String]) -> Vec<&str> {
+        chunks.iter().map(|c| c.strip_prefix("제목\n\n").unwrap_or(c)).collect()
+    }
+
+    #[test]
+    fn chunks_along_paragraphs() {
+        assert_eq!(chunk_note("제목", "  \n\n "), ["제목"]);
+        assert_eq!(bodies(&chunk_note("제목", "첫 문단\n\n\n\n 둘째 문단 ")), ["첫 문단\n\n둘째 문단"]);
+
+        let first = "가".repeat(300);
+        let second = "나".repeat(300);
+        let chunks = chunk_note("제목", &format!("{first}\n\n{second}"));
+        assert_eq!(bodies(&chunks), [first.as_str(), second.as_str()]);
+
+        // Exactly at the cap only once the paragraph break is counted.
+        let second = "나".repeat(CHUNK_CHARS - 300 - 2);
+        let chunks = chunk_note("제목", &format!("{first}\n\n{second}"));
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(bodies(&chunks)[0].chars().count(), CHUNK_CHARS);
+        let chunks = chunk_note("제목", &format!("{first}\n\n{second}나"));
+        assert_eq!(chunks.len(), 2);
+    }
+
+    #[test]
+    fn splits_long_paragraphs_by_characters() {
+        let long = "가".repeat(CHUNK_CHARS * 2 + 100);
+        let chunks = chunk_note("제목", &format!("{long}\n\n끝"));
+        let sizes: Vec<_> = bodies(&chunks).iter().map(|b| b.chars().count()).collect();
+        assert_eq!(sizes, [CHUNK_CHARS, CHUNK_CHARS, 100 + 2 + 1]);
+        assert!(chunks.iter().all(|c| c.starts_with("제목\n\n")));
+    }
+
+    #[test]
+    fn task_chunks_carry_the_end_date() {
+        let tags = ["ops".to_string()];
+        let text = task_text("배포", Some(" 체크리스트 확인 "), "todo", NaiveDate::from_ymd_opt(2024, 12, 5), &tags);
+        assert_eq!(text, "[task][todo] 배포 (end_date 2024-12-05) #ops\n체크리스트 확인");
+        assert_eq!(task_text("배포", None, "done", None, &[]), "[task][done] 배포");
+    }
+
+    #[test]
+    fn cosine_handles_degenerate_vectors() {
+        assert!((cosine(&[1.0, 2.0], &[2.0, 4.0]) - 1.0).abs() < 1e-6);
+        assert!((cosine(&[1.0, 0.0], &[-3.0, 0.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(cosine(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+        assert_eq!(cosine(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+        assert_eq!(cosine(&[1.0, 1.0], &[1.0, 1.0, 1.0]), 0.0);
+        assert_eq!(cosine(&[], &[]), 0.0);
+    }
+}
@@ -0,0 +1,404 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::llm::{estimate_tokens, ChatMessage, LlmError, LlmProvider};
+use crate::models::Note;
+use crate::prompts::{Audience, Prompt, Prompts};
+use crate::usage::Metered;
+
+/// Notes shorter than this are left to on-demand enrichment.
+const BACKGROUND_MIN_CHARS: i32 = 300;
+/// Notes enriched per pass of the background job.
+const BATCH_SIZE: i64 = 8;
+/// How often the job looks for changed notes when nobody wakes it.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Most tags of the user's vocabulary shown to the model.
+const VOCABULARY_SIZE: i64 = 100;
+const MAX_SUGGESTED_TAGS: usize = 5;
+const MAX_ACTION_ITEMS: usize = 10;
+/// Room left in the context window for the instructions and the answer.
+const RESERVE_TOKENS: usize = 1024;
+
+/// A follow-up found in a note, offered as a candidate task.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ActionItem {
+    pub title: String,
+    pub due_date: Option<NaiveDate>,
+}
+
+#[derive(Debug, PartialEq)]
+struct NoteEnrichment {
+    summary: String,
+    tags: Vec<String>,
+    action_items: Vec<ActionItem>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EnrichError {
+    #[error(transparent)]
+    Llm(#[from] LlmError),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+#[derive(Deserialize)]
+struct RawEnrichment {
+    summary: Option<String>,
+    #[serde(default)]
+    tags: Vec<Value>,
+    #[serde(default)]
+    action_items: Vec<Value>,
+}
+
+/// Handle to the background enrichment job. Disabled unless
+/// `AI_ENRICH_ON_SAVE` is set, in which case `wake` is a no-op.
+#[derive(Clone)]
+pub struct Enrichment {
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    pool: PgPool,
+    llm: Arc<dyn LlmProvider>,
+    prompts: Prompts,
+    wake: Notify,
+    context_tokens: usize,
+    tz: Tz,
+}
+
+impl Enrichment {
+    pub fn disabled() -> Self {
+        Self { inner: None }
+    }
+
+    /// Starts the job that enriches long notes after they are saved.
+    pub fn spawn(pool: PgPool, llm: Arc<dyn LlmProvider>, prompts: Prompts, context_tokens: usize, tz: Tz) -> Self {
+        let inner = Arc::new(Inner {
+            pool,
+            llm,
+            prompts,
+            wake: Notify::new(),
+            context_tokens,
+            tz,
+        });
+        let worker = inner.clone();
+        tokio::spawn(async move {
+            loop {
+                match worker.enrich_stale().await {
+                    // A full batch means there is probably more to do.
+                    Ok(n) if n as i64 >= BATCH_SIZE => continue,
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!("enrichment job failed: {err}"),
+                }
+                tokio::select! {
+                    _ = worker.wake.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+        });
+        Self { inner: Some(inner) }
+    }
+
+    /// Tells the job that notes were saved.
+    pub fn wake(&self) {
+        if let Some(inner) = &self.inner {
+            inner.wake.notify_one();
+        }
+    }
+}
+
+impl Inner {
+    /// Enriches one batch of long notes changed since their last
+    /// enrichment. Returns the number of notes handled.
+    async fn enrich_stale(&self) -> Result<usize, EnrichError> {
+        let stale = sqlx::query!(
+            r#"
+            SELECT id, user_id FROM notes
+            WHERE (ai_enriched_at IS NULL OR ai_enriched_at < updated_at)
+              AND char_length(content) >= $1
+            ORDER BY updated_at DESC
+            LIMIT $2
+            "#,
+            BACKGROUND_MIN_CHARS,
+            BATCH_SIZE
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        for row in &stale {
+            let llm = Metered::recorded(self.pool.clone(), self.llm.clone(), row.user_id, "enrichment");
+            let audience = self.prompts.audience(&self.pool, row.user_id, self.tz).await;
+            let result =
+                enrich_note(&self.pool, &llm, &self.prompts, &audience, self.context_tokens, row.user_id, row.id).await;
+            match result {
+                Ok(_) => {}
+                // Retrying the same text would most likely fail the same
+                // way, so the note waits for its next edit.
+                Err(EnrichError::Llm(LlmError::Malformed(msg))) => {
+                    tracing::warn!(note_id = %row.id, "note enrichment unparseable: {msg}");
+                    sqlx::query!(
+                        r#"
+                        UPDATE notes
+                        SET ai_summary = NULL, ai_tags = '{}', ai_action_items = '[]', ai_enriched_at = updated_at
+                        WHERE id = $1
+                        "#,
+                        row.id
+                    )
+                    .execute(&self.pool)
+                    .await?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(stale.len())
+    }
+}
+
+/// Asks the model for a summary, tag suggestions and action items of the
+/// note and stores them next to it. The note's own tags are not touched.
+/// Results made from a version the user has since edited are dropped.
+/// Returns `None` when the note does not exist.
+pub async fn enrich_note(
+    pool: &PgPool,
+    llm: &dyn LlmProvider,
+    prompts: &Prompts,
+    audience: &Audience,
+    context_tokens: usize,
+    user_id: Uuid,
+    note_id: Uuid,
+) -> Result<Option<Note>, EnrichError> {
+    let note = sqlx::query_as!(
+        Note,
+        "SELECT * FROM notes WHERE id = $1 AND user_id = $2",
+        note_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    let Some(note) = note else {
+        return Ok(None);
+    };
+    let vocabulary = tag_vocabulary(pool, user_id).await?;
+
+    let messages = enrichment_messages(prompts, audience, &note, &vocabulary, context_tokens);
+    let completion = llm.chat_json(&messages).await?;
+    let Some(mut enrichment) = parse(&completion.content) else {
+        return Err(LlmError::Malformed(completion.content).into());
+    };
+    enrichment.tags = pick_tags(&enrichment.tags, &vocabulary, &note.tags);
+
+    let action_items = serde_json::to_value(&enrichment.action_items).unwrap_or_default();
+    let updated = sqlx::query_as!(
+        Note,
+        r#"
+        UPDATE notes
+        SET ai_summary = $1, ai_tags = $2, ai_action_items = $3, ai_enriched_at = updated_at
+        WHERE id = $4 AND user_id = $5 AND updated_at = $6
+        RETURNING *
+        "#,
+        enrichment.summary,
+        &enrichment.tags,
+        action_items,
+        note.id,
+        user_id,
+        note.updated_at
+    )
+    .fetch_optional(pool)
+    .await?;
+    match updated {
+        Some(note) => Ok(Some(note)),
+        None => {
+            sqlx::query_as!(
+                Note,
+                "SELECT * FROM notes WHERE id = $1 AND user_id = $2",
+                note_id,
+                user_id
+            )
+            .fetch_optional(pool)
+            .await
+            .map_err(Into::into)
+        }
+    }
+}
+
+/// The user's tags on notes and tasks, most used first.
+async fn tag_vocabulary(pool: &PgPool, user_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT tag AS "tag!" FROM (
+            SELECT unnest(tags) AS tag FROM notes WHERE user_id = $1
+            UNION ALL
+            SELECT unnest(tags) AS tag FROM tasks WHERE user_id = $1
+        ) t
+        GROUP BY tag
+        ORDER BY COUNT(*) DESC, tag
+        LIMIT $2
+        "#,
+        user_id,
+        VOCABULARY_SIZE
+    )
+    .fetch_all(pool)
+    .await
+}
+
+fn enrichment_messages(
+    prompts: &Prompts,
+    audience: &Audience,
+    note: &Note,
+    vocabulary: &[String],
+    context_tokens: usize,
+) -> Vec<ChatMessage> {
+    let system = prompts.render(
+        audience,
+        Prompt::EnrichSystem,
+        minijinja::context! { max_tags => MAX_SUGGESTED_TAGS, tags => vocabulary.join(", ") },
+    );
+    let request = |content: &str| {
+        prompts.render(
+            audience,
+            Prompt::EnrichRequest,
+            minijinja::context! { title => note.title, content },
+        )
+    };
+    let budget = context_tokens
+        .saturating_sub(RESERVE_TOKENS + estimate_tokens(&system) + estimate_tokens(&request("")));
+    let user = request(clip(&note.content, budget));
+    vec![ChatMessage::system(system), ChatMessage::user(user)]
+}
+
+/// Longest prefix of `text` within `max_tokens` estimated tokens.
+fn clip(text: &str, max_tokens: usize) -> &str {
+    let (mut ascii, mut other) = (0usize, 0usize);
+    for (i, c) in text.char_indices() {
+        if c.is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+        if ascii.div_ceil(4) + other > max_tokens {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// Reads the model's JSON, tolerating prose or code fences around it and
+/// action items given as plain strings.
+fn parse(content: &str) -> Option<NoteEnrichment> {
+    let start = content.find('{')?;
+    let end = content.rfind('}')?;
+    let raw: RawEnrichment = serde_json::from_str(content.get(start..=end)?).ok()?;
+    let summary = raw.summary?.trim().to_string();
+    if summary.is_empty() {
+        return None;
+    }
+    let tags = raw
+        .tags
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|t| t.trim().trim_start_matches('#').trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    let action_items = raw
+        .action_items
+        .into_iter()
+        .filter_map(|item| {
+            let (title, due_date) = match item {
+                Value::String(title) => (title, None),
+                Value::Object(mut fields) => match fields.remove("title") {
+                    Some(Value::String(title)) => (
+                        title,
+                        fields
+                            .get("due_date")
+                            .and_then(Value::as_str)
+                            .and_then(|d| NaiveDate::parse_from_str(d.trim(), "%Y-%m-%d").ok()),
+                    ),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let title = title.trim().to_string();
+            (!title.is_empty()).then_some(ActionItem { title, due_date })
+        })
+        .take(MAX_ACTION_ITEMS)
+        .collect();
+    Some(NoteEnrichment {
+        summary,
+        tags,
+        action_items,
+    })
+}
+
+/// Keeps suggestions that name a tag of the vocabulary, spelled as there,
+/// and that the note does not carry yet.
+fn pick_tags(suggested: &[String], vocabulary: &[String], existing: &[String]) -> Vec<String> {
+    let mut picked: Vec<String> = Vec::new();
+    for tag in suggested {
+        let tag = tag.to_lowercase();
+        let Some(known) = vocabulary.iter().find(|v| v.to_lowercase() == tag) else {
+            continue;
+        };
+        if existing.iter().chain(&picked).any(|t| t.to_lowercase() == tag) {
+            continue;
+        }
+        picked.push(known.clone());
+        if picked.len() == MAX_SUGGESTED_TAGS {
+            break;
+        }
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fenced_enrichment() {
+        let content = "결과입니다.\n```json\n{\"summary\": \" 배포 일정 논의 \", \"tags\": [\"#infra\", \"회의\", 3], \
+            \"action_items\": [\"릴리스 노트 작성\", {\"title\": \"롤백 절차 점검\", \"due_date\": \"2024-12-05\"}, {\"due_date\": \"2024-12-06\"}]}\n```";
+        let enrichment = parse(content).unwrap();
+        assert_eq!(enrichment.summary, "배포 일정 논의");
+        assert_eq!(enrichment.tags, ["infra", "회의"]);
+        assert_eq!(
+            enrichment.action_items,
+            [
+                ActionItem {
+                    title: "릴리스 노트 작성".to_string(),
+                    due_date: None,
+                },
+                ActionItem {
+                    title: "롤백 절차 점검".to_string(),
+                    due_date: NaiveDate::from_ymd_opt(2024, 12, 5),
+                },
+            ]
+        );
+        assert_eq!(parse(r#"{"summary": "", "tags": []}"#), None);
+        assert_eq!(parse("요약할 수 없습니다."), None);
+    }
+
+    #[test]
+    fn suggests_only_known_tags_the_note_lacks() {
+        let vocabulary = ["Infra", "회의", "release"].map(String::from);
+        let existing = ["회의".to_string()];
+        let suggested = ["infra", "회의", "new-tag", "INFRA", "release"].map(String::from);
+        assert_eq!(pick_tags(&suggested, &vocabulary, &existing), ["Infra", "release"]);
+        assert!(pick_tags(&suggested, &[], &existing).is_empty());
+    }
+
+    #[test]
+    fn clips_to_token_budget() {
+        assert_eq!(clip("abcdefgh", 2), "abcdefgh");
+        assert_eq!(clip("abcdefghi", 2), "abcdefgh");
+        assert_eq!(clip("가나다라", 3), "가나다");
+        assert_eq!(clip("가나", 0), "");
+    }
+}
@@ -1,33 +1,94 @@
-﻿use axum::{extract::State, response::IntoResponse, Json};
-use chrono::{Datelike, NaiveDate, Utc};
+﻿use std::convert::Infallible;
+use std::sync::{Arc, LazyLock};
+
+use axum::{
+    extract::State,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Json,
+};
+use chrono::NaiveDate;
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
+use regex::Regex;
 use serde_json::json;
 use uuid::Uuid;
 
+use crate::context::{self, Omitted, PromptContext};
+use crate::conversations;
+use crate::dates;
+use crate::embeddings::RetrievedChunk;
+use crate::handlers::tasks::{self, NewTask};
+use crate::intent::{self, Classification, Intent, Slots};
+use crate::llm::{estimate_tokens, ChatMessage, LlmError, LlmProvider, StreamChunk, ToolSpec};
 use crate::middleware::{AppState, AuthUser};
 use crate::models::{Note, Task};
-
-const MAX_CONTEXT_CHARS: usize = 4000;
+use crate::prompts::{Audience, Prompt};
+use crate::settings;
+use crate::tools::{self, ActionLog, PendingAction, ToolAction};
+use crate::usage::{self, Metered};
+
+/// Room left in the context window for the model's answer.
+const REPLY_RESERVE_TOKENS: usize = 512;
+const MAX_TOOL_ROUNDS: usize = 5;
+static COMMAND_PREFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^\s*(?:(?:업무|일정|할\s*일)\s*(?:등록|추가)\s*[:：]?|(?:please\s+)?(?:add|create)\s+(?:a\s+)?(?:new\s+)?task\b(?:\s+to\b)?\s*[:：]?|new\s+task\b\s*[:：]?|task\s*[:：]|remind\s+me\s+to\b)\s*",
+    )
+    .expect("valid command pattern")
+});
+static COMMAND_SUFFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\s*(?:(?:을|를)\s*)?(?:(?:업무|일정|할\s*일)\s*(?:으로|로)?\s*)?(?:(?:등록|추가|생성)(?:\s*해)?|만들어|잡아|넣어)\s*(?:줘요|줘|주세요|줄래)?\s*[.!?]?\s*$",
+    )
+    .expect("valid command pattern")
+});
 
 #[derive(Deserialize)]
 pub struct ChatRequest {
     pub message: String,
+    /// Continue this conversation; a new one is started when absent.
+    pub conversation_id: Option<Uuid>,
 }
 
 #[derive(Serialize)]
 pub struct ChatResponse {
     pub reply: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<Uuid>,
+    /// What the tool-calling loop ran, in order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<ActionLog>,
+    /// Changes waiting for `POST /api/v1/ai/actions`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pending_actions: Vec<PendingAction>,
+    /// Context items left out to fit the model's context window.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub context_omitted: Vec<Omitted>,
 }
 
-#[derive(Deserialize)]
-struct OllamaChatMessage {
-    content: String,
+impl ChatResponse {
+    fn text(reply: impl Into<String>) -> Self {
+        Self {
+            reply: reply.into(),
+            conversation_id: None,
+            actions: Vec::new(),
+            pending_actions: Vec::new(),
+            context_omitted: Vec::new(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
-struct OllamaChatResponse {
-    message: Option<OllamaChatMessage>,
-    response: Option<String>, // fallback shape for /api/generate
+pub struct ConfirmActionsRequest {
+    pub actions: Vec<ToolAction>,
+}
+
+#[derive(Serialize)]
+pub struct ConfirmActionsResponse {
+    pub actions: Vec<ActionLog>,
 }
 
 pub async fn chat(
@@ -35,304 +96,584 @@ pub async fn chat(
     AuthUser { user_id }: AuthUser,
     Json(payload): Json<ChatRequest>,
 ) -> impl IntoResponse {
-    if is_task_create_request(&payload.message) {
-        return handle_task_create(&state, user_id, &payload.message).await;
-    }
-
-    if !is_brief_request(&payload.message) {
-        return Json(ChatResponse {
-            reply: "저는 업무 등록과 브리핑만 도와드려요. 예) \"업무 등록: 회의 준비 2024-12-01\" 또는 \"오늘 브리핑\"".to_string(),
-        })
-        .into_response();
-    }
-
-    let tasks = fetch_tasks(&state, user_id).await.unwrap_or_default();
-    let notes = fetch_notes(&state, user_id).await.unwrap_or_default();
-
-    let context = truncate_context(&build_context(&tasks, &notes));
-
-    let client = reqwest::Client::new();
-    let url = format!("{}/api/chat", state.ai_base_url.trim_end_matches('/'));
-
-    let body = json!({
-        "model": state.ai_model,
-        "messages": [
-            {
-                "role": "system",
-                "content": format!(
-                    "당신은 데일리 업무 비서입니다. 브리핑/요약만 처리합니다. \
-간결한 불릿 포인트로 답하세요. 마감일이 없으면 \"마감 없음\"이라고 표기하세요. \
-제공된 컨텍스트만 사용하세요.\n컨텍스트: {}",
-                    context
-                )
-            },
-            { "role": "user", "content": payload.message }
-        ],
-        "stream": false
-    });
+    let audience = state.prompts.audience(&state.pool, user_id, state.default_tz).await;
+    if let Err(err) = usage::check(&state, user_id).await {
+        return err.respond(&state.prompts, &audience);
+    }
+    let history = match load_history(&state, &audience, user_id, payload.conversation_id).await {
+        Ok(history) => history,
+        Err(resp) => return resp,
+    };
+
+    let llm = Metered::limited(&state, user_id, "chat");
+    let mut response = match respond(&state, &llm, &audience, user_id, &payload.message, &history).await {
+        Ok(response) => response,
+        Err(resp) => return resp,
+    };
+    response.conversation_id =
+        record_turn(&state, &audience, user_id, payload.conversation_id, &payload.message, &response.reply).await;
+    Json(response).into_response()
+}
 
-    let resp = client.post(url).json(&body).send().await;
+/// `llm` stands for this one request; every model call goes through it.
+async fn respond(
+    state: &AppState,
+    llm: &Metered,
+    audience: &Audience,
+    user_id: Uuid,
+    message: &str,
+    history: &[ChatMessage],
+) -> Result<ChatResponse, axum::response::Response> {
+    let related = retrieve_related(state, user_id, message).await;
+    let tools_error = match run_tools(state, llm, audience, user_id, message, history, &related).await {
+        Ok(Some(response)) => return Ok(response),
+        Ok(None) => None,
+        Err(err) => Some(err),
+    };
+
+    match route(state, llm, audience, user_id, message, history, &related, tools_error, None).await {
+        Ok(Routed::Reply(reply)) => return Ok(ChatResponse::text(reply)),
+        Ok(Routed::Failed(err)) => return Err(err.respond(&state.prompts, audience)),
+        Ok(Routed::Model) => {}
+        Err(_) => return Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response()),
+    }
 
-    match resp {
-        Ok(res) if res.status().is_success() => {
-            let parsed = res.json::<OllamaChatResponse>().await.ok();
-            let reply = parsed
-                .and_then(|p| p.message.map(|m| m.content).or(p.response))
-                .unwrap_or_else(|| "모델 응답이 비어 있습니다.".to_string());
-            Json(ChatResponse { reply }).into_response()
-        }
-        Ok(res) => {
-            let status = res.status();
-            let text = res.text().await.unwrap_or_default();
-            (
-                axum::http::StatusCode::BAD_GATEWAY,
-                format!("모델 호출 실패 ({status}): {text}"),
-            )
-                .into_response()
+    let (messages, omitted) = brief_messages(state, audience, user_id, message, history, &related).await;
+
+    match llm.chat(&messages).await {
+        Ok(completion) => {
+            tracing::debug!(
+                model = state.llm.model(),
+                prompt_tokens = completion.prompt_tokens,
+                completion_tokens = completion.completion_tokens,
+                "ai chat completed"
+            );
+            let reply = if completion.content.is_empty() {
+                state.prompts.render(audience, Prompt::ReplyEmpty, minijinja::Value::UNDEFINED)
+            } else {
+                completion.content
+            };
+            let mut response = ChatResponse::text(reply);
+            response.context_omitted = omitted;
+            Ok(response)
         }
-        Err(err) => (
-            axum::http::StatusCode::BAD_GATEWAY,
-            format!("모델 서버 오류: {err} (ollama 실행 여부 확인)"),
-        )
-            .into_response(),
+        Err(err) => Err(err.respond(&state.prompts, audience)),
     }
 }
 
-fn is_task_create_request(message: &str) -> bool {
-    let msg = message.to_lowercase();
-    let has_create_verb = msg.contains("등록")
-        || msg.contains("추가")
-        || msg.contains("생성")
-        || msg.contains("만들");
-    let has_task_noun = msg.contains("업무") || msg.contains("일정") || msg.contains("task");
-    let mentions_note = msg.contains("노트") || msg.contains("메모");
-
-    if mentions_note && !has_task_noun {
-        return false;
-    }
-
-    has_create_verb
-        || (has_task_noun
-            && (msg.contains("업무등록")
-                || msg.contains("업무 등록")
-                || msg.contains("일정등록")
-                || msg.contains("일정 등록")
-                || msg.contains("add task")
-                || msg.contains("create task")
-                || msg.starts_with("task:")))
-}
+/// Earlier turns to replay for `conversation_id`, or 404 if it is not the
+/// user's.
+async fn load_history(
+    state: &AppState,
+    audience: &Audience,
+    user_id: Uuid,
+    conversation_id: Option<Uuid>,
+) -> Result<Vec<ChatMessage>, axum::response::Response> {
+    let Some(conversation_id) = conversation_id else {
+        return Ok(Vec::new());
+    };
+    let history = async {
+        let mut conn = state.pool.acquire().await?;
+        conversations::history(&mut conn, &state.prompts, audience, user_id, conversation_id, state.ai_history_tokens).await
+    }
+    .await;
 
-fn is_brief_request(message: &str) -> bool {
-    let msg = message.to_lowercase();
-    msg.contains("브리핑")
-        || msg.contains("요약")
-        || msg.contains("정리")
-        || msg.contains("우선순위")
-        || msg.contains("brief")
-        || msg.contains("summary")
-        || msg.contains("priorities")
+    match history {
+        Ok(Some(history)) => Ok(history),
+        Ok(None) => Err((axum::http::StatusCode::NOT_FOUND, "conversation not found").into_response()),
+        Err(_) => Err((axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response()),
+    }
 }
 
-async fn handle_task_create(
+/// Stores the turn and kicks off summarization of long threads, and lets
+/// the embedding job pick up anything the turn created or changed. The
+/// reply has already been produced, so a failure here is only logged.
+async fn record_turn(
     state: &AppState,
+    audience: &Audience,
     user_id: Uuid,
+    conversation_id: Option<Uuid>,
     message: &str,
-) -> axum::response::Response {
-    let (title, start_date, end_date) = extract_title_and_range(message);
-    let due_date = end_date.or(start_date);
-    if title.is_empty() {
-        return Json(ChatResponse {
-            reply: "업무 제목을 알려주세요. 예) \"업무 등록: 회의 준비 2024-12-01\"".to_string(),
-        })
-        .into_response();
+    reply: &str,
+) -> Option<Uuid> {
+    state.embeddings.wake();
+    let stored = async {
+        let mut conn = state.pool.acquire().await?;
+        conversations::append_turn(&mut conn, &state.prompts, audience, user_id, conversation_id, message, reply).await
     }
-
-    let empty_tags: Vec<String> = Vec::new();
-    let row = sqlx::query_as!(
-        Task,
-        r#"
-        INSERT INTO tasks (id, user_id, title, description, status, priority, due_date, start_date, end_date, tags)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-        RETURNING id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, created_at, updated_at
-        "#,
-        Uuid::new_v4(),
-        user_id,
-        title,
-        Option::<String>::None,
-        "todo",
-        "medium",
-        due_date,
-        start_date,
-        end_date,
-        &empty_tags
-    )
-    .fetch_one(&state.pool)
     .await;
 
-    match row {
-        Ok(task) => Json(ChatResponse {
-            reply: format!(
-                "업무가 등록됐어요: {}{}",
-                task.title,
-                format_date_range(task.start_date, task.end_date)
-            ),
-        })
-        .into_response(),
-        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db 오류").into_response(),
+    match stored {
+        Ok(id) => {
+            let llm = Arc::new(Metered::recorded(state.pool.clone(), state.llm.clone(), user_id, "summary"));
+            let (pool, prompts, audience) = (state.pool.clone(), state.prompts.clone(), audience.clone());
+            tokio::spawn(async move {
+                if let Err(err) = conversations::summarize_if_long(pool, llm, &prompts, &audience, id).await {
+                    tracing::warn!(conversation_id = %id, "conversation summary failed: {err}");
+                }
+            });
+            Some(id)
+        }
+        Err(err) => {
+            tracing::warn!("storing conversation turn failed: {err}");
+            conversation_id
+        }
     }
 }
 
-fn extract_title_and_range(message: &str) -> (String, Option<NaiveDate>, Option<NaiveDate>) {
-    let (keyword_start, keyword_end) = infer_range_from_keywords(message);
-    let mut start_date = keyword_start;
-    let mut end_date = keyword_end;
-    let mut filtered: Vec<String> = Vec::new();
-
-    if message.contains('~') && start_date.is_none() && end_date.is_none() {
-        let parts: Vec<&str> = message.split('~').collect();
-        if parts.len() >= 2 {
-            start_date = extract_first_date(parts[0]);
-            end_date = extract_first_date(parts[1]);
-        }
+/// Runs changes the user confirmed from `pending_actions`. Arguments are
+/// validated again, exactly as for the matching task and note endpoints.
+pub async fn confirm_actions(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<ConfirmActionsRequest>,
+) -> impl IntoResponse {
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    let mut actions = Vec::with_capacity(payload.actions.len());
+    for action in &payload.actions {
+        actions.push(tools::execute(&state.pool, user_id, &prefs, action).await);
     }
+    state.embeddings.wake();
+    Json(ConfirmActionsResponse { actions })
+}
+
+/// Lets a function-calling model work through the request with the typed
+/// tools. Returns `Ok(None)` when the model cannot call tools, so the caller
+/// falls back to the keyword handling.
+#[allow(clippy::too_many_arguments)]
+async fn run_tools(
+    state: &AppState,
+    llm: &Metered,
+    audience: &Audience,
+    user_id: Uuid,
+    message: &str,
+    history: &[ChatMessage],
+    related: &[RetrievedChunk],
+) -> Result<Option<ChatResponse>, LlmError> {
+    let specs = tools::specs();
+    let (mut messages, omitted) = tool_messages(state, audience, user_id, message, history, related, &specs).await;
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    let mut response = ChatResponse::text("");
+    response.context_omitted = omitted;
+
+    for round in 0..MAX_TOOL_ROUNDS {
+        let completion = match llm.chat_with_tools(&messages, &specs).await {
+            Ok(completion) => completion,
+            Err(LlmError::ToolsUnsupported) if round == 0 => return Ok(None),
+            Err(err) if round == 0 => return Err(err),
+            Err(err) => {
+                response.reply = err.message(&state.prompts, audience);
+                return Ok(Some(response));
+            }
+        };
+        if completion.tool_calls.is_empty() {
+            response.reply = if completion.content.is_empty() {
+                state.prompts.render(audience, Prompt::ReplyEmpty, minijinja::Value::UNDEFINED)
+            } else {
+                completion.content
+            };
+            return Ok(Some(response));
+        }
 
-    for token in message.split_whitespace() {
-        let token_trim = token.trim();
-        if token_trim.contains('~') {
-            continue;
+        messages.push(ChatMessage::assistant_tool_calls(
+            completion.content,
+            completion.tool_calls.clone(),
+        ));
+        for call in &completion.tool_calls {
+            let (log, pending) = tools::handle_call(&state.pool, &state.prompts, audience, user_id, &prefs, call).await;
+            messages.push(ChatMessage::tool_result(call, log.feedback(&state.prompts, audience)));
+            response.actions.push(log);
+            response.pending_actions.extend(pending);
         }
+    }
 
-        if start_date.is_none() {
-            if let Ok(date) = NaiveDate::parse_from_str(token_trim, "%Y-%m-%d") {
-                start_date = Some(date);
-                continue;
+    response.reply = state.prompts.render(audience, Prompt::ReplyTooManySteps, minijinja::Value::UNDEFINED);
+    Ok(Some(response))
+}
+
+/// Same as `chat`, but relays the model output as Server-Sent Events:
+/// `token` events carry `{"content"}` deltas, `action` events report tool
+/// calls, `done` carries the full `ChatResponse`, and failures arrive as an
+/// `error` event with a `code`.
+/// The intent is classified first: briefings and questions skip the tool
+/// loop, whose answer would arrive in one piece, and are streamed.
+/// Dropping the response (client disconnect) drops the upstream request.
+pub async fn chat_stream(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<ChatRequest>,
+) -> impl IntoResponse {
+    let audience = state.prompts.audience(&state.pool, user_id, state.default_tz).await;
+    // Checked up front so an exhausted quota is a plain 429, not an SSE
+    // error event.
+    if let Err(err) = usage::check(&state, user_id).await {
+        return err.respond(&state.prompts, &audience);
+    }
+    let history = match load_history(&state, &audience, user_id, payload.conversation_id).await {
+        Ok(history) => history,
+        Err(resp) => return resp,
+    };
+
+    let stream = async_stream::stream! {
+        let message = payload.message.as_str();
+        let llm = Metered::limited(&state, user_id, "chat");
+        let related = retrieve_related(&state, user_id, message).await;
+        let classification = intent::classify(&llm, &state.prompts, &audience, message, &history).await;
+        let mut response = None;
+        let mut tools_error = None;
+        if !matches!(classification.intent, Intent::Briefing | Intent::Question) {
+            match run_tools(&state, &llm, &audience, user_id, message, &history, &related).await {
+                Ok(Some(handled)) => {
+                    for action in &handled.actions {
+                        yield action_event(action);
+                    }
+                    yield token_event(&handled.reply);
+                    response = Some(handled);
+                }
+                Ok(None) => {}
+                Err(err) => tools_error = Some(err),
             }
-        } else if end_date.is_none() {
-            if let Ok(date) = NaiveDate::parse_from_str(token_trim, "%Y-%m-%d") {
-                end_date = Some(date);
-                continue;
+        }
+
+        if response.is_none() {
+            match route(&state, &llm, &audience, user_id, message, &history, &related, tools_error, Some(classification)).await {
+                Ok(Routed::Reply(reply)) => {
+                    yield token_event(&reply);
+                    response = Some(ChatResponse::text(reply));
+                }
+                Ok(Routed::Failed(err)) => {
+                    yield error_event(err.code(), &err.message(&state.prompts, &audience));
+                    return;
+                }
+                Ok(Routed::Model) => {}
+                Err(_) => {
+                    let message = state.prompts.render(&audience, Prompt::ErrorMessage, minijinja::context! { code => "db_error" });
+                    yield error_event("db_error", &message);
+                    return;
+                }
             }
         }
-        filtered.push(token.to_string());
-    }
-
-    if start_date.is_some() && end_date.is_none() {
-        end_date = start_date;
-    }
-    if end_date.is_some() && start_date.is_none() {
-        start_date = end_date;
-    }
-
-    let cleaned = filtered
-        .join(" ")
-        .replace("업무등록", "")
-        .replace("업무 등록", "")
-        .replace("일정등록", "")
-        .replace("일정 등록", "")
-        .replace("등록해줘요", "")
-        .replace("등록해줘", "")
-        .replace("추가해줘요", "")
-        .replace("추가해줘", "")
-        .replace("추가", "")
-        .replace("해줘요", "")
-        .replace("해줘", "")
-        .replace("등록", "")
-        .replace("task:", "")
-        .replace("add task", "")
-        .replace("create task", "")
-        .replace("오늘", "")
-        .replace("내일", "")
-        .replace("이번주", "")
-        .replace("다음주", "")
-        .replace("이번달", "")
-        .replace("다음달", "")
-        .trim()
-        .to_string();
-
-    (cleaned, start_date, end_date)
+
+        let mut response = match response {
+            Some(response) => response,
+            None => {
+                let (messages, omitted) = brief_messages(&state, &audience, user_id, message, &history, &related).await;
+                let mut upstream = match llm.chat_stream(&messages).await {
+                    Ok(upstream) => upstream,
+                    Err(err) => {
+                        yield error_event(err.code(), &err.message(&state.prompts, &audience));
+                        return;
+                    }
+                };
+
+                let mut reply = String::new();
+                while let Some(chunk) = upstream.next().await {
+                    match chunk {
+                        Ok(StreamChunk::Delta(content)) => {
+                            reply.push_str(&content);
+                            yield token_event(&content);
+                        }
+                        Ok(StreamChunk::Usage { .. }) => {}
+                        Err(err) => {
+                            yield error_event(err.code(), &err.message(&state.prompts, &audience));
+                            return;
+                        }
+                    }
+                }
+                if reply.is_empty() {
+                    reply = state.prompts.render(&audience, Prompt::ReplyEmpty, minijinja::Value::UNDEFINED);
+                }
+                let mut response = ChatResponse::text(reply);
+                response.context_omitted = omitted;
+                response
+            }
+        };
+
+        response.conversation_id =
+            record_turn(&state, &audience, user_id, payload.conversation_id, message, &response.reply).await;
+        yield done_event(&response);
+    };
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+fn token_event(content: &str) -> Result<Event, Infallible> {
+    Ok(Event::default()
+        .event("token")
+        .data(json!({ "content": content }).to_string()))
+}
+
+fn action_event(action: &ActionLog) -> Result<Event, Infallible> {
+    Ok(Event::default()
+        .event("action")
+        .data(serde_json::to_string(action).unwrap_or_default()))
+}
+
+fn done_event(response: &ChatResponse) -> Result<Event, Infallible> {
+    Ok(Event::default()
+        .event("done")
+        .data(serde_json::to_string(response).unwrap_or_default()))
+}
+
+fn error_event(code: &str, message: &str) -> Result<Event, Infallible> {
+    Ok(Event::default()
+        .event("error")
+        .data(json!({ "code": code, "message": message }).to_string()))
+}
+
+/// What to do with a message the tool loop did not handle.
+enum Routed {
+    Reply(String),
+    /// Answer with the model, from the prompt context.
+    Model,
+    Failed(LlmError),
 }
 
-fn extract_first_date(input: &str) -> Option<NaiveDate> {
-    for token in input.split_whitespace() {
-        if let Ok(date) = NaiveDate::parse_from_str(token.trim(), "%Y-%m-%d") {
-            return Some(date);
+/// Classifies the message, unless `classification` already did, and acts on
+/// its intent. `tools_error` is the failure of the tool loop, if any: the
+/// model is then presumed down and only the keyword heuristics and keyword
+/// task creation are left.
+#[allow(clippy::too_many_arguments)]
+async fn route(
+    state: &AppState,
+    llm: &Metered,
+    audience: &Audience,
+    user_id: Uuid,
+    message: &str,
+    history: &[ChatMessage],
+    related: &[RetrievedChunk],
+    tools_error: Option<LlmError>,
+    classification: Option<Classification>,
+) -> Result<Routed, sqlx::Error> {
+    let classification = match (classification, &tools_error) {
+        (Some(classification), _) => classification,
+        (None, Some(_)) => intent::heuristic(message),
+        (None, None) => intent::classify(llm, &state.prompts, audience, message, history).await,
+    };
+    tracing::debug!(
+        intent = ?classification.intent,
+        confidence = classification.confidence,
+        source = ?classification.source,
+        "ai intent"
+    );
+    if classification.needs_clarification() {
+        return Ok(Routed::Reply(classification.clarification(&state.prompts, audience)));
+    }
+
+    match classification.intent {
+        Intent::CreateTask => create_task_from_message(state, audience, user_id, message, &classification.slots)
+            .await
+            .map(Routed::Reply),
+        // Follow-ups inside a conversation, and questions the notes can
+        // answer, still go to the model.
+        Intent::Other if history.is_empty() && related.is_empty() => {
+            Ok(Routed::Reply(state.prompts.render(
+                audience,
+                Prompt::ReplyUnsupported,
+                minijinja::Value::UNDEFINED,
+            )))
         }
+        _ => Ok(match tools_error {
+            Some(err) => Routed::Failed(err),
+            None => Routed::Model,
+        }),
     }
-    None
 }
 
-fn infer_range_from_keywords(message: &str) -> (Option<NaiveDate>, Option<NaiveDate>) {
-    let today = Utc::now().date_naive();
-    if message.contains("오늘") {
-        return (Some(today), Some(today));
-    }
-    if message.contains("내일") {
-        let next = today.succ_opt().unwrap_or(today);
-        return (Some(next), Some(next));
-    }
-    if message.contains("이번주") {
-        let weekday = today.weekday().num_days_from_monday() as i64;
-        let start = today - chrono::Duration::days(weekday);
-        let end = start + chrono::Duration::days(6);
-        return (Some(start), Some(end));
-    }
-    if message.contains("다음주") {
-        let weekday = today.weekday().num_days_from_monday() as i64;
-        let start = today - chrono::Duration::days(weekday) + chrono::Duration::days(7);
-        let end = start + chrono::Duration::days(6);
-        return (Some(start), Some(end));
-    }
-    if message.contains("이번달") {
-        let start = NaiveDate::from_ymd_opt(today.year(), today.month(), 1);
-        if let Some(start_date) = start {
-            let (next_year, next_month) = if today.month() == 12 {
-                (today.year() + 1, 1)
-            } else {
-                (today.year(), today.month() + 1)
-            };
-            let next_month_start = NaiveDate::from_ymd_opt(next_year, next_month, 1)
-                .unwrap_or(start_date);
-            let end = next_month_start - chrono::Duration::days(1);
-            return (Some(start_date), Some(end));
+/// Note and task chunks relevant to the question. Retrieval is best
+/// effort: when the embedding model is down the prompt just goes without.
+async fn retrieve_related(state: &AppState, user_id: Uuid, message: &str) -> Vec<RetrievedChunk> {
+    match state.embeddings.retrieve(user_id, message).await {
+        Ok(related) => related,
+        Err(err) => {
+            tracing::warn!("retrieval failed: {err}");
+            Vec::new()
         }
     }
-    if message.contains("다음달") {
-        let (year, month) = if today.month() == 12 {
-            (today.year() + 1, 1)
-        } else {
-            (today.year(), today.month() + 1)
-        };
-        if let Some(start_date) = NaiveDate::from_ymd_opt(year, month, 1) {
-            let (next_year, next_month) = if month == 12 {
-                (year + 1, 1)
-            } else {
-                (year, month + 1)
-            };
-            let next_month_start = NaiveDate::from_ymd_opt(next_year, next_month, 1)
-                .unwrap_or(start_date);
-            let end = next_month_start - chrono::Duration::days(1);
-            return (Some(start_date), Some(end));
-        }
+}
+
+/// Tokens left for the context once room for the reply, the instructions,
+/// earlier turns, the question and `extra` (tool schemas) are set aside.
+fn context_budget(
+    state: &AppState,
+    instructions: &str,
+    history: &[ChatMessage],
+    message: &str,
+    extra: usize,
+) -> usize {
+    let history_tokens: usize = history.iter().map(|m| estimate_tokens(&m.content)).sum();
+    let fixed = REPLY_RESERVE_TOKENS + estimate_tokens(instructions) + history_tokens + estimate_tokens(message) + extra;
+    state.ai_context_tokens.saturating_sub(fixed)
+}
+
+async fn prompt_context(
+    state: &AppState,
+    audience: &Audience,
+    user_id: Uuid,
+    related: &[RetrievedChunk],
+    budget: usize,
+) -> PromptContext {
+    let tasks = fetch_tasks(state, user_id).await.unwrap_or_default();
+    let notes = fetch_notes(state, user_id).await.unwrap_or_default();
+    let context = context::assemble(&state.prompts, audience, &tasks, &notes, related, audience.today, budget);
+    if !context.omitted.is_empty() {
+        tracing::debug!(budget, omitted = ?context.omitted, "ai context trimmed");
     }
-    (None, None)
+    context
+}
+
+async fn brief_messages(
+    state: &AppState,
+    audience: &Audience,
+    user_id: Uuid,
+    message: &str,
+    history: &[ChatMessage],
+    related: &[RetrievedChunk],
+) -> (Vec<ChatMessage>, Vec<Omitted>) {
+    let instructions = state.prompts.render(audience, Prompt::ChatSystem, minijinja::context! { context => "" });
+    let budget = context_budget(state, &instructions, history, message, 0);
+    let context = prompt_context(state, audience, user_id, related, budget).await;
+
+    let system = state.prompts.render(audience, Prompt::ChatSystem, minijinja::context! { context => context.text });
+    let mut messages = vec![ChatMessage::system(system)];
+    messages.extend_from_slice(history);
+    messages.push(ChatMessage::user(message));
+    (messages, context.omitted)
+}
+
+/// Plenty for a template that lists every open task.
+const TEMPLATE_BUDGET_TOKENS: usize = 20_000;
+
+/// The briefing `chat` gives for "오늘 브리핑" (`Prompt::BriefingRequest`),
+/// from the same context.
+pub(crate) async fn generate_briefing(
+    state: &AppState,
+    llm: &dyn LlmProvider,
+    audience: &Audience,
+    user_id: Uuid,
+) -> Result<String, LlmError> {
+    let request = state.prompts.render(audience, Prompt::BriefingRequest, minijinja::Value::UNDEFINED);
+    let related = retrieve_related(state, user_id, &request).await;
+    let (messages, _) = brief_messages(state, audience, user_id, &request, &[], &related).await;
+    let completion = llm.chat(&messages).await?;
+    if completion.content.trim().is_empty() {
+        return Err(LlmError::Malformed("empty response".to_string()));
+    }
+    Ok(completion.content)
+}
+
+/// Briefing written without the model: open tasks grouped the way the
+/// prompt context groups them.
+pub(crate) async fn template_briefing(
+    state: &AppState,
+    audience: &Audience,
+    user_id: Uuid,
+    date: NaiveDate,
+) -> Result<String, sqlx::Error> {
+    let tasks = fetch_tasks(state, user_id).await?;
+    let context = context::assemble(&state.prompts, audience, &tasks, &[], &[], date, TEMPLATE_BUDGET_TOKENS);
+    Ok(state.prompts.render(
+        audience,
+        Prompt::BriefingTemplate,
+        minijinja::context! { date => date.to_string(), context => context.text },
+    ))
+}
+
+async fn tool_messages(
+    state: &AppState,
+    audience: &Audience,
+    user_id: Uuid,
+    message: &str,
+    history: &[ChatMessage],
+    related: &[RetrievedChunk],
+    specs: &[ToolSpec],
+) -> (Vec<ChatMessage>, Vec<Omitted>) {
+    let instructions = state.prompts.render(audience, Prompt::ToolsSystem, minijinja::context! { context => "" });
+    let schema_tokens = estimate_tokens(&serde_json::to_string(specs).unwrap_or_default());
+    let budget = context_budget(state, &instructions, history, message, schema_tokens);
+    let context = prompt_context(state, audience, user_id, related, budget).await;
+
+    let system = state.prompts.render(audience, Prompt::ToolsSystem, minijinja::context! { context => context.text });
+    let mut messages = vec![ChatMessage::system(system)];
+    messages.extend_from_slice(history);
+    messages.push(ChatMessage::user(message));
+    (messages, context.omitted)
 }
 
-fn format_date_range(start: Option<NaiveDate>, end: Option<NaiveDate>) -> String {
-    match (start, end) {
-        (Some(s), Some(e)) if s == e => format!(" (마감 {})", s),
-        (Some(s), Some(e)) => format!(" ({} ~ {})", s, e),
-        (Some(s), None) => format!(" (마감 {})", s),
-        _ => "".to_string(),
+/// Registers the task a `create_task` message asks for. Dates found in the
+/// message win over the model's slots; its title is preferred to ours.
+/// Dates are read on the user's clock and calendar week.
+async fn create_task_from_message(
+    state: &AppState,
+    audience: &Audience,
+    user_id: Uuid,
+    message: &str,
+    slots: &Slots,
+) -> Result<String, sqlx::Error> {
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    let parsed = dates::extract(message, audience.today, prefs.week_start);
+    let title = slots.title.clone().unwrap_or_else(|| task_title(&parsed.rest));
+    let (start_date, end_date) = if parsed.start_date.is_some() {
+        (parsed.start_date, parsed.end_date)
+    } else {
+        (slots.start_date, slots.end_date)
+    };
+    let due_date = end_date.or(start_date);
+    // Tasks carry no time of day; keep it where the user will see it.
+    let description = parsed.time.map(|time| {
+        state.prompts.render(
+            audience,
+            Prompt::TaskTime,
+            minijinja::context! { time => time.format("%H:%M").to_string() },
+        )
+    });
+    if title.is_empty() {
+        return Ok(state.prompts.render(audience, Prompt::ReplyTaskTitleMissing, minijinja::Value::UNDEFINED));
     }
+
+    let new_task = NewTask {
+        title,
+        description,
+        status: prefs.default_status,
+        priority: prefs.default_priority,
+        due_date,
+        start_date,
+        end_date,
+        ..Default::default()
+    };
+    let task = tasks::insert_task(&mut *state.pool.acquire().await?, user_id, &new_task).await?;
+
+    Ok(state.prompts.render(
+        audience,
+        Prompt::ReplyTaskCreated,
+        minijinja::context! {
+            title => task.title,
+            start_date => task.start_date.map(|d| d.to_string()),
+            end_date => task.end_date.map(|d| d.to_string()),
+        },
+    ))
+}
+
+/// The task title left once the date expressions and the request around it
+/// ("업무 등록:", "추가해줘", "add task") are cut out. After an explicit
+/// command prefix the rest is the title as written.
+fn task_title(rest: &str) -> String {
+    let title = match COMMAND_PREFIX.find(rest) {
+        Some(prefix) => rest[prefix.end()..].to_string(),
+        None => COMMAND_SUFFIX.replace(rest, "").into_owned(),
+    };
+    title
+        .trim_matches(|c: char| c.is_whitespace() || matches!(c, ':' | '：' | ',' | '-' | '"' | '\''))
+        .to_string()
 }
 
 async fn fetch_tasks(state: &AppState, user_id: Uuid) -> Result<Vec<Task>, sqlx::Error> {
     sqlx::query_as!(
         Task,
-        r#"SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, created_at, updated_at FROM tasks
-           WHERE user_id = $1
-           ORDER BY end_date NULLS LAST, updated_at DESC
-           LIMIT 30"#,
+        r#"SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at FROM tasks
+           WHERE user_id = $1 AND status != 'done'
+           ORDER BY COALESCE(end_date, due_date, start_date) NULLS LAST, updated_at DESC
+           LIMIT 200"#,
         user_id
     )
     .fetch_all(&state.pool)
@@ -345,63 +686,338 @@ async fn fetch_notes(state: &AppState, user_id: Uuid) -> Result<Vec<Note>, sqlx:
         r#"SELECT * FROM notes
            WHERE user_id = $1
            ORDER BY updated_at DESC
-           LIMIT 10"#,
+           LIMIT 20"#,
         user_id
     )
     .fetch_all(&state.pool)
     .await
 }
 
-fn build_context(tasks: &[Task], notes: &[Note]) -> String {
-    let mut parts = vec![];
-    if !tasks.is_empty() {
-        let task_lines: Vec<String> = tasks
-            .iter()
-            .map(|t| {
-                let status = &t.status;
-                let prio = &t.priority;
-                let due = match (t.start_date, t.end_date) {
-                    (Some(s), Some(e)) if s == e => format!("마감 {}", s),
-                    (Some(s), Some(e)) => format!("{}~{}", s, e),
-                    (Some(s), None) => format!("마감 {}", s),
-                    _ => "마감 없음".to_string(),
-                };
-                let tags = if t.tags.is_empty() {
-                    "".to_string()
-                } else {
-                    format!(" #{}", t.tags.join(" #"))
-                };
-                format!("- [{}][{}][{}] {}{}", status, prio, due, t.title, tags)
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeDelta;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::briefings::{self, Targets};
+    use crate::testing::{read, read_json, TestApp};
+
+    const BRIEFING_INTENT: &str = r#"{"intent": "briefing", "slots": {}, "confidence": 0.9}"#;
+
+    fn today() -> NaiveDate {
+        dates::today_in(chrono_tz::Asia::Seoul)
+    }
+
+    async fn send(app: &TestApp, message: &str) -> (u16, String) {
+        let payload = ChatRequest {
+            message: message.to_string(),
+            conversation_id: None,
+        };
+        read(chat(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(payload)).await).await
+    }
+
+    async fn send_json(app: &TestApp, message: &str) -> Value {
+        let payload = ChatRequest {
+            message: message.to_string(),
+            conversation_id: None,
+        };
+        let (status, body) =
+            read_json(chat(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(payload)).await).await;
+        assert_eq!(status, 200, "{body}");
+        body
+    }
+
+    async fn send_stream(app: &TestApp, message: &str) -> Vec<(String, Value)> {
+        let payload = ChatRequest {
+            message: message.to_string(),
+            conversation_id: None,
+        };
+        let (status, body) =
+            read(chat_stream(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(payload)).await).await;
+        assert_eq!(status, 200, "{body}");
+        body.split("\n\n")
+            .filter_map(|event| {
+                let name = event.lines().find_map(|l| l.strip_prefix("event: "))?;
+                let data = event.lines().find_map(|l| l.strip_prefix("data: "))?;
+                Some((name.to_string(), serde_json::from_str(data).ok()?))
             })
-            .collect();
-        parts.push(format!("업무: {}", task_lines.join(" | ")));
+            .collect()
+    }
+
+    async fn tasks(app: &TestApp) -> Vec<Task> {
+        sqlx::query_as!(
+            Task,
+            r#"SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at
+               FROM tasks WHERE user_id = $1 ORDER BY created_at"#,
+            app.user_id
+        )
+        .fetch_all(&app.state.pool)
+        .await
+        .unwrap()
+    }
+
+    async fn add_task(app: &TestApp, title: &str, end_date: NaiveDate) {
+        sqlx::query!(
+            r#"
+            INSERT INTO tasks (id, user_id, title, status, priority, due_date, end_date, tags)
+            VALUES ($1, $2, $3, 'todo', 'high', $4, $4, '{}')
+            "#,
+            Uuid::new_v4(),
+            app.user_id,
+            title,
+            end_date
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn creates_tasks_from_korean_phrases() {
+        let app = TestApp::start().await;
+        let today = today();
+        let cases = [
+            ("업무 등록: 주간 보고서 작성 2024-12-06", "주간 보고서 작성", NaiveDate::from_ymd_opt(2024, 12, 6), None),
+            ("내일 오후 3시 고객 미팅 일정 잡아줘", "고객 미팅", today.succ_opt(), Some("시간: 15:00")),
+            ("모레까지 예산안 초안 추가해줘", "예산안 초안", today.checked_add_signed(TimeDelta::days(2)), None),
+        ];
+
+        app.model.push_tools_unsupported();
+        for (message, title, end_date, description) in cases {
+            // The classifier names the intent; title and dates are read from
+            // the message itself.
+            app.model
+                .push_reply(r#"{"intent": "create_task", "slots": {"title": null}, "confidence": 0.95}"#);
+            let body = send_json(&app, message).await;
+            let reply = body["reply"].as_str().unwrap();
+            assert!(reply.starts_with(&format!("업무가 등록됐어요: {title}")), "{message}: {reply}");
+
+            let task = tasks(&app).await.pop().unwrap();
+            assert_eq!(task.title, title, "{message}");
+            assert_eq!(task.end_date, end_date, "{message}");
+            assert_eq!(task.due_date, end_date, "{message}");
+            assert_eq!(task.description.as_deref(), description, "{message}");
+        }
+        assert_eq!(tasks(&app).await.len(), 3);
+        assert_eq!(app.model.remaining(), 0);
+
+        // Only the first message tried function calling; the classifier saw
+        // each message with today's date.
+        let requests = app.model.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].get("tools").is_some());
+        assert_eq!(requests[1]["format"], "json");
+        assert!(app.model.system_prompt(1).contains(&format!("오늘은 {today}")));
+        assert_eq!(requests[1]["messages"].as_array().unwrap().last().unwrap()["content"], cases[0].0);
+    }
+
+    #[tokio::test]
+    async fn creates_tasks_through_tool_calls() {
+        let app = TestApp::start().await;
+        app.model
+            .push_tool_call("create_task", json!({ "title": "분기 회고 준비", "end_date": "2024-12-20" }))
+            .push_reply("분기 회고 준비 업무를 12월 20일 마감으로 등록했어요.");
+
+        let body = send_json(&app, "12월 20일까지 분기 회고 준비 등록해줘").await;
+        assert_eq!(body["reply"], "분기 회고 준비 업무를 12월 20일 마감으로 등록했어요.");
+        assert_eq!(body["actions"][0]["tool"], "create_task");
+        assert_eq!(body["actions"][0]["status"], "executed");
+
+        let task = tasks(&app).await.pop().unwrap();
+        assert_eq!(task.title, "분기 회고 준비");
+        assert_eq!(task.end_date, NaiveDate::from_ymd_opt(2024, 12, 20));
+
+        // The tool result went back to the model before its final answer.
+        let requests = app.model.requests();
+        assert_eq!(requests.len(), 2);
+        let messages = requests[1]["messages"].as_array().unwrap();
+        let result = messages.iter().find(|m| m["role"] == "tool").unwrap();
+        assert!(result["content"].as_str().unwrap().contains("\"status\":\"ok\""));
     }
-    if !notes.is_empty() {
-        let note_lines: Vec<String> = notes
+
+    #[tokio::test]
+    async fn briefing_prompt_carries_the_users_tasks() {
+        let app = TestApp::start().await;
+        let today = today();
+        add_task(&app, "지난 분기 정산", today - TimeDelta::days(3)).await;
+        add_task(&app, "배포 점검", today).await;
+        app.model
+            .push_tools_unsupported()
+            .push_reply(BRIEFING_INTENT)
+            .push_reply("- 지난 분기 정산이 밀렸어요.");
+
+        let body = send_json(&app, "오늘 브리핑").await;
+        assert_eq!(body["reply"], "- 지난 분기 정산이 밀렸어요.");
+        assert!(body["conversation_id"].is_string());
+
+        let requests = app.model.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2]["stream"], false);
+        assert!(requests[2].get("format").is_none());
+        let system = app.model.system_prompt(2);
+        assert!(system.starts_with("당신은 데일리 업무 비서입니다."), "{system}");
+        assert!(system.contains("컨텍스트:"), "{system}");
+        assert!(system.contains("지난 분기 정산"), "{system}");
+        assert!(system.contains("배포 점검"), "{system}");
+        assert!(system.contains(&today.to_string()), "{system}");
+    }
+
+    #[tokio::test]
+    async fn briefing_follows_the_users_locale() {
+        let app = TestApp::start().await;
+        sqlx::query!(
+            "INSERT INTO user_settings (user_id, locale, display_name) VALUES ($1, 'en', 'Jiwoo')",
+            app.user_id
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+        app.model.push_tools_unsupported().push_reply(BRIEFING_INTENT).push_reply("- Nothing due.");
+
+        send_json(&app, "today's briefing").await;
+        let system = app.model.system_prompt(2);
+        assert!(system.contains("English"), "{system}");
+        assert!(system.contains("Jiwoo"), "{system}");
+    }
+
+    #[tokio::test]
+    async fn scheduled_briefing_uses_the_model_or_the_template() {
+        let app = TestApp::start().await;
+        let today = today();
+        add_task(&app, "배포 점검", today).await;
+        let targets = Targets {
+            email: None,
+            webhook_url: None,
+        };
+
+        app.model.push_reply("- 배포 점검");
+        let briefing = briefings::run(&app.state, app.state.llm.as_ref(), app.user_id, today, &targets)
+            .await
+            .unwrap();
+        assert_eq!(briefing.source, "model");
+        assert_eq!(briefing.content, "- 배포 점검");
+        // Asked the same way a chat briefing is.
+        let request = &app.model.requests()[0];
+        assert_eq!(request["messages"].as_array().unwrap().last().unwrap()["content"], "오늘 브리핑");
+        assert!(app.model.system_prompt(0).contains("배포 점검"));
+
+        app.model.push_status(500, "model crashed");
+        let briefing = briefings::run(&app.state, app.state.llm.as_ref(), app.user_id, today, &targets)
+            .await
+            .unwrap();
+        assert_eq!(briefing.source, "template");
+        assert!(briefing.content.starts_with(&format!("{today} 브리핑\n")), "{}", briefing.content);
+        assert!(briefing.content.contains("배포 점검"));
+    }
+
+    #[tokio::test]
+    async fn upstream_500_leaves_keyword_handling() {
+        let app = TestApp::start().await;
+
+        // Task creation needs no model, so it still works.
+        app.model.push_status(500, "model crashed");
+        let body = send_json(&app, "업무 등록: 장애 회고 작성 내일").await;
+        assert!(body["reply"].as_str().unwrap().starts_with("업무가 등록됐어요: 장애 회고 작성"));
+        assert_eq!(tasks(&app).await.len(), 1);
+
+        // A briefing does, and reports the failure.
+        app.model.push_status(500, "model crashed");
+        let (status, body) = send(&app, "오늘 브리핑").await;
+        assert_eq!(status, 502);
+        assert!(body.contains("(500)") && body.contains("model crashed"), "{body}");
+        // One call each: no classifier call once the model failed.
+        assert_eq!(app.model.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn upstream_timeout_is_a_bad_gateway() {
+        let app = TestApp::with_timeout(Duration::from_millis(200)).await;
+        app.model.push_slow_reply(Duration::from_secs(2), "too late");
+
+        let (status, body) = send(&app, "오늘 브리핑").await;
+        assert_eq!(status, 502);
+        assert_eq!(body, "모델 응답 시간 초과");
+    }
+
+    #[tokio::test]
+    async fn malformed_model_json_falls_back() {
+        let app = TestApp::start().await;
+
+        // Classifier prose instead of JSON: the keyword heuristics decide.
+        app.model
+            .push_tools_unsupported()
+            .push_reply("네, 업무 등록 요청으로 보입니다!");
+        let body = send_json(&app, "업무 등록: 보안 점검 12/3").await;
+        assert!(body["reply"].as_str().unwrap().starts_with("업무가 등록됐어요: 보안 점검"));
+        assert_eq!(tasks(&app).await[0].title, "보안 점검");
+
+        // A response that is not JSON at all fails the briefing.
+        app.model.push_reply(BRIEFING_INTENT).push_raw("<html>502 Bad Gateway</html>");
+        let (status, body) = send(&app, "오늘 브리핑").await;
+        assert_eq!(status, 502);
+        assert!(body.starts_with("모델 응답 형식 오류"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn streams_tokens_then_the_full_reply() {
+        let app = TestApp::start().await;
+        app.model
+            .push_reply(BRIEFING_INTENT)
+            .push_reply("오늘은 배포 점검이 있어요.");
+
+        let events = send_stream(&app, "오늘 브리핑").await;
+        let tokens: Vec<_> = events
             .iter()
-            .map(|n| {
-                let tags = if n.tags.is_empty() {
-                    "".to_string()
-                } else {
-                    format!(" #{}", n.tags.join(" #"))
-                };
-                format!("- {}{}", n.title, tags)
-            })
+            .filter(|(name, _)| name == "token")
+            .map(|(_, data)| data["content"].as_str().unwrap())
             .collect();
-        parts.push(format!("노트: {}", note_lines.join(" | ")));
+        assert_eq!(tokens, ["오늘은 ", "배포 ", "점검이 ", "있어요."]);
+        let (name, done) = events.last().unwrap();
+        assert_eq!(name, "done");
+        assert_eq!(done["reply"], "오늘은 배포 점검이 있어요.");
+        // The classifier, then the streamed answer; no tool round.
+        let requests = app.model.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1]["stream"], true);
+        assert!(requests.iter().all(|r| r.get("tools").is_none()));
     }
-    if parts.is_empty() {
-        "등록된 업무나 노트가 없습니다.".to_string()
-    } else {
-        parts.join(" ; ")
+
+    #[tokio::test]
+    async fn tool_capable_models_stream_answers_and_report_actions() {
+        let app = TestApp::start().await;
+        app.model
+            .push_reply(r#"{"intent": "question", "slots": {}, "confidence": 0.9}"#)
+            .push_reply("배포는 금요일 오후에 합니다.");
+        let events = send_stream(&app, "배포는 언제 해?").await;
+        let tokens: Vec<_> = events.iter().filter(|(name, _)| name == "token").collect();
+        assert!(tokens.len() > 1, "{events:?}");
+        assert_eq!(events.last().unwrap().1["reply"], "배포는 금요일 오후에 합니다.");
+
+        // Commands still go through the tools, before the answer.
+        app.model
+            .push_reply(r#"{"intent": "create_task", "slots": {}, "confidence": 0.9}"#)
+            .push_tool_call("create_task", json!({ "title": "배포 공지" }))
+            .push_reply("배포 공지 업무를 등록했어요.");
+        let events = send_stream(&app, "배포 공지 등록해줘").await;
+        let names: Vec<_> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["action", "token", "done"]);
+        assert_eq!(events[0].1["tool"], "create_task");
+        assert_eq!(tasks(&app).await[0].title, "배포 공지");
+        assert_eq!(app.model.remaining(), 0);
     }
-}
 
-fn truncate_context(input: &str) -> String {
-    if input.chars().count() <= MAX_CONTEXT_CHARS {
-        return input.to_string();
+    #[tokio::test]
+    async fn stream_cut_short_ends_with_an_error_event() {
+        let app = TestApp::start().await;
+        app.model
+            .push_reply(BRIEFING_INTENT)
+            .push_truncated_stream("오늘은 배포 점검이");
+
+        let events = send_stream(&app, "오늘 브리핑").await;
+        let names: Vec<_> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["token", "token", "token", "error"]);
+        assert_eq!(events[3].1["code"], "upstream_incomplete");
     }
-    let mut truncated: String = input.chars().take(MAX_CONTEXT_CHARS).collect();
-    truncated.push_str("...");
-    truncated
 }
@@ -0,0 +1,145 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{NaiveDate, TimeDelta};
+use serde::Deserialize;
+
+use crate::analytics::{self, Bucket};
+use crate::middleware::{AppState, AuthUser};
+use crate::settings;
+
+/// Longest range one request may cover, in days.
+const MAX_RANGE_DAYS: i64 = 731;
+
+#[derive(Deserialize)]
+pub struct AnalyticsQuery {
+    /// First day of the range; 29 days before `to` when absent.
+    pub from: Option<NaiveDate>,
+    /// Last day of the range, inclusive; today when absent.
+    pub to: Option<NaiveDate>,
+    /// `day` (default) or `week`.
+    pub bucket: Option<String>,
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Query(query): Query<AnalyticsQuery>,
+) -> impl IntoResponse {
+    let bucket = match query.bucket.as_deref().map(Bucket::parse) {
+        None => Bucket::Day,
+        Some(Some(bucket)) => bucket,
+        Some(None) => return (axum::http::StatusCode::BAD_REQUEST, "invalid bucket").into_response(),
+    };
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    let to = query.to.unwrap_or_else(|| prefs.today());
+    let from = query.from.unwrap_or(to - TimeDelta::days(29));
+    if from > to || (to - from).num_days() >= MAX_RANGE_DAYS {
+        return (axum::http::StatusCode::BAD_REQUEST, "invalid range").into_response();
+    }
+
+    match analytics::compute(&state.pool, user_id, prefs.timezone, prefs.week_start, from, to, bucket).await {
+        Ok(analytics) => Json(analytics).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Path;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::handlers::tasks;
+    use crate::testing::{read, read_json, TaskSeed, TestApp};
+
+    async fn fetch(app: &TestApp, from: &str, to: &str, bucket: Option<&str>) -> (u16, Value) {
+        let query = AnalyticsQuery {
+            from: Some(from.parse().unwrap()),
+            to: Some(to.parse().unwrap()),
+            bucket: bucket.map(str::to_string),
+        };
+        read_json(get(State(app.state.clone()), AuthUser { user_id: app.user_id }, Query(query)).await).await
+    }
+
+    #[tokio::test]
+    async fn series_rates_and_breakdowns() {
+        let app = TestApp::start().await;
+        // Seoul days: done 11-26 (on time), 11-27 (late), 11-28 (no end date).
+        app.add_task(TaskSeed { priority: "high", tags: &["ops", "release"], end_date: Some("2024-11-26"), ..TaskSeed::new("done", "2024-11-25T09:00:00+09:00", "2024-11-26T09:00:00+09:00") }).await;
+        app.add_task(TaskSeed { priority: "high", tags: &["ops"], end_date: Some("2024-11-25"), ..TaskSeed::new("done", "2024-11-26T09:00:00+09:00", "2024-11-27T21:00:00+09:00") }).await;
+        app.add_task(TaskSeed { priority: "low", ..TaskSeed::new("done", "2024-11-26T09:00:00+09:00", "2024-11-28T21:00:00+09:00") }).await;
+        let open = app.add_task(TaskSeed { tags: &["ops"], ..TaskSeed::new("todo", "2024-11-27T00:30:00+09:00", "2024-11-27T00:30:00+09:00") }).await;
+
+        let (status, body) = fetch(&app, "2024-11-25", "2024-12-01", None).await;
+        assert_eq!(status, 200, "{body}");
+        let series = body["series"].as_array().unwrap();
+        assert_eq!(series.len(), 7);
+        assert_eq!(series[1], serde_json::json!({ "start": "2024-11-26", "created": 2, "completed": 1 }));
+        assert_eq!(series[2], serde_json::json!({ "start": "2024-11-27", "created": 1, "completed": 1 }));
+        assert_eq!(body["cycle_time_hours"]["count"], 3);
+        assert_eq!(body["cycle_time_hours"]["p50"], 36.0);
+        assert_eq!(body["on_time"]["completed_with_end_date"], 2);
+        assert_eq!(body["on_time"]["rate"], 0.5);
+        assert_eq!(body["streaks"]["longest"], 3);
+        assert_eq!(body["streaks"]["longest_start"], "2024-11-26");
+        assert_eq!(body["streaks"]["current"], 0);
+        assert_eq!(body["by_priority"][0]["value"], "high");
+        assert_eq!(body["by_priority"][0]["completed"], 2);
+        assert_eq!(body["by_tag"][0]["value"], "ops");
+        assert_eq!(body["by_tag"][0]["created"], 3);
+        assert_eq!(body["by_tag"][0]["on_time"]["on_time"], 1);
+
+        let (_, weekly) = fetch(&app, "2024-11-25", "2024-12-01", Some("week")).await;
+        assert_eq!(weekly["series"], serde_json::json!([{ "start": "2024-11-25", "created": 4, "completed": 3 }]));
+
+        // Finishing a task recounts the day it was finished on.
+        let update = crate::models::TaskUpdate {
+            title: None,
+            description: None,
+            status: Some("done".to_string()),
+            priority: None,
+            due_date: None,
+            start_date: None,
+            end_date: None,
+            tags: None,
+        };
+        let (status, _) = read_json(
+            tasks::update(State(app.state.clone()), AuthUser { user_id: app.user_id }, Path(open), Json(update)).await,
+        )
+        .await;
+        assert_eq!(status, 200);
+        let today = crate::dates::today_in(app.state.default_tz);
+        let (_, body) = fetch(&app, &(today - TimeDelta::days(7)).to_string(), &today.to_string(), None).await;
+        assert_eq!(body["series"].as_array().unwrap().last().unwrap()["completed"], 1);
+        assert_eq!(body["streaks"]["current"], 1);
+
+        // Deleting it takes it out of both the days it was counted on.
+        let (status, _) = read(tasks::delete(State(app.state.clone()), AuthUser { user_id: app.user_id }, Path(open)).await).await;
+        assert_eq!(status, 204);
+        let (_, body) = fetch(&app, &(today - TimeDelta::days(7)).to_string(), &today.to_string(), None).await;
+        assert_eq!(body["series"].as_array().unwrap().last().unwrap()["completed"], 0);
+        let (_, weekly) = fetch(&app, "2024-11-25", "2024-12-01", Some("week")).await;
+        assert_eq!(weekly["series"], serde_json::json!([{ "start": "2024-11-25", "created": 3, "completed": 3 }]));
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_ranges() {
+        let app = TestApp::start().await;
+        for (from, to, bucket) in [
+            ("2024-12-02", "2024-12-01", None),
+            ("2020-01-01", "2024-12-01", None),
+            ("2024-11-01", "2024-12-01", Some("month")),
+        ] {
+            let query = AnalyticsQuery {
+                from: Some(from.parse().unwrap()),
+                to: Some(to.parse().unwrap()),
+                bucket: bucket.map(str::to_string),
+            };
+            let (status, _) = read(get(State(app.state.clone()), AuthUser { user_id: app.user_id }, Query(query)).await).await;
+            assert_eq!(status, 400, "{from}..{to}");
+        }
+    }
+}
@@ -0,0 +1,1053 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::analytics;
+use crate::dashboard::{self, Widget};
+use crate::handlers::briefings::{validate_schedule, ScheduleUpdate, ValidSchedule};
+use crate::handlers::settings::Stored;
+use crate::handlers::tasks::{is_valid_priority, is_valid_status};
+use crate::links;
+use crate::middleware::{AppState, AuthUser};
+use crate::models::UserSettingsUpdate;
+use crate::revisions;
+use crate::settings;
+
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct ArchiveTask {
+    pub id: Uuid,
+    pub title: String,
+    pub description: Option<String>,
+    pub status: String,
+    pub priority: String,
+    pub due_date: Option<NaiveDate>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub tags: Vec<String>,
+    pub external_uid: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Missing from older archives; filled in on import the way the
+    /// migration that added them backfilled existing rows.
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub completed_at: Option<DateTime<Utc>>,
+    /// Also missing from older archives, whose tasks are then top-level.
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+    #[serde(default)]
+    pub estimate_minutes: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct ArchiveNote {
+    pub id: Uuid,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A timer span; `task_id` is remapped with the tasks on import.
+#[derive(Serialize, Deserialize, sqlx::FromRow)]
+pub struct ArchiveTimeEntry {
+    pub id: Uuid,
+    pub task_id: Option<Uuid>,
+    pub started_at: DateTime<Utc>,
+    pub stopped_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    #[serde(default)]
+    pub tasks: Vec<ArchiveTask>,
+    #[serde(default)]
+    pub notes: Vec<ArchiveNote>,
+    #[serde(default)]
+    pub time_entries: Vec<ArchiveTimeEntry>,
+    /// These three are missing from older archives, which then leave the
+    /// account's own unchanged.
+    #[serde(default)]
+    pub settings: Option<UserSettingsUpdate>,
+    #[serde(default)]
+    pub briefing_schedule: Option<ScheduleUpdate>,
+    #[serde(default)]
+    pub dashboard_layout: Option<Vec<Widget>>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictMode {
+    #[default]
+    Skip,
+    Overwrite,
+    Duplicate,
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveImportQuery {
+    pub mode: Option<ConflictMode>,
+}
+
+#[derive(Serialize, Default)]
+pub struct ArchiveImportCounts {
+    pub created: usize,
+    pub overwritten: usize,
+    pub duplicated: usize,
+    pub skipped: usize,
+}
+
+#[derive(Serialize)]
+pub struct ArchiveImportReport {
+    pub tasks: ArchiveImportCounts,
+    pub notes: ArchiveImportCounts,
+    pub time_entries: ArchiveImportCounts,
+    /// Settings, briefing schedule and dashboard layout, one item each.
+    /// Existing ones are only replaced in `overwrite` mode.
+    pub settings: ArchiveImportCounts,
+    /// Archive id -> id in this instance, for every item stored under a new id.
+    pub id_map: HashMap<Uuid, Uuid>,
+}
+
+pub async fn export(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let exported_at = Utc::now();
+    let pool = state.pool.clone();
+
+    // Rows are written out as they are read so large accounts never sit in memory.
+    let stream = async_stream::try_stream! {
+        let stamp = serde_json::to_string(&exported_at)?;
+        yield Bytes::from(format!(
+            r#"{{"version":{ARCHIVE_VERSION},"exported_at":{stamp},"tasks":["#
+        ));
+
+        let mut tasks = sqlx::query_as!(
+            ArchiveTask,
+            r#"SELECT id, title, description, status, priority, due_date, start_date, end_date, tags, external_uid, created_at, updated_at,
+                      started_at, completed_at, parent_id, estimate_minutes
+               FROM tasks WHERE user_id = $1 ORDER BY created_at"#,
+            user_id
+        )
+        .fetch(&pool);
+        let mut first = true;
+        while let Some(task) = tasks.try_next().await? {
+            let sep = if first { "" } else { "," };
+            first = false;
+            let item = serde_json::to_string(&task)?;
+            yield Bytes::from(format!("{sep}{item}"));
+        }
+        drop(tasks);
+
+        yield Bytes::from_static(br#"],"notes":["#);
+        let mut notes = sqlx::query_as!(
+            ArchiveNote,
+            r#"SELECT id, title, content, tags, created_at, updated_at
+               FROM notes WHERE user_id = $1 ORDER BY created_at"#,
+            user_id
+        )
+        .fetch(&pool);
+        let mut first = true;
+        while let Some(note) = notes.try_next().await? {
+            let sep = if first { "" } else { "," };
+            first = false;
+            let item = serde_json::to_string(&note)?;
+            yield Bytes::from(format!("{sep}{item}"));
+        }
+        drop(notes);
+
+        yield Bytes::from_static(br#"],"time_entries":["#);
+        let mut entries = sqlx::query_as!(
+            ArchiveTimeEntry,
+            r#"SELECT id, task_id, started_at, stopped_at
+               FROM time_entries WHERE user_id = $1 ORDER BY started_at"#,
+            user_id
+        )
+        .fetch(&pool);
+        let mut first = true;
+        while let Some(entry) = entries.try_next().await? {
+            let sep = if first { "" } else { "," };
+            first = false;
+            let item = serde_json::to_string(&entry)?;
+            yield Bytes::from(format!("{sep}{item}"));
+        }
+        drop(entries);
+
+        let tags = sqlx::query_scalar!(
+            r#"SELECT DISTINCT tag AS "tag!" FROM (
+                 SELECT unnest(tags) AS tag FROM tasks WHERE user_id = $1
+                 UNION
+                 SELECT unnest(tags) AS tag FROM notes WHERE user_id = $1
+               ) t ORDER BY 1"#,
+            user_id
+        )
+        .fetch_all(&pool)
+        .await?;
+        let tags = serde_json::to_string(&tags)?;
+
+        let settings = sqlx::query_as!(
+            UserSettingsUpdate,
+            r#"SELECT locale, display_name, timezone, week_start, default_priority, default_status
+               FROM user_settings WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_optional(&pool)
+        .await?;
+        let schedule = sqlx::query!(
+            r#"SELECT local_time, weekdays, timezone, email, webhook_url, enabled
+               FROM briefing_schedules WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_optional(&pool)
+        .await?
+        .map(|row| ScheduleUpdate {
+            time: row.local_time.format("%H:%M:%S").to_string(),
+            weekdays: Some(row.weekdays),
+            timezone: row.timezone,
+            email: row.email,
+            webhook_url: row.webhook_url,
+            enabled: Some(row.enabled),
+        });
+        let layout = sqlx::query_scalar!("SELECT widgets FROM dashboard_layouts WHERE user_id = $1", user_id)
+            .fetch_optional(&pool)
+            .await?;
+        let settings = serde_json::to_string(&settings)?;
+        let schedule = serde_json::to_string(&schedule)?;
+        let layout = serde_json::to_string(&layout)?;
+        yield Bytes::from(format!(
+            r#"],"tags":{tags},"settings":{settings},"briefing_schedule":{schedule},"dashboard_layout":{layout}}}"#
+        ));
+    };
+    let stream = stream.inspect_err(|err: &anyhow::Error| tracing::error!("export failed: {err}"));
+
+    let filename = format!("dailyops-export-{}.json", exported_at.format("%Y%m%d"));
+    (
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+pub async fn import(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Query(query): Query<ArchiveImportQuery>,
+    Json(mut archive): Json<Archive>,
+) -> impl IntoResponse {
+    if archive.version != ARCHIVE_VERSION {
+        return (
+            axum::http::StatusCode::BAD_REQUEST,
+            "unsupported archive version",
+        )
+            .into_response();
+    }
+    for task in &archive.tasks {
+        if task.title.trim().is_empty() {
+            return (axum::http::StatusCode::BAD_REQUEST, "title required").into_response();
+        }
+        if !is_valid_status(&task.status) {
+            return (axum::http::StatusCode::BAD_REQUEST, "invalid status").into_response();
+        }
+        if !is_valid_priority(&task.priority) {
+            return (axum::http::StatusCode::BAD_REQUEST, "invalid priority").into_response();
+        }
+    }
+    for note in &archive.notes {
+        if note.title.trim().is_empty() {
+            return (axum::http::StatusCode::BAD_REQUEST, "title required").into_response();
+        }
+    }
+    if archive
+        .time_entries
+        .iter()
+        .any(|e| e.stopped_at.is_some_and(|stopped| stopped < e.started_at))
+    {
+        return (axum::http::StatusCode::BAD_REQUEST, "time entry stops before it starts").into_response();
+    }
+
+    let mut settings = None;
+    if let Some(update) = archive.settings.take() {
+        let mut stored = Stored::default();
+        if let Err(msg) = stored.apply(update, &state.prompts.locales()) {
+            return (axum::http::StatusCode::BAD_REQUEST, msg).into_response();
+        }
+        settings = Some(stored);
+    }
+    let schedule = match archive.briefing_schedule.take().map(validate_schedule).transpose() {
+        Ok(schedule) => schedule,
+        Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if let Some(Err(msg)) = archive.dashboard_layout.as_deref().map(dashboard::validate) {
+        return (axum::http::StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let singletons = Singletons {
+        settings,
+        schedule,
+        layout: archive.dashboard_layout.take(),
+    };
+
+    let mode = query.mode.unwrap_or_default();
+    match import_archive(&state, user_id, archive, singletons, mode).await {
+        Ok(report) => {
+            state.embeddings.wake();
+            state.enrichment.wake();
+            Json(report).into_response()
+        }
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// The per-account parts of an archive, validated.
+struct Singletons {
+    settings: Option<Stored>,
+    schedule: Option<ValidSchedule>,
+    layout: Option<Vec<Widget>>,
+}
+
+enum Placement {
+    /// Id is free everywhere; keep it.
+    Keep,
+    /// Id is taken by another account; store under a fresh id.
+    Remap,
+    /// Id already belongs to this user.
+    Conflict,
+}
+
+fn placement(id: Uuid, own: &HashSet<Uuid>, foreign: &HashSet<Uuid>) -> Placement {
+    if own.contains(&id) {
+        Placement::Conflict
+    } else if foreign.contains(&id) {
+        Placement::Remap
+    } else {
+        Placement::Keep
+    }
+}
+
+async fn import_archive(
+    state: &AppState,
+    user_id: Uuid,
+    archive: Archive,
+    singletons: Singletons,
+    mode: ConflictMode,
+) -> Result<ArchiveImportReport, sqlx::Error> {
+    let mut tx = state.pool.begin().await?;
+    let mut report = ArchiveImportReport {
+        tasks: ArchiveImportCounts::default(),
+        notes: ArchiveImportCounts::default(),
+        time_entries: ArchiveImportCounts::default(),
+        settings: ArchiveImportCounts::default(),
+        id_map: HashMap::new(),
+    };
+
+    let task_ids: Vec<Uuid> = archive.tasks.iter().map(|t| t.id).collect();
+    let (own, foreign) = split_owned(
+        sqlx::query!(
+            "SELECT id, user_id FROM tasks WHERE id = ANY($1)",
+            &task_ids
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|r| (r.id, r.user_id)),
+        user_id,
+    );
+    let mut taken_uids: HashSet<String> = sqlx::query_scalar!(
+        "SELECT external_uid FROM tasks WHERE user_id = $1 AND external_uid IS NOT NULL",
+        user_id
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .flatten()
+    .collect();
+
+    // Parents are linked once every task is in, wherever they ended up.
+    let mut children = Vec::new();
+    for task in archive.tasks {
+        let completed_at = (task.status == "done").then(|| task.completed_at.unwrap_or(task.updated_at));
+        let started_at = (task.status != "todo").then(|| task.started_at.unwrap_or(task.updated_at));
+        let target = match (placement(task.id, &own, &foreign), mode) {
+            (Placement::Keep, _) => task.id,
+            (Placement::Remap, _) | (Placement::Conflict, ConflictMode::Duplicate) => Uuid::new_v4(),
+            (Placement::Conflict, ConflictMode::Skip) => {
+                report.tasks.skipped += 1;
+                continue;
+            }
+            (Placement::Conflict, ConflictMode::Overwrite) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE tasks
+                    SET title = $1, description = $2, status = $3, priority = $4, due_date = $5,
+                        start_date = $6, end_date = $7, tags = $8, created_at = $9, updated_at = $10,
+                        started_at = $11, completed_at = $12, estimate_minutes = $13, parent_id = NULL
+                    WHERE id = $14 AND user_id = $15
+                    "#,
+                    task.title,
+                    task.description,
+                    task.status,
+                    task.priority,
+                    task.due_date,
+                    task.start_date,
+                    task.end_date,
+                    &task.tags,
+                    task.created_at,
+                    task.updated_at,
+                    started_at,
+                    completed_at,
+                    task.estimate_minutes,
+                    task.id,
+                    user_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                if let Some(parent) = task.parent_id {
+                    children.push((task.id, parent));
+                }
+                report.tasks.overwritten += 1;
+                continue;
+            }
+        };
+
+        let external_uid = task.external_uid.filter(|u| taken_uids.insert(u.clone()));
+        sqlx::query!(
+            r#"
+            INSERT INTO tasks (id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, external_uid, created_at, updated_at, started_at, completed_at, estimate_minutes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+            "#,
+            target,
+            user_id,
+            task.title,
+            task.description,
+            task.status,
+            task.priority,
+            task.due_date,
+            task.start_date,
+            task.end_date,
+            &task.tags,
+            external_uid,
+            task.created_at,
+            task.updated_at,
+            started_at,
+            completed_at,
+            task.estimate_minutes
+        )
+        .execute(&mut *tx)
+        .await?;
+        if let Some(parent) = task.parent_id {
+            children.push((target, parent));
+        }
+        if target == task.id {
+            report.tasks.created += 1;
+        } else {
+            if own.contains(&task.id) {
+                report.tasks.duplicated += 1;
+            } else {
+                report.tasks.created += 1;
+            }
+            report.id_map.insert(task.id, target);
+        }
+    }
+
+    // A parent that is in neither the archive nor the account leaves the
+    // subtask top-level.
+    for (child, parent) in children {
+        let parent = report.id_map.get(&parent).copied().unwrap_or(parent);
+        sqlx::query!(
+            r#"
+            UPDATE tasks SET parent_id = $1
+            WHERE id = $2 AND user_id = $3
+              AND EXISTS (SELECT 1 FROM tasks WHERE id = $1 AND user_id = $3)
+            "#,
+            parent,
+            child,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let note_ids: Vec<Uuid> = archive.notes.iter().map(|n| n.id).collect();
+    let (own, foreign) = split_owned(
+        sqlx::query!(
+            "SELECT id, user_id FROM notes WHERE id = ANY($1)",
+            &note_ids
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|r| (r.id, r.user_id)),
+        user_id,
+    );
+
+    // Targets are settled first so links between notes can be rewritten too.
+    let mut placed = Vec::with_capacity(archive.notes.len());
+    for note in archive.notes {
+        let placement = placement(note.id, &own, &foreign);
+        if matches!((&placement, mode), (Placement::Remap, _) | (Placement::Conflict, ConflictMode::Duplicate)) {
+            report.id_map.insert(note.id, Uuid::new_v4());
+        }
+        placed.push((note, placement));
+    }
+
+    for (mut note, placement) in placed {
+        if let Some(content) = links::rewrite_id_links(&note.content, &report.id_map) {
+            note.content = content;
+        }
+        let target = match (placement, mode) {
+            (Placement::Keep, _) => note.id,
+            (Placement::Remap, _) | (Placement::Conflict, ConflictMode::Duplicate) => report.id_map[&note.id],
+            (Placement::Conflict, ConflictMode::Skip) => {
+                report.notes.skipped += 1;
+                continue;
+            }
+            (Placement::Conflict, ConflictMode::Overwrite) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE notes
+                    SET title = $1, content = $2, tags = $3, created_at = $4, updated_at = $5
+                    WHERE id = $6 AND user_id = $7
+                    "#,
+                    note.title,
+                    note.content,
+                    &note.tags,
+                    note.created_at,
+                    note.updated_at,
+                    note.id,
+                    user_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                links::sync_note_links(&mut tx, user_id, note.id, &note.content).await?;
+                revisions::record_revision(
+                    &mut tx,
+                    user_id,
+                    note.id,
+                    &note.title,
+                    &note.content,
+                    &note.tags,
+                    None,
+                )
+                .await?;
+                report.notes.overwritten += 1;
+                continue;
+            }
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO notes (id, user_id, title, content, tags, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            target,
+            user_id,
+            note.title,
+            note.content,
+            &note.tags,
+            note.created_at,
+            note.updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        links::sync_note_links(&mut tx, user_id, target, &note.content).await?;
+        revisions::record_revision(
+            &mut tx,
+            user_id,
+            target,
+            &note.title,
+            &note.content,
+            &note.tags,
+            None,
+        )
+        .await?;
+        if own.contains(&note.id) {
+            report.notes.duplicated += 1;
+        } else {
+            report.notes.created += 1;
+        }
+    }
+
+    import_time_entries(&mut tx, user_id, archive.time_entries, mode, &mut report).await?;
+    import_singletons(&mut tx, user_id, singletons, mode, &mut report.settings).await?;
+    // A restore may bring back any day and a new zone, so the rollup is
+    // counted afresh on the zone the user ends up with.
+    let timezone = sqlx::query_scalar!("SELECT timezone FROM user_settings WHERE user_id = $1", user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .flatten();
+    analytics::rebuild(&mut tx, user_id, settings::zone(timezone.as_deref(), state.default_tz)).await?;
+    tx.commit().await?;
+    Ok(report)
+}
+
+/// Stores the timer spans, after the tasks so their `task_id` can follow
+/// the tasks' new ids. A task that is neither in the archive nor the
+/// user's leaves the span without one. A running span is skipped while the
+/// account already has one running.
+async fn import_time_entries(
+    tx: &mut sqlx::PgConnection,
+    user_id: Uuid,
+    entries: Vec<ArchiveTimeEntry>,
+    mode: ConflictMode,
+    report: &mut ArchiveImportReport,
+) -> Result<(), sqlx::Error> {
+    let entry_ids: Vec<Uuid> = entries.iter().map(|e| e.id).collect();
+    let (own, foreign) = split_owned(
+        sqlx::query!(
+            "SELECT id, user_id FROM time_entries WHERE id = ANY($1)",
+            &entry_ids
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|r| (r.id, r.user_id)),
+        user_id,
+    );
+
+    for entry in entries {
+        let task_id = entry.task_id.map(|id| report.id_map.get(&id).copied().unwrap_or(id));
+        let target = match (placement(entry.id, &own, &foreign), mode) {
+            (Placement::Keep, _) => entry.id,
+            (Placement::Remap, _) | (Placement::Conflict, ConflictMode::Duplicate) => Uuid::new_v4(),
+            (Placement::Conflict, ConflictMode::Skip) => {
+                report.time_entries.skipped += 1;
+                continue;
+            }
+            (Placement::Conflict, ConflictMode::Overwrite) => {
+                let updated = sqlx::query!(
+                    r#"
+                    UPDATE time_entries
+                    SET task_id = (SELECT id FROM tasks WHERE id = $1 AND user_id = $2),
+                        started_at = $3, stopped_at = $4
+                    WHERE id = $5 AND user_id = $2
+                      AND ($4::timestamptz IS NOT NULL
+                           OR NOT EXISTS (SELECT 1 FROM time_entries
+                                          WHERE user_id = $2 AND stopped_at IS NULL AND id <> $5))
+                    "#,
+                    task_id,
+                    user_id,
+                    entry.started_at,
+                    entry.stopped_at,
+                    entry.id
+                )
+                .execute(&mut *tx)
+                .await?;
+                if updated.rows_affected() == 0 {
+                    report.time_entries.skipped += 1;
+                } else {
+                    report.time_entries.overwritten += 1;
+                }
+                continue;
+            }
+        };
+
+        let inserted = sqlx::query!(
+            r#"
+            INSERT INTO time_entries (id, user_id, task_id, started_at, stopped_at)
+            VALUES ($1, $2, (SELECT id FROM tasks WHERE id = $3 AND user_id = $2), $4, $5)
+            ON CONFLICT DO NOTHING
+            "#,
+            target,
+            user_id,
+            task_id,
+            entry.started_at,
+            entry.stopped_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            report.time_entries.skipped += 1;
+            continue;
+        }
+        if target != entry.id {
+            report.id_map.insert(entry.id, target);
+        }
+        if own.contains(&entry.id) {
+            report.time_entries.duplicated += 1;
+        } else {
+            report.time_entries.created += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Stores each of `singletons` the archive carries. One the account
+/// already has is replaced in `overwrite` mode and kept otherwise.
+async fn import_singletons(
+    tx: &mut sqlx::PgConnection,
+    user_id: Uuid,
+    singletons: Singletons,
+    mode: ConflictMode,
+    counts: &mut ArchiveImportCounts,
+) -> Result<(), sqlx::Error> {
+    let overwrite = matches!(mode, ConflictMode::Overwrite);
+    // `None` when kept, otherwise whether a new row was inserted.
+    let mut outcomes: Vec<Option<bool>> = Vec::new();
+    if let Some(settings) = singletons.settings {
+        outcomes.push(
+            sqlx::query_scalar!(
+                r#"
+                INSERT INTO user_settings (user_id, locale, display_name, timezone, week_start, default_priority, default_status)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    locale = EXCLUDED.locale,
+                    display_name = EXCLUDED.display_name,
+                    timezone = EXCLUDED.timezone,
+                    week_start = EXCLUDED.week_start,
+                    default_priority = EXCLUDED.default_priority,
+                    default_status = EXCLUDED.default_status,
+                    updated_at = NOW()
+                WHERE $8
+                RETURNING xmax = 0 AS "inserted!"
+                "#,
+                user_id,
+                settings.locale,
+                settings.display_name,
+                settings.timezone,
+                settings.week_start,
+                settings.default_priority,
+                settings.default_status,
+                overwrite
+            )
+            .fetch_optional(&mut *tx)
+            .await?,
+        );
+    }
+    if let Some(schedule) = singletons.schedule {
+        outcomes.push(
+            sqlx::query_scalar!(
+                r#"
+                INSERT INTO briefing_schedules (user_id, local_time, weekdays, timezone, email, webhook_url, enabled)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (user_id) DO UPDATE SET
+                    local_time = EXCLUDED.local_time,
+                    weekdays = EXCLUDED.weekdays,
+                    timezone = EXCLUDED.timezone,
+                    email = EXCLUDED.email,
+                    webhook_url = EXCLUDED.webhook_url,
+                    enabled = EXCLUDED.enabled,
+                    updated_at = NOW()
+                WHERE $8
+                RETURNING xmax = 0 AS "inserted!"
+                "#,
+                user_id,
+                schedule.local_time,
+                &schedule.weekdays,
+                schedule.timezone,
+                schedule.email,
+                schedule.webhook_url,
+                schedule.enabled,
+                overwrite
+            )
+            .fetch_optional(&mut *tx)
+            .await?,
+        );
+    }
+    if let Some(layout) = singletons.layout {
+        outcomes.push(
+            sqlx::query_scalar!(
+                r#"
+                INSERT INTO dashboard_layouts (user_id, widgets)
+                VALUES ($1, $2)
+                ON CONFLICT (user_id) DO UPDATE SET widgets = EXCLUDED.widgets, updated_at = NOW()
+                WHERE $3
+                RETURNING xmax = 0 AS "inserted!"
+                "#,
+                user_id,
+                serde_json::to_value(&layout).unwrap_or_default(),
+                overwrite
+            )
+            .fetch_optional(&mut *tx)
+            .await?,
+        );
+    }
+    for outcome in outcomes {
+        match outcome {
+            Some(true) => counts.created += 1,
+            Some(false) => counts.overwritten += 1,
+            None => counts.skipped += 1,
+        }
+    }
+    Ok(())
+}
+
+fn split_owned(
+    rows: impl Iterator<Item = (Uuid, Uuid)>,
+    user_id: Uuid,
+) -> (HashSet<Uuid>, HashSet<Uuid>) {
+    let mut own = HashSet::new();
+    let mut foreign = HashSet::new();
+    for (id, owner) in rows {
+        if owner == user_id {
+            own.insert(id);
+        } else {
+            foreign.insert(id);
+        }
+    }
+    (own, foreign)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing::{read, read_json, TestApp};
+
+    async fn exported(app: &TestApp) -> String {
+        let (status, body) = read(export(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(status, 200, "{body}");
+        body
+    }
+
+    async fn import_into(app: &TestApp, body: &str, mode: ConflictMode) -> (u16, Value) {
+        let archive: Archive = serde_json::from_str(body).unwrap();
+        let query = ArchiveImportQuery { mode: Some(mode) };
+        read_json(import(State(app.state.clone()), AuthUser { user_id: app.user_id }, Query(query), Json(archive)).await).await
+    }
+
+    #[tokio::test]
+    async fn round_trips_settings_and_rewrites_links() {
+        let source = TestApp::start().await;
+        let (task, linked, note) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let pool = &source.state.pool;
+        sqlx::query!(
+            "INSERT INTO tasks (id, user_id, title, status, priority, tags) VALUES ($1, $2, '배포', 'todo', 'high', '{}')",
+            task,
+            source.user_id
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        for (id, content) in [(linked, "런북".to_string()), (note, format!("[[task:{task}|배포]] 전에 [[note:{linked}]] 확인"))] {
+            sqlx::query!(
+                "INSERT INTO notes (id, user_id, title, content, tags) VALUES ($1, $2, $3, $4, '{}')",
+                id,
+                source.user_id,
+                id.to_string(),
+                content
+            )
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+        sqlx::query!(
+            "INSERT INTO user_settings (user_id, timezone, week_start, default_priority) VALUES ($1, 'Europe/Berlin', 'sunday', 'low')",
+            source.user_id
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO briefing_schedules (user_id, local_time, weekdays) VALUES ($1, '07:30', '{mon,wed}')",
+            source.user_id
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"INSERT INTO dashboard_layouts (user_id, widgets) VALUES ($1, '[{"type": "week_calendar"}]')"#,
+            source.user_id
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        let archive = exported(&source).await;
+
+        // Every id belongs to another account, so all of them are remapped.
+        let target = TestApp::start().await;
+        let (status, report) = import_into(&target, &archive, ConflictMode::Skip).await;
+        assert_eq!(status, 200, "{report}");
+        assert_eq!(report["tasks"]["created"], 1);
+        assert_eq!(report["notes"]["created"], 2);
+        assert_eq!(report["settings"]["created"], 3);
+        let new_id = |id: Uuid| report["id_map"][id.to_string()].as_str().unwrap().parse::<Uuid>().unwrap();
+        let content = sqlx::query_scalar!("SELECT content FROM notes WHERE id = $1", new_id(note))
+            .fetch_one(&target.state.pool)
+            .await
+            .unwrap();
+        assert_eq!(content, format!("[[task:{}|배포]] 전에 [[note:{}]] 확인", new_id(task), new_id(linked)));
+        let prefs = crate::settings::preferences(&target.state.pool, target.user_id, target.state.default_tz).await;
+        assert_eq!(prefs.timezone, chrono_tz::Europe::Berlin);
+        assert_eq!(prefs.default_priority, "low");
+        let weekdays = sqlx::query_scalar!("SELECT weekdays FROM briefing_schedules WHERE user_id = $1", target.user_id)
+            .fetch_one(&target.state.pool)
+            .await
+            .unwrap();
+        assert_eq!(weekdays, ["mon", "wed"]);
+        assert_eq!(dashboard::layout(&target.state.pool, target.user_id).await.unwrap(), [Widget::WeekCalendar]);
+
+        // Back into the source account: everything is already there.
+        let (_, report) = import_into(&source, &archive, ConflictMode::Skip).await;
+        assert_eq!(report["tasks"]["skipped"], 1);
+        assert_eq!(report["settings"]["skipped"], 3);
+        let (_, report) = import_into(&source, &archive, ConflictMode::Overwrite).await;
+        assert_eq!(report["notes"]["overwritten"], 2);
+        assert_eq!(report["settings"]["overwritten"], 3);
+
+        let broken = archive.replace("Europe/Berlin", "Europe/Atlantis");
+        let (status, _) = read(
+            import(
+                State(target.state.clone()),
+                AuthUser { user_id: target.user_id },
+                Query(ArchiveImportQuery { mode: None }),
+                Json(serde_json::from_str::<Archive>(&broken).unwrap()),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn round_trips_subtask_hierarchies() {
+        let source = TestApp::start().await;
+        let (parent, child) = (Uuid::new_v4(), Uuid::new_v4());
+        for (id, parent_id, title, estimate) in [(parent, None, "출시", None), (child, Some(parent), "QA", Some(90))] {
+            sqlx::query!(
+                "INSERT INTO tasks (id, user_id, parent_id, title, status, priority, tags, estimate_minutes) VALUES ($1, $2, $3, $4, 'todo', 'medium', '{}', $5)",
+                id,
+                source.user_id,
+                parent_id,
+                title,
+                estimate
+            )
+            .execute(&source.state.pool)
+            .await
+            .unwrap();
+        }
+        let archive = exported(&source).await;
+
+        let target = TestApp::start().await;
+        let (status, report) = import_into(&target, &archive, ConflictMode::Skip).await;
+        assert_eq!(status, 200, "{report}");
+        let new_id = |id: Uuid| report["id_map"][id.to_string()].as_str().unwrap().parse::<Uuid>().unwrap();
+        let row = sqlx::query!("SELECT parent_id, estimate_minutes FROM tasks WHERE id = $1", new_id(child))
+            .fetch_one(&target.state.pool)
+            .await
+            .unwrap();
+        assert_eq!(row.parent_id, Some(new_id(parent)));
+        assert_eq!(row.estimate_minutes, Some(90));
+
+        // Duplicates hang under the duplicated parent, not the original.
+        let (_, report) = import_into(&source, &archive, ConflictMode::Duplicate).await;
+        assert_eq!(report["tasks"]["duplicated"], 2);
+        let copy = |id: Uuid| report["id_map"][id.to_string()].as_str().unwrap().parse::<Uuid>().unwrap();
+        let parent_id = sqlx::query_scalar!("SELECT parent_id FROM tasks WHERE id = $1", copy(child))
+            .fetch_one(&source.state.pool)
+            .await
+            .unwrap();
+        assert_eq!(parent_id, Some(copy(parent)));
+
+        // Without its parent the subtask comes in top-level.
+        let mut orphaned: Value = serde_json::from_str(&archive).unwrap();
+        orphaned["tasks"].as_array_mut().unwrap().retain(|t| t["id"] != parent.to_string());
+        let other = TestApp::start().await;
+        let (status, report) = import_into(&other, &orphaned.to_string(), ConflictMode::Skip).await;
+        assert_eq!(status, 200, "{report}");
+        let copy = |id: Uuid| report["id_map"][id.to_string()].as_str().unwrap().parse::<Uuid>().unwrap();
+        let parent_id = sqlx::query_scalar!("SELECT parent_id FROM tasks WHERE id = $1", copy(child))
+            .fetch_one(&other.state.pool)
+            .await
+            .unwrap();
+        assert_eq!(parent_id, None);
+    }
+
+    #[tokio::test]
+    async fn round_trips_time_entries_with_their_tasks() {
+        let source = TestApp::start().await;
+        let task = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO tasks (id, user_id, title, status, priority, tags) VALUES ($1, $2, '보고서', 'todo', 'medium', '{}')",
+            task,
+            source.user_id
+        )
+        .execute(&source.state.pool)
+        .await
+        .unwrap();
+        let (spent, running) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query!(
+            r#"
+            INSERT INTO time_entries (id, user_id, task_id, started_at, stopped_at)
+            VALUES ($1, $3, $4, NOW() - INTERVAL '2 hours', NOW() - INTERVAL '1 hour'),
+                   ($2, $3, NULL, NOW() - INTERVAL '10 minutes', NULL)
+            "#,
+            spent,
+            running,
+            source.user_id,
+            task
+        )
+        .execute(&source.state.pool)
+        .await
+        .unwrap();
+        let mut archive: Value = serde_json::from_str(&exported(&source).await).unwrap();
+        // A span of a task the archive does not carry.
+        let stray = Uuid::new_v4();
+        archive["time_entries"].as_array_mut().unwrap().push(serde_json::json!({
+            "id": stray,
+            "task_id": Uuid::new_v4(),
+            "started_at": "2024-12-02T09:00:00Z",
+            "stopped_at": "2024-12-02T10:00:00Z",
+        }));
+        let archive = archive.to_string();
+
+        // The target's own timer is running, so the archived one is skipped.
+        let target = TestApp::start().await;
+        crate::timers::start(&mut target.state.pool.acquire().await.unwrap(), target.user_id, None)
+            .await
+            .unwrap();
+        let (status, report) = import_into(&target, &archive, ConflictMode::Skip).await;
+        assert_eq!(status, 200, "{report}");
+        assert_eq!(report["time_entries"]["created"], 2);
+        assert_eq!(report["time_entries"]["skipped"], 1);
+        let new_id = |id: Uuid| report["id_map"][id.to_string()].as_str().unwrap().parse::<Uuid>().unwrap();
+        let task_of = |id: Uuid| {
+            let pool = target.state.pool.clone();
+            async move {
+                sqlx::query_scalar!("SELECT task_id FROM time_entries WHERE id = $1", id)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(task_of(new_id(spent)).await, Some(new_id(task)));
+        assert_eq!(task_of(stray).await, None);
+
+        let (_, report) = import_into(&source, &archive, ConflictMode::Skip).await;
+        assert_eq!(report["time_entries"]["skipped"], 2);
+
+        let backwards = archive.replace("2024-12-02T10:00:00Z", "2024-12-02T08:00:00Z");
+        let (status, _) = read(
+            import(
+                State(target.state.clone()),
+                AuthUser { user_id: target.user_id },
+                Query(ArchiveImportQuery { mode: None }),
+                Json(serde_json::from_str::<Archive>(&backwards).unwrap()),
+            )
+            .await,
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+}
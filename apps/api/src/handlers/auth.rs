@@ -1,6 +1,7 @@
 use axum::{extract::State, response::IntoResponse, Json};
 use uuid::Uuid;
 
+use crate::analytics;
 use crate::auth::{create_jwt, hash_password, verify_password};
 use crate::middleware::AppState;
 use crate::models::{AuthResponse, LoginPayload, SignupPayload};
@@ -38,6 +39,11 @@ pub async fn signup(
         }
         return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response();
     }
+    // An empty rollup, so task writes have days to keep from the start.
+    let rollup = async { analytics::rebuild(&mut *state.pool.acquire().await?, user_id, state.default_tz).await };
+    if rollup.await.is_err() {
+        return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response();
+    }
 
     let token = match create_jwt(user_id, &state.jwt_secret) {
         Ok(t) => t,
@@ -0,0 +1,382 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::analytics;
+use crate::handlers::tasks::{self, validate_create, NewTask};
+use crate::llm::{ChatMessage, LlmError, LlmProvider};
+use crate::middleware::{AppState, AuthUser};
+use crate::models::{Task, TaskCreate};
+use crate::prompts::{Audience, Prompt, Prompts};
+use crate::settings::Preferences;
+use crate::usage::Metered;
+
+/// Most subtasks asked of the model, and kept from its answer.
+const MAX_PROPOSED: usize = 10;
+/// Most subtasks accepted in one request.
+const MAX_ACCEPTED: usize = 50;
+
+/// A subtask suggested by the model. Clients send the ones they keep,
+/// edited or not, back to the accept endpoint.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ProposedSubtask {
+    pub title: String,
+    pub description: Option<String>,
+    pub estimate_minutes: Option<i32>,
+    pub start_date: Option<NaiveDate>,
+    pub due_date: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+pub struct BreakdownProposal {
+    pub task_id: Uuid,
+    pub model: String,
+    pub subtasks: Vec<ProposedSubtask>,
+}
+
+#[derive(Deserialize)]
+pub struct BreakdownAccept {
+    pub subtasks: Vec<ProposedSubtask>,
+}
+
+#[derive(Serialize)]
+pub struct Subtask {
+    pub id: Uuid,
+    pub parent_id: Uuid,
+    pub title: String,
+    pub description: Option<String>,
+    pub status: String,
+    pub priority: String,
+    pub due_date: Option<NaiveDate>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub tags: Vec<String>,
+    pub estimate_minutes: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct RawProposal {
+    #[serde(default)]
+    subtasks: Vec<RawSubtask>,
+}
+
+#[derive(Deserialize)]
+struct RawSubtask {
+    title: Option<String>,
+    description: Option<String>,
+    estimate_minutes: Option<Value>,
+    start_date: Option<Value>,
+    due_date: Option<Value>,
+}
+
+/// Asks the model to split the task into subtasks. Nothing is stored; the
+/// proposal is returned for review.
+pub async fn propose(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let task = match fetch_task(&state, user_id, id).await {
+        Ok(Some(task)) => task,
+        Ok(None) => return (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let audience = state.prompts.audience(&state.pool, user_id, state.default_tz).await;
+    let messages = breakdown_messages(&state.prompts, &audience, &task);
+    let llm = Metered::limited(&state, user_id, "breakdown");
+    let subtasks = match llm.chat_json(&messages).await {
+        Ok(completion) => match parse(&completion.content) {
+            Some(subtasks) => subtasks,
+            None => {
+                tracing::warn!(content = %completion.content, "task breakdown unparseable");
+                let reason = state.prompts.render(&audience, Prompt::BreakdownUnreadable, minijinja::Value::UNDEFINED);
+                return LlmError::Malformed(reason).respond(&state.prompts, &audience);
+            }
+        },
+        Err(err) => return err.respond(&state.prompts, &audience),
+    };
+
+    Json(BreakdownProposal {
+        task_id: task.id,
+        model: llm.model().to_string(),
+        subtasks,
+    })
+    .into_response()
+}
+
+/// Stores the given subtasks under the task, all or none. They start as
+/// `todo` and take the parent's priority and tags.
+pub async fn accept(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<BreakdownAccept>,
+) -> impl IntoResponse {
+    if payload.subtasks.is_empty() {
+        return (axum::http::StatusCode::BAD_REQUEST, "subtasks required").into_response();
+    }
+    if payload.subtasks.len() > MAX_ACCEPTED {
+        return (axum::http::StatusCode::BAD_REQUEST, "too many subtasks").into_response();
+    }
+    let parent = match fetch_task(&state, user_id, id).await {
+        Ok(Some(task)) => task,
+        Ok(None) => return (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    // Subtasks name their status and priority, so no user defaults apply.
+    let defaults = Preferences::defaults(state.default_tz);
+    let mut validated = Vec::with_capacity(payload.subtasks.len());
+    for subtask in payload.subtasks {
+        if subtask.estimate_minutes.is_some_and(|m| m <= 0) {
+            return (axum::http::StatusCode::BAD_REQUEST, "invalid estimate_minutes").into_response();
+        }
+        let new_task = validate_create(TaskCreate {
+            title: subtask.title,
+            description: subtask.description,
+            status: Some("todo".to_string()),
+            priority: Some(parent.priority.clone()),
+            due_date: subtask.due_date,
+            start_date: subtask.start_date,
+            end_date: None,
+            tags: Some(parent.tags.clone()),
+        }, &defaults);
+        match new_task {
+            Ok(new_task) => validated.push(NewTask {
+                parent_id: Some(parent.id),
+                estimate_minutes: subtask.estimate_minutes,
+                ..new_task
+            }),
+            Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+        }
+    }
+
+    let created = async {
+        let mut tx = state.pool.begin().await?;
+        let mut created = Vec::with_capacity(validated.len());
+        for task in &validated {
+            let task = tasks::insert_row(&mut tx, user_id, task).await?.ok_or(sqlx::Error::RowNotFound)?;
+            created.push(Subtask {
+                id: task.id,
+                parent_id: parent.id,
+                title: task.title,
+                description: task.description,
+                status: task.status,
+                priority: task.priority,
+                due_date: task.due_date,
+                start_date: task.start_date,
+                end_date: task.end_date,
+                tags: task.tags,
+                estimate_minutes: task.estimate_minutes,
+                created_at: task.created_at,
+            });
+        }
+        let touched: Vec<_> = created.iter().map(|s| s.created_at).collect();
+        analytics::touch(&mut tx, user_id, &touched).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(created)
+    }
+    .await;
+
+    match created {
+        Ok(created) => {
+            state.embeddings.wake();
+            (axum::http::StatusCode::CREATED, Json(created)).into_response()
+        }
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    match fetch_task(&state, user_id, id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+
+    let rows = sqlx::query_as!(
+        Subtask,
+        r#"
+        SELECT id, parent_id AS "parent_id!", title, description, status, priority, due_date, start_date, end_date, tags, estimate_minutes, created_at
+        FROM tasks
+        WHERE parent_id = $1 AND user_id = $2
+        ORDER BY created_at, title
+        "#,
+        id,
+        user_id
+    )
+    .fetch_all(&state.pool)
+    .await;
+
+    match rows {
+        Ok(items) => Json(items).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+async fn fetch_task(state: &AppState, user_id: Uuid, id: Uuid) -> Result<Option<Task>, sqlx::Error> {
+    sqlx::query_as!(
+        Task,
+        "SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at FROM tasks WHERE id = $1 AND user_id = $2",
+        id,
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await
+}
+
+fn breakdown_messages(prompts: &Prompts, audience: &Audience, task: &Task) -> Vec<ChatMessage> {
+    let system = prompts.render(
+        audience,
+        Prompt::BreakdownSystem,
+        minijinja::context! { max_subtasks => MAX_PROPOSED },
+    );
+
+    let end_date = task.end_date.or(task.due_date);
+    let details = prompts.render(
+        audience,
+        Prompt::BreakdownRequest,
+        minijinja::context! {
+            title => task.title,
+            description => task.description.as_deref().map(str::trim).filter(|d| !d.is_empty()),
+            tags => task.tags,
+            start_date => task.start_date.map(|d| d.to_string()),
+            end_date => end_date.map(|d| d.to_string()),
+            priority => task.priority,
+        },
+    );
+
+    vec![ChatMessage::system(system), ChatMessage::user(details)]
+}
+
+/// Reads the model's JSON, tolerating prose or code fences around it.
+/// Items without a title are dropped, and so are values that do not fit
+/// their field, so one sloppy item does not spoil the rest.
+fn parse(content: &str) -> Option<Vec<ProposedSubtask>> {
+    let start = content.find('{')?;
+    let end = content.rfind('}')?;
+    let raw: RawProposal = serde_json::from_str(content.get(start..=end)?).ok()?;
+    let subtasks: Vec<ProposedSubtask> = raw
+        .subtasks
+        .into_iter()
+        .filter_map(|item| {
+            let title = item.title?.trim().to_string();
+            if title.is_empty() {
+                return None;
+            }
+            let mut start_date = item.start_date.as_ref().and_then(date);
+            let due_date = item.due_date.as_ref().and_then(date);
+            if let (Some(start), Some(due)) = (start_date, due_date) {
+                if start > due {
+                    start_date = None;
+                }
+            }
+            Some(ProposedSubtask {
+                title,
+                description: item
+                    .description
+                    .map(|d| d.trim().to_string())
+                    .filter(|d| !d.is_empty()),
+                estimate_minutes: item.estimate_minutes.as_ref().and_then(minutes),
+                start_date,
+                due_date,
+            })
+        })
+        .take(MAX_PROPOSED)
+        .collect();
+    (!subtasks.is_empty()).then_some(subtasks)
+}
+
+fn date(value: &Value) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.as_str()?.trim(), "%Y-%m-%d").ok()
+}
+
+fn minutes(value: &Value) -> Option<i32> {
+    let minutes = match value {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(s) => s.trim().parse().ok()?,
+        _ => return None,
+    };
+    (minutes >= 1.0 && minutes <= i32::MAX as f64).then(|| minutes.round() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompts::tests::{audience, shipped};
+
+    #[test]
+    fn parses_fenced_proposal() {
+        let content = "```json\n{\"subtasks\": [\
+            {\"title\": \" 자료 조사 \", \"estimate_minutes\": 90, \"due_date\": \"2024-12-02\"},\
+            {\"title\": \"초안 작성\", \"description\": \"\", \"estimate_minutes\": \"120\", \"start_date\": \"2024-12-03\", \"due_date\": \"2024-12-04\"}\
+        ]}\n```";
+        let subtasks = parse(content).unwrap();
+        assert_eq!(subtasks.len(), 2);
+        assert_eq!(subtasks[0].title, "자료 조사");
+        assert_eq!(subtasks[0].estimate_minutes, Some(90));
+        assert_eq!(subtasks[0].due_date, NaiveDate::from_ymd_opt(2024, 12, 2));
+        assert_eq!(subtasks[1].description, None);
+        assert_eq!(subtasks[1].estimate_minutes, Some(120));
+        assert_eq!(subtasks[1].start_date, NaiveDate::from_ymd_opt(2024, 12, 3));
+    }
+
+    #[test]
+    fn drops_bad_items_and_values() {
+        let content = r#"{"subtasks": [
+            {"title": ""},
+            {"description": "no title"},
+            {"title": "검토", "estimate_minutes": -5, "start_date": "2024-12-10", "due_date": "2024-12-01"},
+            {"title": "배포", "estimate_minutes": "soon", "due_date": "next week"}
+        ]}"#;
+        let subtasks = parse(content).unwrap();
+        assert_eq!(subtasks.len(), 2);
+        assert_eq!(subtasks[0].estimate_minutes, None);
+        assert_eq!(subtasks[0].start_date, None);
+        assert_eq!(subtasks[0].due_date, NaiveDate::from_ymd_opt(2024, 12, 1));
+        assert_eq!(subtasks[1].due_date, None);
+    }
+
+    #[test]
+    fn rejects_empty_or_prose_answers() {
+        assert_eq!(parse("작업을 나눌 수 없습니다."), None);
+        assert_eq!(parse(r#"{"subtasks": []}"#), None);
+    }
+
+    #[test]
+    fn describes_the_task_in_the_users_locale() {
+        let task = Task {
+            id: Uuid::new_v4(),
+            user_id: Uuid::nil(),
+            title: "출시 준비".to_string(),
+            description: Some("  ".to_string()),
+            status: "todo".to_string(),
+            priority: "high".to_string(),
+            due_date: NaiveDate::from_ymd_opt(2024, 12, 6),
+            start_date: None,
+            end_date: None,
+            tags: vec!["release".to_string(), "q4".to_string()],
+            parent_id: None,
+            estimate_minutes: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+            started_at: None,
+            completed_at: None,
+        };
+        let details = |locale: &str| breakdown_messages(&shipped(), &audience(locale), &task)[1].content.clone();
+        assert_eq!(details("ko"), "제목: 출시 준비\n태그: release, q4\n마감: 2024-12-06\n우선순위: high");
+        assert_eq!(details("en"), "Title: 출시 준비\nTags: release, q4\nDue: 2024-12-06\nPriority: high");
+    }
+}
@@ -0,0 +1,321 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::briefings::{self, Briefing, Schedule, Targets, WEEKDAYS};
+use crate::middleware::{AppState, AuthUser};
+use crate::settings;
+use crate::usage::Metered;
+
+#[derive(Deserialize)]
+pub struct BriefingListQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScheduleUpdate {
+    /// Local time, `HH:MM`.
+    pub time: String,
+    /// Defaults to Monday to Friday.
+    pub weekdays: Option<Vec<String>>,
+    pub timezone: Option<String>,
+    pub email: Option<String>,
+    pub webhook_url: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Query(query): Query<BriefingListQuery>,
+) -> impl IntoResponse {
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = (page - 1) * limit;
+
+    let rows = sqlx::query_as!(
+        Briefing,
+        r#"
+        SELECT id, briefing_date, content, source, model, deliveries, created_at
+        FROM briefings
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        user_id,
+        limit,
+        offset
+    )
+    .fetch_all(&state.pool)
+    .await;
+
+    match rows {
+        Ok(items) => Json(items).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// Writes and sends today's briefing now, outside the schedule.
+pub async fn create(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let schedule = sqlx::query!(
+        "SELECT timezone, email, webhook_url FROM briefing_schedules WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await;
+    let user_tz = settings::preferences(&state.pool, user_id, state.default_tz).await.timezone;
+    let (tz, targets) = match schedule {
+        Ok(Some(row)) => (
+            settings::zone(row.timezone.as_deref(), user_tz),
+            Targets {
+                email: row.email,
+                webhook_url: row.webhook_url,
+            },
+        ),
+        Ok(None) => (user_tz, Targets::default()),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    let llm = Metered::limited(&state, user_id, "briefing");
+    match briefings::run(&state, &llm, user_id, today, &targets).await {
+        Ok(briefing) => (axum::http::StatusCode::CREATED, Json(briefing)).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn get_schedule(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let row = sqlx::query_as!(
+        Schedule,
+        r#"
+        SELECT local_time, weekdays, timezone, email, webhook_url, enabled, last_run_on, created_at, updated_at
+        FROM briefing_schedules
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await;
+
+    match row {
+        Ok(Some(schedule)) => Json(schedule).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn put_schedule(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<ScheduleUpdate>,
+) -> impl IntoResponse {
+    let schedule = match validate_schedule(payload) {
+        Ok(schedule) => schedule,
+        Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    let row = sqlx::query_as!(
+        Schedule,
+        r#"
+        INSERT INTO briefing_schedules (user_id, local_time, weekdays, timezone, email, webhook_url, enabled)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (user_id) DO UPDATE SET
+            local_time = EXCLUDED.local_time,
+            weekdays = EXCLUDED.weekdays,
+            timezone = EXCLUDED.timezone,
+            email = EXCLUDED.email,
+            webhook_url = EXCLUDED.webhook_url,
+            enabled = EXCLUDED.enabled,
+            updated_at = NOW()
+        RETURNING local_time, weekdays, timezone, email, webhook_url, enabled, last_run_on, created_at, updated_at
+        "#,
+        user_id,
+        schedule.local_time,
+        &schedule.weekdays,
+        schedule.timezone,
+        schedule.email,
+        schedule.webhook_url,
+        schedule.enabled
+    )
+    .fetch_one(&state.pool)
+    .await;
+
+    match row {
+        Ok(schedule) => Json(schedule).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// A schedule as it is stored.
+pub(crate) struct ValidSchedule {
+    pub local_time: NaiveTime,
+    pub weekdays: Vec<String>,
+    pub timezone: Option<String>,
+    pub email: Option<String>,
+    pub webhook_url: Option<String>,
+    pub enabled: bool,
+}
+
+pub(crate) fn validate_schedule(payload: ScheduleUpdate) -> Result<ValidSchedule, &'static str> {
+    let time = payload.time.trim();
+    let Ok(local_time) = NaiveTime::parse_from_str(time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+    else {
+        return Err("invalid time");
+    };
+
+    let weekdays = match payload.weekdays {
+        Some(days) => {
+            let days: Vec<String> = days.iter().map(|d| d.trim().to_lowercase()).collect();
+            if days.is_empty() || days.iter().any(|d| !WEEKDAYS.contains(&d.as_str())) {
+                return Err("invalid weekdays");
+            }
+            // Stored in week order, once each.
+            WEEKDAYS
+                .iter()
+                .filter(|w| days.iter().any(|d| d == *w))
+                .map(|w| w.to_string())
+                .collect()
+        }
+        None => WEEKDAYS[..5].iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+    };
+
+    let timezone = payload.timezone.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    if timezone.as_deref().is_some_and(|t| t.parse::<Tz>().is_err()) {
+        return Err("invalid timezone");
+    }
+    let email = payload.email.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+    if email.as_deref().is_some_and(|e| e.parse::<lettre::Address>().is_err()) {
+        return Err("invalid email");
+    }
+    let webhook_url = payload.webhook_url.map(|u| u.trim().to_string()).filter(|u| !u.is_empty());
+    if webhook_url
+        .as_deref()
+        .is_some_and(|u| !reqwest::Url::parse(u).is_ok_and(|url| briefings::is_public_url(&url)))
+    {
+        return Err("invalid webhook_url");
+    }
+
+    Ok(ValidSchedule {
+        local_time,
+        weekdays,
+        timezone,
+        email,
+        webhook_url,
+        enabled: payload.enabled.unwrap_or(true),
+    })
+}
+
+pub async fn delete_schedule(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let res = sqlx::query!("DELETE FROM briefing_schedules WHERE user_id = $1", user_id)
+        .execute(&state.pool)
+        .await;
+
+    match res {
+        Ok(r) if r.rows_affected() == 1 => (axum::http::StatusCode::NO_CONTENT, "").into_response(),
+        Ok(_) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{read, read_json, TestApp};
+
+    async fn put(app: &TestApp, body: serde_json::Value) -> (u16, String) {
+        let payload = serde_json::from_value(body).unwrap();
+        read(put_schedule(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(payload)).await).await
+    }
+
+    #[tokio::test]
+    async fn stores_validated_schedules() {
+        let app = TestApp::start().await;
+        let (status, body) = put(&app, json!({ "time": "07:30" })).await;
+        assert_eq!(status, 200, "{body}");
+        let schedule: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(schedule["weekdays"], json!(["mon", "tue", "wed", "thu", "fri"]));
+        assert_eq!(schedule["enabled"], true);
+
+        let (status, body) = put(
+            &app,
+            json!({
+                "time": "21:00:00",
+                "weekdays": ["Sun", "wed", "wed"],
+                "timezone": "Europe/Berlin",
+                "webhook_url": " https://hooks.example.com/briefing ",
+                "enabled": false
+            }),
+        )
+        .await;
+        assert_eq!(status, 200, "{body}");
+        let schedule: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(schedule["local_time"], "21:00:00");
+        assert_eq!(schedule["weekdays"], json!(["wed", "sun"]));
+        assert_eq!(schedule["webhook_url"], "https://hooks.example.com/briefing");
+        assert_eq!(schedule["enabled"], false);
+
+        for (body, error) in [
+            (json!({ "time": "7시" }), "invalid time"),
+            (json!({ "time": "07:00", "weekdays": [] }), "invalid weekdays"),
+            (json!({ "time": "07:00", "timezone": "Mars/Olympus" }), "invalid timezone"),
+            (json!({ "time": "07:00", "email": "nobody" }), "invalid email"),
+            (json!({ "time": "07:00", "webhook_url": "http://169.254.169.254/latest" }), "invalid webhook_url"),
+            (json!({ "time": "07:00", "webhook_url": "http://localhost:8080/" }), "invalid webhook_url"),
+            (json!({ "time": "07:00", "webhook_url": "file:///etc/passwd" }), "invalid webhook_url"),
+        ] {
+            assert_eq!(put(&app, body).await, (400, error.to_string()));
+        }
+        let enabled = sqlx::query_scalar!("SELECT enabled FROM briefing_schedules WHERE user_id = $1", app.user_id)
+            .fetch_one(&app.state.pool)
+            .await
+            .unwrap();
+        assert!(!enabled, "rejected updates leave the schedule alone");
+    }
+
+    #[tokio::test]
+    async fn creates_a_briefing_and_refuses_local_webhooks() {
+        let app = TestApp::start().await;
+        app.model.push_reply("- 오늘은 배포 점검이 있어요.");
+        let (status, briefing) = read_json(create(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(status, 201, "{briefing}");
+        assert_eq!(briefing["source"], "model");
+        assert_eq!(briefing["content"], "- 오늘은 배포 점검이 있어요.");
+        assert_eq!(briefing["deliveries"], json!([]));
+
+        // Stored before webhooks were checked; delivery still refuses it.
+        sqlx::query!(
+            "INSERT INTO briefing_schedules (user_id, local_time, weekdays, webhook_url) VALUES ($1, '08:00', '{mon}', $2)",
+            app.user_id,
+            app.model.url()
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+        app.model.push_reply("- 할 일 없음");
+        let (status, briefing) = read_json(create(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(status, 201, "{briefing}");
+        let delivery = &briefing["deliveries"][0];
+        assert_eq!(delivery["channel"], "webhook");
+        assert_eq!(delivery["ok"], false);
+        assert_eq!(delivery["error"], "webhook host is not public");
+        assert_eq!(app.model.remaining(), 0);
+    }
+}
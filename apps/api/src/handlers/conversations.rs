@@ -0,0 +1,158 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::conversations::{Conversation, ConversationMessage};
+use crate::middleware::{AppState, AuthUser};
+
+#[derive(Deserialize)]
+pub struct ConversationRename {
+    pub title: String,
+}
+
+#[derive(Serialize)]
+pub struct ConversationDetail {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    pub messages: Vec<ConversationMessage>,
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let rows = sqlx::query_as!(
+        Conversation,
+        r#"
+        SELECT c.id, c.title, c.summary, c.created_at, c.updated_at,
+               (SELECT COUNT(*) FROM ai_messages m WHERE m.conversation_id = c.id) AS "message_count!"
+        FROM ai_conversations c
+        WHERE c.user_id = $1
+        ORDER BY c.updated_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&state.pool)
+    .await;
+
+    match rows {
+        Ok(items) => Json(items).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let row = async {
+        let Some(conversation) = fetch_conversation(&state, user_id, id).await? else {
+            return Ok(None);
+        };
+        let messages = sqlx::query_as!(
+            ConversationMessage,
+            r#"
+            SELECT id, role, content, summarized, created_at FROM ai_messages
+            WHERE conversation_id = $1
+            ORDER BY created_at, id
+            "#,
+            id
+        )
+        .fetch_all(&state.pool)
+        .await?;
+        Ok::<_, sqlx::Error>(Some(ConversationDetail {
+            conversation,
+            messages,
+        }))
+    }
+    .await;
+
+    match row {
+        Ok(Some(detail)) => Json(detail).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn rename(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<ConversationRename>,
+) -> impl IntoResponse {
+    let title = payload.title.trim();
+    if title.is_empty() {
+        return (
+            axum::http::StatusCode::BAD_REQUEST,
+            "title required",
+        )
+            .into_response();
+    }
+
+    let row = async {
+        let updated = sqlx::query!(
+            "UPDATE ai_conversations SET title = $1, updated_at = NOW() WHERE id = $2 AND user_id = $3",
+            title,
+            id,
+            user_id
+        )
+        .execute(&state.pool)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Ok(None);
+        }
+        fetch_conversation(&state, user_id, id).await
+    }
+    .await;
+
+    match row {
+        Ok(Some(conversation)) => Json(conversation).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let res = sqlx::query!(
+        "DELETE FROM ai_conversations WHERE id = $1 AND user_id = $2",
+        id,
+        user_id
+    )
+    .execute(&state.pool)
+    .await;
+
+    match res {
+        Ok(r) if r.rows_affected() == 1 => (axum::http::StatusCode::NO_CONTENT, "").into_response(),
+        Ok(_) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+async fn fetch_conversation(
+    state: &AppState,
+    user_id: Uuid,
+    id: Uuid,
+) -> Result<Option<Conversation>, sqlx::Error> {
+    sqlx::query_as!(
+        Conversation,
+        r#"
+        SELECT c.id, c.title, c.summary, c.created_at, c.updated_at,
+               (SELECT COUNT(*) FROM ai_messages m WHERE m.conversation_id = c.id) AS "message_count!"
+        FROM ai_conversations c
+        WHERE c.id = $1 AND c.user_id = $2
+        "#,
+        id,
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await
+}
@@ -1,70 +1,263 @@
 use axum::{extract::State, response::IntoResponse, Json};
-use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
+use crate::dashboard::{self, Widget};
 use crate::middleware::{AppState, AuthUser};
-use crate::models::{DashboardSummary, Task};
+use crate::models::DashboardSummary;
+use crate::settings;
 
 pub async fn summary(
     State(state): State<AppState>,
     AuthUser { user_id }: AuthUser,
 ) -> impl IntoResponse {
-    let today = Utc::now().date_naive();
-    let week_start = Utc::now() - chrono::Duration::days(7);
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    let (counts, recent_tasks) = futures::join!(
+        dashboard::counts(&state.pool, user_id, &prefs),
+        dashboard::recent_tasks(&state.pool, user_id, 10)
+    );
+    let (Ok(counts), Ok(recent_tasks)) = (counts, recent_tasks) else {
+        return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response();
+    };
 
-    let total_tasks = sqlx::query_scalar!(
-        "SELECT COUNT(*) FROM tasks WHERE user_id = $1",
-        user_id
-    )
-    .fetch_one(&state.pool)
-    .await
-    .unwrap_or(Some(0))
-    .unwrap_or(0);
+    Json(DashboardSummary {
+        total_tasks: counts.total_tasks,
+        due_today: counts.due_today,
+        overdue: counts.overdue,
+        done_this_week: counts.done_this_week,
+        recent_tasks,
+    })
+    .into_response()
+}
 
-    let due_today = sqlx::query_scalar!(
-        "SELECT COUNT(*) FROM tasks WHERE user_id = $1 AND COALESCE(start_date, due_date) <= $2 AND COALESCE(end_date, due_date) >= $2",
-        user_id,
-        today
-    )
-    .fetch_one(&state.pool)
-    .await
-    .unwrap_or(Some(0))
-    .unwrap_or(0);
+#[derive(Serialize, Deserialize)]
+pub struct Layout {
+    pub widgets: Vec<Widget>,
+}
 
-    let overdue = sqlx::query_scalar!(
-        "SELECT COUNT(*) FROM tasks WHERE user_id = $1 AND COALESCE(end_date, due_date) < $2 AND status != 'done'",
-        user_id,
-        today
+#[derive(Serialize)]
+pub struct WidgetResponse {
+    #[serde(flatten)]
+    pub widget: Widget,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    /// Set instead of `data` when this widget could not be computed; the
+    /// others are still shown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DashboardResponse {
+    pub widgets: Vec<WidgetResponse>,
+}
+
+/// Every widget of the user's layout with its data, computed concurrently.
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let widgets = match dashboard::layout(&state.pool, user_id).await {
+        Ok(widgets) => widgets,
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+
+    let computed = futures::future::join_all(
+        widgets
+            .iter()
+            .map(|widget| dashboard::compute(&state.pool, user_id, &prefs, widget)),
     )
-    .fetch_one(&state.pool)
-    .await
-    .unwrap_or(Some(0))
-    .unwrap_or(0);
+    .await;
+    let widgets = widgets
+        .into_iter()
+        .zip(computed)
+        .map(|(widget, data)| match data {
+            Ok(data) => WidgetResponse {
+                widget,
+                data: Some(data),
+                error: None,
+            },
+            Err(err) => {
+                tracing::warn!(user_id = %user_id, ?widget, "dashboard widget failed: {err}");
+                WidgetResponse {
+                    widget,
+                    data: None,
+                    error: Some("db error".to_string()),
+                }
+            }
+        })
+        .collect();
+    Json(DashboardResponse { widgets }).into_response()
+}
 
-    let done_this_week = sqlx::query_scalar!(
-        "SELECT COUNT(*) FROM tasks WHERE user_id = $1 AND status = 'done' AND updated_at >= $2",
+pub async fn get_layout(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    match dashboard::layout(&state.pool, user_id).await {
+        Ok(widgets) => Json(Layout { widgets }).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn put_layout(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<Layout>,
+) -> impl IntoResponse {
+    if let Err(msg) = dashboard::validate(&payload.widgets) {
+        return (axum::http::StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO dashboard_layouts (user_id, widgets)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET widgets = EXCLUDED.widgets, updated_at = NOW()
+        "#,
         user_id,
-        week_start
-    )
-    .fetch_one(&state.pool)
-    .await
-    .unwrap_or(Some(0))
-    .unwrap_or(0);
-
-    let recent_tasks = sqlx::query_as!(
-        Task,
-        "SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, created_at, updated_at FROM tasks WHERE user_id = $1 ORDER BY updated_at DESC LIMIT 10",
-        user_id
+        serde_json::to_value(&payload.widgets).unwrap_or_default()
     )
-    .fetch_all(&state.pool)
-    .await
-    .unwrap_or_default();
+    .execute(&state.pool)
+    .await;
+    match res {
+        Ok(_) => Json(payload).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
 
-    Json(DashboardSummary {
-        total_tasks,
-        due_today,
-        overdue,
-        done_this_week,
-        recent_tasks,
-    })
-    .into_response()
+/// Goes back to the default layout.
+pub async fn delete_layout(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    match sqlx::query!("DELETE FROM dashboard_layouts WHERE user_id = $1", user_id)
+        .execute(&state.pool)
+        .await
+    {
+        Ok(_) => axum::http::StatusCode::NO_CONTENT.into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, Utc};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::testing::{read, read_json, TestApp};
+
+    async fn dashboard(app: &TestApp) -> Value {
+        let (status, body) = read_json(get(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(status, 200, "{body}");
+        body
+    }
+
+    async fn save(app: &TestApp, widgets: Value) -> u16 {
+        let layout: Layout = serde_json::from_value(serde_json::json!({ "widgets": widgets })).unwrap();
+        read(put_layout(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(layout)).await).await.0
+    }
+
+    async fn add_task(app: &TestApp, title: &str, status: &str, tags: &[&str], end_in_days: i64) -> Uuid {
+        let id = Uuid::new_v4();
+        let end = crate::dates::today_in(app.state.default_tz) + TimeDelta::days(end_in_days);
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO tasks (id, user_id, title, status, priority, due_date, start_date, end_date, tags)
+            VALUES ($1, $2, $3, $4, 'medium', $5, $5, $5, $6)
+            "#,
+            id,
+            app.user_id,
+            title,
+            status,
+            end,
+            &tags
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn default_layout_is_the_summary() {
+        let app = TestApp::start().await;
+        add_task(&app, "오늘 할 일", "todo", &[], 0).await;
+        add_task(&app, "밀린 일", "todo", &[], -2).await;
+
+        let body = dashboard(&app).await;
+        let widgets = body["widgets"].as_array().unwrap();
+        assert_eq!(widgets.len(), 2);
+        assert_eq!(widgets[0]["type"], "summary");
+        assert_eq!(widgets[0]["data"]["total_tasks"], 2);
+        assert_eq!(widgets[0]["data"]["due_today"], 1);
+        assert_eq!(widgets[0]["data"]["overdue"], 1);
+        assert_eq!(widgets[1]["type"], "recent_tasks");
+        assert_eq!(widgets[1]["limit"], 10);
+        assert_eq!(widgets[1]["data"].as_array().unwrap().len(), 2);
+
+        let (_, summary) = read_json(summary(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(summary["due_today"], 1);
+        assert_eq!(summary["recent_tasks"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn custom_layout_with_parameters() {
+        let app = TestApp::start().await;
+        let late = add_task(&app, "보고서", "todo", &["ops", "docs"], -3).await;
+        add_task(&app, "배포", "in_progress", &["ops"], 0).await;
+        add_task(&app, "지난 일", "done", &["ops"], -1).await;
+        let note = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO notes (id, user_id, title, content, tags) VALUES ($1, $2, '온콜 절차', '...', '{}')",
+            note,
+            app.user_id
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO time_entries (id, user_id, task_id, started_at) VALUES ($1, $2, $3, $4)",
+            Uuid::new_v4(),
+            app.user_id,
+            late,
+            Utc::now() - TimeDelta::minutes(5)
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+        let widgets = serde_json::json!([
+            { "type": "overdue_tasks", "limit": 5 },
+            { "type": "tag_counts" },
+            { "type": "pinned_notes", "note_ids": [Uuid::new_v4(), note] },
+            { "type": "week_calendar" },
+            { "type": "timer" },
+        ]);
+        assert_eq!(save(&app, widgets).await, 200);
+        let (_, layout) = read_json(get_layout(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(layout["widgets"][1]["limit"], 10);
+
+        let body = dashboard(&app).await;
+        let data = |i: usize| body["widgets"][i]["data"].clone();
+        assert_eq!(data(0)[0]["title"], "보고서");
+        assert_eq!(data(0).as_array().unwrap().len(), 1);
+        assert_eq!(data(1), serde_json::json!([{ "tag": "ops", "tasks": 2 }, { "tag": "docs", "tasks": 1 }]));
+        assert_eq!(data(2)[0]["title"], "온콜 절차");
+        assert_eq!(data(2).as_array().unwrap().len(), 1);
+        let days = data(3);
+        assert_eq!(days.as_array().unwrap().len(), 7);
+        let today = crate::dates::today_in(app.state.default_tz).to_string();
+        let this_day = days.as_array().unwrap().iter().find(|d| d["date"] == today).unwrap();
+        assert_eq!(this_day["tasks"][0]["title"], "배포");
+        assert_eq!(data(4)["task_title"], "보고서");
+        assert!(data(4)["elapsed_seconds"].as_i64().unwrap() >= 300);
+
+        assert_eq!(save(&app, serde_json::json!([{ "type": "recent_tasks", "limit": 500 }])).await, 400);
+        let (status, _) = read(delete_layout(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(status, 204);
+        assert_eq!(dashboard(&app).await["widgets"][0]["type"], "summary");
+    }
 }
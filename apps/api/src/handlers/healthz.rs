@@ -1,5 +1,26 @@
-use axum::response::IntoResponse;
+use axum::{extract::State, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::llm::CircuitState;
+use crate::middleware::AppState;
 
 pub async fn healthz() -> impl IntoResponse {
     "ok"
 }
+
+/// Model server reachability as seen by the circuit breaker; 503 while the
+/// circuit is open.
+pub async fn ai(State(state): State<AppState>) -> impl IntoResponse {
+    let circuit = state.llm.circuit();
+    let status = match &circuit {
+        Some(c) if c.state == CircuitState::Open => axum::http::StatusCode::SERVICE_UNAVAILABLE,
+        _ => axum::http::StatusCode::OK,
+    };
+    (
+        status,
+        Json(json!({
+            "model": state.llm.model(),
+            "circuit": circuit,
+        })),
+    )
+}
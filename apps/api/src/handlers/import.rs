@@ -0,0 +1,698 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::analytics;
+use crate::handlers::tasks::{self, validate_create, NewTask};
+use crate::middleware::{AppState, AuthUser};
+use crate::models::TaskCreate;
+use crate::settings;
+
+const MAX_IMPORT_ROWS: usize = 5000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Ics,
+    Csv,
+}
+
+#[derive(Deserialize)]
+pub struct ImportRequest {
+    pub format: ImportFormat,
+    pub content: String,
+    pub mapping: Option<CsvMapping>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Maps task fields to CSV header names. Unset fields fall back to a column
+/// named after the field itself, if one exists.
+#[derive(Deserialize, Default)]
+pub struct CsvMapping {
+    pub uid: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub status: Option<String>,
+    pub priority: Option<String>,
+    pub due_date: Option<String>,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    pub tags: Option<String>,
+    pub tag_separator: Option<String>,
+    pub date_format: Option<String>,
+    pub delimiter: Option<char>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RowOutcome {
+    Created,
+    WouldCreate,
+    Duplicate,
+    Invalid,
+}
+
+#[derive(Serialize)]
+pub struct ImportRowReport {
+    pub row: usize,
+    pub uid: Option<String>,
+    pub title: Option<String>,
+    pub outcome: RowOutcome,
+    pub error: Option<String>,
+    pub task_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total: usize,
+    pub created: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+    pub rows: Vec<ImportRowReport>,
+}
+
+/// One parsed source record before validation. `row` is 1-based: the CSV
+/// line number (header is line 1) or the component index in the calendar.
+struct ImportRow {
+    row: usize,
+    uid: Option<String>,
+    payload: Result<TaskCreate, String>,
+    /// When the item says it was finished, for a done task.
+    completed_at: Option<DateTime<Utc>>,
+}
+
+pub async fn import(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<ImportRequest>,
+) -> impl IntoResponse {
+    // Rows without a status or priority get the user's defaults, and
+    // calendar times without a zone are on the user's.
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    let parsed = match payload.format {
+        ImportFormat::Ics => Ok(parse_ics(&payload.content, prefs.timezone)),
+        ImportFormat::Csv => parse_csv(&payload.content, &payload.mapping.unwrap_or_default()),
+    };
+    let rows = match parsed {
+        Ok(rows) => rows,
+        Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    if rows.is_empty() {
+        return (axum::http::StatusCode::BAD_REQUEST, "no rows to import").into_response();
+    }
+    if rows.len() > MAX_IMPORT_ROWS {
+        return (axum::http::StatusCode::BAD_REQUEST, "too many rows").into_response();
+    }
+
+    let uids: Vec<String> = rows.iter().filter_map(|r| r.uid.clone()).collect();
+    let existing = sqlx::query_scalar!(
+        "SELECT external_uid FROM tasks WHERE user_id = $1 AND external_uid = ANY($2)",
+        user_id,
+        &uids
+    )
+    .fetch_all(&state.pool)
+    .await;
+    let mut seen: HashSet<String> = match existing {
+        Ok(found) => found.into_iter().flatten().collect(),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let mut reports = Vec::with_capacity(rows.len());
+    let mut pending: Vec<(usize, NewTask)> = Vec::new();
+    for ImportRow { row, uid, payload, completed_at } in rows {
+        let validated = payload.and_then(|p| validate_create(p, &prefs).map_err(|e| e.to_string()));
+        let task = match validated {
+            Ok(task) => task,
+            Err(error) => {
+                reports.push(ImportRowReport {
+                    row,
+                    uid,
+                    title: None,
+                    outcome: RowOutcome::Invalid,
+                    error: Some(error),
+                    task_id: None,
+                });
+                continue;
+            }
+        };
+        if let Some(ref u) = uid {
+            if !seen.insert(u.clone()) {
+                reports.push(ImportRowReport {
+                    row,
+                    uid,
+                    title: Some(task.title),
+                    outcome: RowOutcome::Duplicate,
+                    error: None,
+                    task_id: None,
+                });
+                continue;
+            }
+        }
+        reports.push(ImportRowReport {
+            row,
+            uid: uid.clone(),
+            title: Some(task.title.clone()),
+            outcome: RowOutcome::WouldCreate,
+            error: None,
+            task_id: None,
+        });
+        pending.push((
+            reports.len() - 1,
+            NewTask {
+                external_uid: uid,
+                completed_at,
+                ..task
+            },
+        ));
+    }
+
+    let invalid = reports
+        .iter()
+        .filter(|r| matches!(r.outcome, RowOutcome::Invalid))
+        .count();
+    let duplicates = reports
+        .iter()
+        .filter(|r| matches!(r.outcome, RowOutcome::Duplicate))
+        .count();
+
+    // Any invalid row rejects the whole import so a fixed file can be re-sent as-is.
+    if payload.dry_run || invalid > 0 {
+        let status = if invalid > 0 && !payload.dry_run {
+            axum::http::StatusCode::UNPROCESSABLE_ENTITY
+        } else {
+            axum::http::StatusCode::OK
+        };
+        let report = ImportReport {
+            dry_run: payload.dry_run,
+            total: reports.len(),
+            created: 0,
+            duplicates,
+            invalid,
+            rows: reports,
+        };
+        return (status, Json(report)).into_response();
+    }
+
+    let mut tx = match state.pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+    let mut touched = Vec::new();
+    for (idx, task) in pending.iter() {
+        // A concurrent import may have taken the uid since the check above.
+        match tasks::insert_row(&mut tx, user_id, task).await {
+            Ok(Some(task)) => {
+                reports[*idx].outcome = RowOutcome::Created;
+                reports[*idx].task_id = Some(task.id);
+                touched.extend(tasks::lifecycle(&task));
+            }
+            Ok(None) => reports[*idx].outcome = RowOutcome::Duplicate,
+            Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+        }
+    }
+    if analytics::touch(&mut tx, user_id, &touched).await.is_err() || tx.commit().await.is_err() {
+        return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response();
+    }
+    state.embeddings.wake();
+
+    let created = reports
+        .iter()
+        .filter(|r| matches!(r.outcome, RowOutcome::Created))
+        .count();
+    let duplicates = reports
+        .iter()
+        .filter(|r| matches!(r.outcome, RowOutcome::Duplicate))
+        .count();
+    Json(ImportReport {
+        dry_run: false,
+        total: reports.len(),
+        created,
+        duplicates,
+        invalid,
+        rows: reports,
+    })
+    .into_response()
+}
+
+fn parse_csv(content: &str, mapping: &CsvMapping) -> Result<Vec<ImportRow>, String> {
+    let delimiter = mapping.delimiter.unwrap_or(',');
+    if !delimiter.is_ascii() {
+        return Err("delimiter must be ascii".to_string());
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter as u8)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(content.trim_start_matches('\u{feff}').as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("invalid csv header: {e}"))?
+        .clone();
+    let index: HashMap<&str, usize> = headers.iter().enumerate().map(|(i, h)| (h, i)).collect();
+
+    let column = |mapped: &Option<String>, field: &str| -> Result<Option<usize>, String> {
+        match mapped {
+            Some(name) => index
+                .get(name.as_str())
+                .copied()
+                .map(Some)
+                .ok_or_else(|| format!("column not found: {name}")),
+            None => Ok(index.get(field).copied()),
+        }
+    };
+    let columns = CsvColumns {
+        uid: column(&mapping.uid, "uid")?,
+        title: column(&mapping.title, "title")?.ok_or("title column required")?,
+        description: column(&mapping.description, "description")?,
+        status: column(&mapping.status, "status")?,
+        priority: column(&mapping.priority, "priority")?,
+        due_date: column(&mapping.due_date, "due_date")?,
+        start_date: column(&mapping.start_date, "start_date")?,
+        end_date: column(&mapping.end_date, "end_date")?,
+        tags: column(&mapping.tags, "tags")?,
+    };
+    let separator = mapping.tag_separator.as_deref().unwrap_or(",");
+    let date_format = mapping.date_format.as_deref().unwrap_or("%Y-%m-%d");
+
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        // Line 1 is the header.
+        let row = i + 2;
+        let row = match record {
+            Ok(record) => ImportRow {
+                row,
+                uid: csv_field(&record, columns.uid),
+                payload: csv_record_to_task(&record, &columns, separator, date_format),
+                completed_at: None,
+            },
+            Err(e) => ImportRow {
+                row,
+                uid: None,
+                payload: Err(format!("invalid csv row: {e}")),
+                completed_at: None,
+            },
+        };
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+struct CsvColumns {
+    uid: Option<usize>,
+    title: usize,
+    description: Option<usize>,
+    status: Option<usize>,
+    priority: Option<usize>,
+    due_date: Option<usize>,
+    start_date: Option<usize>,
+    end_date: Option<usize>,
+    tags: Option<usize>,
+}
+
+fn csv_field(record: &csv::StringRecord, col: Option<usize>) -> Option<String> {
+    col.and_then(|c| record.get(c))
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+fn csv_record_to_task(
+    record: &csv::StringRecord,
+    columns: &CsvColumns,
+    separator: &str,
+    date_format: &str,
+) -> Result<TaskCreate, String> {
+    let date = |col: Option<usize>| -> Result<Option<NaiveDate>, String> {
+        match csv_field(record, col) {
+            Some(v) => NaiveDate::parse_from_str(&v, date_format)
+                .map(Some)
+                .map_err(|_| format!("invalid date: {v}")),
+            None => Ok(None),
+        }
+    };
+
+    Ok(TaskCreate {
+        title: csv_field(record, Some(columns.title)).unwrap_or_default(),
+        description: csv_field(record, columns.description),
+        status: csv_field(record, columns.status),
+        priority: csv_field(record, columns.priority),
+        due_date: date(columns.due_date)?,
+        start_date: date(columns.start_date)?,
+        end_date: date(columns.end_date)?,
+        tags: csv_field(record, columns.tags).map(|raw| {
+            raw.split(separator)
+                .map(|t| t.trim())
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_string())
+                .collect()
+        }),
+    })
+}
+
+fn parse_ics(content: &str, tz: Tz) -> Vec<ImportRow> {
+    let mut rows = Vec::new();
+    let mut current: Option<(&'static str, Vec<IcsProperty>)> = None;
+    // Components nested in the current one, such as a VALARM in a VTODO,
+    // whose properties are not the task's.
+    let mut nested: Vec<String> = Vec::new();
+
+    for line in unfold_ics_lines(content) {
+        let Some((head, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, params) = match head.split_once(';') {
+            Some((n, p)) => (n.to_ascii_uppercase(), p.to_ascii_uppercase()),
+            None => (head.to_ascii_uppercase(), String::new()),
+        };
+        let component = value.trim().to_ascii_uppercase();
+        match (name.as_str(), component.as_str()) {
+            ("BEGIN", "VTODO") if current.is_none() => current = Some(("VTODO", Vec::new())),
+            ("BEGIN", "VEVENT") if current.is_none() => current = Some(("VEVENT", Vec::new())),
+            ("BEGIN", _) if current.is_some() => nested.push(component),
+            ("END", _) if nested.last() == Some(&component) => {
+                nested.pop();
+            }
+            ("END", _) if !nested.is_empty() => {}
+            ("END", "VTODO") | ("END", "VEVENT") => {
+                if let Some((kind, props)) = current.take() {
+                    rows.push(ics_component_to_row(rows.len() + 1, kind, &props, tz));
+                }
+            }
+            _ if nested.is_empty() => {
+                if let Some((_, ref mut props)) = current {
+                    props.push((name, params, value.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+    rows
+}
+
+fn unfold_ics_lines(content: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for raw in content.split('\n') {
+        let raw = raw.trim_end_matches('\r');
+        if let Some(rest) = raw.strip_prefix([' ', '\t']) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(rest);
+                continue;
+            }
+        }
+        if !raw.is_empty() {
+            lines.push(raw.to_string());
+        }
+    }
+    lines
+}
+
+type IcsProperty = (String, String, String);
+
+fn ics_component_to_row(row: usize, kind: &str, props: &[IcsProperty], tz: Tz) -> ImportRow {
+    let uid = ics_text(props, "UID").filter(|u| !u.is_empty());
+    let completed_at = ics_prop(props, "COMPLETED")
+        .map(|(_, _, v)| parse_ics_instant(v, tz))
+        .transpose();
+    let (payload, completed_at) = match completed_at {
+        Ok(completed_at) => (ics_component_to_task(kind, props), completed_at),
+        Err(err) => (Err(err), None),
+    };
+    ImportRow {
+        row,
+        uid,
+        payload,
+        completed_at,
+    }
+}
+
+fn ics_prop<'a>(props: &'a [IcsProperty], name: &str) -> Option<&'a IcsProperty> {
+    props.iter().find(|(n, _, _)| n == name)
+}
+
+fn ics_text(props: &[IcsProperty], name: &str) -> Option<String> {
+    ics_prop(props, name).map(|(_, _, v)| unescape_ics_text(v))
+}
+
+fn ics_component_to_task(kind: &str, props: &[IcsProperty]) -> Result<TaskCreate, String> {
+    let start_date = ics_prop(props, "DTSTART")
+        .map(|(_, _, v)| parse_ics_date(v))
+        .transpose()?;
+    let end_date = if kind == "VTODO" {
+        ics_prop(props, "DUE")
+            .map(|(_, _, v)| parse_ics_date(v))
+            .transpose()?
+    } else {
+        match ics_prop(props, "DTEND") {
+            Some((_, params, v)) => {
+                let end = parse_ics_date(v)?;
+                // An all-day DTEND is exclusive.
+                if params.contains("VALUE=DATE") || v.trim().len() == 8 {
+                    Some(end.pred_opt().unwrap_or(end))
+                } else {
+                    Some(end)
+                }
+            }
+            None => start_date,
+        }
+    };
+    let end_date = match (start_date, end_date) {
+        (Some(s), Some(e)) if e < s => Some(s),
+        (_, e) => e,
+    };
+    let status = match ics_text(props, "STATUS").map(|s| s.to_ascii_uppercase()).as_deref() {
+        Some("COMPLETED") => "done",
+        Some("IN-PROCESS") => "in_progress",
+        _ => "todo",
+    };
+    // RFC 5545: 1-4 high, 5 medium, 6-9 low, 0 undefined.
+    let priority = match ics_text(props, "PRIORITY").and_then(|p| p.trim().parse::<u8>().ok()) {
+        Some(1..=4) => "high",
+        Some(6..=9) => "low",
+        _ => "medium",
+    };
+    let tags: Vec<String> = props
+        .iter()
+        .filter(|(n, _, _)| n == "CATEGORIES")
+        .flat_map(|(_, _, v)| split_ics_list(v))
+        .collect();
+
+    Ok(TaskCreate {
+        title: ics_text(props, "SUMMARY").unwrap_or_default(),
+        description: ics_text(props, "DESCRIPTION").filter(|d| !d.trim().is_empty()),
+        status: Some(status.to_string()),
+        priority: Some(priority.to_string()),
+        due_date: None,
+        start_date,
+        end_date,
+        tags: Some(tags),
+    })
+}
+
+fn parse_ics_date(value: &str) -> Result<NaiveDate, String> {
+    let value = value.trim();
+    value
+        .get(..8)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y%m%d").ok())
+        .ok_or_else(|| format!("invalid date: {value}"))
+}
+
+/// A DATE-TIME: in UTC with a trailing `Z`, otherwise on `tz`.
+fn parse_ics_instant(value: &str, tz: Tz) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    let (local, utc) = match value.strip_suffix(['Z', 'z']) {
+        Some(local) => (local, true),
+        None => (value, false),
+    };
+    NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S")
+        .ok()
+        .and_then(|at| {
+            if utc {
+                Some(at.and_utc())
+            } else {
+                tz.from_local_datetime(&at).earliest().map(|at| at.with_timezone(&Utc))
+            }
+        })
+        .ok_or_else(|| format!("invalid date-time: {value}"))
+}
+
+fn unescape_ics_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn split_ics_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' => escaped = true,
+            ',' => items.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    items.push(current);
+    items
+        .into_iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{read_json, TestApp};
+
+    fn task(row: &ImportRow) -> &TaskCreate {
+        row.payload.as_ref().unwrap()
+    }
+
+    #[test]
+    fn unfolds_continuation_lines() {
+        let lines = unfold_ics_lines("SUMMARY:Quarterly\r\n  planning\r\n\t session\r\n\r\nUID:1\n");
+        assert_eq!(lines, ["SUMMARY:Quarterly planning session", "UID:1"]);
+    }
+
+    #[test]
+    fn splits_lists_on_unescaped_commas() {
+        assert_eq!(split_ics_list(r"ops\, infra,release, ,docs"), ["ops, infra", "release", "docs"]);
+        assert_eq!(unescape_ics_text(r"one\ntwo\; three\\"), "one\ntwo; three\\");
+    }
+
+    #[test]
+    fn reads_todos_and_events() {
+        let rows = parse_ics(
+            "BEGIN:VCALENDAR\r\n\
+             BEGIN:VTODO\r\n\
+             UID:todo-1\r\n\
+             SUMMARY:Renew certificate\r\n\
+             DTSTART:20241202T090000Z\r\n\
+             DUE;VALUE=DATE:20241205\r\n\
+             STATUS:IN-PROCESS\r\n\
+             PRIORITY:2\r\n\
+             CATEGORIES:ops,security\r\n\
+             BEGIN:VALARM\r\n\
+             ACTION:DISPLAY\r\n\
+             DESCRIPTION:Reminder\r\n\
+             TRIGGER:-PT15M\r\n\
+             END:VALARM\r\n\
+             END:VTODO\r\n\
+             BEGIN:VEVENT\r\n\
+             SUMMARY:Offsite\r\n\
+             DTSTART;VALUE=DATE:20241210\r\n\
+             DTEND;VALUE=DATE:20241212\r\n\
+             END:VEVENT\r\n\
+             END:VCALENDAR\r\n",
+            chrono_tz::UTC,
+        );
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].uid.as_deref(), Some("todo-1"));
+        let todo = task(&rows[0]);
+        assert_eq!(todo.title, "Renew certificate");
+        // The alarm's DESCRIPTION is not the task's.
+        assert_eq!(todo.description, None);
+        assert_eq!(todo.status.as_deref(), Some("in_progress"));
+        assert_eq!(todo.priority.as_deref(), Some("high"));
+        assert_eq!(todo.end_date, NaiveDate::from_ymd_opt(2024, 12, 5));
+        assert_eq!(todo.tags.as_deref(), Some(&["ops".to_string(), "security".to_string()][..]));
+        let event = task(&rows[1]);
+        assert_eq!(event.start_date, NaiveDate::from_ymd_opt(2024, 12, 10));
+        // All-day DTEND is exclusive.
+        assert_eq!(event.end_date, NaiveDate::from_ymd_opt(2024, 12, 11));
+
+        let bad = parse_ics("BEGIN:VTODO\nSUMMARY:x\nDUE:tomorrow\nEND:VTODO\n", chrono_tz::UTC);
+        assert_eq!(bad[0].payload.as_ref().err().map(String::as_str), Some("invalid date: tomorrow"));
+    }
+
+    #[test]
+    fn reads_completion_times() {
+        let rows = parse_ics(
+            "BEGIN:VTODO\nSUMMARY:a\nSTATUS:COMPLETED\nCOMPLETED:20241127T090000Z\nEND:VTODO\n\
+             BEGIN:VTODO\nSUMMARY:b\nSTATUS:COMPLETED\nCOMPLETED:20241127T090000\nEND:VTODO\n\
+             BEGIN:VTODO\nSUMMARY:c\nSTATUS:COMPLETED\nEND:VTODO\n\
+             BEGIN:VTODO\nSUMMARY:d\nCOMPLETED:yesterday\nEND:VTODO\n",
+            chrono_tz::Asia::Seoul,
+        );
+        assert_eq!(task(&rows[0]).status.as_deref(), Some("done"));
+        assert_eq!(rows[0].completed_at, "2024-11-27T09:00:00Z".parse().ok());
+        // Without a `Z` the time is on the user's zone.
+        assert_eq!(rows[1].completed_at, "2024-11-27T00:00:00Z".parse().ok());
+        assert_eq!(rows[2].completed_at, None);
+        assert_eq!(rows[3].payload.as_ref().err().map(String::as_str), Some("invalid date-time: yesterday"));
+    }
+
+    #[tokio::test]
+    async fn completed_items_keep_their_completion_time() {
+        let app = TestApp::start().await;
+        let request = ImportRequest {
+            format: ImportFormat::Ics,
+            content: "BEGIN:VTODO\nUID:done-1\nSUMMARY:a\nSTATUS:COMPLETED\nCOMPLETED:20241127T090000Z\nEND:VTODO\n".to_string(),
+            mapping: None,
+            dry_run: false,
+        };
+        let (status, body) = read_json(import(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(request)).await).await;
+        assert_eq!(status, 200, "{body}");
+        let id: Uuid = body["rows"][0]["task_id"].as_str().unwrap().parse().unwrap();
+        let task = sqlx::query!("SELECT started_at, completed_at FROM tasks WHERE id = $1", id)
+            .fetch_one(&app.state.pool)
+            .await
+            .unwrap();
+        assert_eq!(task.completed_at, "2024-11-27T09:00:00Z".parse().ok());
+        assert_eq!(task.started_at, task.completed_at);
+    }
+
+    #[test]
+    fn reads_quoted_csv_with_a_mapping() {
+        let mapping = CsvMapping {
+            title: Some("Task".to_string()),
+            tags: Some("Labels".to_string()),
+            end_date: Some("Due".to_string()),
+            tag_separator: Some(";".to_string()),
+            date_format: Some("%d/%m/%Y".to_string()),
+            ..Default::default()
+        };
+        let rows = parse_csv(
+            "\u{feff}Task,Labels,Due,description\n\
+             \"Write, then review\",ops; docs,05/12/2024,\"He said \"\"ship it\"\"\"\n\
+             Deploy,,31/02/2024,\n",
+            &mapping,
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].row, 2);
+        let first = task(&rows[0]);
+        assert_eq!(first.title, "Write, then review");
+        assert_eq!(first.description.as_deref(), Some("He said \"ship it\""));
+        assert_eq!(first.tags.as_deref(), Some(&["ops".to_string(), "docs".to_string()][..]));
+        assert_eq!(first.end_date, NaiveDate::from_ymd_opt(2024, 12, 5));
+        assert_eq!(rows[1].payload.as_ref().err().map(String::as_str), Some("invalid date: 31/02/2024"));
+
+        let missing = CsvMapping {
+            title: Some("Name".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(parse_csv("Task\nx\n", &missing).err().as_deref(), Some("column not found: Name"));
+    }
+}
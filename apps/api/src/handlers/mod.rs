@@ -1,6 +1,17 @@
+pub mod analytics;
+pub mod archive;
 pub mod auth;
+pub mod briefings;
+pub mod breakdown;
+pub mod conversations;
 pub mod dashboard;
 pub mod healthz;
+pub mod import;
 pub mod notes;
+pub mod reports;
+pub mod settings;
 pub mod tasks;
+pub mod timers;
+pub mod usage;
+pub mod vault;
 pub mod ai;
@@ -3,12 +3,40 @@ use axum::{
     response::IntoResponse,
     Json,
 };
-use serde::Deserialize;
-use sqlx::QueryBuilder;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, QueryBuilder};
 use uuid::Uuid;
 
+use crate::enrichment::{self, ActionItem, EnrichError};
+use crate::handlers::tasks;
+use crate::links;
 use crate::middleware::{AppState, AuthUser};
-use crate::models::{Note, NoteCreate, NoteUpdate};
+use crate::models::{Note, NoteCreate, NoteUpdate, Task, TaskCreate};
+use crate::prompts::Prompt;
+use crate::revisions::{self, NoteRevision, NoteRevisionSummary};
+use crate::settings;
+use crate::usage::Metered;
+
+#[derive(Deserialize)]
+pub struct RevisionDiffQuery {
+    pub from: Uuid,
+    pub to: Uuid,
+}
+
+/// Suggestions of the note's enrichment the user confirmed.
+#[derive(Deserialize)]
+pub struct EnrichmentAccept {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub action_items: Vec<ActionItem>,
+}
+
+#[derive(Serialize)]
+pub struct EnrichmentAccepted {
+    pub note: Note,
+    pub tasks: Vec<Task>,
+}
 
 #[derive(Deserialize)]
 pub struct NoteListQuery {
@@ -25,25 +53,51 @@ pub async fn create(
     AuthUser { user_id }: AuthUser,
     Json(payload): Json<NoteCreate>,
 ) -> impl IntoResponse {
+    let (title, content, tags) = match validate_create(payload) {
+        Ok(fields) => fields,
+        Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    let row = async {
+        let mut tx = state.pool.begin().await?;
+        let note = insert_note(&mut tx, user_id, &title, &content, &tags).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(note)
+    }
+    .await;
+
+    match row {
+        Ok(note) => {
+            state.embeddings.wake();
+            state.enrichment.wake();
+            Json(note).into_response()
+        }
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// Returns the trimmed title and content plus tags of a new note.
+pub(crate) fn validate_create(payload: NoteCreate) -> Result<(String, String, Vec<String>), &'static str> {
     let title = payload.title.trim();
     if title.is_empty() {
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            "title required",
-        )
-            .into_response();
+        return Err("title required");
     }
     let content = payload.content.trim();
     if content.is_empty() {
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            "content required",
-        )
-            .into_response();
+        return Err("content required");
     }
+    Ok((title.to_string(), content.to_string(), payload.tags.unwrap_or_default()))
+}
 
-    let tags = payload.tags.unwrap_or_default();
-    let row = sqlx::query_as!(
+/// Inserts a note together with its links and first revision.
+pub(crate) async fn insert_note(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    title: &str,
+    content: &str,
+    tags: &[String],
+) -> Result<Note, sqlx::Error> {
+    let note = sqlx::query_as!(
         Note,
         r#"
         INSERT INTO notes (id, user_id, title, content, tags)
@@ -54,15 +108,14 @@ pub async fn create(
         user_id,
         title,
         content,
-        &tags
+        tags
     )
-    .fetch_one(&state.pool)
-    .await;
-
-    match row {
-        Ok(note) => Json(note).into_response(),
-        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
-    }
+    .fetch_one(&mut *conn)
+    .await?;
+    links::sync_note_links(&mut *conn, user_id, note.id, &note.content).await?;
+    revisions::record_revision(&mut *conn, user_id, note.id, &note.title, &note.content, &note.tags, None)
+        .await?;
+    Ok(note)
 }
 
 pub async fn list(
@@ -71,7 +124,7 @@ pub async fn list(
     Query(query): Query<NoteListQuery>,
 ) -> impl IntoResponse {
     let page = query.page.unwrap_or(1).max(1);
-    let limit = query.limit.unwrap_or(20).max(1).min(100);
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
     let offset = (page - 1) * limit;
 
     let sort = match query.sort.as_deref() {
@@ -165,34 +218,104 @@ pub async fn update(
         }
     }
 
-    let row = sqlx::query_as!(
-        Note,
-        r#"
-        UPDATE notes
-        SET
-            title = COALESCE($1, title),
-            content = COALESCE($2, content),
-            tags = COALESCE($3, tags),
-            updated_at = NOW()
-        WHERE id = $4 AND user_id = $5
-        RETURNING *
-        "#,
-        title,
-        content,
-        payload.tags.as_deref(),
-        id,
-        user_id
-    )
-    .fetch_optional(&state.pool)
+    let rewrite_links = payload.rewrite_links.unwrap_or(false);
+    let row = async {
+        let mut tx = state.pool.begin().await?;
+        let old_title = sqlx::query_scalar!(
+            "SELECT title FROM notes WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(old_title) = old_title else {
+            return Ok(None);
+        };
+
+        let note = sqlx::query_as!(
+            Note,
+            r#"
+            UPDATE notes
+            SET
+                title = COALESCE($1, title),
+                content = COALESCE($2, content),
+                tags = COALESCE($3, tags),
+                updated_at = NOW()
+            WHERE id = $4 AND user_id = $5
+            RETURNING *
+            "#,
+            title,
+            content,
+            payload.tags.as_deref(),
+            id,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if content.is_some() {
+            links::sync_note_links(&mut tx, user_id, note.id, &note.content).await?;
+        }
+        revisions::record_revision(&mut tx, user_id, note.id, &note.title, &note.content, &note.tags, None)
+            .await?;
+        if rewrite_links && !old_title.eq_ignore_ascii_case(&note.title) {
+            rewrite_links_to(&mut tx, user_id, &old_title, &note.title).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(note))
+    }
     .await;
 
     match row {
-        Ok(Some(note)) => Json(note).into_response(),
+        Ok(Some(note)) => {
+            state.embeddings.wake();
+            state.enrichment.wake();
+            Json(note).into_response()
+        }
         Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
         Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
     }
 }
 
+/// Points every `[[old_title]]` link at `new_title` after a rename.
+async fn rewrite_links_to(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    old_title: &str,
+    new_title: &str,
+) -> Result<(), sqlx::Error> {
+    let sources = sqlx::query!(
+        r#"
+        SELECT id, title, content, tags FROM notes
+        WHERE user_id = $1 AND id IN (
+            SELECT source_note_id FROM links
+            WHERE user_id = $1 AND target_type = 'note' AND lower(target_title) = lower($2)
+        )
+        "#,
+        user_id,
+        old_title
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for source in sources {
+        let Some(content) = links::rewrite_title_links(&source.content, old_title, new_title) else {
+            continue;
+        };
+        sqlx::query!(
+            "UPDATE notes SET content = $1, updated_at = NOW() WHERE id = $2 AND user_id = $3",
+            content,
+            source.id,
+            user_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        links::sync_note_links(conn, user_id, source.id, &content).await?;
+        revisions::record_revision(conn, user_id, source.id, &source.title, &content, &source.tags, None)
+            .await?;
+    }
+    Ok(())
+}
+
 pub async fn delete(
     State(state): State<AppState>,
     AuthUser { user_id }: AuthUser,
@@ -207,8 +330,393 @@ pub async fn delete(
     .await;
 
     match res {
-        Ok(r) if r.rows_affected() == 1 => (axum::http::StatusCode::NO_CONTENT, "").into_response(),
+        Ok(r) if r.rows_affected() == 1 => {
+            state.embeddings.wake();
+            (axum::http::StatusCode::NO_CONTENT, "").into_response()
+        }
         Ok(_) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
         Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
     }
 }
+
+pub async fn backlinks(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let title = sqlx::query_scalar!(
+        "SELECT title FROM notes WHERE id = $1 AND user_id = $2",
+        id,
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await;
+    let title = match title {
+        Ok(Some(title)) => title,
+        Ok(None) => return (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let notes = sqlx::query_as!(
+        Note,
+        r#"
+        SELECT * FROM notes
+        WHERE user_id = $1 AND id <> $2 AND id IN (
+            SELECT source_note_id FROM links
+            WHERE user_id = $1 AND target_type = 'note'
+              AND (target_id = $2 OR lower(target_title) = lower($3))
+        )
+        ORDER BY updated_at DESC
+        "#,
+        user_id,
+        id,
+        title
+    )
+    .fetch_all(&state.pool)
+    .await;
+
+    match notes {
+        Ok(items) => Json(items).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn links(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let result = async {
+        let mut conn = state.pool.acquire().await?;
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM notes WHERE id = $1 AND user_id = $2) AS "exists!""#,
+            id,
+            user_id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        if !exists {
+            return Ok(None);
+        }
+        links::resolve_links(&mut conn, user_id, Some(id)).await.map(Some)
+    }
+    .await;
+
+    match result {
+        Ok(Some(items)) => Json(items).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn missing_links(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let result = async {
+        let mut conn = state.pool.acquire().await?;
+        links::resolve_links(&mut conn, user_id, None).await
+    }
+    .await;
+
+    match result {
+        Ok(items) => {
+            let missing: Vec<_> = items.into_iter().filter(|l| l.missing).collect();
+            Json(missing).into_response()
+        }
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn list_revisions(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let rows = sqlx::query_as!(
+        NoteRevisionSummary,
+        r#"
+        SELECT id, revision, title, restored_from, created_at, updated_at
+        FROM note_revisions
+        WHERE note_id = $1 AND user_id = $2
+        ORDER BY revision DESC
+        "#,
+        id,
+        user_id
+    )
+    .fetch_all(&state.pool)
+    .await;
+
+    match rows {
+        Ok(items) if items.is_empty() => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Ok(items) => Json(items).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+async fn fetch_revision(
+    state: &AppState,
+    user_id: Uuid,
+    note_id: Uuid,
+    revision_id: Uuid,
+) -> Result<Option<NoteRevision>, sqlx::Error> {
+    sqlx::query_as!(
+        NoteRevision,
+        r#"
+        SELECT id, note_id, revision, title, content, tags, restored_from, created_at, updated_at
+        FROM note_revisions
+        WHERE id = $1 AND note_id = $2 AND user_id = $3
+        "#,
+        revision_id,
+        note_id,
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await
+}
+
+pub async fn get_revision(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path((id, revision_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    match fetch_revision(&state, user_id, id, revision_id).await {
+        Ok(Some(revision)) => Json(revision).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn diff_revisions(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<RevisionDiffQuery>,
+) -> impl IntoResponse {
+    let from = fetch_revision(&state, user_id, id, query.from).await;
+    let to = fetch_revision(&state, user_id, id, query.to).await;
+
+    match (from, to) {
+        (Ok(Some(from)), Ok(Some(to))) => Json(revisions::diff_revisions(&from, &to)).into_response(),
+        (Ok(_), Ok(_)) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        _ => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn restore_revision(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path((id, revision_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let revision = match fetch_revision(&state, user_id, id, revision_id).await {
+        Ok(Some(revision)) => revision,
+        Ok(None) => return (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let row = async {
+        let mut tx = state.pool.begin().await?;
+        let note = sqlx::query_as!(
+            Note,
+            r#"
+            UPDATE notes
+            SET title = $1, content = $2, tags = $3, updated_at = NOW()
+            WHERE id = $4 AND user_id = $5
+            RETURNING *
+            "#,
+            revision.title,
+            revision.content,
+            &revision.tags,
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(note) = note else {
+            return Ok(None);
+        };
+        links::sync_note_links(&mut tx, user_id, note.id, &note.content).await?;
+        revisions::record_revision(
+            &mut tx,
+            user_id,
+            note.id,
+            &note.title,
+            &note.content,
+            &note.tags,
+            Some(revision.id),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(note))
+    }
+    .await;
+
+    match row {
+        Ok(Some(note)) => {
+            state.embeddings.wake();
+            state.enrichment.wake();
+            Json(note).into_response()
+        }
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// Summarizes the note, suggests tags and pulls out action items now.
+pub async fn enrich(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let audience = state.prompts.audience(&state.pool, user_id, state.default_tz).await;
+    let result = enrichment::enrich_note(
+        &state.pool,
+        &Metered::limited(&state, user_id, "enrichment"),
+        &state.prompts,
+        &audience,
+        state.ai_context_tokens,
+        user_id,
+        id,
+    )
+    .await;
+
+    match result {
+        Ok(Some(note)) => Json(note).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(EnrichError::Llm(err)) => err.respond(&state.prompts, &audience),
+        Err(EnrichError::Db(_)) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// Adds the confirmed tag suggestions to the note's tags and creates tasks
+/// from the confirmed action items. Accepted suggestions are taken off the
+/// pending ones; the rest stay for later.
+pub async fn accept_enrichment(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<EnrichmentAccept>,
+) -> impl IntoResponse {
+    let tags: Vec<String> = payload
+        .tags
+        .iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if tags.is_empty() && payload.action_items.is_empty() {
+        return (axum::http::StatusCode::BAD_REQUEST, "nothing to accept").into_response();
+    }
+
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    let audience = state.prompts.audience(&state.pool, user_id, state.default_tz).await;
+    let mut new_tasks = Vec::with_capacity(payload.action_items.len());
+    for item in &payload.action_items {
+        let task = tasks::validate_create(
+            TaskCreate {
+                title: item.title.clone(),
+                description: None,
+                status: None,
+                priority: None,
+                due_date: item.due_date,
+                start_date: None,
+                end_date: None,
+                tags: None,
+            },
+            &prefs,
+        );
+        match task {
+            Ok(task) => new_tasks.push(task),
+            Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+        }
+    }
+
+    let row = async {
+        let mut tx = state.pool.begin().await?;
+        let note = sqlx::query_as!(
+            Note,
+            "SELECT * FROM notes WHERE id = $1 AND user_id = $2 FOR UPDATE",
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(note) = note else {
+            return Ok(None);
+        };
+
+        let same = |a: &String, b: &String| a.to_lowercase() == b.to_lowercase();
+        let mut note_tags = note.tags.clone();
+        for tag in &tags {
+            if !note_tags.iter().any(|t| same(t, tag)) {
+                note_tags.push(tag.clone());
+            }
+        }
+        let tags_changed = note_tags.len() != note.tags.len();
+        let ai_tags: Vec<String> = note
+            .ai_tags
+            .iter()
+            .filter(|t| !tags.iter().any(|a| same(a, t)))
+            .cloned()
+            .collect();
+        let pending: Vec<ActionItem> = serde_json::from_value(note.ai_action_items.clone()).unwrap_or_default();
+        let pending: Vec<ActionItem> = pending
+            .into_iter()
+            .filter(|p| !new_tasks.iter().any(|t| t.title == p.title.trim()))
+            .collect();
+
+        let source = state.prompts.render(
+            &audience,
+            Prompt::TaskFromNote,
+            minijinja::context! { title => note.title },
+        );
+        let mut created = Vec::with_capacity(new_tasks.len());
+        for mut task in new_tasks {
+            task.description = Some(source.clone());
+            created.push(tasks::insert_task(&mut tx, user_id, &task).await?);
+        }
+
+        // A tag change is a user edit, but it leaves the enrichment of the
+        // content current.
+        let note = sqlx::query_as!(
+            Note,
+            r#"
+            UPDATE notes
+            SET
+                tags = $1,
+                ai_tags = $2,
+                ai_action_items = $3,
+                updated_at = CASE WHEN $4 THEN NOW() ELSE updated_at END,
+                ai_enriched_at = CASE WHEN $4 AND ai_enriched_at = updated_at THEN NOW() ELSE ai_enriched_at END
+            WHERE id = $5 AND user_id = $6
+            RETURNING *
+            "#,
+            &note_tags,
+            &ai_tags,
+            serde_json::to_value(&pending).unwrap_or_default(),
+            tags_changed,
+            id,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if tags_changed {
+            revisions::record_revision(&mut tx, user_id, note.id, &note.title, &note.content, &note.tags, None)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(EnrichmentAccepted {
+            note,
+            tasks: created,
+        }))
+    }
+    .await;
+
+    match row {
+        Ok(Some(accepted)) => {
+            state.embeddings.wake();
+            Json(accepted).into_response()
+        }
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
@@ -0,0 +1,266 @@
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::middleware::{AppState, AuthUser};
+use crate::prompts::Prompt;
+use crate::reports::{self, IsoWeek, WeeklyReport};
+use crate::usage::Metered;
+
+#[derive(Deserialize)]
+pub struct WeeklyQuery {
+    /// ISO week such as `2024-W48`; the current week when absent.
+    pub week: Option<String>,
+    /// `json` (default) or `markdown`.
+    pub format: Option<String>,
+    /// Adds a review written by the model from the report.
+    #[serde(default)]
+    pub narrative: bool,
+}
+
+#[derive(Serialize)]
+pub struct WeeklyResponse {
+    #[serde(flatten)]
+    pub report: WeeklyReport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub narrative: Option<String>,
+    /// Why the narrative is missing when one was asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub narrative_error: Option<String>,
+    pub markdown: String,
+}
+
+pub async fn weekly(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Query(query): Query<WeeklyQuery>,
+) -> impl IntoResponse {
+    let markdown_only = match query.format.as_deref() {
+        None | Some("json") => false,
+        Some("markdown") | Some("md") => true,
+        Some(_) => return (axum::http::StatusCode::BAD_REQUEST, "invalid format").into_response(),
+    };
+    let audience = state.prompts.audience(&state.pool, user_id, state.default_tz).await;
+    let week = match query.week.as_deref() {
+        Some(week) => match IsoWeek::parse(week) {
+            Some(week) => week,
+            None => return (axum::http::StatusCode::BAD_REQUEST, "invalid week").into_response(),
+        },
+        None => IsoWeek::containing(audience.today),
+    };
+
+    let report = match reports::weekly(&state.pool, user_id, week, audience.tz, audience.today).await {
+        Ok(report) => report,
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let (mut narrative, mut narrative_error) = (None, None);
+    if query.narrative {
+        let messages = [
+            ChatMessage::system(state.prompts.render(
+                &audience,
+                Prompt::ReportNarrative,
+                minijinja::context! { week => report.week.clone() },
+            )),
+            ChatMessage::user(report.markdown(&state.prompts, &audience, None)),
+        ];
+        match Metered::limited(&state, user_id, "report").chat(&messages).await {
+            Ok(completion) if !completion.content.trim().is_empty() => narrative = Some(completion.content),
+            Ok(_) => narrative_error = Some(state.prompts.render(&audience, Prompt::ReplyEmpty, minijinja::Value::UNDEFINED)),
+            Err(err) => {
+                tracing::warn!(user_id = %user_id, "report narrative failed: {err}");
+                narrative_error = Some(err.message(&state.prompts, &audience));
+            }
+        }
+    }
+
+    let markdown = report.markdown(&state.prompts, &audience, narrative.as_deref());
+    if markdown_only {
+        return ([(header::CONTENT_TYPE, "text/markdown; charset=utf-8")], markdown).into_response();
+    }
+    Json(WeeklyResponse {
+        report,
+        narrative,
+        narrative_error,
+        markdown,
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Path;
+    use chrono::{NaiveDate, Utc};
+    use serde_json::Value;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::handlers::tasks;
+    use crate::models::TaskUpdate;
+    use crate::testing::{at, read, read_json, TaskSeed, TestApp};
+
+    async fn report(app: &TestApp, query: WeeklyQuery) -> (u16, String) {
+        read(weekly(State(app.state.clone()), AuthUser { user_id: app.user_id }, Query(query)).await).await
+    }
+
+    fn query(week: Option<&str>, narrative: bool) -> WeeklyQuery {
+        WeeklyQuery {
+            week: week.map(str::to_string),
+            format: None,
+            narrative,
+        }
+    }
+
+    fn titles(list: &Value) -> Vec<&str> {
+        list.as_array().unwrap().iter().map(|t| t["title"].as_str().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn weekly_review_of_a_past_week() {
+        let app = TestApp::start().await;
+        // 2024-W48 is Monday 11-25 to Sunday 12-01, in Seoul.
+        app.add_task(TaskSeed { title: "배포 점검", end_date: Some("2024-11-27"), estimate_minutes: Some(90), ..TaskSeed::new("done", "2024-11-20T09:00:00+09:00", "2024-11-27T18:00:00+09:00") }).await;
+        app.add_task(TaskSeed { title: "회의록 정리", ..TaskSeed::new("done", "2024-11-25T00:10:00+09:00", "2024-11-28T10:00:00+09:00") }).await;
+        let slipped = app.add_task(TaskSeed { title: "분기 정산", end_date: Some("2024-11-29"), ..TaskSeed::new("todo", "2024-11-26T09:00:00+09:00", "2024-11-27T09:00:00+09:00") }).await;
+        let pulled_in = app.add_task(TaskSeed { title: "보안 교육", end_date: Some("2024-12-10"), ..TaskSeed::new("todo", "2024-11-01T09:00:00+09:00", "2024-11-27T09:00:00+09:00") }).await;
+        // Done in the next week, and created just before this one.
+        app.add_task(TaskSeed { title: "다음 주 일", estimate_minutes: Some(30), ..TaskSeed::new("done", "2024-11-24T23:59:00+09:00", "2024-12-02T00:01:00+09:00") }).await;
+        // Overdue during the week though finished after it; and made after it.
+        app.add_task(TaskSeed { title: "늦게 낸 보고", end_date: Some("2024-11-28"), ..TaskSeed::new("done", "2024-11-20T09:00:00+09:00", "2024-12-03T09:00:00+09:00") }).await;
+        app.add_task(TaskSeed { title: "나중에 만든 일", end_date: Some("2024-11-20"), ..TaskSeed::new("todo", "2024-12-05T09:00:00+09:00", "2024-12-05T09:00:00+09:00") }).await;
+        for (task, from, to, at_) in [
+            (slipped, "2024-11-26", "2024-11-28", "2024-11-26T12:00:00+09:00"),
+            (slipped, "2024-11-28", "2024-11-29", "2024-11-27T09:00:00+09:00"),
+            (pulled_in, "2024-12-20", "2024-12-10", "2024-11-27T09:00:00+09:00"),
+        ] {
+            sqlx::query!(
+                r#"
+                INSERT INTO task_date_changes (id, task_id, user_id, previous_end_date, end_date, changed_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                "#,
+                Uuid::new_v4(),
+                task,
+                app.user_id,
+                from.parse::<NaiveDate>().unwrap(),
+                to.parse::<NaiveDate>().unwrap(),
+                at(at_)
+            )
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+        }
+        // Timer time counts only within the week.
+        for (started_at, stopped_at) in [
+            ("2024-11-24T23:40:00+09:00", "2024-11-25T00:20:00+09:00"),
+            ("2024-11-27T14:00:00+09:00", "2024-11-27T14:30:00+09:00"),
+        ] {
+            sqlx::query!(
+                "INSERT INTO time_entries (id, user_id, started_at, stopped_at) VALUES ($1, $2, $3, $4)",
+                Uuid::new_v4(),
+                app.user_id,
+                at(started_at),
+                at(stopped_at)
+            )
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+        }
+        sqlx::query!(
+            "INSERT INTO notes (id, user_id, title, content, tags, created_at) VALUES ($1, $2, '장애 회고', '...', '{incident}', $3)",
+            Uuid::new_v4(),
+            app.user_id,
+            at("2024-11-28T15:00:00+09:00")
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+        app.model.push_reply("배포를 예정대로 마쳤지만 분기 정산이 두 번 밀렸습니다.");
+        let (status, body) = report(&app, query(Some("2024-W48"), true)).await;
+        assert_eq!(status, 200, "{body}");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["week"], "2024-W48");
+        assert_eq!(body["start_date"], "2024-11-25");
+        assert_eq!(body["end_date"], "2024-12-01");
+        assert_eq!(titles(&body["completed"]), ["배포 점검", "회의록 정리"]);
+        assert_eq!(titles(&body["created"]), ["회의록 정리", "분기 정산"]);
+        assert_eq!(body["slipped"][0]["title"], "분기 정산");
+        assert_eq!(body["slipped"][0]["from"], "2024-11-26");
+        assert_eq!(body["slipped"][0]["to"], "2024-11-29");
+        assert_eq!(body["slipped"][0]["moves"], 2);
+        assert_eq!(body["slipped"].as_array().unwrap().len(), 1);
+        // Overdue as of the Monday after the week.
+        assert_eq!(titles(&body["overdue"]), ["늦게 낸 보고", "분기 정산"]);
+        assert_eq!(body["overdue"][0]["days_overdue"], 4);
+        assert_eq!(body["overdue"][1]["days_overdue"], 3);
+        assert_eq!(body["time_tracked"]["minutes"], 50);
+        assert_eq!(body["time_tracked"]["estimated_minutes"], 90);
+        assert_eq!(body["time_tracked"]["untracked_tasks"], 1);
+        assert_eq!(titles(&body["notes"]), ["장애 회고"]);
+
+        assert_eq!(body["narrative"], "배포를 예정대로 마쳤지만 분기 정산이 두 번 밀렸습니다.");
+        let markdown = body["markdown"].as_str().unwrap();
+        assert!(markdown.contains("## 회고\n\n배포를 예정대로"), "{markdown}");
+        // The model wrote it from the report itself.
+        let request = &app.model.requests()[0];
+        assert!(app.model.system_prompt(0).contains("2024-W48"));
+        assert!(request["messages"][1]["content"].as_str().unwrap().contains("- 분기 정산: 2024-11-26 → 2024-11-29"));
+    }
+
+    #[tokio::test]
+    async fn moving_an_end_date_later_counts_as_slipped() {
+        let app = TestApp::start().await;
+        let now = Utc::now().to_rfc3339();
+        let today = crate::dates::today_in(app.state.default_tz);
+        let id = app.add_task(TaskSeed { title: "제안서", end_date: Some(&today.to_string()), ..TaskSeed::new("todo", &now, &now) }).await;
+        let later = today + chrono::TimeDelta::days(7);
+        let update = TaskUpdate {
+            title: None,
+            description: None,
+            status: None,
+            priority: None,
+            due_date: None,
+            start_date: None,
+            end_date: Some(later),
+            tags: None,
+        };
+        let (status, _) = read_json(
+            tasks::update(State(app.state.clone()), AuthUser { user_id: app.user_id }, Path(id), Json(update)).await,
+        )
+        .await;
+        assert_eq!(status, 200);
+
+        let (_, body) = report(&app, query(None, false)).await;
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["week"], IsoWeek::containing(today).to_string());
+        assert_eq!(body["slipped"][0]["to"], later.to_string());
+        assert!(body.get("narrative").is_none());
+        assert!(app.model.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn markdown_format_and_failed_narrative() {
+        let app = TestApp::start().await;
+        app.model.push_status(500, "model crashed");
+        let (status, body) = report(
+            &app,
+            WeeklyQuery {
+                week: Some("2024-W48".to_string()),
+                format: Some("markdown".to_string()),
+                narrative: true,
+            },
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.starts_with("# 주간 리뷰 2024-W48"), "{body}");
+        assert!(!body.contains("## 회고"));
+
+        let (status, _) = report(&app, query(Some("2024-W60"), false)).await;
+        assert_eq!(status, 400);
+    }
+}
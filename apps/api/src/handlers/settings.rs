@@ -0,0 +1,280 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use chrono_tz::Tz;
+use uuid::Uuid;
+
+use crate::analytics;
+use crate::handlers::tasks::{is_valid_priority, is_valid_status};
+use crate::middleware::{AppState, AuthUser};
+use crate::models::{UserSettings, UserSettingsUpdate};
+use crate::settings;
+
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    match load(&state, user_id).await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+pub async fn update(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<UserSettingsUpdate>,
+) -> impl IntoResponse {
+    let current = sqlx::query!(
+        r#"
+        SELECT locale, display_name, timezone, week_start, default_priority, default_status
+        FROM user_settings WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await;
+    let mut row = match current {
+        Ok(Some(row)) => Stored {
+            locale: row.locale,
+            display_name: row.display_name,
+            timezone: row.timezone,
+            week_start: row.week_start,
+            default_priority: row.default_priority,
+            default_status: row.default_status,
+        },
+        Ok(None) => Stored::default(),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let zone_before = settings::zone(row.timezone.as_deref(), state.default_tz);
+    if let Err(msg) = row.apply(payload, &state.prompts.locales()) {
+        return (axum::http::StatusCode::BAD_REQUEST, msg).into_response();
+    }
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO user_settings (user_id, locale, display_name, timezone, week_start, default_priority, default_status)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (user_id) DO UPDATE SET
+            locale = EXCLUDED.locale,
+            display_name = EXCLUDED.display_name,
+            timezone = EXCLUDED.timezone,
+            week_start = EXCLUDED.week_start,
+            default_priority = EXCLUDED.default_priority,
+            default_status = EXCLUDED.default_status,
+            updated_at = NOW()
+        "#,
+        user_id,
+        row.locale,
+        row.display_name,
+        row.timezone,
+        row.week_start,
+        row.default_priority,
+        row.default_status
+    )
+    .execute(&state.pool)
+    .await;
+    if res.is_err() {
+        return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response();
+    }
+    // Rollup days are counted on the user's zone.
+    let zone = settings::zone(row.timezone.as_deref(), state.default_tz);
+    if zone != zone_before {
+        let rebuilt = async { analytics::rebuild(&mut *state.pool.acquire().await?, user_id, zone).await };
+        if rebuilt.await.is_err() {
+            return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response();
+        }
+    }
+
+    match load(&state, user_id).await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// The `user_settings` row as stored; `None` is the server default.
+#[derive(Default)]
+pub(crate) struct Stored {
+    pub locale: Option<String>,
+    pub display_name: Option<String>,
+    pub timezone: Option<String>,
+    pub week_start: Option<String>,
+    pub default_priority: Option<String>,
+    pub default_status: Option<String>,
+}
+
+impl Stored {
+    /// Applies the fields `payload` sets; an empty string resets one to the
+    /// server default.
+    pub(crate) fn apply(&mut self, payload: UserSettingsUpdate, locales: &[String]) -> Result<(), &'static str> {
+        if let Some(value) = payload.locale {
+            let value = value.trim();
+            if !value.is_empty() && !locales.iter().any(|l| l == value) {
+                return Err("unsupported locale");
+            }
+            self.locale = Some(value.to_string()).filter(|l| !l.is_empty());
+        }
+        if let Some(value) = payload.display_name {
+            let value = value.trim();
+            if value.chars().count() > 100 {
+                return Err("display_name too long");
+            }
+            self.display_name = Some(value.to_string()).filter(|n| !n.is_empty());
+        }
+        if let Some(value) = payload.timezone {
+            let value = value.trim();
+            if !value.is_empty() && value.parse::<Tz>().is_err() {
+                return Err("invalid timezone");
+            }
+            self.timezone = Some(value.to_string()).filter(|t| !t.is_empty());
+        }
+        if let Some(value) = payload.week_start {
+            let value = value.trim().to_lowercase();
+            if !value.is_empty() && settings::parse_weekday(&value).is_none() {
+                return Err("invalid week_start");
+            }
+            self.week_start = Some(value).filter(|w| !w.is_empty());
+        }
+        if let Some(value) = payload.default_priority {
+            let value = value.trim();
+            if !value.is_empty() && !is_valid_priority(value) {
+                return Err("invalid default_priority");
+            }
+            self.default_priority = Some(value.to_string()).filter(|p| !p.is_empty());
+        }
+        if let Some(value) = payload.default_status {
+            let value = value.trim();
+            if !value.is_empty() && !is_valid_status(value) {
+                return Err("invalid default_status");
+            }
+            self.default_status = Some(value.to_string()).filter(|s| !s.is_empty());
+        }
+        Ok(())
+    }
+}
+
+async fn load(state: &AppState, user_id: Uuid) -> Result<UserSettings, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT locale, display_name FROM user_settings WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(&state.pool)
+    .await?;
+    let (locale, display_name) = row.map(|r| (r.locale, r.display_name)).unwrap_or_default();
+    let available_locales = state.prompts.locales();
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    Ok(UserSettings {
+        // A locale whose templates were removed falls back like an unset one.
+        locale: locale
+            .filter(|l| available_locales.contains(l))
+            .unwrap_or_else(|| state.prompts.default_locale().to_string()),
+        display_name,
+        available_locales,
+        timezone: prefs.timezone.name().to_string(),
+        week_start: settings::weekday_name(prefs.week_start).to_string(),
+        default_priority: prefs.default_priority,
+        default_status: prefs.default_status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::handlers::{dashboard, tasks};
+    use crate::models::TaskCreate;
+    use crate::testing::{read, read_json, TestApp};
+
+    fn changes(field: &str, value: &str) -> UserSettingsUpdate {
+        let mut update: UserSettingsUpdate = serde_json::from_str("{}").unwrap();
+        let value = Some(value.to_string());
+        match field {
+            "timezone" => update.timezone = value,
+            "week_start" => update.week_start = value,
+            "default_priority" => update.default_priority = value,
+            "default_status" => update.default_status = value,
+            _ => unreachable!(),
+        }
+        update
+    }
+
+    async fn patch(app: &TestApp, field: &str, value: &str) -> (u16, String) {
+        read(update(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(changes(field, value))).await).await
+    }
+
+    async fn due_today(app: &TestApp) -> Value {
+        let (_, summary) = read_json(dashboard::summary(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        summary["due_today"].clone()
+    }
+
+    #[tokio::test]
+    async fn preferences_are_validated_and_reset() {
+        let app = TestApp::start().await;
+        let (_, settings) = read_json(get(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(settings["timezone"], "Asia/Seoul");
+        assert_eq!(settings["week_start"], "monday");
+        assert_eq!(settings["default_priority"], "medium");
+        assert_eq!(settings["default_status"], "todo");
+
+        for (field, value) in [
+            ("timezone", "America/New_York"),
+            ("week_start", "Sunday"),
+            ("default_priority", "high"),
+            ("default_status", "in_progress"),
+        ] {
+            assert_eq!(patch(&app, field, value).await.0, 200);
+        }
+        let (_, settings) = read_json(get(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(settings["timezone"], "America/New_York");
+        assert_eq!(settings["week_start"], "sunday");
+        assert_eq!(settings["default_status"], "in_progress");
+
+        // New tasks that name neither get the defaults.
+        let payload: TaskCreate = serde_json::from_str(r#"{"title": "분기 계획"}"#).unwrap();
+        let (status, task) =
+            read_json(tasks::create(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(payload)).await).await;
+        assert_eq!(status, 200);
+        assert_eq!(task["priority"], "high");
+        assert_eq!(task["status"], "in_progress");
+        assert!(task["started_at"].is_string());
+
+        for (field, value) in [
+            ("timezone", "Mars/Olympus"),
+            ("week_start", "someday"),
+            ("default_priority", "urgent"),
+            ("default_status", "blocked"),
+        ] {
+            assert_eq!(patch(&app, field, value).await.0, 400, "{field}");
+        }
+        let (_, body) = patch(&app, "timezone", "").await;
+        let settings: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(settings["timezone"], "Asia/Seoul");
+        assert_eq!(settings["default_priority"], "high");
+    }
+
+    #[tokio::test]
+    async fn today_follows_the_users_timezone() {
+        let app = TestApp::start().await;
+        // UTC+14 and UTC-11 are never on the same date.
+        patch(&app, "timezone", "Pacific/Kiritimati").await;
+        let today = crate::dates::today_in(chrono_tz::Pacific::Kiritimati);
+        let payload = TaskCreate {
+            title: "릴리스".to_string(),
+            description: None,
+            status: None,
+            priority: None,
+            due_date: Some(today),
+            start_date: None,
+            end_date: None,
+            tags: None,
+        };
+        read(tasks::create(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(payload)).await).await;
+        assert_eq!(due_today(&app).await, 1);
+
+        patch(&app, "timezone", "Pacific/Pago_Pago").await;
+        assert_eq!(due_today(&app).await, 0);
+        let audience = app.state.prompts.audience(&app.state.pool, app.user_id, app.state.default_tz).await;
+        assert_eq!(audience.tz, chrono_tz::Pacific::Pago_Pago);
+        assert!(audience.today < today);
+    }
+}
@@ -3,12 +3,15 @@ use axum::{
     response::IntoResponse,
     Json,
 };
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::Deserialize;
-use sqlx::QueryBuilder;
+use sqlx::{Acquire, PgConnection, PgPool, QueryBuilder};
 use uuid::Uuid;
 
+use crate::analytics;
 use crate::middleware::{AppState, AuthUser};
-use crate::models::{Task, TaskCreate, TaskUpdate};
+use crate::models::{Note, Task, TaskCreate, TaskUpdate};
+use crate::settings::{self, Preferences};
 
 #[derive(Deserialize)]
 pub struct TaskListQuery {
@@ -22,80 +25,142 @@ pub struct TaskListQuery {
     pub limit: Option<i64>,
 }
 
-fn is_valid_status(status: &str) -> bool {
+pub(crate) fn is_valid_status(status: &str) -> bool {
     matches!(status, "todo" | "in_progress" | "done")
 }
 
-fn is_valid_priority(priority: &str) -> bool {
+pub(crate) fn is_valid_priority(priority: &str) -> bool {
     matches!(priority, "low" | "medium" | "high")
 }
 
-pub async fn create(
-    State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Json(payload): Json<TaskCreate>,
-) -> impl IntoResponse {
+#[derive(Default)]
+pub(crate) struct NewTask {
+    pub title: String,
+    pub description: Option<String>,
+    pub status: String,
+    pub priority: String,
+    pub due_date: Option<NaiveDate>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub tags: Vec<String>,
+    pub parent_id: Option<Uuid>,
+    pub estimate_minutes: Option<i32>,
+    /// The id of the calendar or file item the task was imported from.
+    pub external_uid: Option<String>,
+    /// When a done task was finished, if known; otherwise now.
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Checks a new task, filling a missing status or priority from `defaults`.
+pub(crate) fn validate_create(payload: TaskCreate, defaults: &Preferences) -> Result<NewTask, &'static str> {
     let title = payload.title.trim();
     if title.is_empty() {
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            "title required",
-        )
-            .into_response();
+        return Err("title required");
     }
-    if !is_valid_status(&payload.status) {
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            "invalid status",
-        )
-            .into_response();
+    let status = payload.status.unwrap_or_else(|| defaults.default_status.clone());
+    if !is_valid_status(&status) {
+        return Err("invalid status");
     }
-    if !is_valid_priority(&payload.priority) {
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            "invalid priority",
-        )
-            .into_response();
+    let priority = payload.priority.unwrap_or_else(|| defaults.default_priority.clone());
+    if !is_valid_priority(&priority) {
+        return Err("invalid priority");
     }
 
-    let tags = payload.tags.unwrap_or_default();
     let start_date = payload.start_date.or(payload.due_date);
     let end_date = payload.end_date.or(payload.due_date);
     if let (Some(start), Some(end)) = (start_date, end_date) {
         if start > end {
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "start_date after end_date",
-            )
-                .into_response();
+            return Err("start_date after end_date");
         }
     }
     let due_date = payload.due_date.or(end_date);
-    let row = sqlx::query_as!(
+
+    Ok(NewTask {
+        title: title.to_string(),
+        description: payload.description,
+        status,
+        priority,
+        due_date,
+        start_date,
+        end_date,
+        tags: payload.tags.unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+pub async fn create(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<TaskCreate>,
+) -> impl IntoResponse {
+    let prefs = settings::preferences(&state.pool, user_id, state.default_tz).await;
+    let task = match validate_create(payload, &prefs) {
+        Ok(task) => task,
+        Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    let inserted = async {
+        let mut conn = state.pool.acquire().await?;
+        insert_task(&mut conn, user_id, &task).await
+    }
+    .await;
+    match inserted {
+        Ok(task) => {
+            state.embeddings.wake();
+            Json(task).into_response()
+        }
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// Inserts the task and counts it in the analytics rollup, within the
+/// caller's transaction if `conn` is in one.
+pub(crate) async fn insert_task(conn: &mut PgConnection, user_id: Uuid, task: &NewTask) -> Result<Task, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    // Only a task with an external uid can be turned away.
+    let task = insert_row(&mut tx, user_id, task).await?.ok_or(sqlx::Error::RowNotFound)?;
+    analytics::touch(&mut tx, user_id, &lifecycle(&task)).await?;
+    tx.commit().await?;
+    Ok(task)
+}
+
+/// Inserts the task, stamping when it was started and finished from its
+/// status. `None` if the user already has a task with its external uid.
+/// Callers that insert in bulk count the rows in the rollup themselves.
+pub(crate) async fn insert_row(conn: &mut PgConnection, user_id: Uuid, task: &NewTask) -> Result<Option<Task>, sqlx::Error> {
+    // LEAST skips a NULL, and keeps a finish time from being in the future.
+    sqlx::query_as!(
         Task,
         r#"
-        INSERT INTO tasks (id, user_id, title, description, status, priority, due_date, start_date, end_date, tags)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-        RETURNING id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, created_at, updated_at
+        INSERT INTO tasks (id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, external_uid, started_at, completed_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13,
+                CASE WHEN $5 IN ('in_progress', 'done') THEN LEAST($14, NOW()) END,
+                CASE WHEN $5 = 'done' THEN LEAST($14, NOW()) END)
+        ON CONFLICT (user_id, external_uid) WHERE external_uid IS NOT NULL DO NOTHING
+        RETURNING id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at
         "#,
         Uuid::new_v4(),
         user_id,
-        title,
-        payload.description,
-        payload.status,
-        payload.priority,
-        due_date,
-        start_date,
-        end_date,
-        &tags
+        task.title,
+        task.description,
+        task.status,
+        task.priority,
+        task.due_date,
+        task.start_date,
+        task.end_date,
+        &task.tags,
+        task.parent_id,
+        task.estimate_minutes,
+        task.external_uid,
+        task.completed_at
     )
-    .fetch_one(&state.pool)
-    .await;
+    .fetch_optional(conn)
+    .await
+}
 
-    match row {
-        Ok(task) => Json(task).into_response(),
-        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
-    }
+/// The instants a task is counted on in the analytics rollup.
+pub(crate) fn lifecycle(task: &Task) -> Vec<DateTime<Utc>> {
+    std::iter::once(task.created_at).chain(task.completed_at).collect()
 }
 
 pub async fn list(
@@ -104,7 +169,7 @@ pub async fn list(
     Query(query): Query<TaskListQuery>,
 ) -> impl IntoResponse {
     let page = query.page.unwrap_or(1).max(1);
-    let limit = query.limit.unwrap_or(20).max(1).min(200);
+    let limit = query.limit.unwrap_or(20).clamp(1, 200);
     let offset = (page - 1) * limit;
 
     let sort = match query.sort.as_deref() {
@@ -118,7 +183,7 @@ pub async fn list(
     };
 
     let mut qb = QueryBuilder::new(
-        "SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, created_at, updated_at FROM tasks WHERE user_id = ",
+        "SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at FROM tasks WHERE user_id = ",
     );
     qb.push_bind(user_id);
 
@@ -168,7 +233,7 @@ pub async fn get(
 ) -> impl IntoResponse {
     let row = sqlx::query_as!(
         Task,
-        "SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, created_at, updated_at FROM tasks WHERE id = $1 AND user_id = $2",
+        "SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at FROM tasks WHERE id = $1 AND user_id = $2",
         id,
         user_id
     )
@@ -182,39 +247,33 @@ pub async fn get(
     }
 }
 
-pub async fn update(
-    State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Path(id): Path<Uuid>,
-    Json(payload): Json<TaskUpdate>,
-) -> impl IntoResponse {
+pub(crate) struct TaskChanges {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub status: Option<String>,
+    pub priority: Option<String>,
+    pub due_date: Option<NaiveDate>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+    pub tags: Option<Vec<String>>,
+}
+
+pub(crate) fn validate_update(payload: TaskUpdate) -> Result<TaskChanges, &'static str> {
     if let Some(status) = payload.status.as_deref() {
         if !is_valid_status(status) {
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "invalid status",
-            )
-                .into_response();
+            return Err("invalid status");
         }
     }
     if let Some(priority) = payload.priority.as_deref() {
         if !is_valid_priority(priority) {
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "invalid priority",
-            )
-                .into_response();
+            return Err("invalid priority");
         }
     }
 
     let title = payload.title.map(|t| t.trim().to_string());
     if let Some(ref t) = title {
         if t.is_empty() {
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "title required",
-            )
-                .into_response();
+            return Err("title required");
         }
     }
 
@@ -222,15 +281,66 @@ pub async fn update(
     let end_date = payload.end_date.or(payload.start_date);
     if let (Some(start), Some(end)) = (start_date, end_date) {
         if start > end {
-            return (
-                axum::http::StatusCode::BAD_REQUEST,
-                "start_date after end_date",
-            )
-                .into_response();
+            return Err("start_date after end_date");
         }
     }
     let due_date = payload.due_date.or(end_date);
-    let row = sqlx::query_as!(
+
+    Ok(TaskChanges {
+        title,
+        description: payload.description,
+        status: payload.status,
+        priority: payload.priority,
+        due_date,
+        start_date,
+        end_date,
+        tags: payload.tags,
+    })
+}
+
+pub async fn update(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<TaskUpdate>,
+) -> impl IntoResponse {
+    let changes = match validate_update(payload) {
+        Ok(changes) => changes,
+        Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    match apply_update(&state.pool, user_id, id, &changes).await {
+        Ok(Some(task)) => {
+            state.embeddings.wake();
+            Json(task).into_response()
+        }
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// Applies `changes`, stamps `started_at`/`completed_at` when the status
+/// changes, and logs a moved end date to `task_date_changes`.
+pub(crate) async fn apply_update(
+    pool: &PgPool,
+    user_id: Uuid,
+    id: Uuid,
+    changes: &TaskChanges,
+) -> Result<Option<Task>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let previous = sqlx::query!(
+        "SELECT end_date, completed_at FROM tasks WHERE id = $1 AND user_id = $2 FOR UPDATE",
+        id,
+        user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(previous) = previous else {
+        return Ok(None);
+    };
+    let previous_end_date = previous.end_date;
+
+    let task = sqlx::query_as!(
         Task,
         r#"
         UPDATE tasks
@@ -243,47 +353,280 @@ pub async fn update(
             start_date = COALESCE($6, start_date),
             end_date = COALESCE($7, end_date),
             tags = COALESCE($8, tags),
+            started_at = CASE
+                WHEN $3 IS NULL OR $3 = status THEN started_at
+                WHEN $3 = 'todo' THEN NULL
+                ELSE COALESCE(started_at, NOW())
+            END,
+            completed_at = CASE
+                WHEN $3 IS NULL OR $3 = status THEN completed_at
+                WHEN $3 = 'done' THEN NOW()
+                ELSE NULL
+            END,
             updated_at = NOW()
         WHERE id = $9 AND user_id = $10
-        RETURNING id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, created_at, updated_at
+        RETURNING id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at
         "#,
-        title,
-        payload.description,
-        payload.status,
-        payload.priority,
-        due_date,
-        start_date,
-        end_date,
-        payload.tags.as_deref(),
+        changes.title,
+        changes.description,
+        changes.status,
+        changes.priority,
+        changes.due_date,
+        changes.start_date,
+        changes.end_date,
+        changes.tags.as_deref(),
         id,
         user_id
     )
-    .fetch_optional(&state.pool)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if task.end_date != previous_end_date {
+        sqlx::query!(
+            r#"
+            INSERT INTO task_date_changes (id, task_id, user_id, previous_end_date, end_date)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            Uuid::new_v4(),
+            task.id,
+            user_id,
+            previous_end_date,
+            task.end_date
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    // Priority, tags and end date count on the same days as the status.
+    let mut touched = lifecycle(&task);
+    touched.extend(previous.completed_at);
+    analytics::touch(&mut tx, user_id, &touched).await?;
+    tx.commit().await?;
+    Ok(Some(task))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(id): Path<Uuid>,
+) -> impl IntoResponse {
+    let res = async {
+        let mut tx = state.pool.begin().await?;
+        // Subtasks go with it.
+        let removed = sqlx::query!(
+            r#"
+            WITH RECURSIVE doomed AS (
+                SELECT id FROM tasks WHERE id = $1 AND user_id = $2
+                UNION
+                SELECT t.id FROM tasks t JOIN doomed d ON t.parent_id = d.id
+            )
+            SELECT t.created_at, t.completed_at FROM tasks t JOIN doomed d ON t.id = d.id
+            "#,
+            id,
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let deleted = sqlx::query!(
+            "DELETE FROM tasks WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        let touched: Vec<_> = removed
+            .into_iter()
+            .flat_map(|t| std::iter::once(t.created_at).chain(t.completed_at))
+            .collect();
+        analytics::touch(&mut tx, user_id, &touched).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(deleted)
+    }
     .await;
 
-    match row {
-        Ok(Some(task)) => Json(task).into_response(),
-        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+    match res {
+        Ok(1) => {
+            state.embeddings.wake();
+            (axum::http::StatusCode::NO_CONTENT, "").into_response()
+        }
+        Ok(_) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
         Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
     }
 }
 
-pub async fn delete(
+pub async fn backlinks(
     State(state): State<AppState>,
     AuthUser { user_id }: AuthUser,
     Path(id): Path<Uuid>,
 ) -> impl IntoResponse {
-    let res = sqlx::query!(
-        "DELETE FROM tasks WHERE id = $1 AND user_id = $2",
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM tasks WHERE id = $1 AND user_id = $2) AS "exists!""#,
         id,
         user_id
     )
-    .execute(&state.pool)
+    .fetch_one(&state.pool)
     .await;
+    match exists {
+        Ok(true) => {}
+        Ok(false) => return (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
 
-    match res {
-        Ok(r) if r.rows_affected() == 1 => (axum::http::StatusCode::NO_CONTENT, "").into_response(),
-        Ok(_) => (axum::http::StatusCode::NOT_FOUND, "not found").into_response(),
+    let notes = sqlx::query_as!(
+        Note,
+        r#"
+        SELECT * FROM notes
+        WHERE user_id = $1 AND id IN (
+            SELECT source_note_id FROM links
+            WHERE user_id = $1 AND target_type = 'task' AND target_id = $2
+        )
+        ORDER BY updated_at DESC
+        "#,
+        user_id,
+        id
+    )
+    .fetch_all(&state.pool)
+    .await;
+
+    match notes {
+        Ok(items) => Json(items).into_response(),
         Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::handlers::dashboard;
+    use crate::testing::{read, read_json, TestApp};
+
+    fn changes(title: Option<&str>, status: Option<&str>) -> TaskUpdate {
+        TaskUpdate {
+            title: title.map(str::to_string),
+            description: None,
+            status: status.map(str::to_string),
+            priority: None,
+            due_date: None,
+            start_date: None,
+            end_date: None,
+            tags: None,
+        }
+    }
+
+    async fn patch(app: &TestApp, id: Uuid, payload: TaskUpdate) -> Value {
+        let (status, task) =
+            read_json(update(State(app.state.clone()), AuthUser { user_id: app.user_id }, Path(id), Json(payload)).await)
+                .await;
+        assert_eq!(status, 200, "{task}");
+        task
+    }
+
+    #[tokio::test]
+    async fn status_changes_stamp_started_and_completed() {
+        let app = TestApp::start().await;
+        let create_payload = TaskCreate {
+            title: "배포".to_string(),
+            description: None,
+            status: None,
+            priority: None,
+            due_date: None,
+            start_date: None,
+            end_date: None,
+            tags: None,
+        };
+        let (_, task) =
+            read_json(create(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(create_payload)).await)
+                .await;
+        assert!(task["started_at"].is_null() && task["completed_at"].is_null());
+        let id: Uuid = task["id"].as_str().unwrap().parse().unwrap();
+
+        let started = patch(&app, id, changes(None, Some("in_progress"))).await;
+        assert!(started["started_at"].is_string());
+        assert!(started["completed_at"].is_null());
+
+        let done = patch(&app, id, changes(None, Some("done"))).await;
+        assert_eq!(done["started_at"], started["started_at"]);
+        assert!(done["completed_at"].is_string());
+
+        // Edits that keep the status keep the stamps.
+        let renamed = patch(&app, id, changes(Some("배포 완료"), Some("done"))).await;
+        assert_eq!(renamed["completed_at"], done["completed_at"]);
+
+        let reopened = patch(&app, id, changes(None, Some("in_progress"))).await;
+        assert_eq!(reopened["started_at"], started["started_at"]);
+        assert!(reopened["completed_at"].is_null());
+        let back = patch(&app, id, changes(None, Some("todo"))).await;
+        assert!(back["started_at"].is_null());
+    }
+
+    #[tokio::test]
+    async fn editing_an_old_done_task_does_not_count_this_week() {
+        let app = TestApp::start().await;
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+            INSERT INTO tasks (id, user_id, title, status, priority, tags, created_at, updated_at, started_at, completed_at)
+            VALUES ($1, $2, '지난 분기 정산', 'done', 'medium', '{}', NOW() - INTERVAL '90 days',
+                    NOW() - INTERVAL '60 days', NOW() - INTERVAL '61 days', NOW() - INTERVAL '60 days')
+            "#,
+            id,
+            app.user_id
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+        patch(&app, id, changes(Some("3분기 정산"), None)).await;
+
+        let (_, summary) = read_json(dashboard::summary(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(summary["done_this_week"], 0);
+        assert_eq!(summary["recent_tasks"][0]["title"], "3분기 정산");
+    }
+
+    #[tokio::test]
+    async fn backlinks_and_missing_task_links() {
+        let app = TestApp::start().await;
+        let other = TestApp::start().await;
+        let mine = Uuid::new_v4();
+        let theirs = Uuid::new_v4();
+        for (id, owner) in [(mine, app.user_id), (theirs, other.user_id)] {
+            sqlx::query!(
+                "INSERT INTO tasks (id, user_id, title, status, priority, tags) VALUES ($1, $2, '배포', 'todo', 'medium', '{}')",
+                id,
+                owner
+            )
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+        }
+        let payload = crate::models::NoteCreate {
+            title: "배포 메모".to_string(),
+            content: format!("[[task:{mine}]] [[task:{theirs}]] [[task:내일]]"),
+            tags: None,
+        };
+        let (status, _) = read_json(
+            crate::handlers::notes::create(State(app.state.clone()), AuthUser { user_id: app.user_id }, Json(payload)).await,
+        )
+        .await;
+        assert_eq!(status, 200);
+
+        let (status, notes) =
+            read_json(backlinks(State(app.state.clone()), AuthUser { user_id: app.user_id }, Path(mine)).await).await;
+        assert_eq!(status, 200);
+        assert_eq!(notes[0]["title"], "배포 메모");
+        for id in [theirs, Uuid::new_v4()] {
+            let (status, _) =
+                read(backlinks(State(app.state.clone()), AuthUser { user_id: app.user_id }, Path(id)).await).await;
+            assert_eq!(status, 404);
+        }
+
+        let (_, missing) = read_json(
+            crate::handlers::notes::missing_links(State(app.state.clone()), AuthUser { user_id: app.user_id }).await,
+        )
+        .await;
+        let missing: Vec<_> = missing.as_array().unwrap().iter().map(|l| (l["target_id"].clone(), l["target_title"].clone())).collect();
+        assert_eq!(missing.len(), 2, "{missing:?}");
+        assert!(missing.contains(&(Value::Null, Value::from("task:내일"))));
+    }
+}
@@ -0,0 +1,162 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::middleware::{AppState, AuthUser};
+use crate::timers;
+
+#[derive(Deserialize, Default)]
+pub struct TimerStart {
+    pub task_id: Option<Uuid>,
+}
+
+/// The running entry, or `null`.
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    match timers::running(&state.pool, user_id).await {
+        Ok(entry) => Json(entry).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// Starts the timer, against a task if one is given. A timer already
+/// running is stopped first, so switching tasks is one call.
+pub async fn start(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    payload: Option<Json<TimerStart>>,
+) -> impl IntoResponse {
+    let Json(payload) = payload.unwrap_or_default();
+    if let Some(task_id) = payload.task_id {
+        let exists = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM tasks WHERE id = $1 AND user_id = $2) AS "exists!""#,
+            task_id,
+            user_id
+        )
+        .fetch_one(&state.pool)
+        .await;
+        match exists {
+            Ok(true) => {}
+            Ok(false) => return (axum::http::StatusCode::NOT_FOUND, "task not found").into_response(),
+            Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+        }
+    }
+    let started = async {
+        let mut conn = state.pool.acquire().await?;
+        timers::start(&mut conn, user_id, payload.task_id).await
+    }
+    .await;
+    match started {
+        Ok(entry) => (axum::http::StatusCode::CREATED, Json(entry)).into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+/// Stops the running timer and returns its entry.
+pub async fn stop(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let stopped = async { timers::stop(&mut *state.pool.acquire().await?, user_id).await }.await;
+    match stopped {
+        Ok(Some(entry)) => Json(entry).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, "no timer running").into_response(),
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, Utc};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing::{read, read_json, TestApp};
+
+    async fn start_timer(app: &TestApp, task_id: Option<Uuid>) -> (u16, Value) {
+        let payload = Some(Json(TimerStart { task_id }));
+        read_json(start(State(app.state.clone()), AuthUser { user_id: app.user_id }, payload).await).await
+    }
+
+    #[tokio::test]
+    async fn starting_switches_and_stopping_records_the_span() {
+        let app = TestApp::start().await;
+        let task = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO tasks (id, user_id, title, status, priority, tags) VALUES ($1, $2, '보고서', 'todo', 'medium', '{}')",
+            task,
+            app.user_id
+        )
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+        let (status, first) = start_timer(&app, None).await;
+        assert_eq!(status, 201, "{first}");
+        let (status, second) = start_timer(&app, Some(task)).await;
+        assert_eq!(status, 201);
+        assert_eq!(second["task_title"], "보고서");
+        // Starting again stopped the first entry.
+        let stopped = sqlx::query_scalar!("SELECT stopped_at FROM time_entries WHERE id = $1", Uuid::parse_str(first["id"].as_str().unwrap()).unwrap())
+            .fetch_one(&app.state.pool)
+            .await
+            .unwrap();
+        assert!(stopped.is_some());
+
+        let (_, running) = read_json(get(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(running["id"], second["id"]);
+
+        let (status, entry) = read_json(stop(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(status, 200);
+        assert_eq!(entry["task_id"], task.to_string());
+        assert!(entry["stopped_at"].is_string());
+        let (status, _) = read(stop(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert_eq!(status, 404);
+        let (_, running) = read_json(get(State(app.state.clone()), AuthUser { user_id: app.user_id }).await).await;
+        assert!(running.is_null());
+
+        let payload = Some(Json(TimerStart { task_id: Some(Uuid::new_v4()) }));
+        let (status, body) = read(start(State(app.state.clone()), AuthUser { user_id: app.user_id }, payload).await).await;
+        assert_eq!((status, body.as_str()), (404, "task not found"));
+    }
+
+    #[tokio::test]
+    async fn concurrent_starts_leave_one_running() {
+        let app = TestApp::start().await;
+        let starts = futures::future::join_all((0..4).map(|_| start_timer(&app, None))).await;
+        assert!(starts.iter().all(|(status, _)| *status == 201), "{starts:?}");
+        let running = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "n!" FROM time_entries WHERE user_id = $1 AND stopped_at IS NULL"#,
+            app.user_id
+        )
+        .fetch_one(&app.state.pool)
+        .await
+        .unwrap();
+        assert_eq!(running, 1);
+    }
+
+    #[tokio::test]
+    async fn minutes_are_clipped_to_the_range() {
+        let app = TestApp::start().await;
+        let now = Utc::now();
+        for (started, stopped) in [(120, Some(60)), (30, None)] {
+            sqlx::query!(
+                "INSERT INTO time_entries (id, user_id, started_at, stopped_at) VALUES ($1, $2, $3, $4)",
+                Uuid::new_v4(),
+                app.user_id,
+                now - TimeDelta::minutes(started),
+                stopped.map(|m| now - TimeDelta::minutes(m))
+            )
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+        }
+        let minutes = timers::minutes_between(&app.state.pool, app.user_id, now - TimeDelta::minutes(90), now + TimeDelta::hours(1))
+            .await
+            .unwrap();
+        // 30 of the first span, and the running one up to now.
+        assert_eq!(minutes, 60);
+    }
+}
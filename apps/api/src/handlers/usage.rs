@@ -0,0 +1,126 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::middleware::{AppState, AuthUser};
+use crate::settings;
+use crate::usage;
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    /// Days of history, today included.
+    pub days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct UsageSummary {
+    pub timezone: String,
+    pub today: TodayUsage,
+    /// Newest first; days without calls are left out.
+    pub days: Vec<DayUsage>,
+    /// Over the same days, most tokens first.
+    pub features: Vec<FeatureUsage>,
+}
+
+/// What counts towards the quotas (embedding calls do not).
+#[derive(Serialize)]
+pub struct TodayUsage {
+    pub date: NaiveDate,
+    pub requests: i64,
+    pub tokens: i64,
+    pub request_quota: Option<i64>,
+    pub token_quota: Option<i64>,
+    pub resets_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct DayUsage {
+    pub date: NaiveDate,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub failed: i64,
+}
+
+#[derive(Serialize)]
+pub struct FeatureUsage {
+    pub feature: String,
+    pub requests: i64,
+    pub tokens: i64,
+}
+
+pub async fn summary(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Query(query): Query<UsageQuery>,
+) -> impl IntoResponse {
+    let days = query.days.unwrap_or(7).clamp(1, 90);
+    let tz = settings::preferences(&state.pool, user_id, state.default_tz).await.timezone;
+    let date = Utc::now().with_timezone(&tz).date_naive();
+    let (start, resets_at) = usage::day_bounds(date, tz);
+    let since = usage::day_bounds(date - TimeDelta::days(days - 1), tz).0;
+
+    let used = match usage::used_since(&state.pool, user_id, start).await {
+        Ok(used) => used,
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let per_day = sqlx::query_as!(
+        DayUsage,
+        r#"
+        SELECT (created_at AT TIME ZONE $2)::date AS "date!",
+               COUNT(*) AS "requests!",
+               COALESCE(SUM(prompt_tokens), 0)::BIGINT AS "prompt_tokens!",
+               COALESCE(SUM(completion_tokens), 0)::BIGINT AS "completion_tokens!",
+               COUNT(*) FILTER (WHERE outcome NOT IN ('ok', 'cancelled')) AS "failed!"
+        FROM ai_usage
+        WHERE user_id = $1 AND created_at >= $3
+        GROUP BY 1
+        ORDER BY 1 DESC
+        "#,
+        user_id,
+        tz.name(),
+        since
+    )
+    .fetch_all(&state.pool)
+    .await;
+
+    let features = sqlx::query_as!(
+        FeatureUsage,
+        r#"
+        SELECT feature,
+               COUNT(*) AS "requests!",
+               COALESCE(SUM(prompt_tokens + completion_tokens), 0)::BIGINT AS "tokens!"
+        FROM ai_usage
+        WHERE user_id = $1 AND created_at >= $2
+        GROUP BY feature
+        ORDER BY 3 DESC, feature
+        "#,
+        user_id,
+        since
+    )
+    .fetch_all(&state.pool)
+    .await;
+
+    match (per_day, features) {
+        (Ok(days), Ok(features)) => Json(UsageSummary {
+            timezone: tz.name().to_string(),
+            today: TodayUsage {
+                date,
+                requests: used.requests,
+                tokens: used.tokens,
+                request_quota: state.ai_quotas.daily_requests,
+                token_quota: state.ai_quotas.daily_tokens,
+                resets_at,
+            },
+            days,
+            features,
+        })
+        .into_response(),
+        _ => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
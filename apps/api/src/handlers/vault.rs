@@ -0,0 +1,438 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read, Write};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
+
+use crate::links;
+use crate::middleware::{AppState, AuthUser};
+use crate::models::Note;
+use crate::revisions;
+
+/// Largest note file read from a vault, uncompressed.
+const MAX_NOTE_BYTES: u64 = 1024 * 1024;
+/// Largest total read from one vault, uncompressed.
+const MAX_VAULT_BYTES: u64 = 200 * 1024 * 1024;
+
+/// YAML front-matter written at the top of every exported note.
+#[derive(Serialize, Deserialize, Default)]
+struct FrontMatter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<Uuid>,
+    /// Only written when the file name could not hold the title verbatim.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct VaultSkipped {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct VaultImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub skipped: Vec<VaultSkipped>,
+}
+
+pub async fn export(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> impl IntoResponse {
+    let notes = sqlx::query_as!(
+        Note,
+        "SELECT * FROM notes WHERE user_id = $1 ORDER BY created_at",
+        user_id
+    )
+    .fetch_all(&state.pool)
+    .await;
+    let notes = match notes {
+        Ok(notes) => notes,
+        Err(_) => return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    };
+
+    let archive = match write_vault(&notes) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::error!("vault export failed: {err}");
+            return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "export failed").into_response();
+        }
+    };
+
+    let filename = format!("dailyops-notes-{}.zip", Utc::now().format("%Y%m%d"));
+    (
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        archive,
+    )
+        .into_response()
+}
+
+pub async fn import(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    body: Bytes,
+) -> impl IntoResponse {
+    let files = match read_vault(&body) {
+        Ok(files) => files,
+        Err(msg) => return (axum::http::StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
+    match import_files(&state, user_id, files).await {
+        Ok(report) => {
+            state.embeddings.wake();
+            state.enrichment.wake();
+            Json(report).into_response()
+        }
+        Err(_) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "db error").into_response(),
+    }
+}
+
+fn write_vault(notes: &[Note]) -> anyhow::Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    let mut used: HashSet<String> = HashSet::new();
+
+    for note in notes {
+        let stem = file_stem_for(&note.title);
+        let mut path = format!("{stem}.md");
+        let mut n = 2;
+        while !used.insert(path.to_lowercase()) {
+            path = format!("{stem} ({n}).md");
+            n += 1;
+        }
+
+        let front = FrontMatter {
+            id: Some(note.id),
+            title: (path != format!("{}.md", note.title)).then(|| note.title.clone()),
+            tags: note.tags.clone(),
+            created_at: Some(note.created_at),
+            updated_at: Some(note.updated_at),
+        };
+        zip.start_file(path, options)?;
+        write!(zip, "---\n{}---\n\n{}\n", serde_yaml::to_string(&front)?, note.content)?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// A file's path and contents, or why it could not be read.
+type VaultFile = (String, Result<String, &'static str>);
+
+/// Returns `(path, contents)` for every markdown file in the archive,
+/// ignoring Obsidian's own config directory.
+fn read_vault(bytes: &[u8]) -> Result<Vec<VaultFile>, &'static str> {
+    let mut zip = ZipArchive::new(Cursor::new(bytes)).map_err(|_| "invalid zip")?;
+    let mut files = Vec::new();
+    let mut total = 0;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|_| "invalid zip")?;
+        let path = entry.name().to_string();
+        let hidden = path.split('/').any(|part| part.starts_with('.'));
+        if !entry.is_file() || hidden || !path.to_lowercase().ends_with(".md") {
+            continue;
+        }
+        // The sizes in the zip's headers are not trusted.
+        let mut raw = Vec::new();
+        let read = entry.by_ref().take(MAX_NOTE_BYTES + 1).read_to_end(&mut raw);
+        total += raw.len() as u64;
+        if total > MAX_VAULT_BYTES {
+            return Err("vault too large");
+        }
+        let contents = match read {
+            Err(_) => Err("unreadable file"),
+            Ok(_) if raw.len() as u64 > MAX_NOTE_BYTES => Err("file too large"),
+            Ok(_) => String::from_utf8(raw).map_err(|_| "not valid utf-8"),
+        };
+        files.push((path, contents));
+    }
+    Ok(files)
+}
+
+async fn import_files(
+    state: &AppState,
+    user_id: Uuid,
+    files: Vec<VaultFile>,
+) -> Result<VaultImportReport, sqlx::Error> {
+    let mut report = VaultImportReport::default();
+    let mut tx = state.pool.begin().await?;
+
+    let owned: HashMap<Uuid, Note> = sqlx::query_as!(
+        Note,
+        "SELECT * FROM notes WHERE user_id = $1",
+        user_id
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|n| (n.id, n))
+    .collect();
+    let mut seen: HashSet<Uuid> = HashSet::new();
+
+    for (path, contents) in files {
+        let contents = match contents {
+            Ok(c) => c,
+            Err(error) => {
+                report.skipped.push(VaultSkipped {
+                    path,
+                    error: error.to_string(),
+                });
+                continue;
+            }
+        };
+        let (front, body) = match split_front_matter(&contents) {
+            Ok(parts) => parts,
+            Err(err) => {
+                report.skipped.push(VaultSkipped { path, error: err });
+                continue;
+            }
+        };
+        let title = front
+            .title
+            .clone()
+            .filter(|t| !t.trim().is_empty())
+            .unwrap_or_else(|| title_from_path(&path));
+        let title = title.trim();
+        let content = body.trim();
+        if title.is_empty() || content.is_empty() {
+            report.skipped.push(VaultSkipped {
+                path,
+                error: "title and content required".to_string(),
+            });
+            continue;
+        }
+
+        // A second file carrying the same id is treated as a new note.
+        let front_id = front.id.filter(|id| seen.insert(*id));
+        match front_id.and_then(|id| owned.get(&id)) {
+            Some(note) => {
+                if note.title == title && note.content == content && note.tags == front.tags {
+                    report.unchanged += 1;
+                    continue;
+                }
+                sqlx::query!(
+                    r#"
+                    UPDATE notes
+                    SET title = $1, content = $2, tags = $3, updated_at = NOW()
+                    WHERE id = $4 AND user_id = $5
+                    "#,
+                    title,
+                    content,
+                    &front.tags,
+                    note.id,
+                    user_id
+                )
+                .execute(&mut *tx)
+                .await?;
+                links::sync_note_links(&mut tx, user_id, note.id, content).await?;
+                revisions::record_revision(&mut tx, user_id, note.id, title, content, &front.tags, None)
+                    .await?;
+                report.updated += 1;
+            }
+            None => {
+                // Keep the vault's id when it is free so the next import matches it.
+                let id = match front_id {
+                    Some(id) => {
+                        let taken = sqlx::query_scalar!(
+                            r#"SELECT EXISTS(SELECT 1 FROM notes WHERE id = $1) AS "taken!""#,
+                            id
+                        )
+                        .fetch_one(&mut *tx)
+                        .await?;
+                        if taken {
+                            Uuid::new_v4()
+                        } else {
+                            id
+                        }
+                    }
+                    None => Uuid::new_v4(),
+                };
+                let created_at = front.created_at.unwrap_or_else(Utc::now);
+                let updated_at = front.updated_at.unwrap_or(created_at);
+                sqlx::query!(
+                    r#"
+                    INSERT INTO notes (id, user_id, title, content, tags, created_at, updated_at)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    "#,
+                    id,
+                    user_id,
+                    title,
+                    content,
+                    &front.tags,
+                    created_at,
+                    updated_at
+                )
+                .execute(&mut *tx)
+                .await?;
+                links::sync_note_links(&mut tx, user_id, id, content).await?;
+                revisions::record_revision(&mut tx, user_id, id, title, content, &front.tags, None)
+                    .await?;
+                report.created += 1;
+            }
+        }
+    }
+
+    tx.commit().await?;
+    Ok(report)
+}
+
+fn split_front_matter(contents: &str) -> Result<(FrontMatter, &str), String> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    let Some(rest) = contents
+        .strip_prefix("---\n")
+        .or_else(|| contents.strip_prefix("---\r\n"))
+    else {
+        return Ok((FrontMatter::default(), contents));
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            let yaml = &rest[..offset];
+            let body = &rest[offset + line.len()..];
+            if yaml.trim().is_empty() {
+                return Ok((FrontMatter::default(), body));
+            }
+            let front = serde_yaml::from_str::<FrontMatter>(yaml)
+                .map_err(|e| format!("invalid front-matter: {e}"))?;
+            return Ok((front, body));
+        }
+        offset += line.len();
+    }
+    Err("unterminated front-matter".to_string())
+}
+
+/// Obsidian accepts `tags: a`, `tags: [a, b]` and block lists, with or without `#`.
+fn deserialize_tags<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let raw = match Option::<Tags>::deserialize(deserializer)? {
+        Some(Tags::One(s)) => s
+            .split([',', ' '])
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>(),
+        Some(Tags::Many(v)) => v,
+        None => Vec::new(),
+    };
+    Ok(raw
+        .into_iter()
+        .map(|t| t.trim().trim_start_matches('#').to_string())
+        .filter(|t| !t.is_empty())
+        .collect())
+}
+
+fn file_stem_for(title: &str) -> String {
+    let cleaned: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | '^' | '[' | ']' => '-',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').trim();
+    if cleaned.is_empty() {
+        "untitled".to_string()
+    } else {
+        cleaned.chars().take(120).collect()
+    }
+}
+
+fn title_from_path(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.get(..name.len().saturating_sub(3))
+        .unwrap_or(name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{read, read_json, TestApp};
+
+    #[tokio::test]
+    async fn round_trips_notes_with_the_same_title() {
+        let source = TestApp::start().await;
+        for content in ["첫 번째", "두 번째"] {
+            sqlx::query!(
+                "INSERT INTO notes (id, user_id, title, content, tags) VALUES ($1, $2, '회의록', $3, '{ops}')",
+                Uuid::new_v4(),
+                source.user_id,
+                content
+            )
+            .execute(&source.state.pool)
+            .await
+            .unwrap();
+        }
+        let response = export(State(source.state.clone()), AuthUser { user_id: source.user_id })
+            .await
+            .into_response();
+        let zip = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let files = read_vault(&zip).unwrap();
+        assert_eq!(files.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>(), ["회의록.md", "회의록 (2).md"]);
+
+        let target = TestApp::start().await;
+        let (status, report) =
+            read_json(import(State(target.state.clone()), AuthUser { user_id: target.user_id }, zip).await).await;
+        assert_eq!(status, 200, "{report}");
+        assert_eq!(report["created"], 2);
+        let notes = sqlx::query!(
+            "SELECT title, content, tags FROM notes WHERE user_id = $1 ORDER BY content",
+            target.user_id
+        )
+        .fetch_all(&target.state.pool)
+        .await
+        .unwrap();
+        assert!(notes.iter().all(|n| n.title == "회의록" && n.tags == ["ops"]));
+        assert_eq!(notes[1].content, "첫 번째");
+    }
+
+    #[tokio::test]
+    async fn skips_oversized_files() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("big.md", SimpleFileOptions::default()).unwrap();
+        zip.write_all(&vec![b'a'; MAX_NOTE_BYTES as usize + 1]).unwrap();
+        zip.start_file("small.md", SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"hello").unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+
+        let files = read_vault(&bytes).unwrap();
+        assert_eq!(files[0].1, Err("file too large"));
+        assert_eq!(files[1].1.as_deref(), Ok("hello"));
+
+        let app = TestApp::start().await;
+        let (status, body) = read(import(State(app.state.clone()), AuthUser { user_id: app.user_id }, Bytes::from_static(b"PK")).await).await;
+        assert_eq!((status, body.as_str()), (400, "invalid zip"));
+    }
+}
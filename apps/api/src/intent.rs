@@ -0,0 +1,389 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::prompts::{Audience, Prompt, Prompts};
+
+/// Model classifications below this confidence get a clarifying question
+/// instead of an action.
+const CLARIFY_BELOW: f32 = 0.6;
+/// Earlier messages shown to the classifier so follow-ups make sense.
+const CONTEXT_MESSAGES: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Intent {
+    /// Register a new task.
+    CreateTask,
+    /// Brief, summarize or prioritize the user's tasks and notes.
+    Briefing,
+    /// Ask about something the user wrote down.
+    Question,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Model,
+    Heuristic,
+}
+
+/// Details the model pulled out of a `create_task` message.
+#[derive(Clone, Debug, Default)]
+pub struct Slots {
+    pub title: Option<String>,
+    pub start_date: Option<NaiveDate>,
+    pub end_date: Option<NaiveDate>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Classification {
+    pub intent: Intent,
+    pub slots: Slots,
+    pub confidence: f32,
+    pub source: Source,
+}
+
+#[derive(Deserialize)]
+struct RawClassification {
+    intent: Intent,
+    #[serde(default)]
+    slots: RawSlots,
+    confidence: Option<f32>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawSlots {
+    title: Option<String>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+}
+
+impl Classification {
+    pub fn needs_clarification(&self) -> bool {
+        self.source == Source::Model && self.confidence < CLARIFY_BELOW
+    }
+
+    pub fn clarification(&self, prompts: &Prompts, audience: &Audience) -> String {
+        match (self.intent, self.slots.title.as_deref()) {
+            (Intent::CreateTask, Some(title)) => {
+                prompts.render(audience, Prompt::ClarifyCreateTask, minijinja::context! { title })
+            }
+            (Intent::Briefing, _) => prompts.render(audience, Prompt::ClarifyBriefing, minijinja::Value::UNDEFINED),
+            _ => prompts.render(audience, Prompt::ClarifyOther, minijinja::Value::UNDEFINED),
+        }
+    }
+}
+
+/// Asks the model what `message` wants. Falls back to the keyword
+/// heuristics when the model is unavailable or answers with something that
+/// is not the requested JSON.
+pub async fn classify(
+    llm: &dyn LlmProvider,
+    prompts: &Prompts,
+    audience: &Audience,
+    message: &str,
+    history: &[ChatMessage],
+) -> Classification {
+    let mut messages = vec![ChatMessage::system(prompts.render(
+        audience,
+        Prompt::IntentSystem,
+        minijinja::Value::UNDEFINED,
+    ))];
+    let skip = history.len().saturating_sub(CONTEXT_MESSAGES);
+    messages.extend(history[skip..].iter().filter(|m| m.role != "system").cloned());
+    messages.push(ChatMessage::user(message));
+
+    match llm.chat_json(&messages).await {
+        Ok(completion) => match parse(&completion.content) {
+            Some(classification) => classification,
+            None => {
+                tracing::warn!(content = %completion.content, "intent classification unparseable");
+                heuristic(message)
+            }
+        },
+        Err(err) => {
+            tracing::warn!("intent classification failed: {err}");
+            heuristic(message)
+        }
+    }
+}
+
+/// Reads the classifier's JSON, tolerating prose or code fences around it.
+fn parse(content: &str) -> Option<Classification> {
+    let start = content.find('{')?;
+    let end = content.rfind('}')?;
+    let raw: RawClassification = serde_json::from_str(content.get(start..=end)?).ok()?;
+    let date = |value: Option<String>| {
+        value.and_then(|v| NaiveDate::parse_from_str(v.trim(), "%Y-%m-%d").ok())
+    };
+    Some(Classification {
+        intent: raw.intent,
+        slots: Slots {
+            title: raw.slots.title.map(|t| t.trim().to_string()).filter(|t| !t.is_empty()),
+            start_date: date(raw.slots.start_date),
+            end_date: date(raw.slots.end_date),
+        },
+        // A model that leaves it out did not express doubt.
+        confidence: raw.confidence.unwrap_or(1.0).clamp(0.0, 1.0),
+        source: Source::Model,
+    })
+}
+
+/// Keyword matching used when the model cannot be asked.
+pub fn heuristic(message: &str) -> Classification {
+    let intent = if is_task_create_request(message) {
+        Intent::CreateTask
+    } else if is_recall_question(message) {
+        Intent::Question
+    } else if is_brief_request(message) {
+        Intent::Briefing
+    } else {
+        Intent::Other
+    };
+    Classification {
+        intent,
+        slots: Slots::default(),
+        confidence: 1.0,
+        source: Source::Heuristic,
+    }
+}
+
+fn words(msg: &str) -> Vec<&str> {
+    msg.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect()
+}
+
+fn mentions_task(msg: &str) -> bool {
+    msg.contains("업무")
+        || msg.contains("일정")
+        || msg.contains("할 일")
+        || words(msg).iter().any(|w| matches!(*w, "task" | "tasks" | "todo"))
+}
+
+fn mentions_notes(msg: &str) -> bool {
+    msg.contains("노트")
+        || msg.contains("메모")
+        || msg.contains("회의록")
+        || msg.contains("문서")
+        || words(msg).iter().any(|w| matches!(*w, "note" | "notes"))
+}
+
+fn is_task_create_request(message: &str) -> bool {
+    let msg = message.to_lowercase();
+    let has_create_verb = ["등록", "추가", "생성", "만들", "넣어", "잡아"]
+        .iter()
+        .any(|verb| msg.contains(verb))
+        || words(&msg)
+            .iter()
+            .any(|w| matches!(*w, "add" | "create" | "schedule" | "remind" | "put"))
+        || msg.contains("new task");
+
+    // "노트북" is not a note; naming a task settles it either way.
+    if mentions_notes(&msg) && !mentions_task(&msg) {
+        return false;
+    }
+    has_create_verb || msg.starts_with("task:")
+}
+
+/// Asking after something the user wrote down earlier.
+fn is_recall_question(message: &str) -> bool {
+    let msg = message.to_lowercase();
+    mentions_notes(&msg)
+        || ["적어", "적혀", "썼", "더라", "였지"].iter().any(|ending| msg.contains(ending))
+        || msg.contains("did i")
+        || words(&msg).contains(&"mentioned")
+}
+
+fn is_brief_request(message: &str) -> bool {
+    let msg = message.to_lowercase();
+    let asks = ["알려", "뭐", "있어"].iter().any(|ask| msg.contains(ask))
+        || words(&msg).iter().any(|w| matches!(*w, "what" | "anything"));
+    msg.contains("브리핑")
+        || msg.contains("요약")
+        || msg.contains("정리")
+        || msg.contains("우선순위")
+        || msg.contains("현황")
+        || msg.contains("brief")
+        || msg.contains("summar")
+        || msg.contains("priorities")
+        || msg.contains("overdue")
+        || msg.contains("on my plate")
+        || ((mentions_task(&msg) || msg.contains("마감")) && asks)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::llm::{CircuitBreaker, HttpSettings, LlmError, MockProvider, OllamaProvider, OpenAiProvider};
+    use crate::prompts::tests::{audience, shipped};
+
+    const CORPUS: &str = include_str!("../testdata/intents.tsv");
+    /// Lines the heuristics were never tuned against.
+    const HOLDOUT: &str = include_str!("../testdata/intents_holdout.tsv");
+    /// 37 of 40 held-out lines (92.5%) when the file was added, rounded
+    /// down. The tuning corpus says little about unseen lines.
+    const HEURISTIC_FLOOR: f32 = 0.9;
+    const MODEL_TARGET: f32 = 0.85;
+
+    /// Both files; the model was tuned on neither.
+    fn corpus() -> Vec<(Intent, &'static str)> {
+        labelled(CORPUS).into_iter().chain(labelled(HOLDOUT)).collect()
+    }
+
+    fn labelled(text: &'static str) -> Vec<(Intent, &'static str)> {
+        text
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (label, text) = line.split_once('\t').expect("intent<TAB>utterance");
+                let intent = serde_json::from_value(serde_json::json!(label)).expect("known intent");
+                (intent, text)
+            })
+            .collect()
+    }
+
+    /// Share of correct predictions, and the misses for the failure message.
+    fn accuracy(results: &[(Intent, Intent, &str)]) -> (f32, String) {
+        let misses: Vec<String> = results
+            .iter()
+            .filter(|(expected, got, _)| expected != got)
+            .map(|(expected, got, text)| format!("{text:?} expected {expected:?}, got {got:?}"))
+            .collect();
+        let accuracy = (results.len() - misses.len()) as f32 / results.len() as f32;
+        (accuracy, misses.join("\n"))
+    }
+
+    async fn classify(
+        llm: &dyn LlmProvider,
+        message: &str,
+        history: &[ChatMessage],
+    ) -> Classification {
+        super::classify(llm, &shipped(), &audience("ko"), message, history).await
+    }
+
+    fn heuristic_accuracy(text: &'static str) -> (f32, usize, String) {
+        let results: Vec<_> = labelled(text)
+            .into_iter()
+            .map(|(expected, text)| (expected, heuristic(text).intent, text))
+            .collect();
+        let (accuracy, misses) = accuracy(&results);
+        (accuracy, results.len(), misses)
+    }
+
+    #[test]
+    fn heuristic_corpus_accuracy() {
+        let (accuracy, lines, misses) = heuristic_accuracy(CORPUS);
+        assert!(
+            accuracy >= HEURISTIC_FLOOR,
+            "heuristic accuracy {:.1}% of {lines} tuning lines, misses:\n{misses}",
+            accuracy * 100.0
+        );
+    }
+
+    #[test]
+    fn heuristic_holdout_accuracy() {
+        let (accuracy, lines, misses) = heuristic_accuracy(HOLDOUT);
+        assert!(
+            accuracy >= HEURISTIC_FLOOR,
+            "heuristic accuracy {:.1}% of {lines} held-out lines, misses:\n{misses}",
+            accuracy * 100.0
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_model_json_inside_fences() {
+        let provider = MockProvider::with_responses(
+            "mock",
+            ["```json\n{\"intent\": \"create_task\", \"slots\": {\"title\": \"창고 정리\", \
+\"start_date\": null, \"end_date\": \"2024-12-03\"}, \"confidence\": 0.92}\n```"],
+        );
+        let result = classify(&provider, "창고 정리 업무 추가해줘 12/3까지", &[]).await;
+        assert_eq!(result.intent, Intent::CreateTask);
+        assert_eq!(result.source, Source::Model);
+        assert_eq!(result.slots.title.as_deref(), Some("창고 정리"));
+        assert_eq!(result.slots.end_date, NaiveDate::from_ymd_opt(2024, 12, 3));
+        assert!(!result.needs_clarification());
+    }
+
+    #[tokio::test]
+    async fn low_confidence_asks_for_clarification() {
+        let provider = MockProvider::with_responses(
+            "mock",
+            [r#"{"intent": "create_task", "slots": {"title": "정리"}, "confidence": 0.3}"#],
+        );
+        let result = classify(&provider, "정리", &[]).await;
+        assert!(result.needs_clarification());
+        assert!(result.clarification(&shipped(), &audience("ko")).contains("\"정리\" 업무를 등록할까요?"));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_heuristics() {
+        let provider = MockProvider::with_responses("mock", ["sure, happy to help"]);
+        let result = classify(&provider, "오늘 브리핑", &[]).await;
+        assert_eq!(result.source, Source::Heuristic);
+        assert_eq!(result.intent, Intent::Briefing);
+
+        provider.push_error(LlmError::Timeout);
+        let result = classify(&provider, "업무 등록: 회의 준비", &[]).await;
+        assert_eq!(result.source, Source::Heuristic);
+        assert_eq!(result.intent, Intent::CreateTask);
+        assert!(!result.needs_clarification());
+    }
+
+    #[tokio::test]
+    async fn classifier_sees_the_previous_turn() {
+        let provider = MockProvider::with_responses("mock", [r#"{"intent": "question", "confidence": 0.8}"#]);
+        let history = [
+            ChatMessage::system("이전 대화 요약"),
+            ChatMessage::user("VPN 장애 관련해서 뭐라고 썼지?"),
+            ChatMessage::assistant("인증서 만료가 원인이었어요."),
+        ];
+        let result = classify(&provider, "재발 방지책은?", &history).await;
+        assert_eq!(result.intent, Intent::Question);
+        let sent = &provider.requests()[0];
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[1].content, "VPN 장애 관련해서 뭐라고 썼지?");
+    }
+
+    /// Measures a real model on the corpus:
+    /// `AI_BASE_URL=... AI_MODEL=... cargo test model_corpus_accuracy -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a model server"]
+    async fn model_corpus_accuracy() {
+        let env = |key: &str, default: &str| std::env::var(key).unwrap_or_else(|_| default.to_string());
+        let settings = HttpSettings {
+            base_url: env("AI_BASE_URL", "http://localhost:11434"),
+            model: env("AI_MODEL", "phi3.5:mini"),
+            embed_model: env("AI_EMBED_MODEL", "nomic-embed-text"),
+            api_key: std::env::var("AI_API_KEY").ok(),
+            api_key_header: env("AI_API_KEY_HEADER", "Authorization"),
+            timeout: Duration::from_secs(120),
+            max_retries: 0,
+            breaker: Arc::new(CircuitBreaker::new(0, Duration::ZERO)),
+        };
+        let client = reqwest::Client::new();
+        let provider: Box<dyn LlmProvider> = match env("AI_PROVIDER", "ollama").as_str() {
+            "openai" => Box::new(OpenAiProvider::new(settings, client)),
+            _ => Box::new(OllamaProvider::new(settings, client)),
+        };
+
+        let mut results = Vec::new();
+        let mut fallbacks = 0;
+        for (expected, text) in corpus() {
+            let result = classify(provider.as_ref(), text, &[]).await;
+            fallbacks += usize::from(result.source == Source::Heuristic);
+            results.push((expected, result.intent, text));
+        }
+        let (accuracy, misses) = accuracy(&results);
+        assert!(
+            accuracy >= MODEL_TARGET,
+            "{} accuracy {:.1}% of {} ({fallbacks} heuristic fallbacks), misses:\n{misses}",
+            provider.model(),
+            accuracy * 100.0,
+            results.len()
+        );
+    }
+}
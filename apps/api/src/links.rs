@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// A `[[...]]` reference found in note content.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WikiLink {
+    /// `[[Some title]]`, resolved against note titles case-insensitively.
+    NoteTitle(String),
+    /// `[[note:<uuid>]]`
+    NoteId(Uuid),
+    /// `[[task:<uuid>]]`
+    TaskId(Uuid),
+    /// `[[task:...]]` with something other than a UUID; never resolves.
+    BadTaskId(String),
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct LinkView {
+    pub source_note_id: Uuid,
+    pub target_type: String,
+    pub target_title: Option<String>,
+    /// The note or task the link points at, if it exists.
+    pub target_id: Option<Uuid>,
+    pub missing: bool,
+}
+
+/// Extracts the distinct links in `content`. Aliases (`[[Title|shown]]`) and
+/// heading anchors (`[[Title#Section]]`) are stripped from the target.
+pub fn parse_links(content: &str) -> Vec<WikiLink> {
+    let mut links = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("[[") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("]]") else {
+            break;
+        };
+        let inner = &after[..end];
+        rest = &after[end + 2..];
+
+        let target = inner
+            .split(['|', '#'])
+            .next()
+            .unwrap_or("")
+            .trim();
+        if target.is_empty() || target.contains('[') {
+            continue;
+        }
+        let link = if let Some(id) = strip_prefix_ci(target, "task:") {
+            match Uuid::parse_str(id.trim()) {
+                Ok(id) => WikiLink::TaskId(id),
+                Err(_) => WikiLink::BadTaskId(target.to_string()),
+            }
+        } else if let Some(id) = strip_prefix_ci(target, "note:").and_then(|id| Uuid::parse_str(id.trim()).ok()) {
+            WikiLink::NoteId(id)
+        } else {
+            WikiLink::NoteTitle(target.to_string())
+        };
+        if !links.contains(&link) {
+            links.push(link);
+        }
+    }
+    links
+}
+
+fn strip_prefix_ci<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    value
+        .get(..prefix.len())
+        .filter(|head| head.eq_ignore_ascii_case(prefix))
+        .map(|_| &value[prefix.len()..])
+}
+
+/// Rewrites every `[[old_title...]]` link in `content` to point at `new_title`,
+/// keeping any alias or heading suffix. Returns `None` when nothing changed.
+pub fn rewrite_title_links(content: &str, old_title: &str, new_title: &str) -> Option<String> {
+    let old = old_title.trim().to_lowercase();
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+    let mut changed = false;
+    while let Some(start) = rest.find("[[") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("]]") else {
+            break;
+        };
+        let inner = &after[..end];
+        let split = inner.find(['|', '#']).unwrap_or(inner.len());
+        out.push_str(&rest[..start + 2]);
+        if inner[..split].trim().to_lowercase() == old {
+            out.push_str(new_title);
+            out.push_str(&inner[split..]);
+            changed = true;
+        } else {
+            out.push_str(inner);
+        }
+        out.push_str("]]");
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    changed.then_some(out)
+}
+
+/// Rewrites `[[task:<id>]]` and `[[note:<id>]]` links whose id is a key of
+/// `ids` to the mapped id. Returns `None` when nothing changed.
+pub fn rewrite_id_links(content: &str, ids: &HashMap<Uuid, Uuid>) -> Option<String> {
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+    let mut changed = false;
+    while let Some(start) = rest.find("[[") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("]]") else {
+            break;
+        };
+        let inner = &after[..end];
+        let split = inner.find(['|', '#']).unwrap_or(inner.len());
+        let target = inner[..split].trim();
+        let mapped = ["task:", "note:"].iter().find_map(|prefix| {
+            let id = strip_prefix_ci(target, prefix)?;
+            let new = ids.get(&Uuid::parse_str(id.trim()).ok()?)?;
+            Some(format!("{}{new}", &target[..prefix.len()]))
+        });
+        out.push_str(&rest[..start + 2]);
+        match mapped {
+            Some(target) => {
+                out.push_str(&target);
+                out.push_str(&inner[split..]);
+                changed = true;
+            }
+            None => out.push_str(inner),
+        }
+        out.push_str("]]");
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    changed.then_some(out)
+}
+
+/// Replaces the stored links of a note with the ones in its current content.
+pub async fn sync_note_links(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    note_id: Uuid,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM links WHERE source_note_id = $1", note_id)
+        .execute(&mut *conn)
+        .await?;
+
+    for link in parse_links(content) {
+        let (target_type, target_id, target_title) = match link {
+            WikiLink::NoteTitle(title) => ("note", None, Some(title)),
+            WikiLink::NoteId(id) => ("note", Some(id), None),
+            WikiLink::TaskId(id) => ("task", Some(id), None),
+            WikiLink::BadTaskId(target) => ("task", None, Some(target)),
+        };
+        sqlx::query!(
+            r#"
+            INSERT INTO links (id, user_id, source_note_id, target_type, target_id, target_title)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            Uuid::new_v4(),
+            user_id,
+            note_id,
+            target_type,
+            target_id,
+            target_title
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Resolves the outgoing links of one note, or of every note when
+/// `source_note_id` is `None`.
+pub async fn resolve_links(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    source_note_id: Option<Uuid>,
+) -> Result<Vec<LinkView>, sqlx::Error> {
+    sqlx::query_as!(
+        LinkView,
+        r#"
+        SELECT
+            l.source_note_id,
+            l.target_type,
+            l.target_title,
+            COALESCE(t.id, n.id, nt.id) AS target_id,
+            COALESCE(t.id, n.id, nt.id) IS NULL AS "missing!"
+        FROM links l
+        LEFT JOIN tasks t
+            ON l.target_type = 'task' AND t.id = l.target_id AND t.user_id = l.user_id
+        LEFT JOIN notes n
+            ON l.target_type = 'note' AND n.id = l.target_id AND n.user_id = l.user_id
+        LEFT JOIN LATERAL (
+            SELECT id FROM notes
+            WHERE user_id = l.user_id AND lower(title) = lower(l.target_title)
+            ORDER BY created_at
+            LIMIT 1
+        ) nt ON l.target_type = 'note' AND l.target_title IS NOT NULL
+        WHERE l.user_id = $1 AND ($2::uuid IS NULL OR l.source_note_id = $2)
+        ORDER BY l.created_at, l.target_title
+        "#,
+        user_id,
+        source_note_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_distinct_links() {
+        let task = Uuid::new_v4();
+        let content = format!(
+            "[[회의록]] [[회의록|어제 회의]] [[Runbook#Rollback]] [[TASK:{task}]] [[task:nope]] [[note:nope]] [[ ]] [[a[b]] [[미완"
+        );
+        assert_eq!(
+            parse_links(&content),
+            [
+                WikiLink::NoteTitle("회의록".to_string()),
+                WikiLink::NoteTitle("Runbook".to_string()),
+                WikiLink::TaskId(task),
+                WikiLink::BadTaskId("task:nope".to_string()),
+                WikiLink::NoteTitle("note:nope".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrites_title_links_keeping_suffixes() {
+        let content = "[[회의록]], [[ 회의록 |어제]] and [[회의록#결정]] but not [[회의록 2]]";
+        assert_eq!(
+            rewrite_title_links(content, "회의록", "주간 회의").as_deref(),
+            Some("[[주간 회의]], [[주간 회의|어제]] and [[주간 회의#결정]] but not [[회의록 2]]")
+        );
+        assert_eq!(rewrite_title_links("[[Runbook]]", "회의록", "주간 회의"), None);
+    }
+
+    #[test]
+    fn rewrites_remapped_ids_only() {
+        let (old, new, other) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let ids = HashMap::from([(old, new)]);
+        let content = format!("[[Task:{old}|배포]] [[note:{other}]] [[{old}]]");
+        assert_eq!(
+            rewrite_id_links(&content, &ids).as_deref(),
+            Some(format!("[[Task:{new}|배포]] [[note:{other}]] [[{old}]]").as_str())
+        );
+        assert_eq!(rewrite_id_links(&format!("[[note:{other}]]"), &ids), None);
+    }
+}
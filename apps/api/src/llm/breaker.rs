@@ -0,0 +1,188 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::LlmError;
+
+/// Stops calling the model server after `threshold` failed calls in a row,
+/// so requests fail fast instead of each waiting out the timeouts. After
+/// `cooldown` one probe call is let through; its outcome closes the circuit
+/// or opens it for another cooldown.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant, opened_at: DateTime<Utc> },
+    HalfOpen { probe_started: Instant, opened_at: DateTime<Utc> },
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
+
+impl CircuitBreaker {
+    /// A `threshold` of 0 never opens the circuit.
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a call may go out now.
+    pub fn check(&self) -> Result<(), LlmError> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until, opened_at } if now >= until => {
+                *state = State::HalfOpen {
+                    probe_started: now,
+                    opened_at,
+                };
+                Ok(())
+            }
+            State::Open { until, .. } => Err(LlmError::CircuitOpen {
+                retry_after_secs: secs_until(now, until),
+            }),
+            // A probe that never reported back (its request was dropped)
+            // does not hold the circuit half-open forever.
+            State::HalfOpen { probe_started, opened_at } if now >= probe_started + self.cooldown => {
+                *state = State::HalfOpen {
+                    probe_started: now,
+                    opened_at,
+                };
+                Ok(())
+            }
+            State::HalfOpen { probe_started, .. } => Err(LlmError::CircuitOpen {
+                retry_after_secs: secs_until(now, probe_started + self.cooldown),
+            }),
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = State::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        if self.threshold == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        *state = match *state {
+            State::Closed { failures } if failures + 1 >= self.threshold => {
+                tracing::warn!(failures = failures + 1, "model server circuit opened");
+                State::Open {
+                    until: now + self.cooldown,
+                    opened_at: Utc::now(),
+                }
+            }
+            State::Closed { failures } => State::Closed { failures: failures + 1 },
+            State::HalfOpen { opened_at, .. } => State::Open {
+                until: now + self.cooldown,
+                opened_at,
+            },
+            open @ State::Open { .. } => open,
+        };
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let now = Instant::now();
+        match *self.state.lock().unwrap() {
+            State::Closed { failures } => CircuitStatus {
+                state: CircuitState::Closed,
+                consecutive_failures: failures,
+                opened_at: None,
+                retry_after_secs: None,
+            },
+            State::Open { until, opened_at } => CircuitStatus {
+                state: CircuitState::Open,
+                consecutive_failures: self.threshold,
+                opened_at: Some(opened_at),
+                retry_after_secs: Some(secs_until(now, until)),
+            },
+            State::HalfOpen { opened_at, .. } => CircuitStatus {
+                state: CircuitState::HalfOpen,
+                consecutive_failures: self.threshold,
+                opened_at: Some(opened_at),
+                retry_after_secs: None,
+            },
+        }
+    }
+}
+
+fn secs_until(now: Instant, until: Instant) -> u64 {
+    until.saturating_duration_since(now).as_secs_f64().ceil().max(1.0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_threshold_and_recovers_through_a_probe() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert_eq!(breaker.status().state, CircuitState::Open);
+        let err = breaker.check().unwrap_err();
+        assert_eq!(err.code(), "circuit_open");
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check().is_ok());
+        assert_eq!(breaker.status().state, CircuitState::HalfOpen);
+        // Only the probe goes out while it is pending.
+        assert!(breaker.check().is_err());
+        breaker.record_success();
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50));
+        breaker.record_failure();
+        let opened_at = breaker.status().opened_at;
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        let status = breaker.status();
+        assert_eq!(status.state, CircuitState::Open);
+        assert_eq!(status.opened_at, opened_at);
+        assert!(breaker.check().is_err());
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(30));
+        for _ in 0..10 {
+            breaker.record_failure();
+        }
+        assert!(breaker.check().is_ok());
+        assert_eq!(breaker.status().state, CircuitState::Closed);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use axum::async_trait;
+use futures::StreamExt;
+
+use super::{ChatCompletion, ChatMessage, LlmError, LlmProvider, StreamChunk, TokenStream, ToolSpec};
+
+const MOCK_EMBED_DIMS: usize = 64;
+
+/// Deterministic provider for tests and offline development. Replies come
+/// from a script when one is queued, otherwise the last user message is
+/// echoed back. Function calling is only offered while a script is queued,
+/// so an unscripted mock exercises the keyword fallback.
+pub struct MockProvider {
+    model: String,
+    script: Mutex<VecDeque<Result<ChatCompletion, LlmError>>>,
+    requests: Mutex<Vec<Vec<ChatMessage>>>,
+}
+
+impl MockProvider {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            script: Mutex::new(VecDeque::new()),
+            requests: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Queues replies returned, in order, by the next chat calls.
+    #[cfg(test)]
+    pub fn with_responses<I, S>(model: &str, responses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let provider = Self::new(model);
+        provider
+            .script
+            .lock()
+            .unwrap()
+            .extend(responses.into_iter().map(|r| {
+                Ok(ChatCompletion {
+                    content: r.into(),
+                    ..Default::default()
+                })
+            }));
+        provider
+    }
+
+    /// Queues a failure for the next chat call.
+    #[cfg(test)]
+    pub fn push_error(&self, err: LlmError) {
+        self.script.lock().unwrap().push_back(Err(err));
+    }
+
+    /// Messages of every chat call made so far.
+    #[cfg(test)]
+    pub fn requests(&self) -> Vec<Vec<ChatMessage>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn next_reply(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.requests.lock().unwrap().push(messages.to_vec());
+        let mut reply = match self.script.lock().unwrap().pop_front() {
+            Some(reply) => reply?,
+            None => ChatCompletion {
+                content: messages
+                    .iter()
+                    .rev()
+                    .find(|m| m.role == "user")
+                    .map(|m| m.content.clone())
+                    .unwrap_or_default(),
+                ..Default::default()
+            },
+        };
+        reply.prompt_tokens = Some(
+            messages
+                .iter()
+                .map(|m| m.content.split_whitespace().count() as i64)
+                .sum(),
+        );
+        reply.completion_tokens = Some(reply.content.split_whitespace().count() as i64);
+        Ok(reply)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn embed_model(&self) -> &str {
+        &self.model
+    }
+
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.next_reply(messages)
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        _tools: &[ToolSpec],
+    ) -> Result<ChatCompletion, LlmError> {
+        if self.script.lock().unwrap().is_empty() {
+            return Err(LlmError::ToolsUnsupported);
+        }
+        self.next_reply(messages)
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage]) -> Result<TokenStream, LlmError> {
+        let reply = self.next_reply(messages)?;
+        let mut chunks: Vec<Result<StreamChunk, LlmError>> = reply
+            .content
+            .split_inclusive(' ')
+            .map(|t| Ok(StreamChunk::Delta(t.to_string())))
+            .collect();
+        chunks.push(Ok(StreamChunk::Usage {
+            prompt_tokens: reply.prompt_tokens,
+            completion_tokens: reply.completion_tokens,
+        }));
+        Ok(futures::stream::iter(chunks).boxed())
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, LlmError> {
+        Ok(inputs.iter().map(|input| mock_embedding(input)).collect())
+    }
+}
+
+/// Bag-of-words hashing, so texts sharing words land close to each other.
+/// The hash is fixed, so vectors stored by one build match the next.
+fn mock_embedding(input: &str) -> Vec<f32> {
+    let mut vector = vec![0f32; MOCK_EMBED_DIMS];
+    for word in input.split_whitespace() {
+        vector[(fnv1a(word.to_lowercase().as_bytes()) % MOCK_EMBED_DIMS as u64) as usize] += 1.0;
+    }
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+/// 64-bit FNV-1a.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn echoes_last_user_message() {
+        let provider: &dyn LlmProvider = &MockProvider::new("mock");
+        let reply = provider
+            .chat(&[ChatMessage::system("sys"), ChatMessage::user("안녕 하세요")])
+            .await
+            .unwrap();
+        assert_eq!(reply.content, "안녕 하세요");
+        assert_eq!(reply.completion_tokens, Some(2));
+    }
+
+    #[tokio::test]
+    async fn scripted_replies_stream_in_order() {
+        let provider = MockProvider::with_responses("mock", ["first reply", "second"]);
+        let chunks: Vec<StreamChunk> = provider
+            .chat_stream(&[ChatMessage::user("hi")])
+            .await
+            .unwrap()
+            .map(|t| t.unwrap())
+            .collect()
+            .await;
+        let delta = |t: &str| StreamChunk::Delta(t.to_string());
+        let usage = StreamChunk::Usage {
+            prompt_tokens: Some(1),
+            completion_tokens: Some(2),
+        };
+        assert_eq!(chunks, [delta("first "), delta("reply"), usage]);
+        let next = provider.chat(&[ChatMessage::user("hi")]).await.unwrap();
+        assert_eq!(next.content, "second");
+        assert_eq!(provider.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn scripted_errors_surface() {
+        let provider = MockProvider::new("mock");
+        provider.push_error(LlmError::Timeout);
+        let err = provider.chat(&[ChatMessage::user("hi")]).await.unwrap_err();
+        assert_eq!(err.code(), "upstream_timeout");
+    }
+
+    #[tokio::test]
+    async fn embeddings_are_deterministic_and_normalized() {
+        let provider = MockProvider::new("mock");
+        let inputs = vec!["weekly report draft".to_string(), "weekly report draft".to_string()];
+        let vectors = provider.embed(&inputs).await.unwrap();
+        assert_eq!(vectors[0], vectors[1]);
+        let norm: f32 = vectors[0].iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-5);
+        // Pinned, so a toolchain upgrade cannot move stored vectors.
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
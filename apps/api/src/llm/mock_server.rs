@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+
+/// What Ollama answers when the model has no function calling.
+const TOOLS_UNSUPPORTED: &str = "registry.ollama.ai/library/mock does not support tools";
+
+/// Ollama's `/api/chat` served from the test process, so the real
+/// `OllamaProvider` and everything above it can be exercised end to end.
+/// Replies come from a script, in order, whatever kind of call asks; every
+/// request body is kept for inspection. Unscripted, tool calls are turned
+/// down the way a model without function calling does, and other calls get
+/// the last user message echoed back.
+pub struct MockOllama {
+    url: String,
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    script: Mutex<VecDeque<Scripted>>,
+    requests: Mutex<Vec<Value>>,
+}
+
+enum Scripted {
+    Reply { content: String, delay: Duration },
+    ToolCalls(Vec<Value>),
+    Status(u16, String),
+    Raw(String),
+    /// Streams the reply but stops before the `done` line.
+    Truncated(String),
+}
+
+impl MockOllama {
+    pub async fn start() -> Self {
+        let shared = Arc::new(Shared::default());
+        let app = Router::new()
+            .route("/api/chat", post(chat))
+            .with_state(shared.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind mock model server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        Self { url, shared }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Queues an assistant reply. Streaming calls get it in several chunks.
+    pub fn push_reply(&self, content: &str) -> &Self {
+        self.push_slow_reply(Duration::ZERO, content)
+    }
+
+    /// Queues a reply sent only after `delay`, for timeouts.
+    pub fn push_slow_reply(&self, delay: Duration, content: &str) -> &Self {
+        self.push(Scripted::Reply {
+            content: content.to_string(),
+            delay,
+        })
+    }
+
+    /// Queues an assistant turn calling one tool.
+    pub fn push_tool_call(&self, name: &str, arguments: Value) -> &Self {
+        self.push(Scripted::ToolCalls(vec![json!({
+            "function": { "name": name, "arguments": arguments }
+        })]))
+    }
+
+    /// Queues an error response with the given status and body.
+    pub fn push_status(&self, status: u16, body: &str) -> &Self {
+        self.push(Scripted::Status(status, body.to_string()))
+    }
+
+    /// Queues a 200 whose body is sent as is, e.g. something that is not JSON.
+    pub fn push_raw(&self, body: &str) -> &Self {
+        self.push(Scripted::Raw(body.to_string()))
+    }
+
+    /// Queues a streamed reply that breaks off before its end marker.
+    pub fn push_truncated_stream(&self, content: &str) -> &Self {
+        self.push(Scripted::Truncated(content.to_string()))
+    }
+
+    /// Queues the answer of a model without function calling.
+    pub fn push_tools_unsupported(&self) -> &Self {
+        self.push_status(400, TOOLS_UNSUPPORTED)
+    }
+
+    fn push(&self, scripted: Scripted) -> &Self {
+        self.shared.script.lock().unwrap().push_back(scripted);
+        self
+    }
+
+    /// Bodies of every `/api/chat` request so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.shared.requests.lock().unwrap().clone()
+    }
+
+    /// System prompt of the `index`th request.
+    pub fn system_prompt(&self, index: usize) -> String {
+        let requests = self.requests();
+        let messages = requests[index]["messages"].as_array().cloned().unwrap_or_default();
+        messages
+            .iter()
+            .find(|m| m["role"] == "system")
+            .and_then(|m| m["content"].as_str())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Script entries not used up yet.
+    pub fn remaining(&self) -> usize {
+        self.shared.script.lock().unwrap().len()
+    }
+}
+
+async fn chat(State(shared): State<Arc<Shared>>, Json(body): Json<Value>) -> Response {
+    shared.requests.lock().unwrap().push(body.clone());
+    let stream = body["stream"].as_bool().unwrap_or(true);
+    let next = shared.script.lock().unwrap().pop_front();
+    let scripted = match next {
+        Some(scripted) => scripted,
+        None if body.get("tools").is_some() => Scripted::Status(400, TOOLS_UNSUPPORTED.to_string()),
+        None => Scripted::Reply {
+            content: last_user_message(&body),
+            delay: Duration::ZERO,
+        },
+    };
+
+    match scripted {
+        Scripted::Reply { content, delay } => {
+            tokio::time::sleep(delay).await;
+            if stream {
+                ndjson(stream_lines(&content))
+            } else {
+                Json(done(json!({ "role": "assistant", "content": content }), &content)).into_response()
+            }
+        }
+        Scripted::ToolCalls(calls) => Json(done(
+            json!({ "role": "assistant", "content": "", "tool_calls": calls }),
+            "",
+        ))
+        .into_response(),
+        Scripted::Status(status, body) => {
+            (StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR), body).into_response()
+        }
+        Scripted::Truncated(content) => {
+            let lines = stream_lines(&content);
+            let cut = lines.trim_end().rsplit_once('\n').map_or("", |(kept, _)| kept);
+            ndjson(format!("{cut}\n"))
+        }
+        Scripted::Raw(body) if stream => ndjson(body),
+        Scripted::Raw(body) => ([(header::CONTENT_TYPE, "application/json")], body).into_response(),
+    }
+}
+
+fn last_user_message(body: &Value) -> String {
+    body["messages"]
+        .as_array()
+        .and_then(|messages| messages.iter().rev().find(|m| m["role"] == "user"))
+        .and_then(|m| m["content"].as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// The final object of a response, with Ollama's token counts: a fixed
+/// prompt count and one completion token per character.
+fn done(message: Value, content: &str) -> Value {
+    json!({
+        "model": "mock",
+        "created_at": "2024-12-02T00:00:00Z",
+        "message": message,
+        "done": true,
+        "prompt_eval_count": 10,
+        "eval_count": content.chars().count(),
+    })
+}
+
+/// One line per word, then the `done` line.
+fn stream_lines(content: &str) -> String {
+    let mut lines: Vec<String> = content
+        .split_inclusive(' ')
+        .map(|word| {
+            json!({ "model": "mock", "message": { "role": "assistant", "content": word }, "done": false })
+                .to_string()
+        })
+        .collect();
+    lines.push(done(json!({ "role": "assistant", "content": "" }), content).to_string());
+    lines.join("\n") + "\n"
+}
+
+fn ndjson(body: String) -> Response {
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from(body)).into_response()
+}
@@ -0,0 +1,443 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::response::{IntoResponse, Response};
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use rand::Rng;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::config::{Config, LlmProviderKind};
+use crate::prompts::{Audience, Prompt, Prompts};
+
+mod breaker;
+mod mock;
+#[cfg(test)]
+mod mock_server;
+mod ollama;
+mod openai;
+
+pub use breaker::{CircuitBreaker, CircuitState, CircuitStatus};
+pub use mock::MockProvider;
+#[cfg(test)]
+pub use mock_server::MockOllama;
+pub use ollama::OllamaProvider;
+pub use openai::OpenAiProvider;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    /// Calls requested by an assistant turn.
+    pub tool_calls: Vec<ToolCall>,
+    /// For `tool` messages, the call this is the result of.
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: "system".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: "user".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn assistant_tool_calls(content: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::assistant(content)
+        }
+    }
+
+    pub fn tool_result(call: &ToolCall, content: impl Into<String>) -> Self {
+        Self {
+            role: "tool".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: Some(call.id.clone()),
+        }
+    }
+}
+
+/// A function the model may call, described by a JSON schema.
+#[derive(Serialize, Clone, Debug)]
+pub struct ToolSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: serde_json::Value,
+}
+
+impl ToolSpec {
+    /// The `{"type": "function", ...}` shape shared by Ollama and OpenAI.
+    fn wire(&self) -> serde_json::Value {
+        json!({ "type": "function", "function": self })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChatCompletion {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+    pub prompt_tokens: Option<i64>,
+    pub completion_tokens: Option<i64>,
+}
+
+/// One item of a streamed completion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamChunk {
+    Delta(String),
+    /// The token counts the server reported, after the last delta. Servers
+    /// that report none send no such item.
+    Usage {
+        prompt_tokens: Option<i64>,
+        completion_tokens: Option<i64>,
+    },
+}
+
+/// Chunks of a streamed completion. The stream ends after the last chunk;
+/// a missing end marker from the server surfaces as `Incomplete`.
+pub type TokenStream = BoxStream<'static, Result<StreamChunk, LlmError>>;
+
+/// Which daily quota ran out, with its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaLimit {
+    Requests(i64),
+    Tokens(i64),
+}
+
+/// `Display` is for logs; users get `message` in their locale.
+#[derive(Debug, thiserror::Error)]
+pub enum LlmError {
+    #[error("model server unavailable: {0}")]
+    Unavailable(String),
+    #[error("model timed out")]
+    Timeout,
+    #[error("model call failed ({status}): {body}")]
+    Status { status: u16, body: String },
+    #[error("malformed model response: {0}")]
+    Malformed(String),
+    #[error("model error: {0}")]
+    Upstream(String),
+    #[error("model stream failed: {0}")]
+    Interrupted(String),
+    #[error("model response cut off")]
+    Incomplete,
+    #[error("model does not support tool calls")]
+    ToolsUnsupported,
+    #[error("circuit open, retry in {retry_after_secs}s")]
+    CircuitOpen { retry_after_secs: u64 },
+    #[error("daily quota used up ({limit:?})")]
+    QuotaExceeded { limit: QuotaLimit, retry_after_secs: u64 },
+}
+
+impl LlmError {
+    /// Stable identifier for clients, e.g. the `code` of an SSE error event.
+    pub fn code(&self) -> &'static str {
+        match self {
+            LlmError::Unavailable(_) => "upstream_unavailable",
+            LlmError::Timeout => "upstream_timeout",
+            LlmError::Status { .. } => "upstream_status",
+            LlmError::Malformed(_) => "malformed_chunk",
+            LlmError::Upstream(_) => "upstream_error",
+            LlmError::Interrupted(_) => "upstream_interrupted",
+            LlmError::Incomplete => "upstream_incomplete",
+            LlmError::ToolsUnsupported => "tools_unsupported",
+            LlmError::CircuitOpen { .. } => "circuit_open",
+            LlmError::QuotaExceeded { .. } => "quota_exceeded",
+        }
+    }
+
+    /// What to tell the user, rendered from the `error_message` template.
+    pub fn message(&self, prompts: &Prompts, audience: &Audience) -> String {
+        // Each code's branch of the template only reads its own variables.
+        let vars = match self {
+            LlmError::Unavailable(detail)
+            | LlmError::Malformed(detail)
+            | LlmError::Upstream(detail)
+            | LlmError::Interrupted(detail) => minijinja::context! { detail },
+            LlmError::Status { status, body } => minijinja::context! { status, detail => body },
+            LlmError::CircuitOpen { retry_after_secs } => minijinja::context! { retry_after_secs },
+            LlmError::QuotaExceeded { limit, retry_after_secs } => {
+                let (limit_kind, limit) = match limit {
+                    QuotaLimit::Requests(n) => ("requests", n),
+                    QuotaLimit::Tokens(n) => ("tokens", n),
+                };
+                minijinja::context! { limit_kind, limit, retry_after_secs }
+            }
+            LlmError::Timeout | LlmError::Incomplete | LlmError::ToolsUnsupported => minijinja::context! {},
+        };
+        prompts.render(audience, Prompt::ErrorMessage, minijinja::context! { code => self.code(), ..vars })
+    }
+
+    /// 503 with `Retry-After` while the circuit is open, 429 with
+    /// `Retry-After` once the user's daily quota is used up, 502 otherwise;
+    /// the body is `message`.
+    pub fn respond(self, prompts: &Prompts, audience: &Audience) -> Response {
+        let message = self.message(prompts, audience);
+        match self {
+            LlmError::CircuitOpen { retry_after_secs } => (
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                [(axum::http::header::RETRY_AFTER, retry_after_secs.to_string())],
+                message,
+            )
+                .into_response(),
+            LlmError::QuotaExceeded { retry_after_secs, .. } => (
+                axum::http::StatusCode::TOO_MANY_REQUESTS,
+                [(axum::http::header::RETRY_AFTER, retry_after_secs.to_string())],
+                message,
+            )
+                .into_response(),
+            _ => (axum::http::StatusCode::BAD_GATEWAY, message).into_response(),
+        }
+    }
+
+    fn from_send(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            LlmError::Timeout
+        } else {
+            LlmError::Unavailable(err.to_string())
+        }
+    }
+
+    fn is_transient(&self) -> bool {
+        match self {
+            LlmError::Unavailable(_) | LlmError::Timeout => true,
+            LlmError::Status { status, .. } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Failures that count towards opening the circuit. A 429 means the
+    /// server is up, just busy.
+    fn is_outage(&self) -> bool {
+        match self {
+            LlmError::Unavailable(_) | LlmError::Timeout => true,
+            LlmError::Status { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    fn model(&self) -> &str;
+
+    /// Model used by `embed`; stored with each vector so a model change
+    /// triggers re-embedding.
+    fn embed_model(&self) -> &str;
+
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError>;
+
+    /// Chat constrained to a JSON object where the backend supports it.
+    /// Callers still validate the content, since not every server does.
+    async fn chat_json(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.chat(messages).await
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage]) -> Result<TokenStream, LlmError>;
+
+    /// Chat with function calling. The completion either carries
+    /// `tool_calls` to run or the final `content`. Backends or models
+    /// without function calling return `LlmError::ToolsUnsupported`.
+    async fn chat_with_tools(
+        &self,
+        _messages: &[ChatMessage],
+        _tools: &[ToolSpec],
+    ) -> Result<ChatCompletion, LlmError> {
+        Err(LlmError::ToolsUnsupported)
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, LlmError>;
+
+    /// State of the circuit breaker in front of the model server, for
+    /// providers that have one.
+    fn circuit(&self) -> Option<CircuitStatus> {
+        None
+    }
+}
+
+/// Rough token count for budgeting prompts: about four ASCII characters per
+/// token, and one token per other character (Hangul is close to that).
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text
+        .chars()
+        .fold((0usize, 0usize), |(a, o), c| if c.is_ascii() { (a + 1, o) } else { (a, o + 1) });
+    ascii.div_ceil(4) + other
+}
+
+/// Connection settings shared by the HTTP-backed providers.
+#[derive(Clone)]
+pub struct HttpSettings {
+    pub base_url: String,
+    pub model: String,
+    pub embed_model: String,
+    pub api_key: Option<String>,
+    pub api_key_header: String,
+    /// Whole-request limit of non-streaming calls.
+    pub timeout: Duration,
+    pub max_retries: u32,
+    /// Shared by every clone, so all calls to the server trip one circuit.
+    pub breaker: Arc<CircuitBreaker>,
+}
+
+impl HttpSettings {
+    fn from_config(cfg: &Config) -> Self {
+        Self {
+            base_url: cfg.ai_base_url.trim_end_matches('/').to_string(),
+            model: cfg.ai_model.clone(),
+            embed_model: cfg.ai_embed_model.clone(),
+            api_key: cfg.ai_api_key.clone(),
+            api_key_header: cfg.ai_api_key_header.clone(),
+            timeout: Duration::from_secs(cfg.ai_timeout_secs),
+            max_retries: cfg.ai_max_retries,
+            breaker: Arc::new(CircuitBreaker::new(
+                cfg.ai_breaker_threshold,
+                Duration::from_secs(cfg.ai_breaker_cooldown_secs),
+            )),
+        }
+    }
+
+    fn authorize(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) if self.api_key_header.eq_ignore_ascii_case("authorization") => {
+                req.bearer_auth(key)
+            }
+            Some(key) => req.header(self.api_key_header.as_str(), key),
+            None => req,
+        }
+    }
+
+    /// Sends `req`, retrying connection failures, 429s and 5xx responses
+    /// with jittered backoff. Streaming callers pass `stream = true` so the
+    /// overall timeout does not cut off a long generation; the client's read
+    /// timeout still ends a stream that goes quiet. Fails fast while the
+    /// circuit is open.
+    async fn send(
+        &self,
+        req: reqwest::RequestBuilder,
+        stream: bool,
+    ) -> Result<reqwest::Response, LlmError> {
+        self.breaker.check()?;
+        let result = self.send_with_retries(req, stream).await;
+        match &result {
+            Err(err) if err.is_outage() => self.breaker.record_failure(),
+            _ => self.breaker.record_success(),
+        }
+        result
+    }
+
+    async fn send_with_retries(
+        &self,
+        req: reqwest::RequestBuilder,
+        stream: bool,
+    ) -> Result<reqwest::Response, LlmError> {
+        let req = self.authorize(req);
+        let req = if stream { req } else { req.timeout(self.timeout) };
+        let mut attempt = 0;
+        loop {
+            let err = match req.try_clone() {
+                Some(this_try) => match this_try.send().await {
+                    Ok(res) if res.status().is_success() => return Ok(res),
+                    Ok(res) => {
+                        let status = res.status().as_u16();
+                        let body = res.text().await.unwrap_or_default();
+                        LlmError::Status { status, body }
+                    }
+                    Err(err) => LlmError::from_send(err),
+                },
+                None => return req.send().await.map_err(LlmError::from_send),
+            };
+            if !err.is_transient() || attempt >= self.max_retries {
+                return Err(err);
+            }
+            attempt += 1;
+            tokio::time::sleep(backoff(attempt)).await;
+        }
+    }
+}
+
+/// Exponential backoff from 500ms, spread by ±50% so clients that failed
+/// together do not retry together.
+fn backoff(attempt: u32) -> Duration {
+    let base = 250 * 2u64.pow(attempt);
+    Duration::from_millis(rand::thread_rng().gen_range(base / 2..=base + base / 2))
+}
+
+/// HTTP client shared by the model providers.
+pub fn http_client(cfg: &Config) -> anyhow::Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(cfg.ai_connect_timeout_secs))
+        .read_timeout(Duration::from_secs(cfg.ai_read_timeout_secs))
+        .build()?)
+}
+
+pub fn from_config(cfg: &Config, client: reqwest::Client) -> Arc<dyn LlmProvider> {
+    let settings = HttpSettings::from_config(cfg);
+    match cfg.ai_provider {
+        LlmProviderKind::Ollama => Arc::new(OllamaProvider::new(settings, client)),
+        LlmProviderKind::OpenAi => Arc::new(OpenAiProvider::new(settings, client)),
+        LlmProviderKind::Mock => Arc::new(MockProvider::new(&settings.model)),
+    }
+}
+
+/// Splits a chunked HTTP body into lines, keeping partial lines buffered
+/// across chunks.
+fn body_lines(
+    body: impl Stream<Item = reqwest::Result<Bytes>> + Send + 'static,
+) -> impl Stream<Item = Result<String, LlmError>> + Send + 'static {
+    async_stream::stream! {
+        let mut body = Box::pin(body);
+        let mut buffer: Vec<u8> = Vec::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    yield Err(LlmError::Interrupted(err.to_string()));
+                    return;
+                }
+            };
+            buffer.extend_from_slice(&chunk);
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                if !line.is_empty() {
+                    yield Ok(line);
+                }
+            }
+        }
+        let rest = String::from_utf8_lossy(&buffer).trim().to_string();
+        if !rest.is_empty() {
+            yield Ok(rest);
+        }
+    }
+}
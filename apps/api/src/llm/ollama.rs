@@ -0,0 +1,252 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::async_trait;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{
+    body_lines, ChatCompletion, ChatMessage, CircuitStatus, HttpSettings, LlmError, LlmProvider, StreamChunk, TokenStream,
+    ToolCall, ToolSpec,
+};
+
+/// Talks to Ollama's native `/api/chat` and `/api/embed` endpoints.
+pub struct OllamaProvider {
+    settings: HttpSettings,
+    client: reqwest::Client,
+    /// Set once the model rejected `tools`, so later turns skip the attempt.
+    tools_unsupported: AtomicBool,
+}
+
+#[derive(Deserialize)]
+struct OllamaChatMessage {
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    tool_calls: Vec<OllamaToolCall>,
+}
+
+#[derive(Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Deserialize)]
+struct OllamaFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Deserialize)]
+struct OllamaChatResponse {
+    message: Option<OllamaChatMessage>,
+    response: Option<String>, // fallback shape for /api/generate
+    prompt_eval_count: Option<i64>,
+    eval_count: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct OllamaStreamChunk {
+    message: Option<OllamaChatMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+    /// Only on the `done` chunk.
+    prompt_eval_count: Option<i64>,
+    eval_count: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl OllamaProvider {
+    pub fn new(settings: HttpSettings, client: reqwest::Client) -> Self {
+        Self {
+            settings,
+            client,
+            tools_unsupported: AtomicBool::new(false),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.settings.base_url, path)
+    }
+
+    async fn complete(&self, body: Value) -> Result<ChatCompletion, LlmError> {
+        let res = self
+            .settings
+            .send(self.client.post(self.url("/api/chat")).json(&body), false)
+            .await?;
+        let parsed = res
+            .json::<OllamaChatResponse>()
+            .await
+            .map_err(|e| LlmError::Malformed(e.to_string()))?;
+
+        let (content, tool_calls) = match parsed.message {
+            Some(message) => (message.content, message.tool_calls),
+            None => (parsed.response.unwrap_or_default(), Vec::new()),
+        };
+        Ok(ChatCompletion {
+            content,
+            // Ollama does not id its calls; number them so results can refer back.
+            tool_calls: tool_calls
+                .into_iter()
+                .enumerate()
+                .map(|(i, call)| ToolCall {
+                    id: format!("call_{i}"),
+                    name: call.function.name,
+                    arguments: call.function.arguments,
+                })
+                .collect(),
+            prompt_tokens: parsed.prompt_eval_count,
+            completion_tokens: parsed.eval_count,
+        })
+    }
+}
+
+fn wire_messages(messages: &[ChatMessage]) -> Vec<Value> {
+    messages
+        .iter()
+        .map(|m| {
+            let mut wire = json!({ "role": m.role, "content": m.content });
+            if !m.tool_calls.is_empty() {
+                wire["tool_calls"] = m
+                    .tool_calls
+                    .iter()
+                    .map(|c| json!({ "function": { "name": c.name, "arguments": c.arguments } }))
+                    .collect();
+            }
+            wire
+        })
+        .collect()
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn model(&self) -> &str {
+        &self.settings.model
+    }
+
+    fn embed_model(&self) -> &str {
+        &self.settings.embed_model
+    }
+
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.complete(json!({
+            "model": self.settings.model,
+            "messages": wire_messages(messages),
+            "stream": false
+        }))
+        .await
+    }
+
+    async fn chat_json(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.complete(json!({
+            "model": self.settings.model,
+            "messages": wire_messages(messages),
+            "format": "json",
+            "stream": false
+        }))
+        .await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolSpec],
+    ) -> Result<ChatCompletion, LlmError> {
+        if self.tools_unsupported.load(Ordering::Relaxed) {
+            return Err(LlmError::ToolsUnsupported);
+        }
+        let body = json!({
+            "model": self.settings.model,
+            "messages": wire_messages(messages),
+            "tools": tools.iter().map(ToolSpec::wire).collect::<Vec<_>>(),
+            "stream": false
+        });
+        match self.complete(body).await {
+            Err(LlmError::Status { status: 400, body }) if body.contains("does not support tools") => {
+                self.tools_unsupported.store(true, Ordering::Relaxed);
+                Err(LlmError::ToolsUnsupported)
+            }
+            other => other,
+        }
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage]) -> Result<TokenStream, LlmError> {
+        let body = json!({
+            "model": self.settings.model,
+            "messages": wire_messages(messages),
+            "stream": true
+        });
+        let res = self
+            .settings
+            .send(self.client.post(self.url("/api/chat")).json(&body), true)
+            .await?;
+
+        // Ollama sends one JSON object per line and marks the last with `done`.
+        let mut lines = Box::pin(body_lines(res.bytes_stream()));
+        let stream = async_stream::stream! {
+            while let Some(line) = lines.next().await {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        yield Err(err);
+                        return;
+                    }
+                };
+                let chunk = match serde_json::from_str::<OllamaStreamChunk>(&line) {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        yield Err(LlmError::Malformed(err.to_string()));
+                        return;
+                    }
+                };
+                if let Some(err) = chunk.error {
+                    yield Err(LlmError::Upstream(err));
+                    return;
+                }
+                if let Some(content) = chunk.message.map(|m| m.content).filter(|c| !c.is_empty()) {
+                    yield Ok(StreamChunk::Delta(content));
+                }
+                if chunk.done {
+                    if chunk.prompt_eval_count.is_some() || chunk.eval_count.is_some() {
+                        yield Ok(StreamChunk::Usage {
+                            prompt_tokens: chunk.prompt_eval_count,
+                            completion_tokens: chunk.eval_count,
+                        });
+                    }
+                    return;
+                }
+            }
+            yield Err(LlmError::Incomplete);
+        };
+        Ok(stream.boxed())
+    }
+
+    fn circuit(&self) -> Option<CircuitStatus> {
+        Some(self.settings.breaker.status())
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, LlmError> {
+        let body = json!({
+            "model": self.settings.embed_model,
+            "input": inputs
+        });
+        let res = self
+            .settings
+            .send(self.client.post(self.url("/api/embed")).json(&body), false)
+            .await?;
+        let parsed = res
+            .json::<OllamaEmbedResponse>()
+            .await
+            .map_err(|e| LlmError::Malformed(e.to_string()))?;
+        if parsed.embeddings.len() != inputs.len() {
+            return Err(LlmError::Malformed("embedding count mismatch".to_string()));
+        }
+        Ok(parsed.embeddings)
+    }
+}
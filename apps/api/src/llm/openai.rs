@@ -0,0 +1,276 @@
+use axum::async_trait;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{
+    body_lines, ChatCompletion, ChatMessage, CircuitStatus, HttpSettings, LlmError, LlmProvider, StreamChunk, TokenStream,
+    ToolCall, ToolSpec,
+};
+
+/// Talks to servers speaking the OpenAI `/v1/chat/completions` protocol,
+/// such as vLLM and llama.cpp's `llama-server`.
+pub struct OpenAiProvider {
+    settings: HttpSettings,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct OpenAiMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAiToolCall>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiToolCall {
+    #[serde(default)]
+    id: String,
+    function: OpenAiFunctionCall,
+}
+
+#[derive(Deserialize)]
+struct OpenAiFunctionCall {
+    name: String,
+    /// JSON-encoded arguments.
+    #[serde(default)]
+    arguments: String,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: Option<OpenAiMessage>,
+    delta: Option<OpenAiMessage>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: Option<i64>,
+    completion_tokens: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChatResponse {
+    #[serde(default)]
+    choices: Vec<OpenAiChoice>,
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedding {
+    embedding: Vec<f32>,
+    #[serde(default)]
+    index: usize,
+}
+
+#[derive(Deserialize)]
+struct OpenAiEmbedResponse {
+    data: Vec<OpenAiEmbedding>,
+}
+
+impl OpenAiProvider {
+    pub fn new(settings: HttpSettings, client: reqwest::Client) -> Self {
+        Self { settings, client }
+    }
+
+    /// Accepts base URLs configured with or without the trailing `/v1`.
+    fn url(&self, path: &str) -> String {
+        let base = self.settings.base_url.trim_end_matches("/v1");
+        format!("{base}/v1{path}")
+    }
+
+    async fn complete(&self, body: Value) -> Result<ChatCompletion, LlmError> {
+        let res = self
+            .settings
+            .send(self.client.post(self.url("/chat/completions")).json(&body), false)
+            .await?;
+        let parsed = res
+            .json::<OpenAiChatResponse>()
+            .await
+            .map_err(|e| LlmError::Malformed(e.to_string()))?;
+
+        let message = parsed.choices.into_iter().next().and_then(|c| c.message);
+        let (content, tool_calls) = match message {
+            Some(message) => (message.content.unwrap_or_default(), message.tool_calls),
+            None => (String::new(), Vec::new()),
+        };
+        let tool_calls = tool_calls
+            .into_iter()
+            .map(|call| {
+                let arguments = serde_json::from_str(&call.function.arguments)
+                    .map_err(|e| LlmError::Malformed(e.to_string()))?;
+                Ok(ToolCall {
+                    id: call.id,
+                    name: call.function.name,
+                    arguments,
+                })
+            })
+            .collect::<Result<Vec<_>, LlmError>>()?;
+        Ok(ChatCompletion {
+            content,
+            tool_calls,
+            prompt_tokens: parsed.usage.as_ref().and_then(|u| u.prompt_tokens),
+            completion_tokens: parsed.usage.as_ref().and_then(|u| u.completion_tokens),
+        })
+    }
+}
+
+fn wire_messages(messages: &[ChatMessage]) -> Vec<Value> {
+    messages
+        .iter()
+        .map(|m| {
+            let mut wire = json!({ "role": m.role, "content": m.content });
+            if !m.tool_calls.is_empty() {
+                wire["tool_calls"] = m
+                    .tool_calls
+                    .iter()
+                    .map(|c| {
+                        json!({
+                            "id": c.id,
+                            "type": "function",
+                            "function": { "name": c.name, "arguments": c.arguments.to_string() }
+                        })
+                    })
+                    .collect();
+            }
+            if let Some(id) = &m.tool_call_id {
+                wire["tool_call_id"] = json!(id);
+            }
+            wire
+        })
+        .collect()
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn model(&self) -> &str {
+        &self.settings.model
+    }
+
+    fn embed_model(&self) -> &str {
+        &self.settings.embed_model
+    }
+
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.complete(json!({
+            "model": self.settings.model,
+            "messages": wire_messages(messages),
+            "stream": false
+        }))
+        .await
+    }
+
+    async fn chat_json(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.complete(json!({
+            "model": self.settings.model,
+            "messages": wire_messages(messages),
+            "response_format": { "type": "json_object" },
+            "stream": false
+        }))
+        .await
+    }
+
+    async fn chat_with_tools(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolSpec],
+    ) -> Result<ChatCompletion, LlmError> {
+        let body = json!({
+            "model": self.settings.model,
+            "messages": wire_messages(messages),
+            "tools": tools.iter().map(ToolSpec::wire).collect::<Vec<_>>(),
+            "stream": false
+        });
+        match self.complete(body).await {
+            // vLLM without a tool parser and similar servers reject the field.
+            Err(LlmError::Status { status: 400, body }) if body.contains("tool") => {
+                Err(LlmError::ToolsUnsupported)
+            }
+            other => other,
+        }
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage]) -> Result<TokenStream, LlmError> {
+        let body = json!({
+            "model": self.settings.model,
+            "messages": wire_messages(messages),
+            "stream": true,
+            "stream_options": { "include_usage": true }
+        });
+        let res = self
+            .settings
+            .send(self.client.post(self.url("/chat/completions")).json(&body), true)
+            .await?;
+
+        // Server-sent events: `data: {...}` per chunk, terminated by `data: [DONE]`.
+        // With `include_usage` the counts come in a last chunk without choices.
+        let mut lines = Box::pin(body_lines(res.bytes_stream()));
+        let stream = async_stream::stream! {
+            let mut finished = false;
+            while let Some(line) = lines.next().await {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        yield Err(err);
+                        return;
+                    }
+                };
+                let Some(data) = line.strip_prefix("data:") else {
+                    continue;
+                };
+                let data = data.trim();
+                if data == "[DONE]" {
+                    return;
+                }
+                let chunk = match serde_json::from_str::<OpenAiChatResponse>(data) {
+                    Ok(chunk) => chunk,
+                    Err(err) => {
+                        yield Err(LlmError::Malformed(err.to_string()));
+                        return;
+                    }
+                };
+                for choice in chunk.choices {
+                    if let Some(content) = choice.delta.and_then(|d| d.content).filter(|c| !c.is_empty()) {
+                        yield Ok(StreamChunk::Delta(content));
+                    }
+                    finished |= choice.finish_reason.is_some();
+                }
+                if let Some(usage) = chunk.usage {
+                    yield Ok(StreamChunk::Usage {
+                        prompt_tokens: usage.prompt_tokens,
+                        completion_tokens: usage.completion_tokens,
+                    });
+                }
+            }
+            if !finished {
+                yield Err(LlmError::Incomplete);
+            }
+        };
+        Ok(stream.boxed())
+    }
+
+    fn circuit(&self) -> Option<CircuitStatus> {
+        Some(self.settings.breaker.status())
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, LlmError> {
+        let body = json!({
+            "model": self.settings.embed_model,
+            "input": inputs
+        });
+        let res = self
+            .settings
+            .send(self.client.post(self.url("/embeddings")).json(&body), false)
+            .await?;
+        let mut parsed = res
+            .json::<OpenAiEmbedResponse>()
+            .await
+            .map_err(|e| LlmError::Malformed(e.to_string()))?;
+        if parsed.data.len() != inputs.len() {
+            return Err(LlmError::Malformed("embedding count mismatch".to_string()));
+        }
+        parsed.data.sort_by_key(|d| d.index);
+        Ok(parsed.data.into_iter().map(|d| d.embedding).collect())
+    }
+}
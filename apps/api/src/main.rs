@@ -4,13 +4,33 @@ use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::EnvFilter;
 
+mod analytics;
 mod auth;
+mod briefings;
 mod config;
+mod context;
+mod conversations;
+mod dashboard;
+mod dates;
 mod db;
+mod embeddings;
+mod enrichment;
 mod handlers;
+mod intent;
+mod links;
+mod llm;
 mod middleware;
 mod models;
+mod prompts;
+mod reports;
+mod revisions;
 mod routes;
+mod settings;
+#[cfg(test)]
+mod testing;
+mod timers;
+mod tools;
+mod usage;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -46,7 +66,7 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
-    let app: Router = routes::app(pool, cfg.clone())
+    let app: Router = routes::app(pool, cfg.clone())?
         .layer(TraceLayer::new_for_http())
         .layer(cors);
 
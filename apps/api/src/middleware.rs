@@ -1,19 +1,38 @@
 use axum::{
     async_trait,
-    extract::{FromRequestParts, State},
+    extract::FromRequestParts,
     http::{header, request::Parts},
     response::{IntoResponse, Response},
 };
+use chrono_tz::Tz;
+use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::auth::decode_jwt;
+use crate::briefings::Mailer;
+use crate::embeddings::Embeddings;
+use crate::enrichment::Enrichment;
+use crate::llm::LlmProvider;
+use crate::prompts::Prompts;
+use crate::usage::Quotas;
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: sqlx::PgPool,
     pub jwt_secret: String,
-    pub ai_base_url: String,
-    pub ai_model: String,
+    pub llm: Arc<dyn LlmProvider>,
+    /// Estimated tokens of earlier turns replayed into a conversation.
+    pub ai_history_tokens: usize,
+    /// Context window of the chat model, in estimated tokens.
+    pub ai_context_tokens: usize,
+    pub ai_quotas: Quotas,
+    pub embeddings: Embeddings,
+    pub enrichment: Enrichment,
+    /// For users without a `timezone` setting; see `settings::preferences`.
+    pub default_tz: Tz,
+    pub prompts: Prompts,
+    /// Sends emailed briefings; unset without `SMTP_URL`.
+    pub mailer: Option<Arc<dyn Mailer>>,
 }
 
 pub struct AuthUser {
@@ -2,6 +2,7 @@ use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+#[allow(dead_code)]
 #[derive(sqlx::FromRow, Serialize)]
 pub struct User {
     pub id: Uuid,
@@ -22,8 +23,15 @@ pub struct Task {
     pub start_date: Option<NaiveDate>,
     pub end_date: Option<NaiveDate>,
     pub tags: Vec<String>,
+    /// The task this one was broken out of.
+    pub parent_id: Option<Uuid>,
+    pub estimate_minutes: Option<i32>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// First moved out of `todo`; cleared when moved back.
+    pub started_at: Option<DateTime<Utc>>,
+    /// Last moved to `done`; cleared when reopened.
+    pub completed_at: Option<DateTime<Utc>>,
 }
 
 #[derive(sqlx::FromRow, Serialize)]
@@ -35,6 +43,12 @@ pub struct Note {
     pub tags: Vec<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Model-made summary, tag suggestions and action items; see
+    /// `enrichment`.
+    pub ai_summary: Option<String>,
+    pub ai_tags: Vec<String>,
+    pub ai_action_items: serde_json::Value,
+    pub ai_enriched_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Deserialize)]
@@ -58,8 +72,9 @@ pub struct AuthResponse {
 pub struct TaskCreate {
     pub title: String,
     pub description: Option<String>,
-    pub status: String,
-    pub priority: String,
+    /// The user's default status and priority when absent.
+    pub status: Option<String>,
+    pub priority: Option<String>,
     pub due_date: Option<NaiveDate>,
     pub start_date: Option<NaiveDate>,
     pub end_date: Option<NaiveDate>,
@@ -90,6 +105,8 @@ pub struct NoteUpdate {
     pub title: Option<String>,
     pub content: Option<String>,
     pub tags: Option<Vec<String>>,
+    /// On rename, also rewrite `[[old title]]` links in other notes.
+    pub rewrite_links: Option<bool>,
 }
 
 #[derive(Serialize)]
@@ -100,3 +117,32 @@ pub struct DashboardSummary {
     pub done_this_week: i64,
     pub recent_tasks: Vec<Task>,
 }
+
+#[derive(Serialize)]
+pub struct UserSettings {
+    /// The server's `DEFAULT_LOCALE` until the user picks one.
+    pub locale: String,
+    pub display_name: Option<String>,
+    /// Locales there are prompt templates for.
+    pub available_locales: Vec<String>,
+    /// IANA zone that "today" and day boundaries follow; the server's
+    /// `DEFAULT_TIMEZONE` until the user picks one.
+    pub timezone: String,
+    /// `monday` .. `sunday`.
+    pub week_start: String,
+    /// Used for new tasks that do not name a priority or status.
+    pub default_priority: String,
+    pub default_status: String,
+}
+
+/// Fields left out stay as they are; an empty string resets one to the
+/// server default.
+#[derive(Serialize, Deserialize)]
+pub struct UserSettingsUpdate {
+    pub locale: Option<String>,
+    pub display_name: Option<String>,
+    pub timezone: Option<String>,
+    pub week_start: Option<String>,
+    pub default_priority: Option<String>,
+    pub default_status: Option<String>,
+}
@@ -0,0 +1,483 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use minijinja::{Environment, UndefinedBehavior, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{dates, settings};
+
+/// How often the template directory is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Prompts and canned replies, one template file each per locale:
+/// `<PROMPTS_DIR>/<locale>/<name>.txt`. Besides their own variables all of
+/// them can use `today`, `weekday`, `user_name` and `locale`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prompt {
+    ChatSystem,
+    ToolsSystem,
+    IntentSystem,
+    BreakdownSystem,
+    BreakdownRequest,
+    BreakdownUnreadable,
+    EnrichSystem,
+    SummarySystem,
+    ReplyUnsupported,
+    ReplyEmpty,
+    ReplyTooManySteps,
+    ReplyTaskCreated,
+    ReplyTaskTitleMissing,
+    ClarifyCreateTask,
+    ClarifyBriefing,
+    ClarifyOther,
+    BriefingRequest,
+    BriefingTemplate,
+    BriefingSubject,
+    ReportNarrative,
+    ErrorMessage,
+    ContextSection,
+    ContextDue,
+    ContextEmpty,
+    ContextOmitted,
+    PendingChange,
+    PendingSummary,
+    PendingFeedback,
+    HistorySummary,
+    ConversationUntitled,
+    SummaryTurn,
+    SummaryRequest,
+    EnrichRequest,
+    TaskFromNote,
+    TaskTime,
+    ReportText,
+}
+
+impl Prompt {
+    pub const ALL: [Prompt; 36] = [
+        Prompt::ChatSystem,
+        Prompt::ToolsSystem,
+        Prompt::IntentSystem,
+        Prompt::BreakdownSystem,
+        Prompt::BreakdownRequest,
+        Prompt::BreakdownUnreadable,
+        Prompt::EnrichSystem,
+        Prompt::SummarySystem,
+        Prompt::ReplyUnsupported,
+        Prompt::ReplyEmpty,
+        Prompt::ReplyTooManySteps,
+        Prompt::ReplyTaskCreated,
+        Prompt::ReplyTaskTitleMissing,
+        Prompt::ClarifyCreateTask,
+        Prompt::ClarifyBriefing,
+        Prompt::ClarifyOther,
+        Prompt::BriefingRequest,
+        Prompt::BriefingTemplate,
+        Prompt::BriefingSubject,
+        Prompt::ReportNarrative,
+        Prompt::ErrorMessage,
+        Prompt::ContextSection,
+        Prompt::ContextDue,
+        Prompt::ContextEmpty,
+        Prompt::ContextOmitted,
+        Prompt::PendingChange,
+        Prompt::PendingSummary,
+        Prompt::PendingFeedback,
+        Prompt::HistorySummary,
+        Prompt::ConversationUntitled,
+        Prompt::SummaryTurn,
+        Prompt::SummaryRequest,
+        Prompt::EnrichRequest,
+        Prompt::TaskFromNote,
+        Prompt::TaskTime,
+        Prompt::ReportText,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Prompt::ChatSystem => "chat_system",
+            Prompt::ToolsSystem => "tools_system",
+            Prompt::IntentSystem => "intent_system",
+            Prompt::BreakdownSystem => "breakdown_system",
+            Prompt::BreakdownRequest => "breakdown_request",
+            Prompt::BreakdownUnreadable => "breakdown_unreadable",
+            Prompt::EnrichSystem => "enrich_system",
+            Prompt::SummarySystem => "summary_system",
+            Prompt::ReplyUnsupported => "reply_unsupported",
+            Prompt::ReplyEmpty => "reply_empty",
+            Prompt::ReplyTooManySteps => "reply_too_many_steps",
+            Prompt::ReplyTaskCreated => "reply_task_created",
+            Prompt::ReplyTaskTitleMissing => "reply_task_title_missing",
+            Prompt::ClarifyCreateTask => "clarify_create_task",
+            Prompt::ClarifyBriefing => "clarify_briefing",
+            Prompt::ClarifyOther => "clarify_other",
+            Prompt::BriefingRequest => "briefing_request",
+            Prompt::BriefingTemplate => "briefing_template",
+            Prompt::BriefingSubject => "briefing_subject",
+            Prompt::ReportNarrative => "report_narrative",
+            Prompt::ErrorMessage => "error_message",
+            Prompt::ContextSection => "context_section",
+            Prompt::ContextDue => "context_due",
+            Prompt::ContextEmpty => "context_empty",
+            Prompt::ContextOmitted => "context_omitted",
+            Prompt::PendingChange => "pending_change",
+            Prompt::PendingSummary => "pending_summary",
+            Prompt::PendingFeedback => "pending_feedback",
+            Prompt::HistorySummary => "history_summary",
+            Prompt::ConversationUntitled => "conversation_untitled",
+            Prompt::SummaryTurn => "summary_turn",
+            Prompt::SummaryRequest => "summary_request",
+            Prompt::EnrichRequest => "enrich_request",
+            Prompt::TaskFromNote => "task_from_note",
+            Prompt::TaskTime => "task_time",
+            Prompt::ReportText => "report_text",
+        }
+    }
+
+    /// Variables passed in on top of the common ones.
+    fn variables(self) -> &'static [&'static str] {
+        match self {
+            Prompt::ChatSystem | Prompt::ToolsSystem => &["context"],
+            Prompt::BreakdownSystem => &["max_subtasks"],
+            Prompt::BreakdownRequest => &["title", "description", "tags", "start_date", "end_date", "priority"],
+            Prompt::EnrichSystem => &["max_tags", "tags"],
+            Prompt::ReplyTaskCreated => &["title", "start_date", "end_date"],
+            Prompt::ClarifyCreateTask => &["title"],
+            Prompt::BriefingTemplate => &["date", "context"],
+            Prompt::BriefingSubject => &["date"],
+            Prompt::ReportNarrative => &["week"],
+            Prompt::ErrorMessage => &["code", "detail", "status", "retry_after_secs", "limit_kind", "limit"],
+            Prompt::ContextSection => &["section", "count"],
+            Prompt::ContextDue => &["start_date", "end_date"],
+            Prompt::ContextOmitted => &["items"],
+            Prompt::PendingChange => &["field", "value"],
+            Prompt::PendingSummary => &["action", "title", "changes"],
+            Prompt::HistorySummary => &["summary"],
+            Prompt::SummaryTurn => &["role", "content"],
+            Prompt::SummaryRequest => &["previous", "transcript"],
+            Prompt::EnrichRequest => &["title", "content"],
+            Prompt::TaskFromNote => &["title"],
+            Prompt::TaskTime => &["time"],
+            Prompt::ReportText => &["key", "week", "start_date", "end_date", "date", "count", "duration", "hours", "minutes"],
+            _ => &[],
+        }
+    }
+}
+
+/// Who a prompt is rendered for.
+#[derive(Clone, Debug)]
+pub struct Audience {
+    pub locale: String,
+    pub user_name: String,
+    /// The user's zone, `DEFAULT_TIMEZONE` until they pick one.
+    pub tz: Tz,
+    /// On the user's clock.
+    pub today: NaiveDate,
+}
+
+/// Handle to the loaded templates. Cheap to clone; `watch` swaps in a new
+/// set when the files change.
+#[derive(Clone)]
+pub struct Prompts {
+    current: Arc<RwLock<Arc<Catalog>>>,
+    dir: PathBuf,
+    default_locale: String,
+}
+
+struct Catalog {
+    env: Environment<'static>,
+    locales: Vec<String>,
+}
+
+impl Prompts {
+    /// Loads every locale under `dir`. Fails, listing every problem, unless
+    /// each locale has all templates and `default_locale` is among them.
+    pub fn load(dir: impl Into<PathBuf>, default_locale: &str) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        let catalog = Catalog::load(&dir, default_locale)?;
+        Ok(Self {
+            current: Arc::new(RwLock::new(Arc::new(catalog))),
+            dir,
+            default_locale: default_locale.to_string(),
+        })
+    }
+
+    /// Starts reloading the templates when files under the directory
+    /// change. A set that fails validation is logged and the previous one
+    /// kept.
+    pub fn watch(&self) {
+        let prompts = self.clone();
+        tokio::spawn(async move {
+            let mut seen = fingerprint(&prompts.dir);
+            loop {
+                tokio::time::sleep(RELOAD_INTERVAL).await;
+                let now = fingerprint(&prompts.dir);
+                if now == seen {
+                    continue;
+                }
+                seen = now;
+                match Catalog::load(&prompts.dir, &prompts.default_locale) {
+                    Ok(catalog) => {
+                        tracing::info!(locales = ?catalog.locales, "prompt templates reloaded");
+                        *prompts.current.write().unwrap() = Arc::new(catalog);
+                    }
+                    Err(err) => tracing::warn!("prompt templates not reloaded: {err:#}"),
+                }
+            }
+        });
+    }
+
+    pub fn default_locale(&self) -> &str {
+        &self.default_locale
+    }
+
+    pub fn locales(&self) -> Vec<String> {
+        self.catalog().locales.clone()
+    }
+
+    fn catalog(&self) -> Arc<Catalog> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// `locale` if templates exist for it, the default otherwise.
+    fn resolve(&self, locale: Option<&str>) -> String {
+        match locale {
+            Some(locale) if self.catalog().locales.iter().any(|l| l == locale) => locale.to_string(),
+            _ => self.default_locale.clone(),
+        }
+    }
+
+    /// The user's locale, name and zone from their settings, with the
+    /// server's defaults (`tz` for the zone) for what they have not set.
+    pub async fn audience(&self, pool: &PgPool, user_id: Uuid, tz: Tz) -> Audience {
+        let row = sqlx::query!(
+            r#"
+            SELECT s.locale AS "locale?", s.display_name AS "display_name?", s.timezone AS "timezone?", u.email
+            FROM users u
+            LEFT JOIN user_settings s ON s.user_id = u.id
+            WHERE u.id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+        .unwrap_or_else(|err| {
+            tracing::warn!(user_id = %user_id, "loading user settings failed: {err}");
+            None
+        });
+        let (locale, display_name, timezone, email) = match row {
+            Some(row) => (row.locale, row.display_name, row.timezone, row.email),
+            None => (None, None, None, String::new()),
+        };
+        let tz = settings::zone(timezone.as_deref(), tz);
+        Audience {
+            locale: self.resolve(locale.as_deref()),
+            user_name: display_name.unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string()),
+            tz,
+            today: dates::today_in(tz),
+        }
+    }
+
+    /// Renders `prompt` in the audience's locale. `vars` must carry the
+    /// prompt's own variables; the common ones are added here.
+    pub fn render(&self, audience: &Audience, prompt: Prompt, vars: Value) -> String {
+        let catalog = self.catalog();
+        let locale = if catalog.locales.contains(&audience.locale) {
+            audience.locale.as_str()
+        } else {
+            self.default_locale.as_str()
+        };
+        let vars = minijinja::context! {
+            today => audience.today.to_string(),
+            weekday => audience.today.format("%A").to_string(),
+            user_name => audience.user_name,
+            locale => locale,
+            ..vars
+        };
+        let rendered = catalog
+            .env
+            .get_template(&template_name(locale, prompt))
+            .and_then(|template| template.render(vars));
+        rendered.unwrap_or_else(|err| {
+            // Startup validation renders every template with its variables,
+            // so this means the code and `Prompt::variables` disagree.
+            tracing::error!(prompt = prompt.name(), locale, "rendering prompt failed: {err:#}");
+            String::new()
+        })
+    }
+}
+
+impl Catalog {
+    fn load(dir: &Path, default_locale: &str) -> anyhow::Result<Self> {
+        let mut locales = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("PROMPTS_DIR unreadable: {}", dir.display()))? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                locales.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        locales.sort();
+
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        // Prompts are plain text, not HTML.
+        env.set_auto_escape_callback(|_| minijinja::AutoEscape::None);
+
+        let mut problems = Vec::new();
+        if !locales.iter().any(|l| l == default_locale) {
+            problems.push(format!("no templates for DEFAULT_LOCALE {default_locale}"));
+        }
+        for locale in &locales {
+            for prompt in Prompt::ALL {
+                let path = dir.join(locale).join(format!("{}.txt", prompt.name()));
+                let source = match std::fs::read_to_string(&path) {
+                    Ok(source) => source,
+                    Err(err) => {
+                        problems.push(format!("{}: {err}", path.display()));
+                        continue;
+                    }
+                };
+                if let Err(err) = env.add_template_owned(template_name(locale, prompt), source) {
+                    problems.push(format!("{}: {err}", path.display()));
+                }
+            }
+        }
+        // Rendering with every variable set catches misspelled names.
+        for locale in &locales {
+            for prompt in Prompt::ALL {
+                let Ok(template) = env.get_template(&template_name(locale, prompt)) else {
+                    continue;
+                };
+                let vars: Value = ["today", "weekday", "user_name", "locale"]
+                    .into_iter()
+                    .chain(prompt.variables().iter().copied())
+                    .map(|name| (name, Value::from(name)))
+                    .collect();
+                if let Err(err) = template.render(vars) {
+                    problems.push(format!("{}/{}.txt: {err}", locale, prompt.name()));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(Self { env, locales })
+        } else {
+            anyhow::bail!("prompt templates invalid:\n{}", problems.join("\n"))
+        }
+    }
+}
+
+fn template_name(locale: &str, prompt: Prompt) -> String {
+    format!("{}/{}", locale, prompt.name())
+}
+
+/// Paths, sizes and modification times of the files under `dir`.
+fn fingerprint(dir: &Path) -> Vec<(PathBuf, u64, Option<SystemTime>)> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else {
+                files.push((entry.path(), meta.len(), meta.modified().ok()));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// The templates shipped in `prompts/`.
+    pub(crate) fn shipped() -> Prompts {
+        Prompts::load(concat!(env!("CARGO_MANIFEST_DIR"), "/prompts"), "ko").expect("shipped templates valid")
+    }
+
+    pub(crate) fn audience(locale: &str) -> Audience {
+        Audience {
+            locale: locale.to_string(),
+            user_name: "jiwoo".to_string(),
+            tz: chrono_tz::Asia::Seoul,
+            today: NaiveDate::from_ymd_opt(2024, 12, 2).unwrap(),
+        }
+    }
+
+    #[test]
+    fn shipped_templates_render_per_locale() {
+        let prompts = shipped();
+        assert_eq!(prompts.locales(), ["en", "ko"]);
+        let created = |locale: &str, start: Option<&str>, end: Option<&str>| {
+            prompts.render(
+                &audience(locale),
+                Prompt::ReplyTaskCreated,
+                minijinja::context! { title => "회의 준비", start_date => start, end_date => end },
+            )
+        };
+        assert_eq!(created("ko", Some("2024-12-03"), Some("2024-12-05")), "업무가 등록됐어요: 회의 준비 (2024-12-03 ~ 2024-12-05)");
+        assert_eq!(created("ko", None, Some("2024-12-03")), "업무가 등록됐어요: 회의 준비 (마감 2024-12-03)");
+        assert_eq!(created("en", None, None), "Task added: 회의 준비");
+        // Unknown locales get the default.
+        assert_eq!(created("fr", None, None), "업무가 등록됐어요: 회의 준비");
+
+        let system = prompts.render(
+            &audience("en"),
+            Prompt::ToolsSystem,
+            minijinja::context! { context => "- 보고서 <초안>" },
+        );
+        assert!(system.contains("Today is 2024-12-02 (Monday)"));
+        assert!(system.ends_with("Context:\n- 보고서 <초안>"));
+    }
+
+    #[test]
+    fn error_messages_follow_the_locale() {
+        use crate::llm::{LlmError, QuotaLimit};
+
+        let prompts = shipped();
+        let quota = LlmError::QuotaExceeded { limit: QuotaLimit::Tokens(1000), retry_after_secs: 60 };
+        assert_eq!(
+            quota.message(&prompts, &audience("ko")),
+            "오늘 AI 사용 한도(토큰 1000개)를 모두 썼습니다. 자정에 초기화됩니다."
+        );
+        assert_eq!(
+            quota.message(&prompts, &audience("en")),
+            "You have used today's AI quota (1000 tokens). It resets at midnight."
+        );
+        let status = LlmError::Status { status: 500, body: "boom".to_string() };
+        assert_eq!(status.message(&prompts, &audience("en")), "Model call failed (500): boom");
+        assert_eq!(LlmError::Timeout.message(&prompts, &audience("ko")), "모델 응답 시간 초과");
+    }
+
+    #[test]
+    fn missing_templates_and_unknown_variables_fail_validation() {
+        let dir = std::env::temp_dir().join(format!("prompts-{}", Uuid::new_v4()));
+        let shipped = Path::new(env!("CARGO_MANIFEST_DIR")).join("prompts/ko");
+        std::fs::create_dir_all(dir.join("ko")).unwrap();
+        for entry in std::fs::read_dir(&shipped).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), dir.join("ko").join(entry.file_name())).unwrap();
+        }
+        assert!(Prompts::load(&dir, "ko").is_ok());
+        assert!(Prompts::load(&dir, "en").is_err());
+
+        std::fs::remove_file(dir.join("ko/reply_empty.txt")).unwrap();
+        std::fs::write(dir.join("ko/clarify_create_task.txt"), "{{ titel }}").unwrap();
+        let err = Prompts::load(&dir, "ko").err().unwrap().to_string();
+        assert!(err.contains("reply_empty.txt"), "{err}");
+        assert!(err.contains("clarify_create_task.txt"), "{err}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use std::fmt::Write;
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Datelike, NaiveDate, TimeDelta, Utc, Weekday};
+use chrono_tz::Tz;
+use minijinja::{context, Value};
+use regex::Regex;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::prompts::{Audience, Prompt, Prompts};
+use crate::timers;
+use crate::usage::day_bounds;
+
+static ISO_WEEK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d{4})-?W(\d{1,2})$").expect("valid week pattern"));
+
+/// An ISO 8601 week, Monday to Sunday.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IsoWeek {
+    pub year: i32,
+    pub week: u32,
+}
+
+impl IsoWeek {
+    /// Reads `2024-W48` (or `2024W48`).
+    pub fn parse(value: &str) -> Option<Self> {
+        let caps = ISO_WEEK.captures(value.trim())?;
+        let week = Self {
+            year: caps[1].parse().ok()?,
+            week: caps[2].parse().ok()?,
+        };
+        NaiveDate::from_isoywd_opt(week.year, week.week, Weekday::Mon).map(|_| week)
+    }
+
+    pub fn containing(date: NaiveDate) -> Self {
+        let iso = date.iso_week();
+        Self {
+            year: iso.year(),
+            week: iso.week(),
+        }
+    }
+
+    pub fn monday(self) -> NaiveDate {
+        NaiveDate::from_isoywd_opt(self.year, self.week, Weekday::Mon).expect("validated week")
+    }
+
+    pub fn sunday(self) -> NaiveDate {
+        self.monday() + TimeDelta::days(6)
+    }
+}
+
+impl std::fmt::Display for IsoWeek {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-W{:02}", self.year, self.week)
+    }
+}
+
+#[derive(Serialize)]
+pub struct WeeklyReport {
+    pub week: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub timezone: String,
+    pub completed: Vec<ReportTask>,
+    pub created: Vec<ReportTask>,
+    /// Tasks whose end date was moved later during the week.
+    pub slipped: Vec<SlippedTask>,
+    /// Tasks that were open and past their end date at the end of the week
+    /// (or today, for the current week), including ones finished since.
+    pub overdue: Vec<OverdueTask>,
+    pub time_tracked: TimeTracked,
+    pub notes: Vec<ReportNote>,
+}
+
+#[derive(Serialize)]
+pub struct ReportTask {
+    pub id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub priority: String,
+    pub end_date: Option<NaiveDate>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct SlippedTask {
+    pub id: Uuid,
+    pub title: String,
+    /// End date before the week's first move.
+    pub from: NaiveDate,
+    /// End date after the week's last move.
+    pub to: NaiveDate,
+    pub moves: i64,
+}
+
+#[derive(Serialize)]
+pub struct OverdueTask {
+    pub id: Uuid,
+    pub title: String,
+    pub priority: String,
+    pub end_date: NaiveDate,
+    pub days_overdue: i64,
+}
+
+/// Time recorded with the timer during the week, and the estimates of the
+/// tasks completed in it.
+#[derive(Serialize)]
+pub struct TimeTracked {
+    pub minutes: i64,
+    pub estimated_minutes: i64,
+    /// Completed tasks with an estimate.
+    pub tasks: i64,
+    /// Completed tasks without an estimate.
+    pub untracked_tasks: i64,
+}
+
+#[derive(Serialize)]
+pub struct ReportNote {
+    pub id: Uuid,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Gathers the review of `week` on the user's clock. `today` bounds what
+/// counts as overdue.
+pub async fn weekly(
+    pool: &PgPool,
+    user_id: Uuid,
+    week: IsoWeek,
+    tz: Tz,
+    today: NaiveDate,
+) -> Result<WeeklyReport, sqlx::Error> {
+    let (start, _) = day_bounds(week.monday(), tz);
+    let (_, end) = day_bounds(week.sunday(), tz);
+    let as_of = today.min(week.sunday().succ_opt().unwrap_or(today));
+
+    let completed = sqlx::query_as!(
+        ReportTask,
+        r#"
+        SELECT id, title, status, priority, end_date, tags FROM tasks
+        WHERE user_id = $1 AND completed_at >= $2 AND completed_at < $3
+        ORDER BY completed_at
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let created = sqlx::query_as!(
+        ReportTask,
+        r#"
+        SELECT id, title, status, priority, end_date, tags FROM tasks
+        WHERE user_id = $1 AND created_at >= $2 AND created_at < $3
+        ORDER BY created_at
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let slipped = sqlx::query_as!(
+        SlippedTask,
+        r#"
+        SELECT t.id, t.title, c.first_end AS "from!", c.last_end AS "to!", c.moves AS "moves!"
+        FROM (
+            SELECT task_id,
+                   (array_agg(previous_end_date ORDER BY changed_at))[1] AS first_end,
+                   (array_agg(end_date ORDER BY changed_at DESC))[1] AS last_end,
+                   COUNT(*) AS moves
+            FROM task_date_changes
+            WHERE user_id = $1 AND changed_at >= $2 AND changed_at < $3
+            GROUP BY task_id
+        ) c
+        JOIN tasks t ON t.id = c.task_id
+        WHERE c.last_end > c.first_end
+        ORDER BY c.last_end - c.first_end DESC, t.title
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let overdue = sqlx::query_as!(
+        OverdueTask,
+        r#"
+        SELECT id, title, priority,
+               COALESCE(end_date, due_date) AS "end_date!",
+               ($2::date - COALESCE(end_date, due_date))::BIGINT AS "days_overdue!"
+        FROM tasks
+        WHERE user_id = $1 AND COALESCE(end_date, due_date) < $2
+          AND created_at < $3 AND (completed_at IS NULL OR completed_at >= $3)
+        ORDER BY 4, title
+        "#,
+        user_id,
+        as_of,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let notes = sqlx::query_as!(
+        ReportNote,
+        r#"
+        SELECT id, title, tags, created_at FROM notes
+        WHERE user_id = $1 AND created_at >= $2 AND created_at < $3
+        ORDER BY created_at
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let estimated = sqlx::query!(
+        r#"
+        SELECT COALESCE(SUM(estimate_minutes), 0)::BIGINT AS "minutes!",
+               COUNT(estimate_minutes) AS "tasks!",
+               COUNT(*) FILTER (WHERE estimate_minutes IS NULL) AS "untracked_tasks!"
+        FROM tasks
+        WHERE user_id = $1 AND completed_at >= $2 AND completed_at < $3
+        "#,
+        user_id,
+        start,
+        end
+    )
+    .fetch_one(pool)
+    .await?;
+    let time_tracked = TimeTracked {
+        minutes: timers::minutes_between(pool, user_id, start, end).await?,
+        estimated_minutes: estimated.minutes,
+        tasks: estimated.tasks,
+        untracked_tasks: estimated.untracked_tasks,
+    };
+
+    Ok(WeeklyReport {
+        week: week.to_string(),
+        start_date: week.monday(),
+        end_date: week.sunday(),
+        timezone: tz.name().to_string(),
+        completed,
+        created,
+        slipped,
+        overdue,
+        time_tracked,
+        notes,
+    })
+}
+
+impl WeeklyReport {
+    /// The report as a Markdown document in the audience's locale, opening
+    /// with `narrative` when there is one.
+    pub fn markdown(&self, prompts: &Prompts, audience: &Audience, narrative: Option<&str>) -> String {
+        // Each key's branch of the template only reads its own variables.
+        let text = |key: &str, vars: Value| prompts.render(audience, Prompt::ReportText, context! { key, ..vars });
+        let heading = |key: &str| text(key, context! {});
+        let mut md = format!(
+            "# {}\n",
+            text("title", context! { week => self.week, start_date => self.start_date, end_date => self.end_date })
+        );
+        if let Some(narrative) = narrative {
+            let _ = write!(md, "\n## {}\n\n{}\n", heading("narrative"), narrative.trim());
+        }
+
+        let none = heading("none");
+        let task_line = |task: &ReportTask| {
+            let due = task
+                .end_date
+                .map(|date| format!(" ({})", text("due", context! { date })))
+                .unwrap_or_default();
+            format!("{} [{}]{}{}", task.title, task.priority, due, hashtags(&task.tags))
+        };
+        section(&mut md, &heading("completed"), &none, &self.completed, task_line);
+        section(&mut md, &heading("created"), &none, &self.created, task_line);
+        section(&mut md, &heading("slipped"), &none, &self.slipped, |t| {
+            format!("{}: {} → {} ({})", t.title, t.from, t.to, text("moves", context! { count => t.moves }))
+        });
+        section(&mut md, &heading("overdue"), &none, &self.overdue, |t| {
+            let late = text("overdue_by", context! { date => t.end_date, count => t.days_overdue });
+            format!("{} [{}]: {}", t.title, t.priority, late)
+        });
+
+        let time = &self.time_tracked;
+        let duration = |minutes: i64| text("duration", context! { hours => minutes / 60, minutes => minutes % 60 });
+        let _ = write!(
+            md,
+            "\n## {}\n\n- {}\n- {}",
+            heading("time"),
+            text("timed_total", context! { duration => duration(time.minutes) }),
+            text("estimated_total", context! { duration => duration(time.estimated_minutes) })
+        );
+        if time.untracked_tasks > 0 {
+            let _ = write!(md, " ({})", text("untracked", context! { count => time.untracked_tasks }));
+        }
+        md.push('\n');
+
+        section(&mut md, &heading("notes"), &none, &self.notes, |n| format!("{}{}", n.title, hashtags(&n.tags)));
+        md
+    }
+}
+
+fn section<T>(md: &mut String, title: &str, none: &str, items: &[T], line: impl Fn(&T) -> String) {
+    let _ = write!(md, "\n## {} ({})\n\n", title, items.len());
+    if items.is_empty() {
+        let _ = writeln!(md, "- {none}");
+    }
+    for item in items {
+        let _ = writeln!(md, "- {}", line(item));
+    }
+}
+
+fn hashtags(tags: &[String]) -> String {
+    tags.iter().map(|t| format!(" #{t}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompts::tests::{audience, shipped};
+
+    #[test]
+    fn parses_iso_weeks() {
+        let week = IsoWeek::parse("2024-W48").unwrap();
+        assert_eq!(week.monday(), NaiveDate::from_ymd_opt(2024, 11, 25).unwrap());
+        assert_eq!(week.sunday(), NaiveDate::from_ymd_opt(2024, 12, 1).unwrap());
+        assert_eq!(week.to_string(), "2024-W48");
+        assert_eq!(IsoWeek::parse("2024W5").unwrap().to_string(), "2024-W05");
+        // 2020 has 53 weeks, 2024 does not.
+        assert!(IsoWeek::parse("2020-W53").is_some());
+        assert!(IsoWeek::parse("2024-W53").is_none());
+        assert!(IsoWeek::parse("2024-W00").is_none());
+        assert!(IsoWeek::parse("2024-48").is_none());
+        // ISO years start on the Monday of the week with January 4th.
+        let first = IsoWeek::containing(NaiveDate::from_ymd_opt(2024, 12, 30).unwrap());
+        assert_eq!(first.to_string(), "2025-W01");
+    }
+
+    #[test]
+    fn renders_markdown() {
+        let task = |title: &str, end: Option<NaiveDate>, tags: &[&str]| ReportTask {
+            id: Uuid::nil(),
+            title: title.to_string(),
+            status: "done".to_string(),
+            priority: "high".to_string(),
+            end_date: end,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        };
+        let date = |d: u32| NaiveDate::from_ymd_opt(2024, 11, d).unwrap();
+        let report = WeeklyReport {
+            week: "2024-W48".to_string(),
+            start_date: date(25),
+            end_date: NaiveDate::from_ymd_opt(2024, 12, 1).unwrap(),
+            timezone: "Asia/Seoul".to_string(),
+            completed: vec![task("배포 점검", Some(date(27)), &["ops"])],
+            created: Vec::new(),
+            slipped: vec![SlippedTask {
+                id: Uuid::nil(),
+                title: "정산".to_string(),
+                from: date(26),
+                to: date(29),
+                moves: 2,
+            }],
+            overdue: Vec::new(),
+            time_tracked: TimeTracked {
+                minutes: 45,
+                estimated_minutes: 150,
+                tasks: 1,
+                untracked_tasks: 2,
+            },
+            notes: Vec::new(),
+        };
+
+        let prompts = shipped();
+        let md = report.markdown(&prompts, &audience("ko"), Some("순조로운 한 주였습니다.\n"));
+        assert!(md.starts_with("# 주간 리뷰 2024-W48 (2024-11-25 ~ 2024-12-01)\n\n## 회고\n\n순조로운 한 주였습니다.\n"));
+        assert!(md.contains("## 완료한 업무 (1)\n\n- 배포 점검 [high] (마감 2024-11-27) #ops\n"));
+        assert!(md.contains("## 새로 만든 업무 (0)\n\n- 없음\n"));
+        assert!(md.contains("- 정산: 2024-11-26 → 2024-11-29 (2회 변경)\n"));
+        assert!(md.contains("- 타이머로 기록한 시간: 45분\n- 완료한 업무의 예상 시간 합계: 2시간 30분 (예상 시간이 없는 업무 2건 제외)\n"));
+        assert!(!report.markdown(&prompts, &audience("ko"), None).contains("## 회고"));
+
+        let md = report.markdown(&prompts, &audience("en"), None);
+        assert!(md.starts_with("# Weekly review 2024-W48 (2024-11-25 ~ 2024-12-01)\n\n## Completed (1)\n\n- 배포 점검 [high] (due 2024-11-27) #ops\n"));
+        assert!(md.contains("## Created (0)\n\n- None\n"));
+        assert!(md.contains("- 정산: 2024-11-26 → 2024-11-29 (moved 2 times)\n"));
+        assert!(md.contains("- Recorded with the timer: 45 min\n- Estimated time of completed tasks: 2 h 30 min (2 tasks without an estimate left out)\n"));
+    }
+}
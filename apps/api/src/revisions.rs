@@ -0,0 +1,255 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// Saves landing within this many seconds of a revision's first save are
+/// folded into it, so autosave does not produce one revision per keystroke.
+const REVISION_COALESCE_SECS: i64 = 120;
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct NoteRevision {
+    pub id: Uuid,
+    pub note_id: Uuid,
+    pub revision: i32,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub restored_from: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct NoteRevisionSummary {
+    pub id: Uuid,
+    pub revision: i32,
+    pub title: String,
+    pub restored_from: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct DiffLine {
+    pub op: &'static str,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct RevisionDiff {
+    pub from: Uuid,
+    pub to: Uuid,
+    pub title_changed: bool,
+    pub lines: Vec<DiffLine>,
+    pub unified: String,
+}
+
+/// Snapshots the current state of a note. A plain save close to the previous
+/// one updates that revision in place; restores always get their own. Call it
+/// inside a transaction so the note stays locked until the save commits.
+pub async fn record_revision(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    note_id: Uuid,
+    title: &str,
+    content: &str,
+    tags: &[String],
+    restored_from: Option<Uuid>,
+) -> Result<(), sqlx::Error> {
+    // Concurrent saves would otherwise both take the same next revision.
+    sqlx::query!("SELECT id FROM notes WHERE id = $1 FOR UPDATE", note_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let latest = sqlx::query!(
+        r#"
+        SELECT id, revision, title, content, tags, restored_from,
+               created_at > NOW() - make_interval(secs => $2) AS "recent!"
+        FROM note_revisions
+        WHERE note_id = $1
+        ORDER BY revision DESC
+        LIMIT 1
+        "#,
+        note_id,
+        REVISION_COALESCE_SECS as f64
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if let Some(ref latest) = latest {
+        if restored_from.is_none() {
+            if latest.title == title && latest.content == content && latest.tags == tags {
+                return Ok(());
+            }
+            if latest.recent && latest.restored_from.is_none() {
+                sqlx::query!(
+                    r#"
+                    UPDATE note_revisions
+                    SET title = $1, content = $2, tags = $3, updated_at = NOW()
+                    WHERE id = $4
+                    "#,
+                    title,
+                    content,
+                    tags,
+                    latest.id
+                )
+                .execute(&mut *conn)
+                .await?;
+                return Ok(());
+            }
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO note_revisions (id, note_id, user_id, revision, title, content, tags, restored_from)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        Uuid::new_v4(),
+        note_id,
+        user_id,
+        latest.map(|l| l.revision + 1).unwrap_or(1),
+        title,
+        content,
+        tags,
+        restored_from
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub fn diff_revisions(from: &NoteRevision, to: &NoteRevision) -> RevisionDiff {
+    let diff = TextDiff::from_lines(&from.content, &to.content);
+    let lines = diff
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            op: match change.tag() {
+                ChangeTag::Equal => "equal",
+                ChangeTag::Delete => "delete",
+                ChangeTag::Insert => "insert",
+            },
+            old_line: change.old_index().map(|i| i + 1),
+            new_line: change.new_index().map(|i| i + 1),
+            text: change.value().trim_end_matches(['\r', '\n']).to_string(),
+        })
+        .collect();
+    let unified = diff
+        .unified_diff()
+        .header(
+            &format!("revision {}", from.revision),
+            &format!("revision {}", to.revision),
+        )
+        .to_string();
+
+    RevisionDiff {
+        from: from.id,
+        to: to.id,
+        title_changed: from.title != to.title,
+        lines,
+        unified,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revision(number: i32, title: &str, content: &str) -> NoteRevision {
+        NoteRevision {
+            id: Uuid::new_v4(),
+            note_id: Uuid::nil(),
+            revision: number,
+            title: title.to_string(),
+            content: content.to_string(),
+            tags: Vec::new(),
+            restored_from: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_saves_take_turns() {
+        let app = crate::testing::TestApp::start().await;
+        let pool = app.state.pool.clone();
+        let note = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO notes (id, user_id, title, content, tags) VALUES ($1, $2, '메모', '', '{}')",
+            note,
+            app.user_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // An old revision, so neither save is folded into it.
+        sqlx::query!(
+            r#"
+            INSERT INTO note_revisions (id, note_id, user_id, revision, title, content, tags, created_at)
+            VALUES ($1, $2, $3, 1, '메모', '', '{}', NOW() - INTERVAL '1 hour')
+            "#,
+            Uuid::new_v4(),
+            note,
+            app.user_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut first = pool.begin().await.unwrap();
+        record_revision(&mut first, app.user_id, note, "메모", "하나", &[], None).await.unwrap();
+        let second = tokio::spawn({
+            let pool = pool.clone();
+            let user_id = app.user_id;
+            async move {
+                let mut tx = pool.begin().await?;
+                record_revision(&mut tx, user_id, note, "메모", "둘", &[], None).await?;
+                tx.commit().await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        first.commit().await.unwrap();
+        second.await.unwrap().unwrap();
+
+        // The second save lands on the revision the first one made.
+        let revisions = sqlx::query!("SELECT revision, content FROM note_revisions WHERE note_id = $1 ORDER BY revision", note)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let revisions: Vec<_> = revisions.into_iter().map(|r| (r.revision, r.content)).collect();
+        assert_eq!(revisions, [(1, String::new()), (2, "둘".to_string())]);
+    }
+
+    #[test]
+    fn diffs_lines_with_numbers() {
+        let from = revision(1, "배포", "준비\r\n배포\n확인\n");
+        let to = revision(2, "배포 절차", "준비\n롤백 계획\n배포\n");
+        let diff = diff_revisions(&from, &to);
+        assert!(diff.title_changed);
+        let ops: Vec<_> = diff
+            .lines
+            .iter()
+            .map(|l| (l.op, l.old_line, l.new_line, l.text.as_str()))
+            .collect();
+        assert_eq!(
+            ops,
+            [
+                // Only the line ending changed; the text is shown without it.
+                ("delete", Some(1), None, "준비"),
+                ("insert", None, Some(1), "준비"),
+                ("insert", None, Some(2), "롤백 계획"),
+                ("equal", Some(2), Some(3), "배포"),
+                ("delete", Some(3), None, "확인"),
+            ]
+        );
+        assert!(diff.unified.starts_with("--- revision 1\n+++ revision 2\n"));
+
+        let same = diff_revisions(&to, &to);
+        assert!(!same.title_changed);
+        assert!(same.lines.iter().all(|l| l.op == "equal"));
+    }
+}
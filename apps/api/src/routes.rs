@@ -1,26 +1,98 @@
-use axum::{routing::get, routing::post, Router};
+use std::sync::Arc;
 
+use axum::{extract::DefaultBodyLimit, routing::get, routing::post, Router};
+
+use crate::analytics;
+use crate::briefings::{self, Mailer, SmtpMailer};
 use crate::config::Config;
+use crate::embeddings::Embeddings;
+use crate::enrichment::Enrichment;
 use crate::handlers;
+use crate::llm;
 use crate::middleware::AppState;
+use crate::prompts::Prompts;
+use crate::usage::Quotas;
+
+const ARCHIVE_BODY_LIMIT: usize = 50 * 1024 * 1024;
 
-pub fn app(pool: sqlx::PgPool, cfg: Config) -> Router {
+pub fn app(pool: sqlx::PgPool, cfg: Config) -> anyhow::Result<Router> {
+    let prompts = Prompts::load(&cfg.prompts_dir, &cfg.default_locale)?;
+    prompts.watch();
+    let http = llm::http_client(&cfg)?;
+    let llm = llm::from_config(&cfg, http);
+    let embeddings = if cfg.ai_embeddings {
+        Embeddings::spawn(pool.clone(), llm.clone(), cfg.ai_rag_top_k)
+    } else {
+        Embeddings::disabled()
+    };
+    let enrichment = if cfg.ai_enrich_on_save {
+        Enrichment::spawn(pool.clone(), llm.clone(), prompts.clone(), cfg.context_window(), cfg.default_timezone)
+    } else {
+        Enrichment::disabled()
+    };
+    let mailer = match &cfg.smtp_url {
+        Some(url) => Some(Arc::new(SmtpMailer::from_url(url, &cfg.mail_from)?) as Arc<dyn Mailer>),
+        None => None,
+    };
     let state = AppState {
         pool,
+        llm,
+        ai_history_tokens: cfg.ai_history_tokens,
+        ai_context_tokens: cfg.context_window(),
+        ai_quotas: Quotas::new(cfg.ai_daily_token_quota, cfg.ai_daily_request_quota),
+        embeddings,
+        enrichment,
+        default_tz: cfg.default_timezone,
+        prompts,
+        mailer,
         jwt_secret: cfg.jwt_secret,
-        ai_base_url: cfg.ai_base_url,
-        ai_model: cfg.ai_model,
     };
 
-    Router::new()
+    briefings::spawn_scheduler(state.clone());
+    analytics::spawn_backfill(state.pool.clone(), state.default_tz);
+
+    Ok(Router::new()
         .route("/healthz", get(handlers::healthz::healthz))
+        .route("/healthz/ai", get(handlers::healthz::ai))
         .route("/api/v1/auth/signup", post(handlers::auth::signup))
         .route("/api/v1/auth/login", post(handlers::auth::login))
         .route("/api/v1/tasks", post(handlers::tasks::create).get(handlers::tasks::list))
         .route("/api/v1/tasks/:id", get(handlers::tasks::get).patch(handlers::tasks::update).delete(handlers::tasks::delete))
+        .route("/api/v1/tasks/:id/backlinks", get(handlers::tasks::backlinks))
+        .route("/api/v1/tasks/:id/subtasks", get(handlers::breakdown::list))
+        .route("/api/v1/tasks/:id/ai/breakdown", post(handlers::breakdown::propose))
+        .route("/api/v1/tasks/:id/ai/breakdown/accept", post(handlers::breakdown::accept))
         .route("/api/v1/notes", post(handlers::notes::create).get(handlers::notes::list))
+        .route("/api/v1/notes/vault", get(handlers::vault::export).post(handlers::vault::import).layer(DefaultBodyLimit::max(ARCHIVE_BODY_LIMIT)))
         .route("/api/v1/notes/:id", get(handlers::notes::get).patch(handlers::notes::update).delete(handlers::notes::delete))
+        .route("/api/v1/notes/:id/backlinks", get(handlers::notes::backlinks))
+        .route("/api/v1/notes/:id/ai/enrich", post(handlers::notes::enrich))
+        .route("/api/v1/notes/:id/ai/accept", post(handlers::notes::accept_enrichment))
+        .route("/api/v1/notes/:id/links", get(handlers::notes::links))
+        .route("/api/v1/notes/:id/revisions", get(handlers::notes::list_revisions))
+        .route("/api/v1/notes/:id/revisions/diff", get(handlers::notes::diff_revisions))
+        .route("/api/v1/notes/:id/revisions/:revision_id", get(handlers::notes::get_revision))
+        .route("/api/v1/notes/:id/revisions/:revision_id/restore", post(handlers::notes::restore_revision))
+        .route("/api/v1/links/missing", get(handlers::notes::missing_links))
+        .route("/api/v1/import", post(handlers::import::import))
+        .route("/api/v1/import/archive", post(handlers::archive::import).layer(DefaultBodyLimit::max(ARCHIVE_BODY_LIMIT)))
+        .route("/api/v1/export", get(handlers::archive::export))
+        .route("/api/v1/dashboard", get(handlers::dashboard::get))
+        .route("/api/v1/dashboard/layout", get(handlers::dashboard::get_layout).put(handlers::dashboard::put_layout).delete(handlers::dashboard::delete_layout))
         .route("/api/v1/dashboard/summary", get(handlers::dashboard::summary))
+        .route("/api/v1/timer", get(handlers::timers::get))
+        .route("/api/v1/timer/start", post(handlers::timers::start))
+        .route("/api/v1/timer/stop", post(handlers::timers::stop))
         .route("/api/v1/ai/chat", post(handlers::ai::chat))
-        .with_state(state)
+        .route("/api/v1/ai/chat/stream", post(handlers::ai::chat_stream))
+        .route("/api/v1/ai/actions", post(handlers::ai::confirm_actions))
+        .route("/api/v1/ai/usage", get(handlers::usage::summary))
+        .route("/api/v1/settings", get(handlers::settings::get).patch(handlers::settings::update))
+        .route("/api/v1/reports/weekly", get(handlers::reports::weekly))
+        .route("/api/v1/analytics", get(handlers::analytics::get))
+        .route("/api/v1/briefings", get(handlers::briefings::list).post(handlers::briefings::create))
+        .route("/api/v1/briefings/schedule", get(handlers::briefings::get_schedule).put(handlers::briefings::put_schedule).delete(handlers::briefings::delete_schedule))
+        .route("/api/v1/ai/conversations", get(handlers::conversations::list))
+        .route("/api/v1/ai/conversations/:id", get(handlers::conversations::get).patch(handlers::conversations::rename).delete(handlers::conversations::delete))
+        .with_state(state))
 }
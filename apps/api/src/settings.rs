@@ -0,0 +1,117 @@
+use chrono::{Datelike, NaiveDate, TimeDelta, Weekday};
+use chrono_tz::Tz;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub const DEFAULT_PRIORITY: &str = "medium";
+pub const DEFAULT_STATUS: &str = "todo";
+
+/// The user's preferences from `user_settings`, with the server defaults
+/// filled in for anything unset.
+#[derive(Clone, Debug)]
+pub struct Preferences {
+    pub timezone: Tz,
+    pub week_start: Weekday,
+    /// For new tasks that do not name one.
+    pub default_priority: String,
+    pub default_status: String,
+}
+
+impl Preferences {
+    pub fn defaults(timezone: Tz) -> Self {
+        Self {
+            timezone,
+            week_start: Weekday::Mon,
+            default_priority: DEFAULT_PRIORITY.to_string(),
+            default_status: DEFAULT_STATUS.to_string(),
+        }
+    }
+
+    /// Today on the user's clock.
+    pub fn today(&self) -> NaiveDate {
+        crate::dates::today_in(self.timezone)
+    }
+
+    /// First day of the user's week that `day` falls in.
+    pub fn week_of(&self, day: NaiveDate) -> NaiveDate {
+        week_of(day, self.week_start)
+    }
+}
+
+pub fn week_of(day: NaiveDate, week_start: Weekday) -> NaiveDate {
+    let back = (7 + day.weekday().num_days_from_monday() - week_start.num_days_from_monday()) % 7;
+    day - TimeDelta::days(back as i64)
+}
+
+/// `monday` .. `sunday`, as stored and served.
+pub fn parse_weekday(value: &str) -> Option<Weekday> {
+    match value {
+        "monday" => Some(Weekday::Mon),
+        "tuesday" => Some(Weekday::Tue),
+        "wednesday" => Some(Weekday::Wed),
+        "thursday" => Some(Weekday::Thu),
+        "friday" => Some(Weekday::Fri),
+        "saturday" => Some(Weekday::Sat),
+        "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+pub fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "monday",
+        Weekday::Tue => "tuesday",
+        Weekday::Wed => "wednesday",
+        Weekday::Thu => "thursday",
+        Weekday::Fri => "friday",
+        Weekday::Sat => "saturday",
+        Weekday::Sun => "sunday",
+    }
+}
+
+/// The user's preferences. A zone or value that no longer parses, or the
+/// database being unreachable, falls back to the defaults; the latter is
+/// logged.
+pub async fn preferences(pool: &PgPool, user_id: Uuid, default_tz: Tz) -> Preferences {
+    let row = sqlx::query!(
+        "SELECT timezone, week_start, default_priority, default_status FROM user_settings WHERE user_id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .unwrap_or_else(|err| {
+        tracing::warn!(user_id = %user_id, "loading user settings failed: {err}");
+        None
+    });
+    let defaults = Preferences::defaults(default_tz);
+    let Some(row) = row else {
+        return defaults;
+    };
+    Preferences {
+        timezone: zone(row.timezone.as_deref(), default_tz),
+        week_start: row.week_start.as_deref().and_then(parse_weekday).unwrap_or(defaults.week_start),
+        default_priority: row.default_priority.unwrap_or(defaults.default_priority),
+        default_status: row.default_status.unwrap_or(defaults.default_status),
+    }
+}
+
+/// `timezone` if it names an IANA zone, else `fallback`.
+pub fn zone(timezone: Option<&str>, fallback: Tz) -> Tz {
+    timezone.and_then(|tz| tz.parse().ok()).unwrap_or(fallback)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weeks_start_on_the_chosen_day() {
+        // 2024-12-04 is a Wednesday.
+        let day = NaiveDate::from_ymd_opt(2024, 12, 4).unwrap();
+        assert_eq!(week_of(day, Weekday::Mon).to_string(), "2024-12-02");
+        assert_eq!(week_of(day, Weekday::Sun).to_string(), "2024-12-01");
+        assert_eq!(week_of(day, Weekday::Wed), day);
+        assert_eq!(week_of(day, Weekday::Thu).to_string(), "2024-11-28");
+        assert_eq!(parse_weekday(weekday_name(Weekday::Sat)), Some(Weekday::Sat));
+    }
+}
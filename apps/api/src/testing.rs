@@ -0,0 +1,168 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+use sqlx::postgres::PgPoolOptions;
+use uuid::Uuid;
+
+use crate::analytics;
+use crate::embeddings::Embeddings;
+use crate::enrichment::Enrichment;
+use crate::llm::{CircuitBreaker, HttpSettings, MockOllama, OllamaProvider};
+use crate::middleware::AppState;
+use crate::prompts::tests::shipped;
+use crate::usage::Quotas;
+
+/// The API wired to a `MockOllama` and to the database in `DATABASE_URL`,
+/// which the query macros need to build anyway. Each one signs up its own
+/// user, so tests do not see each other's rows.
+pub struct TestApp {
+    pub state: AppState,
+    pub user_id: Uuid,
+    pub model: MockOllama,
+}
+
+impl TestApp {
+    pub async fn start() -> Self {
+        Self::with_timeout(Duration::from_secs(10)).await
+    }
+
+    /// Same, with `timeout` for non-streaming model calls. Retries and the
+    /// circuit breaker are off, so every scripted failure reaches the caller.
+    pub async fn with_timeout(timeout: Duration) -> Self {
+        dotenvy::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must point at a migrated database");
+        let pool = PgPoolOptions::new()
+            .max_connections(4)
+            .connect(&url)
+            .await
+            .expect("connect to DATABASE_URL");
+
+        let model = MockOllama::start().await;
+        let settings = HttpSettings {
+            base_url: model.url().to_string(),
+            model: "mock".to_string(),
+            embed_model: "mock-embed".to_string(),
+            api_key: None,
+            api_key_header: "Authorization".to_string(),
+            timeout,
+            max_retries: 0,
+            breaker: Arc::new(CircuitBreaker::new(0, Duration::ZERO)),
+        };
+        let state = AppState {
+            pool: pool.clone(),
+            jwt_secret: "test".to_string(),
+            llm: Arc::new(OllamaProvider::new(settings, reqwest::Client::new())),
+            ai_history_tokens: 1500,
+            ai_context_tokens: 4096,
+            ai_quotas: Quotas::new(0, 0),
+            embeddings: Embeddings::disabled(),
+            enrichment: Enrichment::disabled(),
+            default_tz: chrono_tz::Asia::Seoul,
+            prompts: shipped(),
+            mailer: None,
+        };
+
+        let user_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO users (id, email, password_hash) VALUES ($1, $2, $3)",
+            user_id,
+            format!("{user_id}@test.invalid"),
+            "-"
+        )
+        .execute(&pool)
+        .await
+        .expect("create test user");
+        analytics::rebuild(&mut pool.acquire().await.expect("acquire a connection"), user_id, state.default_tz)
+            .await
+            .expect("build the task rollup");
+
+        Self { state, user_id, model }
+    }
+
+    /// Inserts `seed` as the user's task, with its timestamps as given, and
+    /// counts it into the rollup like the task handlers do.
+    pub async fn add_task(&self, seed: TaskSeed<'_>) -> Uuid {
+        let id = Uuid::new_v4();
+        let end_date = seed.end_date.map(|d| d.parse::<NaiveDate>().unwrap());
+        let tags: Vec<String> = seed.tags.iter().map(|t| t.to_string()).collect();
+        let (created_at, updated_at) = (at(seed.created_at), at(seed.updated_at));
+        let completed_at = (seed.status == "done").then_some(updated_at);
+        let mut conn = self.state.pool.acquire().await.unwrap();
+        sqlx::query!(
+            r#"
+            INSERT INTO tasks (id, user_id, title, status, priority, due_date, end_date, estimate_minutes, tags, created_at, updated_at, completed_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $6, $7, $8, $9, $10, $11)
+            "#,
+            id,
+            self.user_id,
+            seed.title,
+            seed.status,
+            seed.priority,
+            end_date,
+            seed.estimate_minutes,
+            &tags,
+            created_at,
+            updated_at,
+            completed_at
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        let instants: Vec<_> = std::iter::once(created_at).chain(completed_at).collect();
+        analytics::touch(&mut conn, self.user_id, &instants).await.unwrap();
+        id
+    }
+}
+
+/// A task for `TestApp::add_task`; a done one was completed at `updated_at`.
+pub struct TaskSeed<'a> {
+    pub title: &'a str,
+    pub status: &'a str,
+    pub priority: &'a str,
+    pub tags: &'a [&'a str],
+    /// Also stored as the due date.
+    pub end_date: Option<&'a str>,
+    pub estimate_minutes: Option<i32>,
+    pub created_at: &'a str,
+    pub updated_at: &'a str,
+}
+
+impl<'a> TaskSeed<'a> {
+    pub fn new(status: &'a str, created_at: &'a str, updated_at: &'a str) -> Self {
+        Self {
+            title: "작업",
+            status,
+            priority: "medium",
+            tags: &[],
+            end_date: None,
+            estimate_minutes: None,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+/// An RFC 3339 timestamp.
+pub fn at(value: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(value).unwrap().with_timezone(&Utc)
+}
+
+/// Status and body of a handler's response.
+pub async fn read(response: impl IntoResponse) -> (u16, String) {
+    let response: Response = response.into_response();
+    let status = response.status().as_u16();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read response body");
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Status and JSON body of a handler's response.
+pub async fn read_json(response: impl IntoResponse) -> (u16, Value) {
+    let (status, body) = read(response).await;
+    let json = serde_json::from_str(&body).unwrap_or_else(|_| panic!("not JSON ({status}): {body}"));
+    (status, json)
+}
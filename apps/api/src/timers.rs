@@ -0,0 +1,110 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Acquire, PgConnection, PgPool};
+use uuid::Uuid;
+
+/// A span the timer ran for; `stopped_at` is unset while it runs.
+#[derive(Serialize, Debug)]
+pub struct TimeEntry {
+    pub id: Uuid,
+    pub task_id: Option<Uuid>,
+    pub task_title: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    /// Up to now for the running entry.
+    pub elapsed_seconds: i64,
+}
+
+/// The user's running entry, if any.
+pub async fn running(pool: &PgPool, user_id: Uuid) -> Result<Option<TimeEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        TimeEntry,
+        r#"
+        SELECT e.id, e.task_id, t.title AS "task_title?", e.started_at, e.stopped_at,
+               EXTRACT(EPOCH FROM NOW() - e.started_at)::BIGINT AS "elapsed_seconds!"
+        FROM time_entries e
+        LEFT JOIN tasks t ON t.id = e.task_id
+        WHERE e.user_id = $1 AND e.stopped_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// Starts an entry against `task_id`, stopping the one running first.
+/// The task must be the user's.
+pub async fn start(conn: &mut PgConnection, user_id: Uuid, task_id: Option<Uuid>) -> Result<TimeEntry, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    // Locking the user row makes a concurrent start wait and then stop this
+    // entry, instead of both inserting a running one. Times are taken when
+    // each statement runs, not when the transaction began before the wait,
+    // so the entry stopped here never ends before it started.
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    stop(&mut tx, user_id).await?;
+    let entry = sqlx::query_as!(
+        TimeEntry,
+        r#"
+        WITH e AS (
+            INSERT INTO time_entries (id, user_id, task_id, started_at)
+            VALUES ($1, $2, $3, statement_timestamp())
+            RETURNING id, task_id, started_at, stopped_at
+        )
+        SELECT e.id AS "id!", e.task_id, t.title AS "task_title?", e.started_at AS "started_at!", e.stopped_at,
+               0::BIGINT AS "elapsed_seconds!"
+        FROM e LEFT JOIN tasks t ON t.id = e.task_id
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        task_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(entry)
+}
+
+/// Stops the running entry and returns it, or `None` if none was running.
+pub async fn stop(conn: &mut PgConnection, user_id: Uuid) -> Result<Option<TimeEntry>, sqlx::Error> {
+    sqlx::query_as!(
+        TimeEntry,
+        r#"
+        WITH e AS (
+            UPDATE time_entries SET stopped_at = statement_timestamp()
+            WHERE user_id = $1 AND stopped_at IS NULL
+            RETURNING id, task_id, started_at, stopped_at
+        )
+        SELECT e.id AS "id!", e.task_id, t.title AS "task_title?", e.started_at AS "started_at!", e.stopped_at,
+               EXTRACT(EPOCH FROM e.stopped_at - e.started_at)::BIGINT AS "elapsed_seconds!"
+        FROM e LEFT JOIN tasks t ON t.id = e.task_id
+        "#,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+/// Minutes of the user's entries that fall between `from` and `to`, the
+/// running one counted up to now.
+pub async fn minutes_between(
+    pool: &PgPool,
+    user_id: Uuid,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(SUM(EXTRACT(EPOCH FROM LEAST(COALESCE(stopped_at, NOW()), $3) - GREATEST(started_at, $2))), 0)::BIGINT / 60
+            AS "minutes!"
+        FROM time_entries
+        WHERE user_id = $1 AND started_at < $3 AND COALESCE(stopped_at, NOW()) > $2
+        "#,
+        user_id,
+        from,
+        to
+    )
+    .fetch_one(pool)
+    .await
+}
@@ -0,0 +1,628 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{PgPool, QueryBuilder};
+use uuid::Uuid;
+
+use crate::handlers::{notes, tasks};
+use crate::llm::{ToolCall, ToolSpec};
+use crate::models::{NoteCreate, Task, TaskCreate, TaskUpdate};
+use crate::prompts::{Audience, Prompt, Prompts};
+use crate::settings::Preferences;
+
+const SEARCH_LIMIT: i64 = 10;
+
+/// A validated-shape tool invocation. Serialized as
+/// `{"tool": "update_task", "arguments": {...}}`, which is also what the
+/// confirmation endpoint accepts back.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "tool", content = "arguments", rename_all = "snake_case")]
+pub enum ToolAction {
+    SearchTasks(SearchTasksArgs),
+    CreateTask(CreateTaskArgs),
+    UpdateTask(UpdateTaskArgs),
+    CompleteTask(CompleteTaskArgs),
+    CreateNote(CreateNoteArgs),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchTasksArgs {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CreateTaskArgs {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpdateTaskArgs {
+    pub id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_date: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompleteTaskArgs {
+    pub id: Uuid,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CreateNoteArgs {
+    pub title: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+/// One entry of the execution log returned with an assistant reply.
+#[derive(Serialize)]
+pub struct ActionLog {
+    pub tool: String,
+    pub arguments: Value,
+    /// `executed`, `failed` or `pending_confirmation`.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A change to existing data the model asked for, held back until the user
+/// confirms it via `POST /api/v1/ai/actions`.
+#[derive(Serialize)]
+pub struct PendingAction {
+    pub summary: String,
+    pub action: ToolAction,
+}
+
+impl ActionLog {
+    /// What the model gets to see as the tool result.
+    pub fn feedback(&self, prompts: &Prompts, audience: &Audience) -> String {
+        match self.status {
+            "executed" => json!({ "status": "ok", "result": self.result }),
+            "pending_confirmation" => json!({
+                "status": "pending_confirmation",
+                "message": prompts.render(audience, Prompt::PendingFeedback, minijinja::Value::UNDEFINED),
+            }),
+            _ => json!({ "status": "error", "error": self.error }),
+        }
+        .to_string()
+    }
+}
+
+impl ToolAction {
+    pub fn parse(call: &ToolCall) -> Result<Self, String> {
+        serde_json::from_value(json!({ "tool": call.name, "arguments": call.arguments }))
+            .map_err(|e| format!("invalid arguments for {}: {e}", call.name))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToolAction::SearchTasks(_) => "search_tasks",
+            ToolAction::CreateTask(_) => "create_task",
+            ToolAction::UpdateTask(_) => "update_task",
+            ToolAction::CompleteTask(_) => "complete_task",
+            ToolAction::CreateNote(_) => "create_note",
+        }
+    }
+
+    /// Actions that change existing records need the user's go-ahead.
+    pub fn requires_confirmation(&self) -> bool {
+        matches!(self, ToolAction::UpdateTask(_) | ToolAction::CompleteTask(_))
+    }
+
+    fn arguments(&self) -> Value {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|mut v| v.get_mut("arguments").map(Value::take))
+            .unwrap_or(Value::Null)
+    }
+
+    fn task_update(&self) -> Option<(Uuid, TaskUpdate)> {
+        match self {
+            ToolAction::UpdateTask(args) => Some((args.id, args.to_update())),
+            ToolAction::CompleteTask(args) => Some((args.id, completion_update())),
+            _ => None,
+        }
+    }
+}
+
+impl UpdateTaskArgs {
+    fn to_update(&self) -> TaskUpdate {
+        TaskUpdate {
+            title: self.title.clone(),
+            description: self.description.clone(),
+            status: self.status.clone(),
+            priority: self.priority.clone(),
+            due_date: self.due_date,
+            start_date: self.start_date,
+            end_date: self.end_date,
+            tags: self.tags.clone(),
+        }
+    }
+}
+
+fn completion_update() -> TaskUpdate {
+    TaskUpdate {
+        title: None,
+        description: None,
+        status: Some("done".to_string()),
+        priority: None,
+        due_date: None,
+        start_date: None,
+        end_date: None,
+        tags: None,
+    }
+}
+
+pub fn specs() -> Vec<ToolSpec> {
+    let date = json!({ "type": "string", "description": "YYYY-MM-DD" });
+    let tags = json!({ "type": "array", "items": { "type": "string" } });
+    let status = json!({ "type": "string", "enum": ["todo", "in_progress", "done"] });
+    let priority = json!({ "type": "string", "enum": ["low", "medium", "high"] });
+    vec![
+        ToolSpec {
+            name: "search_tasks",
+            description: "Find the user's tasks by text, status or tag. Use it to get task ids before updating.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "Text to match in title or description" },
+                    "status": status,
+                    "tag": { "type": "string" }
+                }
+            }),
+        },
+        ToolSpec {
+            name: "create_task",
+            description: "Create a new task.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string" },
+                    "description": { "type": "string" },
+                    "priority": priority,
+                    "due_date": date,
+                    "start_date": date,
+                    "end_date": date,
+                    "tags": tags
+                },
+                "required": ["title"]
+            }),
+        },
+        ToolSpec {
+            name: "update_task",
+            description: "Change fields of an existing task. Only pass the fields to change.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string", "description": "Task id from search_tasks" },
+                    "title": { "type": "string" },
+                    "description": { "type": "string" },
+                    "status": status,
+                    "priority": priority,
+                    "due_date": date,
+                    "start_date": date,
+                    "end_date": date,
+                    "tags": tags
+                },
+                "required": ["id"]
+            }),
+        },
+        ToolSpec {
+            name: "complete_task",
+            description: "Mark an existing task as done.",
+            parameters: json!({
+                "type": "object",
+                "properties": { "id": { "type": "string", "description": "Task id from search_tasks" } },
+                "required": ["id"]
+            }),
+        },
+        ToolSpec {
+            name: "create_note",
+            description: "Create a note.",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string" },
+                    "content": { "type": "string" },
+                    "tags": tags
+                },
+                "required": ["title", "content"]
+            }),
+        },
+    ]
+}
+
+/// Runs a call the model made. Changes to existing records are validated
+/// and returned as pending instead of being applied.
+pub async fn handle_call(
+    pool: &PgPool,
+    prompts: &Prompts,
+    audience: &Audience,
+    user_id: Uuid,
+    prefs: &Preferences,
+    call: &ToolCall,
+) -> (ActionLog, Option<PendingAction>) {
+    let action = match ToolAction::parse(call) {
+        Ok(action) => action,
+        Err(err) => {
+            return (
+                ActionLog {
+                    tool: call.name.clone(),
+                    arguments: call.arguments.clone(),
+                    status: "failed",
+                    result: None,
+                    error: Some(err),
+                },
+                None,
+            )
+        }
+    };
+
+    if !action.requires_confirmation() {
+        return (execute(pool, user_id, prefs, &action).await, None);
+    }
+
+    match describe_pending(pool, prompts, audience, user_id, &action).await {
+        Ok(summary) => (
+            ActionLog {
+                tool: action.name().to_string(),
+                arguments: action.arguments(),
+                status: "pending_confirmation",
+                result: None,
+                error: None,
+            },
+            Some(PendingAction { summary, action }),
+        ),
+        Err(err) => (failed(&action, err), None),
+    }
+}
+
+pub async fn execute(pool: &PgPool, user_id: Uuid, prefs: &Preferences, action: &ToolAction) -> ActionLog {
+    match run(pool, user_id, prefs, action).await {
+        Ok(result) => ActionLog {
+            tool: action.name().to_string(),
+            arguments: action.arguments(),
+            status: "executed",
+            result: Some(result),
+            error: None,
+        },
+        Err(err) => failed(action, err),
+    }
+}
+
+fn failed(action: &ToolAction, err: String) -> ActionLog {
+    ActionLog {
+        tool: action.name().to_string(),
+        arguments: action.arguments(),
+        status: "failed",
+        result: None,
+        error: Some(err),
+    }
+}
+
+/// `prefs` supplies the status and priority of tasks the model creates
+/// without naming them.
+async fn run(pool: &PgPool, user_id: Uuid, prefs: &Preferences, action: &ToolAction) -> Result<Value, String> {
+    let db_error = |_| "db error".to_string();
+    match action {
+        ToolAction::SearchTasks(args) => {
+            let tasks = search_tasks(pool, user_id, args).await.map_err(db_error)?;
+            Ok(Value::Array(tasks.iter().map(task_brief).collect()))
+        }
+        ToolAction::CreateTask(args) => {
+            let task = tasks::validate_create(
+                TaskCreate {
+                    title: args.title.clone(),
+                    description: args.description.clone(),
+                    status: None,
+                    priority: args.priority.clone(),
+                    due_date: args.due_date,
+                    start_date: args.start_date,
+                    end_date: args.end_date,
+                    tags: args.tags.clone(),
+                },
+                prefs,
+            )?;
+            let mut conn = pool.acquire().await.map_err(db_error)?;
+            let task = tasks::insert_task(&mut conn, user_id, &task).await.map_err(db_error)?;
+            Ok(task_brief(&task))
+        }
+        ToolAction::UpdateTask(args) => update_task(pool, user_id, args.id, args.to_update()).await,
+        ToolAction::CompleteTask(args) => update_task(pool, user_id, args.id, completion_update()).await,
+        ToolAction::CreateNote(args) => {
+            let (title, content, tags) = notes::validate_create(NoteCreate {
+                title: args.title.clone(),
+                content: args.content.clone(),
+                tags: args.tags.clone(),
+            })?;
+            let note = async {
+                let mut tx = pool.begin().await?;
+                let note = notes::insert_note(&mut tx, user_id, &title, &content, &tags).await?;
+                tx.commit().await?;
+                Ok::<_, sqlx::Error>(note)
+            }
+            .await
+            .map_err(db_error)?;
+            Ok(json!({ "id": note.id, "title": note.title, "tags": note.tags }))
+        }
+    }
+}
+
+async fn update_task(pool: &PgPool, user_id: Uuid, id: Uuid, update: TaskUpdate) -> Result<Value, String> {
+    let changes = tasks::validate_update(update)?;
+    match tasks::apply_update(pool, user_id, id, &changes).await {
+        Ok(Some(task)) => Ok(task_brief(&task)),
+        Ok(None) => Err("task not found".to_string()),
+        Err(_) => Err("db error".to_string()),
+    }
+}
+
+/// Validates a held-back change and describes it for the confirmation
+/// prompt, in the audience's locale.
+async fn describe_pending(
+    pool: &PgPool,
+    prompts: &Prompts,
+    audience: &Audience,
+    user_id: Uuid,
+    action: &ToolAction,
+) -> Result<String, String> {
+    let Some((id, update)) = action.task_update() else {
+        return Ok(action.name().to_string());
+    };
+    let task = sqlx::query!(
+        "SELECT title FROM tasks WHERE id = $1 AND user_id = $2",
+        id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| "db error".to_string())?
+    .ok_or_else(|| "task not found".to_string())?;
+
+    let mut changes = Vec::new();
+    if !matches!(action, ToolAction::CompleteTask(_)) {
+        let update = tasks::validate_update(update)?;
+        let mut change = |field: &str, value: Option<String>| {
+            changes.push(prompts.render(audience, Prompt::PendingChange, minijinja::context! { field, value }));
+        };
+        if let Some(title) = &update.title {
+            change("title", Some(title.clone()));
+        }
+        if update.description.is_some() {
+            change("description", None);
+        }
+        if let Some(status) = &update.status {
+            change("status", Some(status.clone()));
+        }
+        if let Some(priority) = &update.priority {
+            change("priority", Some(priority.clone()));
+        }
+        match (update.start_date, update.end_date) {
+            (Some(start), Some(end)) if start != end => change("period", Some(format!("{start} ~ {end}"))),
+            (_, Some(end)) => change("due", Some(end.to_string())),
+            _ => {
+                if let Some(due) = update.due_date {
+                    change("due", Some(due.to_string()));
+                }
+            }
+        }
+        if let Some(tags) = &update.tags {
+            change("tags", Some(tags.join(", ")));
+        }
+        if changes.is_empty() {
+            return Err("no changes".to_string());
+        }
+    }
+    Ok(prompts.render(
+        audience,
+        Prompt::PendingSummary,
+        minijinja::context! { action => action.name(), title => task.title, changes },
+    ))
+}
+
+async fn search_tasks(pool: &PgPool, user_id: Uuid, args: &SearchTasksArgs) -> Result<Vec<Task>, sqlx::Error> {
+    let mut qb = QueryBuilder::new(
+        "SELECT id, user_id, title, description, status, priority, due_date, start_date, end_date, tags, parent_id, estimate_minutes, created_at, updated_at, started_at, completed_at FROM tasks WHERE user_id = ",
+    );
+    qb.push_bind(user_id);
+    if let Some(q) = args.query.as_deref().filter(|q| !q.trim().is_empty()) {
+        let like = format!("%{}%", q.trim());
+        qb.push(" AND (title ILIKE ");
+        qb.push_bind(like.clone());
+        qb.push(" OR description ILIKE ");
+        qb.push_bind(like);
+        qb.push(")");
+    }
+    if let Some(status) = args.status.clone() {
+        qb.push(" AND status = ");
+        qb.push_bind(status);
+    }
+    if let Some(tag) = args.tag.clone() {
+        qb.push(" AND ");
+        qb.push_bind(tag);
+        qb.push(" = ANY(tags)");
+    }
+    qb.push(" ORDER BY updated_at DESC LIMIT ");
+    qb.push_bind(SEARCH_LIMIT);
+    qb.build_query_as::<Task>().fetch_all(pool).await
+}
+
+fn task_brief(task: &Task) -> Value {
+    json!({
+        "id": task.id,
+        "title": task.title,
+        "status": task.status,
+        "priority": task.priority,
+        "due_date": task.due_date,
+        "start_date": task.start_date,
+        "end_date": task.end_date,
+        "tags": task.tags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompts::tests::{audience, shipped};
+    use crate::testing::TestApp;
+
+    fn call(name: &str, arguments: Value) -> ToolCall {
+        ToolCall {
+            id: "call-1".to_string(),
+            name: name.to_string(),
+            arguments,
+        }
+    }
+
+    #[test]
+    fn parses_known_tools_and_rejects_bad_arguments() {
+        let id = Uuid::new_v4();
+        let action = ToolAction::parse(&call("complete_task", json!({ "id": id }))).unwrap();
+        assert_eq!(action.name(), "complete_task");
+        assert!(action.requires_confirmation());
+        assert_eq!(action.arguments(), json!({ "id": id }));
+
+        let action = ToolAction::parse(&call("create_task", json!({ "title": "보고서", "due_date": "2024-03-01" }))).unwrap();
+        assert!(!action.requires_confirmation());
+
+        let err = ToolAction::parse(&call("create_task", json!({ "description": "제목 없음" }))).unwrap_err();
+        assert!(err.starts_with("invalid arguments for create_task"), "{err}");
+        assert!(ToolAction::parse(&call("update_task", json!({ "id": "not-a-uuid" }))).is_err());
+        assert!(ToolAction::parse(&call("create_task", json!({ "title": "x", "due_date": "3월 1일" }))).is_err());
+        assert!(ToolAction::parse(&call("delete_task", json!({ "id": id }))).is_err());
+    }
+
+    #[tokio::test]
+    async fn creates_directly_and_holds_back_changes() {
+        let app = TestApp::start().await;
+        let pool = &app.state.pool;
+        let prefs = Preferences::defaults(app.state.default_tz);
+        let (prompts, audience) = (shipped(), audience("ko"));
+
+        let (log, pending) = handle_call(pool, &prompts, &audience, app.user_id, &prefs, &call("create_task", json!({ "title": "회의 준비" }))).await;
+        assert_eq!(log.status, "executed");
+        assert!(pending.is_none());
+        let result = log.result.clone().unwrap();
+        assert_eq!(result["priority"], prefs.default_priority);
+        assert_eq!(result["status"], prefs.default_status);
+        let id: Uuid = serde_json::from_value(result["id"].clone()).unwrap();
+        assert!(log.feedback(&prompts, &audience).contains("\"status\":\"ok\""));
+
+        let (log, pending) = handle_call(
+            pool,
+            &prompts,
+            &audience,
+            app.user_id,
+            &prefs,
+            &call("update_task", json!({ "id": id, "title": "회의 자료 준비", "priority": "high" })),
+        )
+        .await;
+        assert_eq!(log.status, "pending_confirmation");
+        assert!(log.feedback(&prompts, &audience).contains("pending_confirmation"));
+        let pending = pending.unwrap();
+        assert_eq!(pending.summary, "업무 수정: 회의 준비 (제목 → 회의 자료 준비, 우선순위 → high)");
+        let title = sqlx::query_scalar!("SELECT title FROM tasks WHERE id = $1", id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(title, "회의 준비");
+
+        let log = execute(pool, app.user_id, &prefs, &pending.action).await;
+        assert_eq!(log.status, "executed");
+        assert_eq!(log.result.unwrap()["title"], "회의 자료 준비");
+
+        let (log, pending) = handle_call(pool, &prompts, &audience, app.user_id, &prefs, &call("complete_task", json!({ "id": id }))).await;
+        assert_eq!(log.status, "pending_confirmation");
+        assert_eq!(pending.as_ref().unwrap().summary, "업무 완료 처리: 회의 자료 준비");
+        let log = execute(pool, app.user_id, &prefs, &pending.unwrap().action).await;
+        assert_eq!(log.result.unwrap()["status"], "done");
+    }
+
+    #[tokio::test]
+    async fn reports_failures_to_the_model() {
+        let app = TestApp::start().await;
+        let pool = &app.state.pool;
+        let prefs = Preferences::defaults(app.state.default_tz);
+        let (prompts, audience) = (shipped(), audience("ko"));
+
+        let (log, pending) = handle_call(pool, &prompts, &audience, app.user_id, &prefs, &call("create_task", json!({ "priority": "high" }))).await;
+        assert_eq!(log.status, "failed");
+        assert!(pending.is_none());
+        assert!(log.feedback(&prompts, &audience).contains("invalid arguments"));
+
+        let (log, pending) = handle_call(pool, &prompts, &audience, app.user_id, &prefs, &call("create_task", json!({ "title": "  " }))).await;
+        assert_eq!(log.status, "failed");
+        assert!(pending.is_none());
+
+        let missing = call("complete_task", json!({ "id": Uuid::new_v4() }));
+        let (log, pending) = handle_call(pool, &prompts, &audience, app.user_id, &prefs, &missing).await;
+        assert_eq!(log.status, "failed");
+        assert!(pending.is_none());
+        assert_eq!(log.error.as_deref(), Some("task not found"));
+
+        let (log, _) = handle_call(pool, &prompts, &audience, app.user_id, &prefs, &call("create_task", json!({ "title": "정리" }))).await;
+        let id = log.result.unwrap()["id"].clone();
+        let (log, pending) = handle_call(pool, &prompts, &audience, app.user_id, &prefs, &call("update_task", json!({ "id": id }))).await;
+        assert_eq!(log.error.as_deref(), Some("no changes"));
+        assert!(pending.is_none());
+        let (log, pending) =
+            handle_call(pool, &prompts, &audience, app.user_id, &prefs, &call("update_task", json!({ "id": id, "status": "blocked" }))).await;
+        assert_eq!(log.status, "failed");
+        assert!(pending.is_none());
+
+        let other = TestApp::start().await;
+        let (log, _) = handle_call(&other.state.pool, &prompts, &audience, other.user_id, &prefs, &call("complete_task", json!({ "id": id }))).await;
+        assert_eq!(log.error.as_deref(), Some("task not found"));
+    }
+
+    #[tokio::test]
+    async fn searches_only_the_users_tasks() {
+        let app = TestApp::start().await;
+        let other = TestApp::start().await;
+        let prefs = Preferences::defaults(app.state.default_tz);
+        let (prompts, audience) = (shipped(), audience("ko"));
+        for (app, title) in [(&app, "분기 보고서"), (&app, "주간 보고"), (&other, "남의 보고서")] {
+            let (log, _) = handle_call(&app.state.pool, &prompts, &audience, app.user_id, &prefs, &call("create_task", json!({ "title": title }))).await;
+            assert_eq!(log.status, "executed");
+        }
+
+        let (log, _) = handle_call(&app.state.pool, &prompts, &audience, app.user_id, &prefs, &call("search_tasks", json!({ "query": "보고서" }))).await;
+        let found = log.result.unwrap();
+        let titles: Vec<_> = found.as_array().unwrap().iter().map(|t| t["title"].as_str().unwrap()).collect();
+        assert_eq!(titles, ["분기 보고서"]);
+    }
+}
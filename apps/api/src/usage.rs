@@ -0,0 +1,486 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use futures::StreamExt;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::llm::{
+    estimate_tokens, ChatCompletion, ChatMessage, CircuitStatus, LlmError, LlmProvider, QuotaLimit, StreamChunk,
+    TokenStream, ToolSpec,
+};
+use crate::middleware::AppState;
+use crate::settings;
+
+/// Daily limits per user; `None` is unlimited. Only calls made on the
+/// user's request count towards them, not embeddings or background jobs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Quotas {
+    pub daily_tokens: Option<i64>,
+    pub daily_requests: Option<i64>,
+}
+
+impl Quotas {
+    /// From the config values, where 0 is unlimited.
+    pub fn new(daily_tokens: u64, daily_requests: u64) -> Self {
+        let limit = |n: u64| (n > 0).then(|| n.min(i64::MAX as u64) as i64);
+        Self {
+            daily_tokens: limit(daily_tokens),
+            daily_requests: limit(daily_requests),
+        }
+    }
+
+    /// The limit `used` has reached.
+    fn exceeded(&self, used: &Used) -> Option<QuotaLimit> {
+        if let Some(limit) = self.daily_requests.filter(|&limit| used.requests >= limit) {
+            return Some(QuotaLimit::Requests(limit));
+        }
+        self.daily_tokens.filter(|&limit| used.tokens >= limit).map(QuotaLimit::Tokens)
+    }
+}
+
+/// What counts towards the quotas so far today.
+#[derive(Debug, Default)]
+pub struct Used {
+    pub requests: i64,
+    pub tokens: i64,
+}
+
+/// Start of `date` and of the day after on the `tz` clock, in UTC.
+pub fn day_bounds(date: NaiveDate, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    let next = date.succ_opt().unwrap_or(date);
+    (local_midnight(date, tz), local_midnight(next, tz))
+}
+
+/// Where a DST change skips midnight, the day starts at the first hour
+/// that exists.
+fn local_midnight(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    (0..3)
+        .find_map(|hours| {
+            tz.from_local_datetime(&(midnight + chrono::TimeDelta::hours(hours)))
+                .earliest()
+        })
+        .map(|start| start.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&midnight))
+}
+
+/// Usage counted towards the quotas since `since`: user requests that got
+/// at least one answer from the model, each once however many calls it
+/// made, and the tokens of all their calls.
+pub async fn used_since(pool: &PgPool, user_id: Uuid, since: DateTime<Utc>) -> Result<Used, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(DISTINCT request_id) FILTER (WHERE outcome = 'ok') AS "requests!",
+               COALESCE(SUM(prompt_tokens + completion_tokens), 0)::BIGINT AS "tokens!"
+        FROM ai_usage
+        WHERE user_id = $1 AND created_at >= $2 AND request_id IS NOT NULL AND operation <> 'embed'
+        "#,
+        user_id,
+        since
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(Used {
+        requests: row.requests,
+        tokens: row.tokens,
+    })
+}
+
+/// `QuotaExceeded` once the user has used up a daily quota. The database
+/// being unreachable does not block the model; it is only logged.
+pub async fn check(state: &AppState, user_id: Uuid) -> Result<(), LlmError> {
+    enforce(&state.pool, user_id, state.ai_quotas, state.default_tz).await
+}
+
+async fn enforce(pool: &PgPool, user_id: Uuid, quotas: Quotas, tz: Tz) -> Result<(), LlmError> {
+    if quotas.daily_tokens.is_none() && quotas.daily_requests.is_none() {
+        return Ok(());
+    }
+    // Days run on the user's clock; `tz` is the server default.
+    let tz = settings::preferences(pool, user_id, tz).await.timezone;
+    let now = Utc::now();
+    let (start, next) = day_bounds(now.with_timezone(&tz).date_naive(), tz);
+    let used = match used_since(pool, user_id, start).await {
+        Ok(used) => used,
+        Err(err) => {
+            tracing::warn!(user_id = %user_id, "usage lookup failed: {err}");
+            return Ok(());
+        }
+    };
+    match quotas.exceeded(&used) {
+        Some(limit) => Err(LlmError::QuotaExceeded {
+            limit,
+            retry_after_secs: (next - now).num_seconds().max(1) as u64,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Provider that records each call of `user_id` in `ai_usage` and, for
+/// calls made on the user's request, holds them to the daily quotas.
+/// A limited instance stands for one request: make one per handler call and
+/// pass it down, so its calls are counted as that one request.
+pub struct Metered {
+    inner: Arc<dyn LlmProvider>,
+    recorder: Recorder,
+    quotas: Option<(Quotas, Tz)>,
+}
+
+impl Metered {
+    /// For calls the user asked for: recorded under a new request id and
+    /// held to the quotas.
+    pub fn limited(state: &AppState, user_id: Uuid, feature: &'static str) -> Self {
+        let mut metered = Self::recorded(state.pool.clone(), state.llm.clone(), user_id, feature);
+        metered.recorder.request_id = Some(Uuid::new_v4());
+        metered.quotas = Some((state.ai_quotas, state.default_tz));
+        metered
+    }
+
+    /// Recorded only. For background jobs, so one user's quota never holds
+    /// up a job that works for everyone, and for embeddings.
+    pub fn recorded(pool: PgPool, llm: Arc<dyn LlmProvider>, user_id: Uuid, feature: &'static str) -> Self {
+        Self {
+            inner: llm,
+            recorder: Recorder {
+                pool,
+                user_id,
+                feature,
+                request_id: None,
+            },
+            quotas: None,
+        }
+    }
+
+    async fn admit(&self) -> Result<(), LlmError> {
+        match self.quotas {
+            Some((quotas, tz)) => enforce(&self.recorder.pool, self.recorder.user_id, quotas, tz).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn metered(
+        &self,
+        operation: &'static str,
+        messages: &[ChatMessage],
+        call: impl Future<Output = Result<ChatCompletion, LlmError>>,
+    ) -> Result<ChatCompletion, LlmError> {
+        self.admit().await?;
+        let started = Instant::now();
+        let result = call.await;
+        let (tokens, outcome) = match &result {
+            Ok(completion) => (Tokens::of(messages, completion), "ok"),
+            // Finding out the model cannot call tools is not a use of it,
+            // and providers remember the answer without asking again.
+            Err(LlmError::ToolsUnsupported) => return result,
+            Err(err) => (Tokens::default(), err.code()),
+        };
+        self.recorder
+            .record(self.inner.model(), operation, tokens, started.elapsed(), outcome)
+            .await;
+        result
+    }
+}
+
+#[async_trait]
+impl LlmProvider for Metered {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn embed_model(&self) -> &str {
+        self.inner.embed_model()
+    }
+
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.metered("chat", messages, self.inner.chat(messages)).await
+    }
+
+    async fn chat_json(&self, messages: &[ChatMessage]) -> Result<ChatCompletion, LlmError> {
+        self.metered("chat_json", messages, self.inner.chat_json(messages)).await
+    }
+
+    /// Records the counts the stream ends with, estimating those it leaves
+    /// out. A stream the client abandons is recorded as `cancelled`.
+    async fn chat_stream(&self, messages: &[ChatMessage]) -> Result<TokenStream, LlmError> {
+        self.admit().await?;
+        let started = Instant::now();
+        let mut upstream = match self.inner.chat_stream(messages).await {
+            Ok(upstream) => upstream,
+            Err(err) => {
+                self.recorder
+                    .record(self.inner.model(), "chat_stream", Tokens::default(), started.elapsed(), err.code())
+                    .await;
+                return Err(err);
+            }
+        };
+
+        let mut call = StreamCall {
+            recorder: self.recorder.clone(),
+            model: self.inner.model().to_string(),
+            prompt_tokens: prompt_estimate(messages),
+            output: String::new(),
+            reported: (None, None),
+            started,
+            finished: false,
+        };
+        Ok(Box::pin(async_stream::stream! {
+            while let Some(chunk) = upstream.next().await {
+                match &chunk {
+                    Ok(StreamChunk::Delta(content)) => call.output.push_str(content),
+                    Ok(StreamChunk::Usage { prompt_tokens, completion_tokens }) => {
+                        call.reported = (*prompt_tokens, *completion_tokens);
+                    }
+                    Err(err) => {
+                        call.finish(err.code()).await;
+                        yield chunk;
+                        return;
+                    }
+                }
+                yield chunk;
+            }
+            call.finish("ok").await;
+        }))
+    }
+
+    async fn chat_with_tools(&self, messages: &[ChatMessage], tools: &[ToolSpec]) -> Result<ChatCompletion, LlmError> {
+        self.metered("chat_with_tools", messages, self.inner.chat_with_tools(messages, tools))
+            .await
+    }
+
+    async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>, LlmError> {
+        let started = Instant::now();
+        let result = self.inner.embed(inputs).await;
+        let (tokens, outcome) = match &result {
+            Ok(_) => (
+                Tokens {
+                    prompt: inputs.iter().map(|i| estimate_tokens(i)).sum::<usize>() as i64,
+                    completion: 0,
+                    estimated: true,
+                },
+                "ok",
+            ),
+            Err(err) => (Tokens::default(), err.code()),
+        };
+        self.recorder
+            .record(self.inner.embed_model(), "embed", tokens, started.elapsed(), outcome)
+            .await;
+        result
+    }
+
+    fn circuit(&self) -> Option<CircuitStatus> {
+        self.inner.circuit()
+    }
+}
+
+#[derive(Default)]
+struct Tokens {
+    prompt: i64,
+    completion: i64,
+    estimated: bool,
+}
+
+impl Tokens {
+    /// The counts the server reported, estimated where it left them out.
+    fn of(messages: &[ChatMessage], completion: &ChatCompletion) -> Self {
+        Self {
+            prompt: completion.prompt_tokens.unwrap_or_else(|| prompt_estimate(messages)),
+            completion: completion
+                .completion_tokens
+                .unwrap_or_else(|| estimate_tokens(&completion.content) as i64),
+            estimated: completion.prompt_tokens.is_none() || completion.completion_tokens.is_none(),
+        }
+    }
+}
+
+fn prompt_estimate(messages: &[ChatMessage]) -> i64 {
+    messages.iter().map(|m| estimate_tokens(&m.content)).sum::<usize>() as i64
+}
+
+#[derive(Clone)]
+struct Recorder {
+    pool: PgPool,
+    user_id: Uuid,
+    feature: &'static str,
+    /// Set for calls made on the user's request; see `Metered::limited`.
+    request_id: Option<Uuid>,
+}
+
+impl Recorder {
+    /// Accounting never fails the call it describes; errors are logged.
+    async fn record(&self, model: &str, operation: &str, tokens: Tokens, latency: Duration, outcome: &str) {
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO ai_usage
+                (id, user_id, model, operation, feature, prompt_tokens, completion_tokens, tokens_estimated, latency_ms, outcome, request_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#,
+            Uuid::new_v4(),
+            self.user_id,
+            model,
+            operation,
+            self.feature,
+            tokens.prompt.min(i32::MAX as i64) as i32,
+            tokens.completion.min(i32::MAX as i64) as i32,
+            tokens.estimated,
+            latency.as_millis().min(i32::MAX as u128) as i32,
+            outcome,
+            self.request_id
+        )
+        .execute(&self.pool)
+        .await;
+        if let Err(err) = res {
+            tracing::warn!(user_id = %self.user_id, "recording ai usage failed: {err}");
+        }
+    }
+}
+
+/// A stream in flight; recorded once it ends, or as `cancelled` when it is
+/// dropped first.
+struct StreamCall {
+    recorder: Recorder,
+    model: String,
+    /// Estimated.
+    prompt_tokens: i64,
+    output: String,
+    /// Prompt and completion tokens from the stream's `Usage` chunk.
+    reported: (Option<i64>, Option<i64>),
+    started: Instant,
+    finished: bool,
+}
+
+impl StreamCall {
+    fn tokens(&self) -> Tokens {
+        let (prompt, completion) = self.reported;
+        Tokens {
+            prompt: prompt.unwrap_or(self.prompt_tokens),
+            completion: completion.unwrap_or_else(|| estimate_tokens(&self.output) as i64),
+            estimated: prompt.is_none() || completion.is_none(),
+        }
+    }
+
+    async fn finish(&mut self, outcome: &str) {
+        self.finished = true;
+        self.recorder
+            .record(&self.model, "chat_stream", self.tokens(), self.started.elapsed(), outcome)
+            .await;
+    }
+}
+
+impl Drop for StreamCall {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let (recorder, model, tokens, latency) = (
+            self.recorder.clone(),
+            std::mem::take(&mut self.model),
+            self.tokens(),
+            self.started.elapsed(),
+        );
+        tokio::spawn(async move {
+            recorder
+                .record(&model, "chat_stream", tokens, latency, "cancelled")
+                .await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MockProvider;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn quotas_are_reached_at_the_limit() {
+        let quotas = Quotas::new(1000, 3);
+        assert_eq!(quotas.exceeded(&Used { requests: 2, tokens: 999 }), None);
+        assert_eq!(quotas.exceeded(&Used { requests: 3, tokens: 0 }), Some(QuotaLimit::Requests(3)));
+        assert_eq!(quotas.exceeded(&Used { requests: 0, tokens: 1000 }), Some(QuotaLimit::Tokens(1000)));
+        assert_eq!(
+            Quotas::new(0, 0).exceeded(&Used { requests: 1_000_000, tokens: 1_000_000 }),
+            None
+        );
+    }
+
+    #[test]
+    fn days_follow_the_local_clock() {
+        let seoul: Tz = "Asia/Seoul".parse().unwrap();
+        let (start, next) = day_bounds(NaiveDate::from_ymd_opt(2024, 12, 2).unwrap(), seoul);
+        assert_eq!(start.to_rfc3339(), "2024-12-01T15:00:00+00:00");
+        assert_eq!(next.to_rfc3339(), "2024-12-02T15:00:00+00:00");
+
+        // Midnight does not exist in Santiago on the day DST starts.
+        let santiago: Tz = "America/Santiago".parse().unwrap();
+        let (start, next) = day_bounds(NaiveDate::from_ymd_opt(2024, 9, 8).unwrap(), santiago);
+        assert_eq!(start.to_rfc3339(), "2024-09-08T04:00:00+00:00");
+        assert_eq!(next.to_rfc3339(), "2024-09-09T03:00:00+00:00");
+    }
+
+    #[tokio::test]
+    async fn streams_record_the_counts_the_server_reports() {
+        let app = TestApp::start().await;
+        app.model.push_reply("하나 둘").push_truncated_stream("끊긴 답");
+        let llm = Metered::recorded(app.state.pool.clone(), app.state.llm.clone(), app.user_id, "chat");
+        for _ in 0..2 {
+            let chunks: Vec<_> = llm.chat_stream(&[ChatMessage::user("질문")]).await.unwrap().collect().await;
+            assert!(!chunks.is_empty());
+        }
+
+        let rows = sqlx::query!(
+            "SELECT prompt_tokens, completion_tokens, tokens_estimated, outcome FROM ai_usage WHERE user_id = $1 ORDER BY created_at",
+            app.user_id
+        )
+        .fetch_all(&app.state.pool)
+        .await
+        .unwrap();
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|r| (r.prompt_tokens, r.completion_tokens, r.tokens_estimated, r.outcome.as_str()))
+            .collect();
+        // The mock reports 10 prompt tokens and one per character; a stream
+        // cut short before `done` falls back to estimates.
+        assert_eq!(
+            rows,
+            [(10, 4, false, "ok"), (2, estimate_tokens("끊긴 답") as i32, true, "upstream_incomplete")]
+        );
+    }
+
+    #[tokio::test]
+    async fn quotas_count_each_user_request_once() {
+        let app = TestApp::start().await;
+        app.model.push_reply("첫째").push_reply("둘째").push_reply("요약");
+        let request = Metered::limited(&app.state, app.user_id, "chat");
+        request.chat(&[ChatMessage::user("의도")]).await.unwrap();
+        request.chat(&[ChatMessage::user("답")]).await.unwrap();
+        Metered::recorded(app.state.pool.clone(), app.state.llm.clone(), app.user_id, "enrichment")
+            .chat(&[ChatMessage::user("노트")])
+            .await
+            .unwrap();
+        let down = MockProvider::new("mock");
+        down.push_error(LlmError::CircuitOpen { retry_after_secs: 30 });
+        let state = AppState {
+            llm: Arc::new(down),
+            ..app.state.clone()
+        };
+        let refused = Metered::limited(&state, app.user_id, "chat").chat(&[ChatMessage::user("질문")]).await;
+        assert!(matches!(refused, Err(LlmError::CircuitOpen { .. })));
+
+        let calls: i64 = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "n!" FROM ai_usage WHERE user_id = $1"#, app.user_id)
+            .fetch_one(&app.state.pool)
+            .await
+            .unwrap();
+        assert_eq!(calls, 4);
+        // Two calls of one request; the enrichment and the refused call
+        // are left out. The mock reports 10 prompt tokens and one per character.
+        let used = used_since(&app.state.pool, app.user_id, Utc::now() - chrono::TimeDelta::hours(1))
+            .await
+            .unwrap();
+        assert_eq!((used.requests, used.tokens), (1, 24));
+    }
+}